@@ -64,7 +64,7 @@ impl fmt::Display for AsioError {
                 "sample clock or rate cannot be determined or is not present"
             ),
             AsioError::ASE_NoMemory => write!(f, "not enough memory for completing the request"),
-            AsioError::InvalidBufferSize => write!(f, "buffersize out of range for device"),
+            AsioError::InvalidBufferSize => write!(f, "buffersize not supported by device"),
             AsioError::UnknownError => write!(f, "Error not in SDK"),
         }
     }
@@ -96,12 +96,29 @@ impl Error for AsioError {
             AsioError::HardwareStuck => "hardware is not running when sample position is inquired",
             AsioError::NoRate => "sample clock or rate cannot be determined or is not present",
             AsioError::ASE_NoMemory => "not enough memory for completing the request",
-            AsioError::InvalidBufferSize => "buffersize out of range for device",
+            AsioError::InvalidBufferSize => "buffersize not supported by device",
             AsioError::UnknownError => "Error not in SDK",
         }
     }
 }
 
+impl AsioError {
+    /// The `ASIOError` code the SDK returned for this error, if it came from the SDK.
+    pub fn code(&self) -> Option<i32> {
+        let code = match *self {
+            AsioError::NoDrivers => AsioErrorWrapper::ASE_NotPresent,
+            AsioError::HardwareMalfunction => AsioErrorWrapper::ASE_HWMalfunction,
+            AsioError::InvalidInput => AsioErrorWrapper::ASE_InvalidParameter,
+            AsioError::BadMode => AsioErrorWrapper::ASE_InvalidMode,
+            AsioError::HardwareStuck => AsioErrorWrapper::ASE_SPNotAdvancing,
+            AsioError::NoRate => AsioErrorWrapper::ASE_NoClock,
+            AsioError::ASE_NoMemory => AsioErrorWrapper::ASE_NoMemory,
+            AsioError::InvalidBufferSize | AsioError::UnknownError => return None,
+        };
+        Some(code as i32)
+    }
+}
+
 impl From<AsioError> for LoadDriverError {
     fn from(err: AsioError) -> Self {
         LoadDriverError::InitializationFailed(err)
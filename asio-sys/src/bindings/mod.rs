@@ -51,6 +51,8 @@ struct DriverInner {
     state: Mutex<DriverState>,
     // The unique name associated with this driver.
     name: String,
+    // The version the driver reported when it was initialised.
+    version: c_long,
     // Track whether or not the driver has been destroyed.
     //
     // This allows for the user to manually destroy the driver and handle any errors if they wish.
@@ -89,6 +91,8 @@ pub struct SampleRate {
 pub struct CallbackInfo {
     pub buffer_index: i32,
     pub system_time: ai::ASIOTimeStamp,
+    /// The sample position at `system_time`, counted since `ASIOStart()`.
+    pub sample_position: ai::ASIOSamples,
 }
 
 /// Holds the pointer to the callbacks that come from cpal
@@ -236,18 +240,41 @@ pub struct AsioTimeCode {
 /// fields.
 pub type AsioSampleRate = f64;
 
-// A helper type to simplify retrieval of available buffer sizes.
-#[derive(Default)]
-struct BufferSizes {
-    min: c_long,
-    max: c_long,
-    pref: c_long,
-    grans: c_long,
+/// The buffer sizes supported by the driver, in frames, as reported by `ASIOGetBufferSize`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BufferSizes {
+    pub min: c_long,
+    pub max: c_long,
+    /// The size the driver would like to be used. Other sizes may perform badly.
+    pub pref: c_long,
+    /// The step between supported sizes: `-1` for powers of two from `min` up to `max`, `0` when
+    /// `min` and `max` are equal, and otherwise a linear step from `min`.
+    pub grans: c_long,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CallbackId(usize);
 
+impl BufferSizes {
+    /// Whether the driver accepts buffers of `size` frames.
+    pub fn supports(&self, size: c_long) -> bool {
+        if size < self.min || size > self.max {
+            return false;
+        }
+        match self.grans {
+            -1 => {
+                let mut supported = self.min;
+                while supported > 0 && supported < size {
+                    supported *= 2;
+                }
+                supported == size
+            }
+            grans if grans > 0 => (size - self.min) % grans == 0,
+            _ => true,
+        }
+    }
+}
+
 lazy_static! {
     /// A global way to access all the callbacks.
     ///
@@ -258,6 +285,11 @@ lazy_static! {
     ///
     /// The indices are how we match a callback with a stream.
     static ref BUFFER_CALLBACK: Mutex<Vec<(CallbackId, BufferCallback)>> = Mutex::new(Vec::new());
+
+    /// The callbacks called when the driver requests to be reset, matched with their IDs like the
+    /// buffer callbacks.
+    static ref RESET_CALLBACK: Mutex<Vec<(CallbackId, Box<dyn FnMut() + Send>)>> =
+        Mutex::new(Vec::new());
 }
 
 impl Asio {
@@ -339,12 +371,14 @@ impl Asio {
                 true => {
                     // Initialize ASIO.
                     asio_result!(ai::ASIOInit(driver_info.as_mut_ptr()))?;
-                    let _driver_info = driver_info.assume_init();
+                    let driver_info = driver_info.assume_init();
                     let state = Mutex::new(DriverState::Initialized);
                     let name = driver_name.to_string();
+                    let version = driver_info.driverVersion;
                     let destroyed = false;
                     let inner = Arc::new(DriverInner {
                         name,
+                        version,
                         state,
                         destroyed,
                     });
@@ -374,6 +408,11 @@ impl Driver {
         &self.inner.name
     }
 
+    /// The version of the driver, as reported by the driver when it was loaded.
+    pub fn version(&self) -> c_long {
+        self.inner.version
+    }
+
     /// Returns the number of input and output channels available on the driver.
     pub fn channels(&self) -> Result<Channels, AsioError> {
         let mut ins: c_long = 0;
@@ -393,6 +432,11 @@ impl Driver {
         Ok((min, max))
     }
 
+    /// Get the minimum, maximum and preferred buffer sizes of the driver, and their granularity.
+    pub fn buffer_sizes(&self) -> Result<BufferSizes, AsioError> {
+        asio_get_buffer_sizes()
+    }
+
     /// Get current sample rate of the driver.
     pub fn sample_rate(&self) -> Result<c_double, AsioError> {
         let mut rate: c_double = 0.0;
@@ -440,8 +484,8 @@ impl Driver {
     /// This will destroy any already allocated buffers.
     ///
     /// If buffersize is None then the preferred buffer size from ASIO is used,
-    /// otherwise the desired buffersize is used if the requested size is within
-    /// the range of accepted buffersizes for the device.
+    /// otherwise the desired buffersize is used if it is one of the buffersizes
+    /// accepted by the device.
     fn create_buffers(
         &self,
         buffer_infos: &mut [AsioBufferInfo],
@@ -465,7 +509,7 @@ impl Driver {
 
         let buffer_size = match buffer_size {
             Some(v) => {
-                if v <= buffer_sizes.max {
+                if buffer_sizes.supports(v) {
                     v
                 } else {
                     return Err(AsioError::InvalidBufferSize);
@@ -672,6 +716,30 @@ impl Driver {
         bc.retain(|&(id, _)| id != rem_id);
     }
 
+    /// Adds a callback called when the driver requests to be reset, which it does when its
+    /// settings change, e.g. in its control panel. The callback is called on a thread of the
+    /// driver.
+    ///
+    /// Returns an ID uniquely associated with the given callback so that it may be removed later.
+    pub fn add_reset_callback<F>(&self, callback: F) -> CallbackId
+    where
+        F: 'static + FnMut() + Send,
+    {
+        let mut rc = RESET_CALLBACK.lock().unwrap();
+        let id = rc
+            .last()
+            .map(|&(id, _)| CallbackId(id.0.checked_add(1).expect("callback ID overflowed")))
+            .unwrap_or(CallbackId(0));
+        rc.push((id, Box::new(callback)));
+        id
+    }
+
+    /// Remove the reset callback with the given ID.
+    pub fn remove_reset_callback(&self, rem_id: CallbackId) {
+        let mut rc = RESET_CALLBACK.lock().unwrap();
+        rc.retain(|&(id, _)| id != rem_id);
+    }
+
     /// Consumes and destroys the `Driver`, stopping the streams if they are running and releasing
     /// any associated resources.
     ///
@@ -895,6 +963,11 @@ extern "C" fn asio_message(
             // the driver is done by completely destruct it. I.e. ASIOStop(), ASIODisposeBuffers(),
             // Destruction. Afterwards you initialize the driver again.
             // TODO: Handle this.
+            if let Ok(mut rc) = RESET_CALLBACK.lock() {
+                for &mut (_, ref mut callback) in rc.iter_mut() {
+                    callback();
+                }
+            }
             1
         }
 
@@ -957,6 +1030,7 @@ extern "C" fn buffer_switch_time_info(
     let callback_info = CallbackInfo {
         buffer_index: double_buffer_index,
         system_time: asio_time.time_info.system_time,
+        sample_position: asio_time.time_info.sample_position,
     };
     for &mut (_, ref mut bc) in bcs.iter_mut() {
         bc.run(&callback_info);
@@ -1,4 +1,7 @@
 use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
 
 const CPAL_ASIO_DIR: &str = "CPAL_ASIO_DIR";
 
@@ -9,4 +12,28 @@ fn main() {
         Err(_) => {}
         Ok(_) => println!("cargo:rustc-cfg=asio"),
     };
+
+    if env::var_os("CARGO_FEATURE_CAPI").is_some() {
+        generate_c_header();
+    }
+}
+
+// Generates the C API header with the `cbindgen` tool, if it is installed, and warns if the
+// checked-in `include/cpal.h` differs from it.
+fn generate_c_header() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("cpal.h");
+    let status = Command::new("cbindgen")
+        .args(["--config", "cbindgen.toml", "--quiet", "--output"])
+        .arg(&out)
+        .status();
+    if !matches!(status, Ok(status) if status.success()) {
+        return;
+    }
+    let header = fs::read_to_string(&out).unwrap_or_default();
+    if fs::read_to_string("include/cpal.h").ok().as_ref() != Some(&header) {
+        println!(
+            "cargo:warning=include/cpal.h is out of date, regenerate it with \
+             `cbindgen --config cbindgen.toml --output include/cpal.h`"
+        );
+    }
 }
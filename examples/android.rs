@@ -16,9 +16,11 @@ fn main() {
     let config = device.default_output_config().unwrap();
 
     match config.sample_format() {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()).unwrap(),
+        cpal::SampleFormat::F32 => run::<f32>(&device, &config.config()).unwrap(),
+        cpal::SampleFormat::I16 => run::<i16>(&device, &config.config()).unwrap(),
+        cpal::SampleFormat::U16 => run::<u16>(&device, &config.config()).unwrap(),
+        cpal::SampleFormat::I24 => run::<cpal::I24>(&device, &config.config()).unwrap(),
+        cpal::SampleFormat::I24Packed => run::<cpal::I24Packed>(&device, &config.config()).unwrap(),
     }
 }
 
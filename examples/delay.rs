@@ -0,0 +1,160 @@
+//! Measures the round-trip delay from the output device to the input device.
+//!
+//! Plays a short click every `INTERVAL_MS` milliseconds and detects it in the input with a
+//! `cpal::analysis::Gate`, printing the time between the instant the click was played and the
+//! instant it was captured. Place the microphone near the speakers, or connect the output to the
+//! input with a cable.
+//!
+//! Both directions run through one duplex stream, whose data callback receives the captured and
+//! the played frames along with the instants of both.
+//!
+//! Assumes that the output device supports the input device's default sample rate and that both
+//! support the f32 sample format.
+
+extern crate anyhow;
+extern crate clap;
+extern crate cpal;
+
+use anyhow::Context;
+use clap::arg;
+use cpal::analysis::{Gate, GateEdge};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::StreamInstant;
+use std::sync::mpsc;
+use std::time::Duration;
+
+const INTERVAL_MS: u64 = 500;
+const CLICK_MS: u64 = 5;
+
+#[derive(Debug)]
+struct Opt {
+    threshold: f32,
+    input_device: String,
+    output_device: String,
+}
+
+impl Opt {
+    fn from_args() -> anyhow::Result<Self> {
+        let matches = clap::Command::new("delay")
+            .arg(arg!(
+            -t --threshold [LEVEL] "Specify the input level that detects a click [default: 0.1]"))
+            .arg(arg!([IN] "The input audio device to use"))
+            .arg(arg!([OUT] "The output audio device to use"))
+            .get_matches();
+        let threshold: f32 = matches
+            .value_of("threshold")
+            .unwrap_or("0.1")
+            .parse()
+            .context("parsing threshold option")?;
+        let input_device = matches.value_of("IN").unwrap_or("default").to_string();
+        let output_device = matches.value_of("OUT").unwrap_or("default").to_string();
+        Ok(Opt {
+            threshold,
+            input_device,
+            output_device,
+        })
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::from_args()?;
+    let host = cpal::default_host();
+
+    // Find devices.
+    let input_device = if opt.input_device == "default" {
+        host.default_input_device()
+    } else {
+        host.input_devices()?
+            .find(|x| x.name().map(|y| y == opt.input_device).unwrap_or(false))
+    }
+    .expect("failed to find input device");
+
+    let output_device = if opt.output_device == "default" {
+        host.default_output_device()
+    } else {
+        host.output_devices()?
+            .find(|x| x.name().map(|y| y == opt.output_device).unwrap_or(false))
+    }
+    .expect("failed to find output device");
+
+    println!("Using input device: \"{}\"", input_device.name()?);
+    println!("Using output device: \"{}\"", output_device.name()?);
+
+    let input_config = input_device.default_input_config()?;
+    let config = cpal::DuplexStreamConfig {
+        input_channels: input_config.channels(),
+        output_channels: output_device.default_output_config()?.channels(),
+        sample_rate: input_config.sample_rate(),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let channels = config.output_channels as usize;
+    let sample_rate = config.sample_rate.0 as u64;
+
+    // The callbacks report instants through bounded channels, which neither block nor allocate.
+    let (click_tx, click_rx) = mpsc::sync_channel::<StreamInstant>(16);
+    let (onset_tx, onset_rx) = mpsc::sync_channel::<StreamInstant>(16);
+
+    let interval_frames = config
+        .sample_rate
+        .frames_in_rounded(Duration::from_millis(INTERVAL_MS));
+    let click_frames = config
+        .sample_rate
+        .frames_in_rounded(Duration::from_millis(CLICK_MS));
+    let mut frame = 0u64;
+    let mut gate = Gate::new(config.input_channels, config.sample_rate, opt.threshold)
+        .with_release(Duration::from_millis(INTERVAL_MS / 4));
+    let data_fn = move |input: &[f32], output: &mut [f32], info: &cpal::DuplexCallbackInfo| {
+        let timestamp = info.timestamp();
+        gate.process(input, timestamp.capture, |event| {
+            if event.edge == GateEdge::Onset {
+                let _ = onset_tx.try_send(event.instant);
+            }
+        });
+        for (i, samples) in output.chunks_mut(channels).enumerate() {
+            let position = frame % interval_frames;
+            if position == 0 {
+                let offset = Duration::from_nanos(i as u64 * 1_000_000_000 / sample_rate);
+                if let Some(instant) = timestamp.playback.add(offset) {
+                    let _ = click_tx.try_send(instant);
+                }
+            }
+            let value = if position < click_frames { 0.8 } else { 0.0 };
+            for sample in samples {
+                *sample = value;
+            }
+            frame += 1;
+        }
+    };
+
+    let stream = input_device.build_duplex_stream(&output_device, &config, data_fn, err_fn)?;
+    stream.play()?;
+
+    // Pair each onset with the latest click played before it.
+    println!("Measuring for 5 seconds...");
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let mut clicks = vec![];
+    while let Some(timeout) = deadline.checked_duration_since(std::time::Instant::now()) {
+        let onset = match onset_rx.recv_timeout(timeout) {
+            Ok(onset) => onset,
+            Err(_) => break,
+        };
+        clicks.extend(click_rx.try_iter());
+        let stale = clicks.len().saturating_sub(4);
+        clicks.drain(..stale);
+        let delay = clicks
+            .iter()
+            .filter_map(|click| onset.duration_since(click))
+            .min();
+        match delay {
+            Some(delay) => println!("delay: {:.2} ms", delay.as_secs_f64() * 1_000.0),
+            None => println!("detected an onset before any click was played"),
+        }
+    }
+    drop(stream);
+    println!("Done!");
+    Ok(())
+}
+
+fn err_fn(err: cpal::StreamError) {
+    eprintln!("an error occurred on stream: {}", err);
+}
@@ -122,10 +122,10 @@ fn main() -> anyhow::Result<()> {
     println!("Using output device: \"{}\"", output_device.name()?);
 
     // We'll try and use the same configuration between streams to keep it simple.
-    let config: cpal::StreamConfig = input_device.default_input_config()?.into();
+    let config: cpal::StreamConfig = input_device.default_input_config()?.config();
 
     // Create a delay in case the input and output devices aren't synced.
-    let latency_frames = (opt.latency / 1_000.0) * config.sample_rate.0 as f32;
+    let latency_frames = config.sample_rate.samples_per_ms() * opt.latency as f64;
     let latency_samples = latency_frames as usize * config.channels as usize;
 
     // The buffer to share samples
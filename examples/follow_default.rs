@@ -0,0 +1,95 @@
+//! Plays a quiet tone on the default output device, and moves it to the new default output device
+//! whenever the default changes, e.g. when headphones are plugged in. Devices being added and
+//! removed, and changes to their volume, are printed as well.
+
+extern crate anyhow;
+extern crate cpal;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::DeviceEvent;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const RUN_FOR: Duration = Duration::from_secs(60);
+
+fn main() -> anyhow::Result<()> {
+    let host = cpal::default_host();
+
+    // The callback runs on a thread of the host, so hand the events over to the main thread
+    // rather than building streams from the callback.
+    let (sender, events) = mpsc::channel();
+    let _watcher = host.watch_devices(move |event| {
+        let _ = sender.send(event);
+    })?;
+
+    let device = host
+        .default_output_device()
+        .expect("failed to find a default output device");
+    let mut stream = Some(play(&device)?);
+
+    let started = Instant::now();
+    while let Some(remaining) = RUN_FOR.checked_sub(started.elapsed()) {
+        let event = match events.recv_timeout(remaining) {
+            Ok(event) => event,
+            Err(_) => break,
+        };
+        match event {
+            DeviceEvent::Added { device } => println!("Added: {}", device.name()?),
+            DeviceEvent::Removed { id } => println!("Removed: {}", id),
+            DeviceEvent::DefaultInputChanged { .. } => {}
+            DeviceEvent::VolumeChanged { device, volume } => {
+                println!("Volume of {}: {:.0}%", device.name()?, volume * 100.0)
+            }
+            DeviceEvent::DefaultOutputChanged { device } => {
+                // Stop playing on the previous default before opening the new one.
+                stream = None;
+                match device {
+                    Some(device) => stream = Some(play(&device)?),
+                    None => println!("No default output device"),
+                }
+            }
+        }
+    }
+    drop(stream);
+
+    Ok(())
+}
+
+fn play(device: &cpal::Device) -> anyhow::Result<cpal::Stream> {
+    println!("Playing on: {}", device.name()?);
+    let config = device.default_output_config()?;
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build::<f32>(device, &config.config())?,
+        cpal::SampleFormat::I16 => build::<i16>(device, &config.config())?,
+        cpal::SampleFormat::U16 => build::<u16>(device, &config.config())?,
+        cpal::SampleFormat::I24 => build::<cpal::I24>(device, &config.config())?,
+        cpal::SampleFormat::I24Packed => build::<cpal::I24Packed>(device, &config.config())?,
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+fn build<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> anyhow::Result<cpal::Stream>
+where
+    T: cpal::Sample,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let mut sample_clock = 0f32;
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for frame in data.chunks_mut(channels) {
+                sample_clock = (sample_clock + 1.0) % sample_rate;
+                let value =
+                    0.1 * (sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin();
+                let value: T = cpal::Sample::from::<f32>(&value);
+                for sample in frame.iter_mut() {
+                    *sample = value;
+                }
+            }
+        },
+        |err| eprintln!("an error occurred on stream: {}", err),
+    )?;
+    Ok(stream)
+}
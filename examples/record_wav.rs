@@ -113,20 +113,30 @@ fn main() -> Result<(), anyhow::Error> {
 
     let stream = match config.sample_format() {
         cpal::SampleFormat::F32 => device.build_input_stream(
-            &config.into(),
+            &config.config(),
             move |data, _: &_| write_input_data::<f32, f32>(data, &writer_2),
             err_fn,
         )?,
         cpal::SampleFormat::I16 => device.build_input_stream(
-            &config.into(),
+            &config.config(),
             move |data, _: &_| write_input_data::<i16, i16>(data, &writer_2),
             err_fn,
         )?,
         cpal::SampleFormat::U16 => device.build_input_stream(
-            &config.into(),
+            &config.config(),
             move |data, _: &_| write_input_data::<u16, i16>(data, &writer_2),
             err_fn,
         )?,
+        cpal::SampleFormat::I24 => device.build_input_stream(
+            &config.config(),
+            move |data, _: &_| write_input_data_i24::<cpal::I24>(data, &writer_2),
+            err_fn,
+        )?,
+        cpal::SampleFormat::I24Packed => device.build_input_stream(
+            &config.config(),
+            move |data, _: &_| write_input_data_i24::<cpal::I24Packed>(data, &writer_2),
+            err_fn,
+        )?,
     };
 
     stream.play()?;
@@ -143,15 +153,22 @@ fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
     match format {
         cpal::SampleFormat::U16 => hound::SampleFormat::Int,
         cpal::SampleFormat::I16 => hound::SampleFormat::Int,
+        cpal::SampleFormat::I24 => hound::SampleFormat::Int,
+        cpal::SampleFormat::I24Packed => hound::SampleFormat::Int,
         cpal::SampleFormat::F32 => hound::SampleFormat::Float,
     }
 }
 
 fn wav_spec_from_config(config: &cpal::SupportedStreamConfig) -> hound::WavSpec {
+    // `I24` samples take 4 bytes, but are written to the file as 24-bit samples.
+    let bits_per_sample = match config.sample_format() {
+        cpal::SampleFormat::I24 => 24,
+        sample_format => sample_format.sample_size() * 8,
+    };
     hound::WavSpec {
         channels: config.channels() as _,
         sample_rate: config.sample_rate().0 as _,
-        bits_per_sample: (config.sample_format().sample_size() * 8) as _,
+        bits_per_sample: bits_per_sample as _,
         sample_format: sample_format(config.sample_format()),
     }
 }
@@ -172,3 +189,16 @@ where
         }
     }
 }
+
+fn write_input_data_i24<T>(input: &[T], writer: &WavWriterHandle)
+where
+    T: cpal::Sample,
+{
+    if let Ok(mut guard) = writer.try_lock() {
+        if let Some(writer) = guard.as_mut() {
+            for sample in input.iter() {
+                writer.write_sample(sample.to_i24().get()).ok();
+            }
+        }
+    }
+}
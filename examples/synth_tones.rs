@@ -43,9 +43,15 @@ where
     let (_host, device, config) = host_device_setup()?;
 
     match config.sample_format() {
-        cpal::SampleFormat::F32 => stream_make::<f32, _>(&device, &config.into(), on_sample),
-        cpal::SampleFormat::I16 => stream_make::<i16, _>(&device, &config.into(), on_sample),
-        cpal::SampleFormat::U16 => stream_make::<u16, _>(&device, &config.into(), on_sample),
+        cpal::SampleFormat::F32 => stream_make::<f32, _>(&device, &config.config(), on_sample),
+        cpal::SampleFormat::I16 => stream_make::<i16, _>(&device, &config.config(), on_sample),
+        cpal::SampleFormat::U16 => stream_make::<u16, _>(&device, &config.config(), on_sample),
+        cpal::SampleFormat::I24 => {
+            stream_make::<cpal::I24, _>(&device, &config.config(), on_sample)
+        }
+        cpal::SampleFormat::I24Packed => {
+            stream_make::<cpal::I24Packed, _>(&device, &config.config(), on_sample)
+        }
     }
 }
 
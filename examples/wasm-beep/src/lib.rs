@@ -35,9 +35,11 @@ pub fn beep() -> Handle {
     let config = device.default_output_config().unwrap();
 
     Handle(match config.sample_format() {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into()),
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()),
+        cpal::SampleFormat::F32 => run::<f32>(&device, &config.config()),
+        cpal::SampleFormat::I16 => run::<i16>(&device, &config.config()),
+        cpal::SampleFormat::U16 => run::<u16>(&device, &config.config()),
+        cpal::SampleFormat::I24 => run::<cpal::I24>(&device, &config.config()),
+        cpal::SampleFormat::I24Packed => run::<cpal::I24Packed>(&device, &config.config()),
     })
 }
 
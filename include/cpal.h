@@ -0,0 +1,279 @@
+#ifndef CPAL_H
+#define CPAL_H
+
+// Generated by cbindgen from src/capi.rs. Do not edit by hand.
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Error codes returned by the C API, mirroring the variants of CPAL's error enums.
+typedef enum CpalError {
+  // The call succeeded.
+  CPAL_ERROR_OK = 0,
+  // A required pointer argument was null.
+  CPAL_ERROR_NULL_ARGUMENT,
+  // The device is not available, or there is no default device.
+  CPAL_ERROR_DEVICE_NOT_AVAILABLE,
+  // An argument is invalid, such as a zero channel count.
+  CPAL_ERROR_INVALID_ARGUMENT,
+  // The device does not support the requested stream config.
+  CPAL_ERROR_STREAM_CONFIG_NOT_SUPPORTED,
+  // The device does not support input or output, as requested.
+  CPAL_ERROR_STREAM_TYPE_NOT_SUPPORTED,
+  // The host ran out of stream IDs.
+  CPAL_ERROR_STREAM_ID_OVERFLOW,
+  // A caller-provided buffer is too small for the result.
+  CPAL_ERROR_BUFFER_TOO_SMALL,
+  // The stream's data callback did not return before the stream was closed.
+  CPAL_ERROR_CLOSE_TIMED_OUT,
+  // The stream's data callback panicked.
+  CPAL_ERROR_CALLBACK_PANICKED,
+  // A backend-specific error occurred.
+  CPAL_ERROR_BACKEND_SPECIFIC,
+  // CPAL panicked while handling the call.
+  CPAL_ERROR_PANICKED,
+  // A call to the stream's data callback has not returned in time.
+  CPAL_ERROR_CALLBACK_STALLED,
+  // Another application took the audio focus of the stream.
+  CPAL_ERROR_FOCUS_LOST,
+  // The Bluetooth device of the stream switched profile.
+  CPAL_ERROR_BLUETOOTH_PROFILE_CHANGED,
+} CpalError;
+
+// The format of the samples passed to stream callbacks.
+typedef enum CpalSampleFormat {
+  CPAL_SAMPLE_FORMAT_I16,
+  CPAL_SAMPLE_FORMAT_U16,
+  CPAL_SAMPLE_FORMAT_F32,
+  // A signed 24-bit integer in the lower three bytes of a 32-bit integer.
+  CPAL_SAMPLE_FORMAT_I24,
+  // A signed 24-bit integer packed into three bytes in native byte order.
+  CPAL_SAMPLE_FORMAT_I24_PACKED,
+} CpalSampleFormat;
+
+// An audio device. Free with `cpal_device_free`, unless borrowed from a `CpalDeviceList`.
+typedef struct CpalDevice CpalDevice;
+
+// The devices of a host. Free with `cpal_device_list_free`.
+typedef struct CpalDeviceList CpalDeviceList;
+
+// An audio host. Free with `cpal_host_free`.
+typedef struct CpalHost CpalHost;
+
+// A running or paused stream. Free with `cpal_stream_free`, which stops the stream.
+typedef struct CpalStream CpalStream;
+
+// The parameters of a stream.
+typedef struct CpalStreamConfig {
+  uint16_t channels;
+  uint32_t sample_rate;
+  // The number of frames per callback, or 0 to let the host decide.
+  uint32_t buffer_size;
+} CpalStreamConfig;
+
+// Called with each buffer of an input stream, holding `samples` interleaved samples.
+typedef void (*CpalInputCallback)(void *user_data,
+                                  const void *data,
+                                  size_t samples,
+                                  CpalSampleFormat format);
+
+// Called when an error occurs while a stream is running.
+typedef void (*CpalErrorCallback)(void *user_data, CpalError error);
+
+// Called with each buffer of an output stream to fill with `samples` interleaved samples.
+typedef void (*CpalOutputCallback)(void *user_data,
+                                   void *data,
+                                   size_t samples,
+                                   CpalSampleFormat format);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns a static, nul-terminated description of `error`.
+const char *cpal_error_message(CpalError error);
+
+// Creates the default host of the platform.
+//
+// # Safety
+//
+// `out` must be valid for writes.
+CpalError cpal_default_host(CpalHost **out);
+
+// Frees a host. Null is ignored.
+//
+// # Safety
+//
+// `host` must be null or come from `cpal_default_host`, and must not be used afterwards.
+void cpal_host_free(CpalHost *host);
+
+// Copies the name of the host into `buf`, see `cpal_device_name`.
+//
+// # Safety
+//
+// `host` must be a valid host, `buf` must be valid for writes of `len` bytes and `needed` must
+// be null or valid for writes.
+CpalError cpal_host_name(const CpalHost *host, char *buf, size_t len, size_t *needed);
+
+// Gets the default input device of the host, or `CPAL_ERROR_DEVICE_NOT_AVAILABLE` if there is
+// none.
+//
+// # Safety
+//
+// `host` must be a valid host and `out` must be valid for writes.
+CpalError cpal_host_default_input_device(const CpalHost *host, CpalDevice **out);
+
+// Gets the default output device of the host, or `CPAL_ERROR_DEVICE_NOT_AVAILABLE` if there is
+// none.
+//
+// # Safety
+//
+// `host` must be a valid host and `out` must be valid for writes.
+CpalError cpal_host_default_output_device(const CpalHost *host, CpalDevice **out);
+
+// Lists all devices of the host.
+//
+// # Safety
+//
+// `host` must be a valid host and `out` must be valid for writes.
+CpalError cpal_host_devices(const CpalHost *host, CpalDeviceList **out);
+
+// Returns the number of devices in the list, or 0 if `list` is null.
+//
+// # Safety
+//
+// `list` must be null or a valid device list.
+size_t cpal_device_list_len(const CpalDeviceList *list);
+
+// Returns the device at `index`, or null if `index` is out of bounds. The device is owned by the
+// list and must not be freed.
+//
+// # Safety
+//
+// `list` must be null or a valid device list.
+const CpalDevice *cpal_device_list_get(const CpalDeviceList *list, size_t index);
+
+// Frees a device list along with its devices. Null is ignored.
+//
+// # Safety
+//
+// `list` must be null or come from `cpal_host_devices`, and neither it nor its devices may be
+// used afterwards.
+void cpal_device_list_free(CpalDeviceList *list);
+
+// Frees a device. Null is ignored.
+//
+// # Safety
+//
+// `device` must be null or come from `cpal_host_default_input_device` or
+// `cpal_host_default_output_device`, and must not be used afterwards.
+void cpal_device_free(CpalDevice *device);
+
+// Copies the name of the device into `buf` as a nul-terminated UTF-8 string.
+//
+// If `needed` is non-null, the size of the name in bytes including the terminator is stored in
+// it, even if `buf` is too small, in which case `CPAL_ERROR_BUFFER_TOO_SMALL` is returned. Pass
+// a `len` of 0 to query the size.
+//
+// # Safety
+//
+// `device` must be a valid device, `buf` must be valid for writes of `len` bytes and `needed`
+// must be null or valid for writes.
+CpalError cpal_device_name(const CpalDevice *device, char *buf, size_t len, size_t *needed);
+
+// Gets the default input config of the device.
+//
+// # Safety
+//
+// `device` must be a valid device, and `config` and `format` must be valid for writes.
+CpalError cpal_device_default_input_config(const CpalDevice *device,
+                                           CpalStreamConfig *config,
+                                           CpalSampleFormat *format);
+
+// Gets the default output config of the device.
+//
+// # Safety
+//
+// `device` must be a valid device, and `config` and `format` must be valid for writes.
+CpalError cpal_device_default_output_config(const CpalDevice *device,
+                                            CpalStreamConfig *config,
+                                            CpalSampleFormat *format);
+
+// Builds an input stream. The stream may start playing immediately on some hosts; call
+// `cpal_stream_play` to be sure it does.
+//
+// `data_callback` and `error_callback` are called on the audio thread with `user_data`, which
+// must stay valid until the stream is freed. `error_callback` may be null.
+//
+// # Safety
+//
+// `device` must be a valid device, `config` must be valid for reads and `out` must be valid for
+// writes. The callbacks must not unwind.
+CpalError cpal_device_build_input_stream(const CpalDevice *device,
+                                         const CpalStreamConfig *config,
+                                         CpalSampleFormat format,
+                                         CpalInputCallback data_callback,
+                                         CpalErrorCallback error_callback,
+                                         void *user_data,
+                                         CpalStream **out);
+
+// Builds an output stream. The stream may start playing immediately on some hosts; call
+// `cpal_stream_play` to be sure it does.
+//
+// `data_callback` and `error_callback` are called on the audio thread with `user_data`, which
+// must stay valid until the stream is freed. `error_callback` may be null.
+//
+// # Safety
+//
+// `device` must be a valid device, `config` must be valid for reads and `out` must be valid for
+// writes. The callbacks must not unwind.
+CpalError cpal_device_build_output_stream(const CpalDevice *device,
+                                          const CpalStreamConfig *config,
+                                          CpalSampleFormat format,
+                                          CpalOutputCallback data_callback,
+                                          CpalErrorCallback error_callback,
+                                          void *user_data,
+                                          CpalStream **out);
+
+// Starts or resumes the stream.
+//
+// # Safety
+//
+// `stream` must be a valid stream.
+CpalError cpal_stream_play(const CpalStream *stream);
+
+// Pauses the stream, if the host supports it.
+//
+// # Safety
+//
+// `stream` must be a valid stream.
+CpalError cpal_stream_pause(const CpalStream *stream);
+
+// Stops and frees the stream. Null is ignored.
+//
+// # Safety
+//
+// `stream` must be null or come from `cpal_device_build_input_stream` or
+// `cpal_device_build_output_stream`, and must not be used afterwards.
+void cpal_stream_free(CpalStream *stream);
+
+// Renders `frames` frames by calling `data_callback` as an output stream would, without a
+// device; see `cpal::offline::render`. The samples are `CPAL_SAMPLE_FORMAT_F32` and are written
+// to `out`, which must hold at least `frames * config->channels` samples.
+//
+// # Safety
+//
+// `config` must be valid for reads, `out` must be valid for writes of `out_len` samples and
+// `data_callback` must not unwind.
+CpalError cpal_offline_render(const CpalStreamConfig *config,
+                              size_t frames,
+                              CpalOutputCallback data_callback,
+                              void *user_data,
+                              float *out,
+                              size_t out_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CPAL_H */
@@ -0,0 +1,329 @@
+//! Analysis of audio within stream callbacks.
+//!
+//! Everything in this module is allocation-free once constructed, and may be used from within a
+//! data callback.
+
+use crate::{ChannelCount, Sample, SampleRate, StreamInstant};
+use std::time::Duration;
+
+/// Whether a `Gate` follows the level of each channel separately.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GateMode {
+    /// Follows the loudest channel of each frame, reporting events with no channel.
+    Mixed,
+    /// Follows each channel separately, reporting events with the channel they occurred on.
+    PerChannel,
+}
+
+/// Whether a `GateEvent` opened or closed the gate.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GateEdge {
+    /// The envelope rose to the open threshold.
+    Onset,
+    /// The envelope fell below the close threshold.
+    Offset,
+}
+
+/// A threshold crossing reported by `Gate::process`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GateEvent {
+    pub edge: GateEdge,
+    /// The channel that crossed the threshold, or `None` with `GateMode::Mixed`.
+    pub channel: Option<usize>,
+    /// The index of the frame that crossed the threshold within the buffer given to `process`.
+    pub frame: usize,
+    /// The instant of that frame, derived from the timestamp given to `process`.
+    pub instant: StreamInstant,
+}
+
+/// Detects when a signal rises above or falls below a threshold, with frame accuracy.
+///
+/// The gate follows the envelope of the signal: it rises towards the absolute value of each sample
+/// with the attack time constant, and decays with the release time constant. The gate opens when
+/// the envelope reaches the open threshold, and closes when it falls below the close threshold,
+/// which is lower to avoid chattering around a single threshold.
+///
+/// ```
+/// use cpal::analysis::{Gate, GateEdge};
+/// use cpal::{InputCallbackInfo, SampleRate};
+///
+/// let mut gate = Gate::new(2, SampleRate(48_000), 0.3);
+/// let data_callback = move |data: &[f32], info: &InputCallbackInfo| {
+///     gate.process(data, info.timestamp().capture, |event| {
+///         if event.edge == GateEdge::Onset {
+///             // Signal the onset at `event.instant` without blocking, e.g. through an atomic.
+///         }
+///     });
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct Gate {
+    channels: usize,
+    sample_rate: SampleRate,
+    open_threshold: f32,
+    close_threshold: f32,
+    attack: f32,
+    release: f32,
+    mode: GateMode,
+    // One state for `GateMode::Mixed`, else one per channel.
+    states: Vec<State>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct State {
+    envelope: f32,
+    open: bool,
+}
+
+/// The release time of a new `Gate`.
+pub const DEFAULT_GATE_RELEASE: Duration = Duration::from_millis(10);
+
+impl Gate {
+    /// A gate in `GateMode::Mixed` that opens at `threshold` and closes at half of it, with an
+    /// instant attack and a release of `DEFAULT_GATE_RELEASE`.
+    ///
+    /// `threshold` is an absolute sample value, where `1.0` is full scale.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` is zero.
+    pub fn new(channels: ChannelCount, sample_rate: SampleRate, threshold: f32) -> Self {
+        assert!(channels > 0, "a gate needs at least one channel");
+        Gate {
+            channels: channels as usize,
+            sample_rate,
+            open_threshold: threshold,
+            close_threshold: threshold / 2.0,
+            attack: 0.0,
+            release: coefficient(DEFAULT_GATE_RELEASE, sample_rate),
+            mode: GateMode::Mixed,
+            states: vec![State::default()],
+        }
+    }
+
+    /// Closes the gate when the envelope falls below `close_threshold` rather than half of the
+    /// open threshold. It is clamped to the open threshold.
+    pub fn with_close_threshold(mut self, close_threshold: f32) -> Self {
+        self.close_threshold = close_threshold.min(self.open_threshold);
+        self
+    }
+
+    /// Sets the time constant with which the envelope rises. Zero, the default, follows the peaks
+    /// of the signal exactly, so that the onset is reported at the first frame to reach the
+    /// threshold.
+    pub fn with_attack(mut self, attack: Duration) -> Self {
+        self.attack = coefficient(attack, self.sample_rate);
+        self
+    }
+
+    /// Sets the time constant with which the envelope decays.
+    pub fn with_release(mut self, release: Duration) -> Self {
+        self.release = coefficient(release, self.sample_rate);
+        self
+    }
+
+    /// Sets whether the gate follows the channels separately. Resets the gate.
+    pub fn with_mode(mut self, mode: GateMode) -> Self {
+        let states = match mode {
+            GateMode::Mixed => 1,
+            GateMode::PerChannel => self.channels,
+        };
+        self.mode = mode;
+        self.states = vec![State::default(); states];
+        self
+    }
+
+    /// Whether the gate is open, on the given channel with `GateMode::PerChannel`. The channel is
+    /// ignored with `GateMode::Mixed`.
+    pub fn is_open(&self, channel: usize) -> bool {
+        self.state(channel).open
+    }
+
+    /// The current envelope, on the given channel with `GateMode::PerChannel`. The channel is
+    /// ignored with `GateMode::Mixed`.
+    pub fn envelope(&self, channel: usize) -> f32 {
+        self.state(channel).envelope
+    }
+
+    /// Closes the gate and resets the envelope, e.g. after a discontinuity in the input.
+    pub fn reset(&mut self) {
+        for state in &mut self.states {
+            *state = State::default();
+        }
+    }
+
+    /// Processes a buffer of interleaved samples, calling `on_event` for each threshold crossing
+    /// in order.
+    ///
+    /// `timestamp` is the instant of the first frame, usually `InputStreamTimestamp::capture`. Any
+    /// samples after the last whole frame are ignored.
+    pub fn process<T, F>(&mut self, data: &[T], timestamp: StreamInstant, mut on_event: F)
+    where
+        T: Sample,
+        F: FnMut(GateEvent),
+    {
+        let Gate {
+            channels,
+            sample_rate,
+            open_threshold,
+            close_threshold,
+            attack,
+            release,
+            mode,
+            ref mut states,
+        } = *self;
+        let mut emit = |edge, channel, frame| {
+            let offset = frame as u64 * 1_000_000_000 / sample_rate.0 as u64;
+            let instant = timestamp
+                .add(Duration::from_nanos(offset))
+                .unwrap_or(timestamp);
+            on_event(GateEvent {
+                edge,
+                channel,
+                frame,
+                instant,
+            });
+        };
+        for (frame, samples) in data.chunks_exact(channels).enumerate() {
+            match mode {
+                GateMode::Mixed => {
+                    let level = samples
+                        .iter()
+                        .map(|sample| sample.to_f32().abs())
+                        .fold(0.0, f32::max);
+                    let edge =
+                        states[0].update(level, attack, release, open_threshold, close_threshold);
+                    if let Some(edge) = edge {
+                        emit(edge, None, frame);
+                    }
+                }
+                GateMode::PerChannel => {
+                    for (channel, (state, sample)) in states.iter_mut().zip(samples).enumerate() {
+                        let level = sample.to_f32().abs();
+                        let edge =
+                            state.update(level, attack, release, open_threshold, close_threshold);
+                        if let Some(edge) = edge {
+                            emit(edge, Some(channel), frame);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn state(&self, channel: usize) -> &State {
+        match self.mode {
+            GateMode::Mixed => &self.states[0],
+            GateMode::PerChannel => &self.states[channel],
+        }
+    }
+}
+
+impl State {
+    fn update(
+        &mut self,
+        level: f32,
+        attack: f32,
+        release: f32,
+        open_threshold: f32,
+        close_threshold: f32,
+    ) -> Option<GateEdge> {
+        let coefficient = if level > self.envelope {
+            attack
+        } else {
+            release
+        };
+        self.envelope = level + coefficient * (self.envelope - level);
+        // Keep a decaying envelope from lingering in the slow subnormal range during silence.
+        if self.envelope < f32::MIN_POSITIVE {
+            self.envelope = 0.0;
+        }
+        if !self.open && self.envelope >= open_threshold {
+            self.open = true;
+            Some(GateEdge::Onset)
+        } else if self.open && self.envelope < close_threshold {
+            self.open = false;
+            Some(GateEdge::Offset)
+        } else {
+            None
+        }
+    }
+}
+
+// The one-pole smoothing coefficient for a time constant.
+fn coefficient(time: Duration, sample_rate: SampleRate) -> f32 {
+    let samples = time.as_secs_f64() * sample_rate.0 as f64;
+    if samples <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp() as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Gate, GateEdge, GateEvent, GateMode};
+    use crate::{SampleRate, StreamInstant};
+    use std::time::Duration;
+
+    fn events(gate: &mut Gate, data: &[f32]) -> Vec<GateEvent> {
+        let mut events = vec![];
+        gate.process(data, StreamInstant::new(1, 0), |event| events.push(event));
+        events
+    }
+
+    #[test]
+    fn test_onset_is_frame_accurate() {
+        let mut gate = Gate::new(2, SampleRate(1_000), 0.5);
+        let mut data = [0.0f32; 20];
+        data[14] = 0.1;
+        data[15] = -0.8;
+        let events = events(&mut gate, &data);
+        assert_eq!(
+            events,
+            [GateEvent {
+                edge: GateEdge::Onset,
+                channel: None,
+                frame: 7,
+                instant: StreamInstant::new(1, 7_000_000),
+            }]
+        );
+        assert!(gate.is_open(0));
+    }
+
+    #[test]
+    fn test_hysteresis_and_release() {
+        let mut gate = Gate::new(1, SampleRate(1_000), 0.5)
+            .with_close_threshold(0.2)
+            .with_release(Duration::from_millis(10));
+        // Hovering between the thresholds doesn't close the gate.
+        let mut data = vec![0.6, 0.3, 0.4, 0.3, 0.4];
+        data.extend(std::iter::repeat_n(0.0, 100));
+        let events = events(&mut gate, &data);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].edge, GateEdge::Onset);
+        assert_eq!(events[0].frame, 0);
+        assert_eq!(events[1].edge, GateEdge::Offset);
+        // The envelope has only decayed to about 0.52 by the last non-zero frame, and takes
+        // ln(0.52 / 0.2) time constants, i.e. 10 frames, to fall below 0.2.
+        assert_eq!(events[1].frame, 4 + 10);
+    }
+
+    #[test]
+    fn test_silence_flushes_envelope() {
+        let mut gate = Gate::new(1, SampleRate(1_000), 0.5);
+        events(&mut gate, &[1.0]);
+        // Long enough to decay past the subnormal range.
+        events(&mut gate, &[0.0; 2_000]);
+        assert_eq!(gate.envelope(0), 0.0);
+    }
+
+    #[test]
+    fn test_per_channel() {
+        let mut gate = Gate::new(2, SampleRate(1_000), 0.5).with_mode(GateMode::PerChannel);
+        let events = events(&mut gate, &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let onsets: Vec<_> = events.iter().map(|e| (e.channel, e.frame)).collect();
+        assert_eq!(onsets, [(Some(1), 1), (Some(0), 2)]);
+        assert!(gate.is_open(0) && gate.is_open(1));
+    }
+}
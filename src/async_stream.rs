@@ -0,0 +1,440 @@
+//! Streams as futures `Stream`s and `Sink`s, enabled with the `async` feature.
+//!
+//! Applications built on an async runtime would otherwise have to pass buffers between the data
+//! callback and their tasks through channels of their own. [`DeviceAsyncExt`] builds streams whose
+//! captured samples are read with `while let Some(buffer) = stream.next().await`, and whose played
+//! samples are written with `sink.send(buffer).await`, using the combinators of the `futures`
+//! crate.
+//!
+//! Samples are `f32`, interleaved. The data callback and the async side share a lock-free ring of
+//! samples, so the audio thread never waits for a task or allocates. After writing to or reading
+//! from the ring, the data callback wakes the waiting task, if any.
+//!
+//! # Latency
+//!
+//! The ring holds [`BRIDGE_DURATION`] of audio, on top of the buffers of the host.
+//!
+//! - An input stream yields the samples captured since its last item, so its items are delayed by
+//!   however long the task takes to be polled after a wakeup. Once the ring is full because the
+//!   task falls behind, whole buffers of the host are dropped until it catches up.
+//! - An output sink is ready for more samples as long as the ring is not full, so a task that
+//!   sends as fast as it can keeps the ring full and adds up to [`BRIDGE_DURATION`] of latency.
+//!   When the ring runs empty, the stream plays silence.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use cpal::async_stream::DeviceAsyncExt;
+//! use cpal::traits::{DeviceTrait, HostTrait};
+//! use futures::{SinkExt, StreamExt};
+//!
+//! let host = cpal::default_host();
+//! let input = host.default_input_device().unwrap();
+//! let output = host.default_output_device().unwrap();
+//! let config = input.default_input_config()?.config();
+//! let mut captured = input.input_stream_async(&config)?;
+//! let mut played = output.output_sink_async(&config)?;
+//! while let Some(buffer) = captured.next().await {
+//!     played.send(buffer).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::ring::Ring;
+use crate::traits::{DeviceTrait, StreamTrait};
+use crate::{BuildStreamError, ChannelCount, PlayStreamError, StreamConfig, StreamError};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// The duration of audio held by the ring between a stream's data callback and the async side.
+pub const BRIDGE_DURATION: Duration = Duration::from_millis(100);
+
+/// Builds streams bridged to futures, see the [module documentation](self).
+///
+/// Implemented for every device. The streams are playing when returned.
+pub trait DeviceAsyncExt: DeviceTrait {
+    /// Builds an input stream whose captured samples are yielded by the returned `Stream`.
+    ///
+    /// Each item holds the whole frames captured since the previous item. The stream ends after
+    /// the last samples captured before the stream failed, see
+    /// [`AsyncInputStream::take_error`].
+    fn input_stream_async(
+        &self,
+        config: &StreamConfig,
+    ) -> Result<AsyncInputStream<Self::Stream>, BuildStreamError> {
+        let shared = Arc::new(Shared::new(config));
+        let data_shared = shared.clone();
+        let error_shared = shared.clone();
+        let stream = self.build_input_stream::<f32, _, _>(
+            config,
+            move |data, _| data_shared.capture(data),
+            move |err| error_shared.fail(err),
+        )?;
+        stream.play().map_err(build_stream_error)?;
+        Ok(AsyncInputStream {
+            stream,
+            shared,
+            channels: config.channels,
+        })
+    }
+
+    /// Builds an output stream that plays the samples sent to the returned `Sink`.
+    ///
+    /// Items may have any length, and are played back to back. Once the stream fails, the next
+    /// call to the sink returns its error.
+    fn output_sink_async(
+        &self,
+        config: &StreamConfig,
+    ) -> Result<AsyncOutputSink<Self::Stream>, BuildStreamError> {
+        let shared = Arc::new(Shared::new(config));
+        let data_shared = shared.clone();
+        let error_shared = shared.clone();
+        let stream = self.build_output_stream::<f32, _, _>(
+            config,
+            move |data, _| data_shared.play(data),
+            move |err| error_shared.fail(err),
+        )?;
+        stream.play().map_err(build_stream_error)?;
+        Ok(AsyncOutputSink {
+            stream,
+            shared,
+            pending: Vec::new(),
+            sent: 0,
+        })
+    }
+}
+
+impl<D: DeviceTrait> DeviceAsyncExt for D {}
+
+fn build_stream_error(err: PlayStreamError) -> BuildStreamError {
+    match err {
+        PlayStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        PlayStreamError::BackendSpecific { err } => err.into(),
+    }
+}
+
+/// The captured samples of an input stream, as a futures `Stream` of interleaved `f32` buffers.
+///
+/// Dropping it drops the stream.
+pub struct AsyncInputStream<S> {
+    stream: S,
+    shared: Arc<Shared>,
+    channels: ChannelCount,
+}
+
+impl<S> AsyncInputStream<S> {
+    /// The underlying stream, e.g. to pause it.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    /// The error that ended the stream, if any.
+    pub fn take_error(&mut self) -> Option<StreamError> {
+        self.shared.error.lock().ok()?.take()
+    }
+
+    // Takes the whole frames in the ring.
+    fn take_frames(&mut self) -> Option<Vec<f32>> {
+        let len = self.shared.ring.len();
+        let len = len - len % self.channels as usize;
+        if len == 0 {
+            return None;
+        }
+        let mut buffer = vec![0.0; len];
+        self.shared.ring.pop(&mut buffer);
+        Some(buffer)
+    }
+}
+
+impl<S: Unpin> Stream for AsyncInputStream<S> {
+    type Item = Vec<f32>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<f32>>> {
+        let this = self.get_mut();
+        if let Some(buffer) = this.take_frames() {
+            return Poll::Ready(Some(buffer));
+        }
+        if this.shared.failed.load(Ordering::Acquire) {
+            return Poll::Ready(None);
+        }
+        this.shared.waker.register(cx.waker());
+        // Samples captured while registering may not have woken the task.
+        match this.take_frames() {
+            Some(buffer) => Poll::Ready(Some(buffer)),
+            None if this.shared.failed.load(Ordering::Acquire) => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// The samples to play on an output stream, as a futures `Sink` of interleaved `f32` buffers.
+///
+/// Dropping it drops the stream, along with the samples that have not been played yet. Close the
+/// sink first to wait until they have been.
+pub struct AsyncOutputSink<S> {
+    stream: S,
+    shared: Arc<Shared>,
+    // The last item sent, of which the first `sent` samples have been written to the ring.
+    pending: Vec<f32>,
+    sent: usize,
+}
+
+impl<S> AsyncOutputSink<S> {
+    /// The underlying stream, e.g. to pause it.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    fn error(&self) -> Option<StreamError> {
+        if !self.shared.failed.load(Ordering::Acquire) {
+            return None;
+        }
+        let err = self.shared.error.lock().ok().and_then(|mut err| err.take());
+        // The error was returned by a previous call.
+        Some(err.unwrap_or(StreamError::DeviceNotAvailable))
+    }
+
+    // Writes the rest of the pending item to the ring, returning whether all of it is written.
+    fn send_pending(&mut self) -> bool {
+        self.sent += self.shared.ring.push(&self.pending[self.sent..]);
+        self.sent == self.pending.len()
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        if let Some(err) = self.error() {
+            return Poll::Ready(Err(err));
+        }
+        if self.send_pending() {
+            return Poll::Ready(Ok(()));
+        }
+        self.shared.waker.register(cx.waker());
+        // Samples played while registering may not have woken the task.
+        if let Some(err) = self.error() {
+            Poll::Ready(Err(err))
+        } else if self.send_pending() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<S: Unpin> Sink<Vec<f32>> for AsyncOutputSink<S> {
+    type Error = StreamError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<f32>) -> Result<(), StreamError> {
+        let this = self.get_mut();
+        debug_assert_eq!(this.sent, this.pending.len(), "`poll_ready` was not called");
+        this.pending = item;
+        this.sent = 0;
+        this.send_pending();
+        Ok(())
+    }
+
+    /// Ready once every item sent has been written to the ring, not necessarily played.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    /// Ready once every item sent has been played. Never ready while the stream is paused.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            poll => return poll,
+        }
+        if this.shared.ring.len() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        this.shared.waker.register(cx.waker());
+        if let Some(err) = this.error() {
+            Poll::Ready(Err(err))
+        } else if this.shared.ring.len() == 0 {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// The state shared by the data callback, the error callback and the async side.
+struct Shared {
+    ring: Ring,
+    waker: WakerSlot,
+    failed: AtomicBool,
+    error: Mutex<Option<StreamError>>,
+}
+
+impl Shared {
+    fn new(config: &StreamConfig) -> Self {
+        let frames = config.sample_rate.frames_in_rounded(BRIDGE_DURATION);
+        Shared {
+            ring: Ring::new(frames as usize * config.channels as usize),
+            waker: WakerSlot::default(),
+            failed: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }
+    }
+
+    // Called by the data callback of an input stream.
+    fn capture(&self, data: &[f32]) {
+        // Dropping whole buffers when the ring is full keeps the frames aligned.
+        if self.ring.free() >= data.len() {
+            self.ring.push(data);
+        }
+        self.waker.wake();
+    }
+
+    // Called by the data callback of an output stream.
+    fn play(&self, data: &mut [f32]) {
+        let len = self.ring.pop(data);
+        data[len..].fill(0.0);
+        self.waker.wake();
+    }
+
+    fn fail(&self, err: StreamError) {
+        if let Ok(mut error) = self.error.lock() {
+            error.get_or_insert(err);
+        }
+        self.failed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+// The waker of the task waiting on the ring.
+//
+// The audio thread only ever tries to lock it. When the lock is held, the task is registering its
+// waker, and checks the ring again afterwards.
+#[derive(Default)]
+struct WakerSlot(Mutex<Option<Waker>>);
+
+impl WakerSlot {
+    fn register(&self, waker: &Waker) {
+        if let Ok(mut slot) = self.0.lock() {
+            match &*slot {
+                Some(registered) if registered.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        }
+    }
+
+    fn wake(&self) {
+        let waker = match self.0.try_lock() {
+            Ok(mut slot) => slot.take(),
+            Err(_) => None,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AsyncInputStream, AsyncOutputSink, Shared};
+    use crate::{BufferSize, SampleRate, StreamConfig};
+    use futures_core::Stream;
+    use futures_sink::Sink;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    // Counts its wakeups.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // A config whose ring holds 4 samples.
+    const CONFIG: StreamConfig = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(20),
+        buffer_size: BufferSize::Default,
+    };
+
+    #[test]
+    fn test_input_stream() {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let shared = Arc::new(Shared::new(&CONFIG));
+        let mut stream = AsyncInputStream {
+            stream: (),
+            shared: shared.clone(),
+            channels: CONFIG.channels,
+        };
+
+        shared.capture(&[1.0, 2.0]);
+        assert_eq!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(Some(vec![1.0, 2.0]))
+        );
+        assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Pending);
+        shared.capture(&[3.0, 4.0]);
+        // Dropped, as the ring is full.
+        shared.capture(&[5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(Some(vec![3.0, 4.0]))
+        );
+
+        shared.fail(crate::StreamError::DeviceNotAvailable);
+        assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(None));
+        assert!(stream.take_error().is_some());
+    }
+
+    #[test]
+    fn test_output_sink() {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let shared = Arc::new(Shared::new(&CONFIG));
+        let mut sink = AsyncOutputSink {
+            stream: (),
+            shared: shared.clone(),
+            pending: Vec::new(),
+            sent: 0,
+        };
+
+        assert!(matches!(
+            Pin::new(&mut sink).poll_ready(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        Pin::new(&mut sink)
+            .start_send(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0])
+            .unwrap();
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_pending());
+
+        let mut output = [0.0; 4];
+        shared.play(&mut output);
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            Pin::new(&mut sink).poll_ready(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_pending());
+
+        shared.play(&mut output);
+        assert_eq!(output, [5.0, 6.0, 0.0, 0.0]);
+        assert!(matches!(
+            Pin::new(&mut sink).poll_close(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+//! Building several streams at once, so that either all of them are built or none are.
+//!
+//! Applications recording from an array of microphones open one stream per device. Building them
+//! in a loop leaves the devices opened before a failure open, unless every error path remembers to
+//! drop them. `HostTrait::build_streams` does so, and reports which of the streams failed and why.
+
+use crate::traits::DeviceTrait;
+use crate::{
+    BuildStreamError, Data, DataCallback, ErrorCallback, InputCallbackInfo, OutputCallbackInfo,
+    SampleFormat, StreamConfig, StreamError, StreamOptions,
+};
+use std::error::Error;
+use std::fmt;
+
+/// A stream to be built by `HostTrait::build_streams`.
+pub struct StreamSpec<'a, D: DeviceTrait> {
+    device: &'a D,
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    data_callback: DataCallback,
+    error_callback: Box<ErrorCallback>,
+    options: StreamOptions,
+}
+
+impl<'a, D: DeviceTrait> StreamSpec<'a, D> {
+    /// An input stream on `device`, as `DeviceTrait::build_input_stream_raw_with_options`.
+    pub fn input<F, E>(
+        device: &'a D,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: F,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Self
+    where
+        F: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        StreamSpec {
+            device,
+            config: config.clone(),
+            sample_format,
+            data_callback: DataCallback::Input(Box::new(data_callback)),
+            error_callback: Box::new(error_callback),
+            options: options.clone(),
+        }
+    }
+
+    /// An output stream on `device`, as `DeviceTrait::build_output_stream_raw_with_options`.
+    pub fn output<F, E>(
+        device: &'a D,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: F,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Self
+    where
+        F: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        StreamSpec {
+            device,
+            config: config.clone(),
+            sample_format,
+            data_callback: DataCallback::Output(Box::new(data_callback)),
+            error_callback: Box::new(error_callback),
+            options: options.clone(),
+        }
+    }
+
+    fn build(self) -> Result<D::Stream, BuildStreamError> {
+        match self.data_callback {
+            DataCallback::Input(data_callback) => self.device.build_input_stream_raw_with_options(
+                &self.config,
+                self.sample_format,
+                data_callback,
+                self.error_callback,
+                &self.options,
+            ),
+            DataCallback::Output(data_callback) => {
+                self.device.build_output_stream_raw_with_options(
+                    &self.config,
+                    self.sample_format,
+                    data_callback,
+                    self.error_callback,
+                    &self.options,
+                )
+            }
+        }
+    }
+}
+
+/// What `HostTrait::build_streams` does with the streams it built when another one failed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OnFailure {
+    /// Drops the streams that were built, releasing their devices.
+    DropAll,
+    /// Returns the streams that were built within the error.
+    KeepBuilt,
+}
+
+/// The error returned by `HostTrait::build_streams` when any of the streams failed to build.
+pub struct BuildStreamsError<S> {
+    /// The index of each spec whose stream failed to build, with the reason.
+    pub errors: Vec<(usize, BuildStreamError)>,
+    /// The stream of each spec, or `None` for those that failed. Empty with `OnFailure::DropAll`.
+    pub streams: Vec<Option<S>>,
+}
+
+impl<S> fmt::Debug for BuildStreamsError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BuildStreamsError")
+            .field("errors", &self.errors)
+            .field(
+                "streams",
+                &self
+                    .streams
+                    .iter()
+                    .map(|stream| stream.as_ref().map(|_| ".."))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<S> fmt::Display for BuildStreamsError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to build {} of the streams", self.errors.len())?;
+        for (index, err) in &self.errors {
+            write!(f, "; stream {}: {}", index, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Error for BuildStreamsError<S> {}
+
+/// The result of `HostTrait::build_streams`.
+pub type BuildStreamsResult<S> = Result<Vec<S>, BuildStreamsError<S>>;
+
+// Every spec is built, even after a failure, so that all failures are reported at once.
+pub(crate) fn build_streams<'a, D, I>(
+    specs: I,
+    on_failure: OnFailure,
+) -> BuildStreamsResult<D::Stream>
+where
+    D: DeviceTrait + 'a,
+    I: IntoIterator<Item = StreamSpec<'a, D>>,
+{
+    let mut streams = Vec::new();
+    let mut errors = Vec::new();
+    for (index, spec) in specs.into_iter().enumerate() {
+        match spec.build() {
+            Ok(stream) => streams.push(Some(stream)),
+            Err(err) => {
+                streams.push(None);
+                errors.push((index, err));
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(streams.into_iter().flatten().collect());
+    }
+    if on_failure == OnFailure::DropAll {
+        streams.clear();
+    }
+    Err(BuildStreamsError { errors, streams })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OnFailure, StreamSpec};
+    use crate::mock::{MockDevice, MockHost};
+    use crate::traits::{HostTrait, StreamTrait};
+    use crate::{
+        BufferSize, BuildStreamError, ChannelCount, Data, InputCallbackInfo, SampleFormat,
+        SampleRate, StreamConfig, StreamOptions,
+    };
+    use std::sync::atomic::Ordering;
+
+    fn input(device: &MockDevice, channels: ChannelCount) -> StreamSpec<'_, MockDevice> {
+        let config = StreamConfig {
+            channels,
+            sample_rate: SampleRate(48_000),
+            buffer_size: BufferSize::Default,
+        };
+        StreamSpec::input(
+            device,
+            &config,
+            SampleFormat::F32,
+            |_: &Data, _: &InputCallbackInfo| (),
+            |_| (),
+            &StreamOptions::default(),
+        )
+    }
+
+    #[test]
+    fn test_build_streams() {
+        let device = MockDevice::default();
+        let streams = MockHost
+            .build_streams(
+                vec![input(&device, 1), input(&device, 2)],
+                OnFailure::DropAll,
+            )
+            .unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[1].channels(), 2);
+        assert_eq!(device.alive.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_build_streams_failure() {
+        let device = MockDevice::default();
+        let specs = |device| vec![input(device, 1), input(device, 8), input(device, 2)];
+
+        let Err(err) = MockHost.build_streams(specs(&device), OnFailure::DropAll) else {
+            panic!("the streams were built");
+        };
+        assert!(matches!(
+            err.errors[..],
+            [(1, BuildStreamError::StreamConfigNotSupported)]
+        ));
+        assert!(err.streams.is_empty());
+        assert_eq!(device.alive.load(Ordering::SeqCst), 0);
+
+        let Err(err) = MockHost.build_streams(specs(&device), OnFailure::KeepBuilt) else {
+            panic!("the streams were built");
+        };
+        assert_eq!(err.errors.len(), 1);
+        let built: Vec<bool> = err.streams.iter().map(Option::is_some).collect();
+        assert_eq!(built, [true, false, true]);
+        assert_eq!(device.alive.load(Ordering::SeqCst), 2);
+    }
+}
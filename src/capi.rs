@@ -0,0 +1,805 @@
+//! A C API over hosts, devices and streams, enabled with the `capi` feature.
+//!
+//! Hosts, devices and streams are opaque handles created by the `cpal_*` functions and released
+//! with the matching `*_free` function. Fallible functions return a `CpalError`, writing their
+//! result through an out pointer only on `CPAL_ERROR_OK`. Strings are UTF-8 and copied into
+//! caller-provided buffers. The header, `include/cpal.h`, is generated from this module with
+//! cbindgen; see `cbindgen.toml`.
+//!
+//! Streams pass their buffers to C function pointers together with an opaque `user_data` pointer,
+//! using the raw `build_*_stream_raw` layer underneath. The trampolines between a stream and
+//! these function pointers neither allocate nor lock. No panic unwinds into C: every function
+//! catches panics and returns `CPAL_ERROR_PANICKED` instead, and panics in the stream callbacks
+//! are contained as described in `DeviceTrait`.
+
+use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crate::{
+    BufferSize, BuildStreamError, Data, DefaultStreamConfigError, Device, DeviceNameError,
+    DevicesError, Host, PauseStreamError, PlayStreamError, SampleFormat, SampleRate, Stream,
+    StreamConfig, StreamError, SupportedStreamConfig,
+};
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Error codes returned by the C API, mirroring the variants of CPAL's error enums.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CpalError {
+    /// The call succeeded.
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullArgument,
+    /// The device is not available, or there is no default device.
+    DeviceNotAvailable,
+    /// An argument is invalid, such as a zero channel count.
+    InvalidArgument,
+    /// The device does not support the requested stream config.
+    StreamConfigNotSupported,
+    /// The device does not support input or output, as requested.
+    StreamTypeNotSupported,
+    /// The host ran out of stream IDs.
+    StreamIdOverflow,
+    /// A caller-provided buffer is too small for the result.
+    BufferTooSmall,
+    /// The stream's data callback did not return before the stream was closed.
+    CloseTimedOut,
+    /// The stream's data callback panicked.
+    CallbackPanicked,
+    /// A backend-specific error occurred.
+    BackendSpecific,
+    /// CPAL panicked while handling the call.
+    Panicked,
+    /// A call to the stream's data callback has not returned in time.
+    CallbackStalled,
+    /// Another application took the audio focus of the stream.
+    FocusLost,
+    /// The Bluetooth device of the stream switched profile.
+    BluetoothProfileChanged,
+}
+
+/// The format of the samples passed to stream callbacks.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CpalSampleFormat {
+    I16,
+    U16,
+    F32,
+    /// A signed 24-bit integer in the lower three bytes of a 32-bit integer.
+    I24,
+    /// A signed 24-bit integer packed into three bytes in native byte order.
+    I24Packed,
+}
+
+/// The parameters of a stream.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CpalStreamConfig {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// The number of frames per callback, or 0 to let the host decide.
+    pub buffer_size: u32,
+}
+
+/// Called with each buffer of an input stream, holding `samples` interleaved samples.
+pub type CpalInputCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        data: *const c_void,
+        samples: usize,
+        format: CpalSampleFormat,
+    ),
+>;
+
+/// Called with each buffer of an output stream to fill with `samples` interleaved samples.
+pub type CpalOutputCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        data: *mut c_void,
+        samples: usize,
+        format: CpalSampleFormat,
+    ),
+>;
+
+/// Called when an error occurs while a stream is running.
+pub type CpalErrorCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, error: CpalError)>;
+
+/// An audio host. Free with `cpal_host_free`.
+pub struct CpalHost(Host);
+
+/// An audio device. Free with `cpal_device_free`, unless borrowed from a `CpalDeviceList`.
+pub struct CpalDevice(Device);
+
+/// The devices of a host. Free with `cpal_device_list_free`.
+pub struct CpalDeviceList(Vec<CpalDevice>);
+
+/// A running or paused stream. Free with `cpal_stream_free`, which stops the stream.
+pub struct CpalStream(Stream);
+
+// The caller is responsible for making `user_data` safe to use from the audio thread.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+impl UserData {
+    // Closures must capture the whole `UserData` through this method rather than its field, which
+    // is not `Send`.
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+impl From<SampleFormat> for CpalSampleFormat {
+    fn from(format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::I16 => CpalSampleFormat::I16,
+            SampleFormat::U16 => CpalSampleFormat::U16,
+            SampleFormat::F32 => CpalSampleFormat::F32,
+            SampleFormat::I24 => CpalSampleFormat::I24,
+            SampleFormat::I24Packed => CpalSampleFormat::I24Packed,
+        }
+    }
+}
+
+impl From<CpalSampleFormat> for SampleFormat {
+    fn from(format: CpalSampleFormat) -> Self {
+        match format {
+            CpalSampleFormat::I16 => SampleFormat::I16,
+            CpalSampleFormat::U16 => SampleFormat::U16,
+            CpalSampleFormat::F32 => SampleFormat::F32,
+            CpalSampleFormat::I24 => SampleFormat::I24,
+            CpalSampleFormat::I24Packed => SampleFormat::I24Packed,
+        }
+    }
+}
+
+impl From<CpalStreamConfig> for StreamConfig {
+    fn from(config: CpalStreamConfig) -> Self {
+        StreamConfig {
+            channels: config.channels,
+            sample_rate: SampleRate(config.sample_rate),
+            buffer_size: match config.buffer_size {
+                0 => BufferSize::Default,
+                frames => BufferSize::Fixed(frames),
+            },
+        }
+    }
+}
+
+impl From<&SupportedStreamConfig> for CpalStreamConfig {
+    fn from(config: &SupportedStreamConfig) -> Self {
+        CpalStreamConfig {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            buffer_size: 0,
+        }
+    }
+}
+
+impl From<DevicesError> for CpalError {
+    fn from(err: DevicesError) -> Self {
+        match err {
+            DevicesError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<DeviceNameError> for CpalError {
+    fn from(err: DeviceNameError) -> Self {
+        match err {
+            DeviceNameError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<DefaultStreamConfigError> for CpalError {
+    fn from(err: DefaultStreamConfigError) -> Self {
+        match err {
+            DefaultStreamConfigError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            DefaultStreamConfigError::StreamTypeNotSupported => CpalError::StreamTypeNotSupported,
+            DefaultStreamConfigError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<BuildStreamError> for CpalError {
+    fn from(err: BuildStreamError) -> Self {
+        match err {
+            BuildStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            BuildStreamError::StreamConfigNotSupported => CpalError::StreamConfigNotSupported,
+            BuildStreamError::InvalidArgument => CpalError::InvalidArgument,
+            BuildStreamError::StreamIdOverflow => CpalError::StreamIdOverflow,
+            BuildStreamError::BufferSizeTooSmall { .. }
+            | BuildStreamError::BufferSizeTooLarge { .. } => CpalError::StreamConfigNotSupported,
+            BuildStreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<PlayStreamError> for CpalError {
+    fn from(err: PlayStreamError) -> Self {
+        match err {
+            PlayStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            PlayStreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<PauseStreamError> for CpalError {
+    fn from(err: PauseStreamError) -> Self {
+        match err {
+            PauseStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            PauseStreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<&StreamError> for CpalError {
+    fn from(err: &StreamError) -> Self {
+        match err {
+            StreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            StreamError::CloseTimedOut => CpalError::CloseTimedOut,
+            StreamError::CallbackPanicked { .. } => CpalError::CallbackPanicked,
+            StreamError::CallbackStalled { .. } => CpalError::CallbackStalled,
+            StreamError::FocusLost { .. } => CpalError::FocusLost,
+            StreamError::BluetoothProfileChanged { .. } => CpalError::BluetoothProfileChanged,
+            StreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+// Runs the body of an exported function, turning a panic into an error code.
+fn ffi_guard(f: impl FnOnce() -> Result<(), CpalError>) -> CpalError {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => CpalError::Ok,
+        Ok(Err(err)) => err,
+        Err(_) => CpalError::Panicked,
+    }
+}
+
+// Writes `value` through `out`, which must be non-null.
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), CpalError> {
+    if out.is_null() {
+        return Err(CpalError::NullArgument);
+    }
+    out.write(value);
+    Ok(())
+}
+
+// Copies `s` as a nul-terminated string into `buf` of `len` bytes, storing the required length
+// including the terminator in `needed` if it is non-null.
+unsafe fn write_str(
+    s: &str,
+    buf: *mut c_char,
+    len: usize,
+    needed: *mut usize,
+) -> Result<(), CpalError> {
+    if !needed.is_null() {
+        needed.write(s.len() + 1);
+    }
+    if len < s.len() + 1 {
+        return Err(CpalError::BufferTooSmall);
+    }
+    if buf.is_null() {
+        return Err(CpalError::NullArgument);
+    }
+    ptr::copy_nonoverlapping(s.as_ptr(), buf as *mut u8, s.len());
+    buf.add(s.len()).write(0);
+    Ok(())
+}
+
+fn invoke_error_callback(callback: CpalErrorCallback, user_data: &UserData, err: &StreamError) {
+    if let Some(callback) = callback {
+        unsafe { callback(user_data.get(), err.into()) };
+    }
+}
+
+/// Returns a static, nul-terminated description of `error`.
+#[no_mangle]
+pub extern "C" fn cpal_error_message(error: CpalError) -> *const c_char {
+    let message: &'static str = match error {
+        CpalError::Ok => "no error\0",
+        CpalError::NullArgument => "a required pointer argument was null\0",
+        CpalError::DeviceNotAvailable => "the requested device is not available\0",
+        CpalError::InvalidArgument => "an argument is invalid\0",
+        CpalError::StreamConfigNotSupported => "the requested stream config is not supported\0",
+        CpalError::StreamTypeNotSupported => "the requested stream type is not supported\0",
+        CpalError::StreamIdOverflow => "the host ran out of stream IDs\0",
+        CpalError::BufferTooSmall => "the buffer is too small for the result\0",
+        CpalError::CloseTimedOut => "timed out waiting for the data callback to return\0",
+        CpalError::CallbackPanicked => "the stream's data callback panicked\0",
+        CpalError::BackendSpecific => "a backend-specific error occurred\0",
+        CpalError::Panicked => "CPAL panicked\0",
+        CpalError::CallbackStalled => "the stream's data callback has not returned in time\0",
+        CpalError::FocusLost => "another application took the audio focus of the stream\0",
+        CpalError::BluetoothProfileChanged => {
+            "the Bluetooth device of the stream switched profile\0"
+        }
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Creates the default host of the platform.
+///
+/// # Safety
+///
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_default_host(out: *mut *mut CpalHost) -> CpalError {
+    ffi_guard(|| {
+        let host = Box::new(CpalHost(crate::default_host()));
+        write_out(out, Box::into_raw(host))
+    })
+}
+
+/// Frees a host. Null is ignored.
+///
+/// # Safety
+///
+/// `host` must be null or come from `cpal_default_host`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_free(host: *mut CpalHost) {
+    if !host.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(host))));
+    }
+}
+
+/// Copies the name of the host into `buf`, see `cpal_device_name`.
+///
+/// # Safety
+///
+/// `host` must be a valid host, `buf` must be valid for writes of `len` bytes and `needed` must
+/// be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_name(
+    host: *const CpalHost,
+    buf: *mut c_char,
+    len: usize,
+    needed: *mut usize,
+) -> CpalError {
+    ffi_guard(|| {
+        let host = host.as_ref().ok_or(CpalError::NullArgument)?;
+        write_str(host.0.id().name(), buf, len, needed)
+    })
+}
+
+/// Gets the default input device of the host, or `CPAL_ERROR_DEVICE_NOT_AVAILABLE` if there is
+/// none.
+///
+/// # Safety
+///
+/// `host` must be a valid host and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_default_input_device(
+    host: *const CpalHost,
+    out: *mut *mut CpalDevice,
+) -> CpalError {
+    ffi_guard(|| {
+        let host = host.as_ref().ok_or(CpalError::NullArgument)?;
+        let device = host
+            .0
+            .default_input_device()
+            .ok_or(CpalError::DeviceNotAvailable)?;
+        write_out(out, Box::into_raw(Box::new(CpalDevice(device))))
+    })
+}
+
+/// Gets the default output device of the host, or `CPAL_ERROR_DEVICE_NOT_AVAILABLE` if there is
+/// none.
+///
+/// # Safety
+///
+/// `host` must be a valid host and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_default_output_device(
+    host: *const CpalHost,
+    out: *mut *mut CpalDevice,
+) -> CpalError {
+    ffi_guard(|| {
+        let host = host.as_ref().ok_or(CpalError::NullArgument)?;
+        let device = host
+            .0
+            .default_output_device()
+            .ok_or(CpalError::DeviceNotAvailable)?;
+        write_out(out, Box::into_raw(Box::new(CpalDevice(device))))
+    })
+}
+
+/// Lists all devices of the host.
+///
+/// # Safety
+///
+/// `host` must be a valid host and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_devices(
+    host: *const CpalHost,
+    out: *mut *mut CpalDeviceList,
+) -> CpalError {
+    ffi_guard(|| {
+        let host = host.as_ref().ok_or(CpalError::NullArgument)?;
+        let devices = host.0.devices()?.map(CpalDevice).collect();
+        write_out(out, Box::into_raw(Box::new(CpalDeviceList(devices))))
+    })
+}
+
+/// Returns the number of devices in the list, or 0 if `list` is null.
+///
+/// # Safety
+///
+/// `list` must be null or a valid device list.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_list_len(list: *const CpalDeviceList) -> usize {
+    list.as_ref().map_or(0, |list| list.0.len())
+}
+
+/// Returns the device at `index`, or null if `index` is out of bounds. The device is owned by the
+/// list and must not be freed.
+///
+/// # Safety
+///
+/// `list` must be null or a valid device list.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_list_get(
+    list: *const CpalDeviceList,
+    index: usize,
+) -> *const CpalDevice {
+    list.as_ref()
+        .and_then(|list| list.0.get(index))
+        .map_or(ptr::null(), |device| device as *const CpalDevice)
+}
+
+/// Frees a device list along with its devices. Null is ignored.
+///
+/// # Safety
+///
+/// `list` must be null or come from `cpal_host_devices`, and neither it nor its devices may be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_list_free(list: *mut CpalDeviceList) {
+    if !list.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(list))));
+    }
+}
+
+/// Frees a device. Null is ignored.
+///
+/// # Safety
+///
+/// `device` must be null or come from `cpal_host_default_input_device` or
+/// `cpal_host_default_output_device`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_free(device: *mut CpalDevice) {
+    if !device.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(device))));
+    }
+}
+
+/// Copies the name of the device into `buf` as a nul-terminated UTF-8 string.
+///
+/// If `needed` is non-null, the size of the name in bytes including the terminator is stored in
+/// it, even if `buf` is too small, in which case `CPAL_ERROR_BUFFER_TOO_SMALL` is returned. Pass
+/// a `len` of 0 to query the size.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `buf` must be valid for writes of `len` bytes and `needed`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_name(
+    device: *const CpalDevice,
+    buf: *mut c_char,
+    len: usize,
+    needed: *mut usize,
+) -> CpalError {
+    ffi_guard(|| {
+        let device = device.as_ref().ok_or(CpalError::NullArgument)?;
+        write_str(&device.0.name()?, buf, len, needed)
+    })
+}
+
+/// Gets the default input config of the device.
+///
+/// # Safety
+///
+/// `device` must be a valid device, and `config` and `format` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_default_input_config(
+    device: *const CpalDevice,
+    config: *mut CpalStreamConfig,
+    format: *mut CpalSampleFormat,
+) -> CpalError {
+    ffi_guard(|| {
+        let device = device.as_ref().ok_or(CpalError::NullArgument)?;
+        if config.is_null() || format.is_null() {
+            return Err(CpalError::NullArgument);
+        }
+        let supported = device.0.default_input_config()?;
+        config.write((&supported).into());
+        format.write(supported.sample_format().into());
+        Ok(())
+    })
+}
+
+/// Gets the default output config of the device.
+///
+/// # Safety
+///
+/// `device` must be a valid device, and `config` and `format` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_default_output_config(
+    device: *const CpalDevice,
+    config: *mut CpalStreamConfig,
+    format: *mut CpalSampleFormat,
+) -> CpalError {
+    ffi_guard(|| {
+        let device = device.as_ref().ok_or(CpalError::NullArgument)?;
+        if config.is_null() || format.is_null() {
+            return Err(CpalError::NullArgument);
+        }
+        let supported = device.0.default_output_config()?;
+        config.write((&supported).into());
+        format.write(supported.sample_format().into());
+        Ok(())
+    })
+}
+
+/// Builds an input stream. The stream may start playing immediately on some hosts; call
+/// `cpal_stream_play` to be sure it does.
+///
+/// `data_callback` and `error_callback` are called on the audio thread with `user_data`, which
+/// must stay valid until the stream is freed. `error_callback` may be null.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `config` must be valid for reads and `out` must be valid for
+/// writes. The callbacks must not unwind.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_build_input_stream(
+    device: *const CpalDevice,
+    config: *const CpalStreamConfig,
+    format: CpalSampleFormat,
+    data_callback: CpalInputCallback,
+    error_callback: CpalErrorCallback,
+    user_data: *mut c_void,
+    out: *mut *mut CpalStream,
+) -> CpalError {
+    ffi_guard(|| {
+        let device = device.as_ref().ok_or(CpalError::NullArgument)?;
+        let config = config.as_ref().ok_or(CpalError::NullArgument)?;
+        let data_callback = data_callback.ok_or(CpalError::NullArgument)?;
+        if out.is_null() {
+            return Err(CpalError::NullArgument);
+        }
+        let data_user_data = UserData(user_data);
+        let error_user_data = UserData(user_data);
+        let stream = device.0.build_input_stream_raw(
+            &(*config).into(),
+            format.into(),
+            move |data: &Data, _| {
+                let format = data.sample_format().into();
+                let bytes = data.bytes().as_ptr() as *const c_void;
+                data_callback(data_user_data.get(), bytes, data.len(), format);
+            },
+            move |err| invoke_error_callback(error_callback, &error_user_data, &err),
+        )?;
+        write_out(out, Box::into_raw(Box::new(CpalStream(stream))))
+    })
+}
+
+/// Builds an output stream. The stream may start playing immediately on some hosts; call
+/// `cpal_stream_play` to be sure it does.
+///
+/// `data_callback` and `error_callback` are called on the audio thread with `user_data`, which
+/// must stay valid until the stream is freed. `error_callback` may be null.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `config` must be valid for reads and `out` must be valid for
+/// writes. The callbacks must not unwind.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_build_output_stream(
+    device: *const CpalDevice,
+    config: *const CpalStreamConfig,
+    format: CpalSampleFormat,
+    data_callback: CpalOutputCallback,
+    error_callback: CpalErrorCallback,
+    user_data: *mut c_void,
+    out: *mut *mut CpalStream,
+) -> CpalError {
+    ffi_guard(|| {
+        let device = device.as_ref().ok_or(CpalError::NullArgument)?;
+        let config = config.as_ref().ok_or(CpalError::NullArgument)?;
+        let data_callback = data_callback.ok_or(CpalError::NullArgument)?;
+        if out.is_null() {
+            return Err(CpalError::NullArgument);
+        }
+        let data_user_data = UserData(user_data);
+        let error_user_data = UserData(user_data);
+        let stream = device.0.build_output_stream_raw(
+            &(*config).into(),
+            format.into(),
+            move |data: &mut Data, _| {
+                let format = data.sample_format().into();
+                let len = data.len();
+                let bytes = data.bytes_mut().as_mut_ptr() as *mut c_void;
+                data_callback(data_user_data.get(), bytes, len, format);
+            },
+            move |err| invoke_error_callback(error_callback, &error_user_data, &err),
+        )?;
+        write_out(out, Box::into_raw(Box::new(CpalStream(stream))))
+    })
+}
+
+/// Starts or resumes the stream.
+///
+/// # Safety
+///
+/// `stream` must be a valid stream.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_play(stream: *const CpalStream) -> CpalError {
+    ffi_guard(|| {
+        let stream = stream.as_ref().ok_or(CpalError::NullArgument)?;
+        Ok(stream.0.play()?)
+    })
+}
+
+/// Pauses the stream, if the host supports it.
+///
+/// # Safety
+///
+/// `stream` must be a valid stream.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_pause(stream: *const CpalStream) -> CpalError {
+    ffi_guard(|| {
+        let stream = stream.as_ref().ok_or(CpalError::NullArgument)?;
+        Ok(stream.0.pause()?)
+    })
+}
+
+/// Stops and frees the stream. Null is ignored.
+///
+/// # Safety
+///
+/// `stream` must be null or come from `cpal_device_build_input_stream` or
+/// `cpal_device_build_output_stream`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_free(stream: *mut CpalStream) {
+    if !stream.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(stream))));
+    }
+}
+
+/// Renders `frames` frames by calling `data_callback` as an output stream would, without a
+/// device; see `cpal::offline::render`. The samples are `CPAL_SAMPLE_FORMAT_F32` and are written
+/// to `out`, which must hold at least `frames * config->channels` samples.
+///
+/// # Safety
+///
+/// `config` must be valid for reads, `out` must be valid for writes of `out_len` samples and
+/// `data_callback` must not unwind.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_offline_render(
+    config: *const CpalStreamConfig,
+    frames: usize,
+    data_callback: CpalOutputCallback,
+    user_data: *mut c_void,
+    out: *mut f32,
+    out_len: usize,
+) -> CpalError {
+    ffi_guard(|| {
+        let config = config.as_ref().ok_or(CpalError::NullArgument)?;
+        let data_callback = data_callback.ok_or(CpalError::NullArgument)?;
+        if out.is_null() {
+            return Err(CpalError::NullArgument);
+        }
+        if config.channels == 0 || config.sample_rate == 0 {
+            return Err(CpalError::InvalidArgument);
+        }
+        let samples = frames
+            .checked_mul(config.channels as usize)
+            .ok_or(CpalError::InvalidArgument)?;
+        if out_len < samples {
+            return Err(CpalError::BufferTooSmall);
+        }
+        let rendered = crate::offline::render(&(*config).into(), frames, |data, _| {
+            let len = data.len();
+            let data = data.as_mut_ptr() as *mut c_void;
+            data_callback(user_data, data, len, CpalSampleFormat::F32);
+        });
+        ptr::copy_nonoverlapping(rendered.as_ptr(), out, rendered.len());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    unsafe extern "C" fn ramp(
+        user_data: *mut c_void,
+        data: *mut c_void,
+        samples: usize,
+        format: CpalSampleFormat,
+    ) {
+        assert_eq!(format, CpalSampleFormat::F32);
+        let next = &mut *(user_data as *mut f32);
+        for sample in std::slice::from_raw_parts_mut(data as *mut f32, samples) {
+            *sample = *next;
+            *next += 1.0;
+        }
+    }
+
+    #[test]
+    fn test_offline_render() {
+        let config = CpalStreamConfig {
+            channels: 2,
+            sample_rate: 48_000,
+            buffer_size: 3,
+        };
+        let mut next = 0.0f32;
+        let mut out = [0.0f32; 10];
+        let err = unsafe {
+            cpal_offline_render(
+                &config,
+                5,
+                Some(ramp),
+                &mut next as *mut f32 as *mut c_void,
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+        assert_eq!(err, CpalError::Ok);
+        assert_eq!(out, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    }
+
+    #[test]
+    fn test_offline_render_checks_arguments() {
+        let mut config = CpalStreamConfig {
+            channels: 2,
+            sample_rate: 48_000,
+            buffer_size: 0,
+        };
+        let mut out = [0.0f32; 4];
+        let render = |config: &CpalStreamConfig, out: &mut [f32]| unsafe {
+            cpal_offline_render(
+                config,
+                4,
+                Some(ramp),
+                ptr::null_mut(),
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+        assert_eq!(render(&config, &mut out), CpalError::BufferTooSmall);
+        config.channels = 0;
+        assert_eq!(render(&config, &mut out), CpalError::InvalidArgument);
+        let err =
+            unsafe { cpal_offline_render(&config, 4, None, ptr::null_mut(), out.as_mut_ptr(), 4) };
+        assert_eq!(err, CpalError::NullArgument);
+    }
+
+    #[test]
+    fn test_write_str() {
+        let mut buf = [0 as c_char; 4];
+        let mut needed = 0;
+        let err = unsafe { write_str("abcd", buf.as_mut_ptr(), buf.len(), &mut needed) };
+        assert_eq!(err, Err(CpalError::BufferTooSmall));
+        assert_eq!(needed, 5);
+        let err = unsafe { write_str("abc", buf.as_mut_ptr(), buf.len(), &mut needed) };
+        assert_eq!(err, Ok(()));
+        assert_eq!(needed, 4);
+        let written = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!(written.to_str(), Ok("abc"));
+    }
+
+    #[test]
+    fn test_error_messages_are_terminated() {
+        let message = unsafe { CStr::from_ptr(cpal_error_message(CpalError::BufferTooSmall)) };
+        assert_eq!(
+            message.to_str(),
+            Ok("the buffer is too small for the result")
+        );
+    }
+}
@@ -0,0 +1,3 @@
+//! Adapters for code written against other audio APIs, to ease migrating it to CPAL.
+
+pub mod portaudio;
@@ -0,0 +1,241 @@
+//! Running PortAudio-style stream callbacks on CPAL streams.
+//!
+//! Code migrating from PortAudio often has its processing in a callback of the shape
+//! `fn(input: *const c_void, output: *mut c_void, frames: u32)`. The functions in this module
+//! build CPAL streams that call such a callback, so that the code can use CPAL's devices and hosts
+//! before its processing is rewritten against CPAL's typed callbacks.
+//!
+//! The format of the samples is given as a `PaSampleFormat`, as it would be to `Pa_OpenStream`.
+//! Only the formats with a CPAL equivalent are supported:
+//!
+//! | PortAudio | CPAL |
+//! | --------- | ---- |
+//! | `paFloat32` | `SampleFormat::F32` |
+//! | `paInt24` | `SampleFormat::I24Packed` |
+//! | `paInt16` | `SampleFormat::I16` |
+//!
+//! Building a stream with any other format, or with `paNonInterleaved`, fails with
+//! `BuildStreamError::StreamConfigNotSupported`. CPAL's `SampleFormat::U16` and
+//! `SampleFormat::I24`, whose samples take four bytes, have no PortAudio equivalent.
+//!
+//! # Pointers passed to the callback
+//!
+//! During each call, the buffer pointer of the stream's direction points to `frames` frames of
+//! interleaved samples of the stream's format, `frames * config.channels` samples in total. An
+//! input stream passes the captured samples as `input` and a null `output`; an output stream
+//! passes a null `input` and the samples to fill as `output`. The pointers are only valid until
+//! the callback returns. Streams only run in one direction, so the callback never receives both.
+
+use crate::traits::DeviceTrait;
+use crate::{
+    BuildStreamError, Data, InputCallbackInfo, OutputCallbackInfo, SampleFormat, StreamConfig,
+    StreamError,
+};
+use std::ffi::c_void;
+use std::os::raw::c_ulong;
+use std::ptr;
+
+/// The format of the samples passed to a callback, as PortAudio's `PaSampleFormat`.
+pub type PaSampleFormat = c_ulong;
+
+/// `paFloat32`, mapped to `SampleFormat::F32`.
+pub const PA_FLOAT32: PaSampleFormat = 0x0000_0001;
+/// `paInt32`, which has no CPAL equivalent.
+pub const PA_INT32: PaSampleFormat = 0x0000_0002;
+/// `paInt24`, which has no CPAL equivalent.
+pub const PA_INT24: PaSampleFormat = 0x0000_0004;
+/// `paInt16`, mapped to `SampleFormat::I16`.
+pub const PA_INT16: PaSampleFormat = 0x0000_0008;
+/// `paInt8`, which has no CPAL equivalent.
+pub const PA_INT8: PaSampleFormat = 0x0000_0010;
+/// `paUInt8`, which has no CPAL equivalent.
+pub const PA_UINT8: PaSampleFormat = 0x0000_0020;
+/// `paNonInterleaved`, which is not supported.
+pub const PA_NON_INTERLEAVED: PaSampleFormat = 0x8000_0000;
+
+/// The CPAL equivalent of a PortAudio sample format, if there is one.
+pub fn sample_format(format: PaSampleFormat) -> Option<SampleFormat> {
+    match format {
+        PA_FLOAT32 => Some(SampleFormat::F32),
+        PA_INT24 => Some(SampleFormat::I24Packed),
+        PA_INT16 => Some(SampleFormat::I16),
+        _ => None,
+    }
+}
+
+/// The PortAudio equivalent of a CPAL sample format, if there is one.
+pub fn pa_sample_format(format: SampleFormat) -> Option<PaSampleFormat> {
+    match format {
+        SampleFormat::F32 => Some(PA_FLOAT32),
+        SampleFormat::I24Packed => Some(PA_INT24),
+        SampleFormat::I16 => Some(PA_INT16),
+        SampleFormat::U16 | SampleFormat::I24 => None,
+    }
+}
+
+/// Builds an input stream on `device` that passes the captured samples to `callback`.
+///
+/// See the module documentation for the supported formats and the pointers passed to `callback`.
+pub fn build_input_stream<D, C, E>(
+    device: &D,
+    config: &StreamConfig,
+    format: PaSampleFormat,
+    callback: C,
+    error_callback: E,
+) -> Result<D::Stream, BuildStreamError>
+where
+    D: DeviceTrait,
+    C: FnMut(*const c_void, *mut c_void, u32) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let sample_format = stream_sample_format(config, format)?;
+    device.build_input_stream_raw(
+        config,
+        sample_format,
+        input_callback(config, callback),
+        error_callback,
+    )
+}
+
+/// Builds an output stream on `device` whose samples are filled in by `callback`.
+///
+/// See the module documentation for the supported formats and the pointers passed to `callback`.
+pub fn build_output_stream<D, C, E>(
+    device: &D,
+    config: &StreamConfig,
+    format: PaSampleFormat,
+    callback: C,
+    error_callback: E,
+) -> Result<D::Stream, BuildStreamError>
+where
+    D: DeviceTrait,
+    C: FnMut(*const c_void, *mut c_void, u32) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let sample_format = stream_sample_format(config, format)?;
+    device.build_output_stream_raw(
+        config,
+        sample_format,
+        output_callback(config, callback),
+        error_callback,
+    )
+}
+
+fn stream_sample_format(
+    config: &StreamConfig,
+    format: PaSampleFormat,
+) -> Result<SampleFormat, BuildStreamError> {
+    if config.channels == 0 {
+        return Err(BuildStreamError::StreamConfigNotSupported);
+    }
+    sample_format(format).ok_or(BuildStreamError::StreamConfigNotSupported)
+}
+
+fn input_callback<C>(
+    config: &StreamConfig,
+    mut callback: C,
+) -> impl FnMut(&Data, &InputCallbackInfo)
+where
+    C: FnMut(*const c_void, *mut c_void, u32),
+{
+    let channels = config.channels as usize;
+    move |data, _| {
+        let frames = (data.len() / channels) as u32;
+        callback(
+            data.bytes().as_ptr() as *const c_void,
+            ptr::null_mut(),
+            frames,
+        );
+    }
+}
+
+fn output_callback<C>(
+    config: &StreamConfig,
+    mut callback: C,
+) -> impl FnMut(&mut Data, &OutputCallbackInfo)
+where
+    C: FnMut(*const c_void, *mut c_void, u32),
+{
+    let channels = config.channels as usize;
+    move |data, _| {
+        let frames = (data.len() / channels) as u32;
+        callback(
+            ptr::null(),
+            data.bytes_mut().as_mut_ptr() as *mut c_void,
+            frames,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        input_callback, output_callback, pa_sample_format, sample_format, PA_FLOAT32, PA_INT16,
+        PA_INT32, PA_NON_INTERLEAVED,
+    };
+    use crate::{offline, BufferSize, Data, SampleFormat, SampleRate, StreamConfig};
+    use std::ffi::c_void;
+    use std::slice;
+
+    const CONFIG: StreamConfig = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(48_000),
+        buffer_size: BufferSize::Default,
+    };
+
+    #[test]
+    fn test_sample_formats() {
+        for format in [
+            SampleFormat::F32,
+            SampleFormat::I24Packed,
+            SampleFormat::I16,
+        ] {
+            assert_eq!(
+                pa_sample_format(format).and_then(sample_format),
+                Some(format)
+            );
+        }
+        assert_eq!(pa_sample_format(SampleFormat::U16), None);
+        assert_eq!(pa_sample_format(SampleFormat::I24), None);
+        assert_eq!(sample_format(PA_INT32), None);
+        assert_eq!(sample_format(PA_FLOAT32 | PA_NON_INTERLEAVED), None);
+        assert_eq!(sample_format(PA_INT16), Some(SampleFormat::I16));
+    }
+
+    #[test]
+    fn test_output_callback() {
+        let mut callback = output_callback(&CONFIG, |input, output, frames| {
+            assert!(input.is_null());
+            let output = output as *mut f32;
+            for i in 0..frames as usize * 2 {
+                unsafe { *output.add(i) = i as f32 };
+            }
+        });
+        let output = offline::render(&CONFIG, 3, |block, info| {
+            let len = block.len();
+            let mut data =
+                unsafe { Data::from_parts(block.as_mut_ptr() as *mut (), len, SampleFormat::F32) };
+            callback(&mut data, info);
+        });
+        assert_eq!(output, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_input_callback() {
+        let mut received = Vec::new();
+        let mut callback = input_callback(&CONFIG, |input: *const c_void, output, frames| {
+            assert!(output.is_null());
+            let len = frames as usize * 2;
+            let input = unsafe { slice::from_raw_parts(input as *const f32, len) };
+            received.extend_from_slice(input);
+        });
+        offline::capture(&CONFIG, &[1.0, 2.0, 3.0, 4.0], |block, info| {
+            let data = unsafe {
+                Data::from_parts(block.as_ptr() as *mut (), block.len(), SampleFormat::F32)
+            };
+            callback(&data, info);
+        });
+        drop(callback);
+        assert_eq!(received, [1.0, 2.0, 3.0, 4.0]);
+    }
+}
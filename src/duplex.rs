@@ -0,0 +1,500 @@
+//! Streams that capture and play through one data callback, see
+//! `DeviceTrait::build_duplex_stream`.
+
+use crate::ring::Ring;
+use crate::traits::{DeviceTrait, StreamTrait};
+use crate::{
+    BufferSize, BuildStreamError, ChannelCount, FrameCount, OutputCallbackInfo, PauseStreamError,
+    PlayStreamError, Sample, SampleFormat, SampleRate, StreamConfig, StreamDirection, StreamError,
+    StreamInstant, SupportedStreamConfigsError, I24,
+};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The duration of audio the ring between the input and the output stream of a `DuplexStream` can
+/// hold.
+pub const DUPLEX_RING_DURATION: Duration = Duration::from_millis(500);
+
+/// The config of a `DuplexStream`. Both directions share the sample rate and buffer size, while
+/// their channel counts may differ.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplexStreamConfig {
+    pub input_channels: ChannelCount,
+    pub output_channels: ChannelCount,
+    pub sample_rate: SampleRate,
+    pub buffer_size: BufferSize,
+}
+
+impl DuplexStreamConfig {
+    /// The config of the input stream.
+    pub fn input_config(&self) -> StreamConfig {
+        StreamConfig {
+            channels: self.input_channels,
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size.clone(),
+        }
+    }
+
+    /// The config of the output stream.
+    pub fn output_config(&self) -> StreamConfig {
+        StreamConfig {
+            channels: self.output_channels,
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size.clone(),
+        }
+    }
+}
+
+/// A timestamp associated with a call to a duplex stream's data callback.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DuplexStreamTimestamp {
+    /// The instant the stream's data callback was invoked.
+    pub callback: StreamInstant,
+    /// The instant the first input frame was captured from the device.
+    ///
+    /// The instant of the callback until the input stream has captured anything.
+    pub capture: StreamInstant,
+    /// The predicted instant the first output frame will be played by the device.
+    pub playback: StreamInstant,
+}
+
+/// Information relevant to a single call to the user's duplex stream data callback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplexCallbackInfo {
+    timestamp: DuplexStreamTimestamp,
+    missing_input_frames: FrameCount,
+}
+
+impl DuplexCallbackInfo {
+    /// The timestamp associated with the call to a duplex stream's data callback.
+    pub fn timestamp(&self) -> DuplexStreamTimestamp {
+        self.timestamp
+    }
+
+    /// The number of frames at the end of the input buffer that are silence, because the input
+    /// stream had not captured them yet.
+    pub fn missing_input_frames(&self) -> FrameCount {
+        self.missing_input_frames
+    }
+}
+
+/// An input and an output stream bridged by a ring, driving one data callback, see
+/// `DeviceTrait::build_duplex_stream`.
+///
+/// Applications that process their input into their output, such as echo cancellation or live
+/// effects, need to know which captured frame was played alongside which. No host opens native
+/// duplex streams yet, e.g. ALSA PCMs linked with `snd_pcm_link` or a CoreAudio aggregate device,
+/// so a `DuplexStream` is a fallback bridging two separate streams, each running on callbacks of
+/// its own: the input stream writes the frames it captures to a lock-free ring, and each call to
+/// the output stream's data callback takes as many frames from it as it plays, passing both to
+/// the user's callback.
+///
+/// The input passed lags the capture by at most one input and one output buffer. Frames queued
+/// beyond that are discarded, which happens when the input device's clock runs faster than the
+/// output device's. Frames missing because it runs slower are passed as silence, see
+/// [`DuplexCallbackInfo::missing_input_frames`]. Either way, [`DuplexStreamTimestamp::capture`]
+/// is the instant the first input frame passed was captured.
+///
+/// Playing and pausing it plays and pauses the two streams one after the other, which is not
+/// atomic: the input starts first and stops last, so that the output never runs without the
+/// input. Frames captured before the output starts are queued in the ring, and discarded beyond
+/// the lag above. If the output fails to play, the input is paused again. Dropping it drops both
+/// streams.
+pub struct DuplexStream<S> {
+    input: S,
+    output: S,
+}
+
+impl<S> DuplexStream<S> {
+    /// The stream capturing the input.
+    pub fn input_stream(&self) -> &S {
+        &self.input
+    }
+
+    /// The stream playing the output, whose data callback calls the duplex stream's.
+    pub fn output_stream(&self) -> &S {
+        &self.output
+    }
+}
+
+/// The sample rate, channel count and sample format are those of the output stream.
+impl<S: StreamTrait> StreamTrait for DuplexStream<S> {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        self.input.play()?;
+        if let Err(err) = self.output.play() {
+            let _ = self.input.pause();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        self.output.pause()?;
+        self.input.pause()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.output.sample_rate()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.output.channels()
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.output.sample_format()
+    }
+
+    fn next_block_frames(&self) -> Option<FrameCount> {
+        self.output.next_block_frames()
+    }
+}
+
+// Builds a duplex stream bridging an input stream on `input_device` and an output stream on
+// `output_device`, see `DeviceTrait::build_duplex_stream`.
+pub(crate) fn build<Dev, T, U, D, E>(
+    input_device: &Dev,
+    output_device: &Dev,
+    config: &DuplexStreamConfig,
+    data_callback: D,
+    error_callback: E,
+) -> Result<DuplexStream<Dev::Stream>, BuildStreamError>
+where
+    Dev: DeviceTrait + ?Sized,
+    T: Sample + Send + 'static,
+    U: Sample,
+    D: FnMut(&[T], &mut [U], &DuplexCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    // Neither stream is built unless both configs are supported.
+    let input_config = config.input_config();
+    let output_config = config.output_config();
+    check_config(
+        input_device,
+        StreamDirection::Input,
+        &input_config,
+        T::FORMAT,
+    )?;
+    check_config(
+        output_device,
+        StreamDirection::Output,
+        &output_config,
+        U::FORMAT,
+    )?;
+
+    let frames = config.sample_rate.frames_in_rounded(DUPLEX_RING_DURATION) as usize;
+    let bridge = Arc::new(Bridge::new(config.input_channels, frames));
+    let error_callback = Arc::new(Mutex::new(error_callback));
+
+    let input_bridge = bridge.clone();
+    let input_error_callback = error_callback.clone();
+    // Samples beyond the capacity of the ring would be dropped by `capture` anyway, so the buffer
+    // never grows in the callback.
+    let capacity = bridge.capacity;
+    let mut samples = Vec::with_capacity(capacity);
+    let input = input_device.build_input_stream::<T, _, _>(
+        &input_config,
+        move |data, info| {
+            samples.clear();
+            samples.extend(data.iter().take(capacity).map(encode));
+            input_bridge.capture(&samples, info.timestamp().capture);
+        },
+        move |err| report(&input_error_callback, err),
+    )?;
+
+    let mut output_side = OutputSide::new(bridge, config, data_callback);
+    let output = output_device.build_output_stream::<U, _, _>(
+        &output_config,
+        move |data, info| output_side.play(data, info),
+        move |err| report(&error_callback, err),
+    )?;
+    Ok(DuplexStream { input, output })
+}
+
+// Returns an error unless `device` supports `config` with `sample_format` in `direction`.
+fn check_config<D>(
+    device: &D,
+    direction: StreamDirection,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+) -> Result<(), BuildStreamError>
+where
+    D: DeviceTrait + ?Sized,
+{
+    let configs = device
+        .supported_configs(direction)
+        .map_err(|err| match err {
+            SupportedStreamConfigsError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+            SupportedStreamConfigsError::InvalidArgument => BuildStreamError::InvalidArgument,
+            SupportedStreamConfigsError::BackendSpecific { err } => err.into(),
+        })?;
+    if configs.contains(config, sample_format) {
+        Ok(())
+    } else {
+        Err(BuildStreamError::StreamConfigNotSupported)
+    }
+}
+
+// Both streams report their errors to the user's error callback.
+fn report<E>(error_callback: &Mutex<E>, err: StreamError)
+where
+    E: FnMut(StreamError),
+{
+    // A poisoned lock means the callback panicked before; don't call it again.
+    if let Ok(mut error_callback) = error_callback.lock() {
+        error_callback(err);
+    }
+}
+
+// Samples are stored in the ring as `f32`s holding their exact value, so that integer samples pass
+// through unchanged rather than being scaled to `-1.0..=1.0` and back.
+fn encode<T: Sample>(sample: &T) -> f32 {
+    match T::FORMAT {
+        SampleFormat::I16 => sample.to_i16() as f32,
+        SampleFormat::U16 => sample.to_u16() as f32,
+        SampleFormat::I24 | SampleFormat::I24Packed => sample.to_i24().get() as f32,
+        SampleFormat::F32 => sample.to_f32(),
+    }
+}
+
+fn decode<T: Sample>(sample: f32) -> T {
+    match T::FORMAT {
+        SampleFormat::I16 => T::from(&(sample as i16)),
+        SampleFormat::U16 => T::from(&(sample as u16)),
+        SampleFormat::I24 | SampleFormat::I24Packed => T::from(&I24::new_clamped(sample as i32)),
+        SampleFormat::F32 => T::from(&sample),
+    }
+}
+
+// The state shared by the input and the output stream.
+struct Bridge {
+    channels: usize,
+    // The captured samples, always whole frames.
+    ring: Ring,
+    // The number of samples the ring holds.
+    capacity: usize,
+    // The most frames passed to one call of the input stream's data callback.
+    max_input_frames: AtomicUsize,
+    // The number of frames written to the ring so far.
+    written_frames: AtomicU64,
+    // The capture instant of the last input buffer, with the number of frames written to the ring
+    // before it. Both callbacks only ever try to lock it.
+    last_capture: Mutex<Option<(StreamInstant, u64)>>,
+}
+
+impl Bridge {
+    fn new(channels: ChannelCount, frames: usize) -> Self {
+        let capacity = frames.max(1) * channels as usize;
+        Bridge {
+            channels: channels as usize,
+            ring: Ring::new(capacity),
+            capacity,
+            max_input_frames: AtomicUsize::new(0),
+            written_frames: AtomicU64::new(0),
+            last_capture: Mutex::new(None),
+        }
+    }
+
+    // Called by the input stream's data callback.
+    fn capture(&self, samples: &[f32], capture: StreamInstant) {
+        let frames = samples.len() / self.channels;
+        self.max_input_frames.fetch_max(frames, Ordering::Relaxed);
+        let written = self.written_frames.load(Ordering::Relaxed);
+        if let Ok(mut last_capture) = self.last_capture.try_lock() {
+            *last_capture = Some((capture, written));
+        }
+        // Frames that do not fit are dropped, keeping the frames in the ring aligned.
+        let len = samples
+            .len()
+            .min(self.ring.free() / self.channels * self.channels);
+        let pushed = self.ring.push(&samples[..len]) / self.channels;
+        self.written_frames
+            .store(written + pushed as u64, Ordering::Relaxed);
+    }
+}
+
+// The output stream's side of the bridge, which calls the user's callback.
+struct OutputSide<T, D> {
+    bridge: Arc<Bridge>,
+    sample_rate: SampleRate,
+    output_channels: usize,
+    data_callback: D,
+    // The samples taken from the ring, and decoded for the user's callback.
+    samples: Vec<f32>,
+    input: Vec<T>,
+    // The number of frames taken from or discarded in the ring so far.
+    read_frames: u64,
+    // The most frames passed to one call of the output stream's data callback.
+    max_output_frames: usize,
+    last_capture: Option<(StreamInstant, u64)>,
+}
+
+impl<T: Sample, D> OutputSide<T, D> {
+    fn new(bridge: Arc<Bridge>, config: &DuplexStreamConfig, data_callback: D) -> Self {
+        // Output buffers are shorter than the ring, so these don't grow in the callback.
+        let capacity = bridge.capacity;
+        OutputSide {
+            bridge,
+            sample_rate: config.sample_rate,
+            output_channels: config.output_channels as usize,
+            data_callback,
+            samples: Vec::with_capacity(capacity),
+            input: Vec::with_capacity(capacity),
+            read_frames: 0,
+            max_output_frames: 0,
+            last_capture: None,
+        }
+    }
+
+    // Called by the output stream's data callback.
+    fn play<U>(&mut self, data: &mut [U], info: &OutputCallbackInfo)
+    where
+        U: Sample,
+        D: FnMut(&[T], &mut [U], &DuplexCallbackInfo),
+    {
+        let channels = self.bridge.channels;
+        let frames = data.len() / self.output_channels;
+        self.max_output_frames = self.max_output_frames.max(frames);
+
+        // Frames beyond one input and one output buffer only accumulate when the input clock runs
+        // faster than the output clock.
+        let queued = self.bridge.ring.len() / channels;
+        let keep = self.bridge.max_input_frames.load(Ordering::Relaxed) + self.max_output_frames;
+        if queued > keep {
+            let skipped = self.bridge.ring.skip((queued - keep) * channels) / channels;
+            self.read_frames += skipped as u64;
+        }
+
+        if let Ok(last_capture) = self.bridge.last_capture.try_lock() {
+            if last_capture.is_some() {
+                self.last_capture = *last_capture;
+            }
+        }
+        let timestamp = info.timestamp();
+        let capture = self
+            .last_capture
+            .and_then(|(instant, frame)| self.frame_instant(instant, frame))
+            .unwrap_or(timestamp.callback);
+
+        self.samples.resize(frames * channels, 0.0);
+        let taken = self.bridge.ring.pop(&mut self.samples) / channels;
+        self.read_frames += taken as u64;
+        self.input.clear();
+        self.input.extend(
+            self.samples[..taken * channels]
+                .iter()
+                .map(|&sample| decode::<T>(sample)),
+        );
+        self.input.resize(frames * channels, T::from(&0.0f32));
+
+        let info = DuplexCallbackInfo {
+            timestamp: DuplexStreamTimestamp {
+                callback: timestamp.callback,
+                capture,
+                playback: timestamp.playback,
+            },
+            missing_input_frames: (frames - taken) as FrameCount,
+        };
+        (self.data_callback)(&self.input, data, &info);
+    }
+
+    // The capture instant of the next frame to be read, given that frame `frame` was captured at
+    // `instant`.
+    fn frame_instant(&self, instant: StreamInstant, frame: u64) -> Option<StreamInstant> {
+        let nanos =
+            |frames: u64| Duration::from_nanos(frames * 1_000_000_000 / self.sample_rate.0 as u64);
+        if self.read_frames >= frame {
+            instant.add(nanos(self.read_frames - frame))
+        } else {
+            instant.sub(nanos(frame - self.read_frames))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bridge, DuplexCallbackInfo, DuplexStreamConfig, OutputSide};
+    use crate::mock::output_info_at;
+    use crate::{BufferSize, SampleRate, StreamInstant};
+    use std::sync::{Arc, Mutex};
+
+    // The input and info passed to each call of the user's callback.
+    type Received = Arc<Mutex<Vec<(Vec<i16>, DuplexCallbackInfo)>>>;
+
+    const CONFIG: DuplexStreamConfig = DuplexStreamConfig {
+        input_channels: 1,
+        output_channels: 2,
+        sample_rate: SampleRate(1_000),
+        buffer_size: BufferSize::Default,
+    };
+
+    // Runs the output side for one buffer of `frames` frames, returning the input passed to the
+    // user's callback and its info.
+    fn play(
+        output_side: &mut OutputSide<i16, impl FnMut(&[i16], &mut [f32], &DuplexCallbackInfo)>,
+        received: &Received,
+        frames: usize,
+    ) -> (Vec<i16>, DuplexCallbackInfo) {
+        let mut data = vec![0.0f32; frames * 2];
+        output_side.play(
+            &mut data,
+            &output_info_at(StreamInstant::new(1, 500_000_000)),
+        );
+        received.lock().unwrap().pop().unwrap()
+    }
+
+    fn user_callback(received: &Received) -> impl FnMut(&[i16], &mut [f32], &DuplexCallbackInfo) {
+        let received = received.clone();
+        move |input, output, info| {
+            assert_eq!(input.len() * 2, output.len());
+            received
+                .lock()
+                .unwrap()
+                .push((input.to_vec(), info.clone()));
+        }
+    }
+
+    #[test]
+    fn test_input_is_aligned_with_output() {
+        let bridge = Arc::new(Bridge::new(1, 100));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut output_side = OutputSide::new(bridge.clone(), &CONFIG, user_callback(&received));
+
+        // Before any input, the input is silence captured at the instant of the callback.
+        let (input, info) = play(&mut output_side, &received, 4);
+        assert_eq!(input, [0; 4]);
+        assert_eq!(info.missing_input_frames(), 4);
+        assert_eq!(info.timestamp().capture, StreamInstant::new(1, 500_000_000));
+
+        // Frames 0 to 5 are captured from 1.2 s, one per millisecond.
+        let samples: Vec<f32> = (0..6).map(|frame| frame as f32).collect();
+        bridge.capture(&samples, StreamInstant::new(1, 200_000_000));
+        let (input, info) = play(&mut output_side, &received, 4);
+        assert_eq!(input, [0, 1, 2, 3]);
+        assert_eq!(info.missing_input_frames(), 0);
+        assert_eq!(info.timestamp().capture, StreamInstant::new(1, 200_000_000));
+        let (input, info) = play(&mut output_side, &received, 4);
+        assert_eq!(input, [4, 5, 0, 0]);
+        assert_eq!(info.missing_input_frames(), 2);
+        assert_eq!(info.timestamp().capture, StreamInstant::new(1, 204_000_000));
+    }
+
+    #[test]
+    fn test_excess_input_is_discarded() {
+        let bridge = Arc::new(Bridge::new(1, 100));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut output_side = OutputSide::new(bridge.clone(), &CONFIG, user_callback(&received));
+        play(&mut output_side, &received, 4);
+
+        // Three input buffers of 4 frames queue up while one output buffer of 4 frames is played,
+        // so the oldest 4 frames are beyond one input and one output buffer.
+        for buffer in 0..3 {
+            let samples: Vec<f32> = (0..4).map(|frame| (buffer * 4 + frame) as f32).collect();
+            bridge.capture(&samples, StreamInstant::new(2, buffer * 4_000_000));
+        }
+        let (input, info) = play(&mut output_side, &received, 4);
+        assert_eq!(input, [4, 5, 6, 7]);
+        assert_eq!(info.timestamp().capture, StreamInstant::new(2, 4_000_000));
+    }
+}
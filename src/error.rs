@@ -1,3 +1,5 @@
+use crate::{BtProfile, FrameCount};
+use std::time::Duration;
 use thiserror::Error;
 
 /// The requested host, although supported on this platform, is unavailable.
@@ -5,6 +7,12 @@ use thiserror::Error;
 #[error("the requested host is unavailable")]
 pub struct HostUnavailable;
 
+/// The host doesn't report the buffer sizes a `SupportedStreamConfig` supports, so converting it
+/// into a `StreamConfig` can't pick one. See `TryFrom<SupportedStreamConfig> for StreamConfig`.
+#[derive(Clone, Debug, Error)]
+#[error("the supported buffer sizes are unknown")]
+pub struct BufferSizeUnknown;
+
 /// Some error has occurred that is specific to the backend from which it was produced.
 ///
 /// This error is often used as a catch-all in cases where:
@@ -21,6 +29,18 @@ pub struct HostUnavailable;
 #[error("A backend-specific error has occurred: {description}")]
 pub struct BackendSpecificError {
     pub description: String,
+    /// The error code returned by the backend, where it returned one.
+    ///
+    /// | Host | Code |
+    /// | ---- | ---- |
+    /// | ALSA | `errno` |
+    /// | WASAPI | `HRESULT` |
+    /// | CoreAudio | `OSStatus` |
+    /// | ASIO | `ASIOError` |
+    /// | AAudio | `aaudio_result_t` |
+    ///
+    /// Errors raised by CPAL itself, e.g. for a sample type it cannot convert, have no code.
+    pub code: Option<i32>,
 }
 
 /// An error that might occur while attempting to enumerate the available devices on a system.
@@ -102,6 +122,27 @@ pub enum BuildStreamError {
     /// Occurs if adding a new Stream ID would cause an integer overflow.
     #[error("Adding a new stream ID would cause an overflow")]
     StreamIdOverflow,
+    /// The requested `BufferSize::Fixed` is smaller than the device supports. Set
+    /// `StreamOptions::clamp_buffer_size` to use the smallest supported size instead.
+    ///
+    /// The `*_with_options` builders check fixed sizes against the buffer size range of the
+    /// device's default config on every host that reports one. The other builders only check
+    /// them on ALSA, ASIO and CoreAudio (macOS); other hosts may accept the size, or fail with
+    /// another error.
+    #[error("The requested buffer size of {requested} frames is below the device's minimum of {min} frames")]
+    BufferSizeTooSmall {
+        requested: FrameCount,
+        min: FrameCount,
+    },
+    /// The requested `BufferSize::Fixed` is larger than the device supports. Set
+    /// `StreamOptions::clamp_buffer_size` to use the largest supported size instead.
+    ///
+    /// Checked on the same hosts as `BufferSizeTooSmall`.
+    #[error("The requested buffer size of {requested} frames is above the device's maximum of {max} frames")]
+    BufferSizeTooLarge {
+        requested: FrameCount,
+        max: FrameCount,
+    },
     /// See the `BackendSpecificError` docs for more information about this error variant.
     #[error("{err}")]
     BackendSpecific {
@@ -146,6 +187,157 @@ pub enum PauseStreamError {
     },
 }
 
+/// Errors that might occur when calling `request_focus()`.
+#[derive(Debug, Error)]
+pub enum RequestFocusError {
+    /// The host does not support requesting audio focus.
+    #[error("Audio focus requests are not supported by this host")]
+    NotSupported,
+    /// The request was denied, e.g. because another application holds audio focus.
+    #[error("The audio focus request was denied")]
+    Denied,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `rt::set_thread_realtime()`.
+#[derive(Debug, Error)]
+pub enum ThreadRealtimeError {
+    /// Realtime scheduling of arbitrary threads is not supported on this platform.
+    #[error("Realtime thread scheduling is not supported on this platform")]
+    NotSupported,
+    /// The system does not allow the thread to be scheduled in realtime.
+    #[error("The system did not permit realtime scheduling of the thread")]
+    PermissionDenied,
+    /// The durations are zero or out of order, or too long for the platform.
+    #[error("The computation must be non-zero, within the constraint, and the constraint within the period")]
+    InvalidConstraints,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `set_keep_alive()`.
+#[derive(Debug, Error)]
+pub enum KeepAliveError {
+    /// The host does not support the requested `KeepAlive` mode for this stream.
+    #[error("The requested keep-alive mode is not supported for this stream")]
+    NotSupported,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `set_volume()`.
+#[derive(Debug, Error)]
+pub enum DeviceVolumeError {
+    /// The host cannot control the volume of this device, or the device has no volume control.
+    #[error("The volume of this device cannot be controlled")]
+    NotSupported,
+    /// The device no longer exists. This can happen if the device is disconnected while the
+    /// program is running.
+    #[error("The requested device is no longer available. For example, it has been unplugged.")]
+    DeviceNotAvailable,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `set_sample_rate()`.
+#[derive(Debug, Error)]
+pub enum DeviceSampleRateError {
+    /// The host cannot change the sample rate of devices.
+    #[error("Changing the sample rate of a device is not supported by this host")]
+    NotSupported,
+    /// The device does not support the requested sample rate.
+    #[error("The requested sample rate is not supported by the device")]
+    SampleRateNotSupported,
+    /// The sample rate of the device cannot be changed right now, for example because its clock is
+    /// driven by another device or another process has exclusive access to it.
+    #[error("The sample rate of the device is locked")]
+    SampleRateLocked,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `watch_default()`.
+#[derive(Debug, Error)]
+pub enum WatchDefaultError {
+    /// The host cannot notify about changes to the default device.
+    #[error("Watching the default device is not supported by this host")]
+    NotSupported,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `watch_devices()`.
+#[derive(Debug, Error)]
+pub enum WatchDevicesError {
+    /// The host cannot notify about changes to its devices.
+    #[error("Watching the devices is not supported by this host")]
+    NotSupported,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// Errors that might occur when calling `smoke_test()`, by the step that failed.
+#[derive(Debug, Error)]
+pub enum SmokeTestError {
+    /// The device has no default config in the tested direction.
+    #[error("Failed to get the default stream config: {err}")]
+    DefaultStreamConfig {
+        #[from]
+        err: DefaultStreamConfigError,
+    },
+    /// The stream could not be built at the default config.
+    #[error("Failed to build the stream: {err}")]
+    BuildStream {
+        #[from]
+        err: BuildStreamError,
+    },
+    /// The stream was built but could not be started.
+    #[error("Failed to play the stream: {err}")]
+    PlayStream {
+        #[from]
+        err: PlayStreamError,
+    },
+    /// The stream reported an error while running.
+    #[error("The stream failed while running: {err}")]
+    Stream {
+        #[from]
+        err: StreamError,
+    },
+    /// The stream started, but its data callback was called fewer than `SMOKE_TEST_CALLBACKS`
+    /// times before `SMOKE_TEST_TIMEOUT` elapsed.
+    #[error("The stream's data callback was called {callbacks} times within {timeout:?}.")]
+    NoCallbacks { callbacks: usize, timeout: Duration },
+}
+
 /// Errors that might occur while a stream is running.
 #[derive(Debug, Error)]
 pub enum StreamError {
@@ -153,6 +345,30 @@ pub enum StreamError {
     /// program is running.
     #[error("The requested device is no longer available. For example, it has been unplugged.")]
     DeviceNotAvailable,
+    /// The stream's data callback did not return before the timeout given to `close` elapsed.
+    #[error("Timed out waiting for the stream's data callback to return.")]
+    CloseTimedOut,
+    /// The stream's data callback panicked. It will not be called again: output streams play
+    /// silence and input streams discard their data until the stream is dropped.
+    #[error("The stream's data callback panicked: {message}")]
+    CallbackPanicked { message: String },
+    /// A call to the stream's data callback has not returned within the timeout given by
+    /// `StreamOptions::callback_watchdog`. Reported once per stalled call.
+    #[error("The stream's data callback has not returned for {timeout:?}.")]
+    CallbackStalled { timeout: Duration },
+    /// Another application took the audio focus acquired through `StreamTrait::request_focus`.
+    ///
+    /// The stream keeps running; pause it, or lower its volume if `may_duck` is set. If
+    /// `transient` is set, the other application is expected to release the focus shortly, at
+    /// which point it returns to the stream without being reported.
+    #[error("Another application took the audio focus of the stream.")]
+    FocusLost { transient: bool, may_duck: bool },
+    /// The Bluetooth device of the stream switched profile, see `DeviceTrait::bluetooth_profile`.
+    ///
+    /// The stream keeps running at its configured sample rate, but a switch to
+    /// `BtProfile::HandsFree` degrades playback to narrowband audio.
+    #[error("The Bluetooth device of the stream switched to the {profile:?} profile.")]
+    BluetoothProfileChanged { profile: BtProfile },
     /// See the `BackendSpecificError` docs for more information about this error variant.
     #[error("{err}")]
     BackendSpecific {
@@ -160,3 +376,53 @@ pub enum StreamError {
         err: BackendSpecificError,
     },
 }
+
+// Implements `raw_os_error` for error enums with a `BackendSpecific` variant.
+macro_rules! impl_raw_os_error {
+    ($($err:ident),*) => {
+        $(
+            impl $err {
+                /// The error code returned by the backend, see `BackendSpecificError::code`.
+                pub fn raw_os_error(&self) -> Option<i32> {
+                    match self {
+                        $err::BackendSpecific { err } => err.code,
+                        // Some errors have no other variant.
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_raw_os_error!(
+    DevicesError,
+    DeviceNameError,
+    SupportedStreamConfigsError,
+    DefaultStreamConfigError,
+    BuildStreamError,
+    PlayStreamError,
+    PauseStreamError,
+    RequestFocusError,
+    ThreadRealtimeError,
+    KeepAliveError,
+    DeviceVolumeError,
+    DeviceSampleRateError,
+    WatchDefaultError,
+    WatchDevicesError,
+    StreamError
+);
+
+impl SmokeTestError {
+    /// The error code returned by the backend, see `BackendSpecificError::code`.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            SmokeTestError::DefaultStreamConfig { err } => err.raw_os_error(),
+            SmokeTestError::BuildStream { err } => err.raw_os_error(),
+            SmokeTestError::PlayStream { err } => err.raw_os_error(),
+            SmokeTestError::Stream { err } => err.raw_os_error(),
+            SmokeTestError::NoCallbacks { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Broadcasting the samples captured by one input stream to several receivers, see
+//! [`InputFanout`].
+
+use crate::ring::Ring;
+use crate::traits::DeviceTrait;
+use crate::{BuildStreamError, ChannelCount, StreamConfig, StreamError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The duration of audio held by the ring of each [`InputReceiver`].
+pub const FANOUT_DURATION: Duration = Duration::from_millis(500);
+
+/// What happens to the samples captured for an [`InputReceiver`] whose ring is full.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum FanoutOverflow {
+    /// The frames that do not fit are dropped, keeping the oldest frames in the ring.
+    #[default]
+    DropNewest,
+    /// The oldest frames in the ring are dropped to make room, keeping the latest audio.
+    DropOldest,
+}
+
+/// An input stream whose captured samples are copied to every subscribed [`InputReceiver`], like a
+/// broadcast channel.
+///
+/// Samples are `f32`, interleaved. Each receiver gets a copy of the frames captured while it is
+/// subscribed, through its own lock-free ring holding [`FANOUT_DURATION`] of audio. The rings are
+/// independent: a receiver that is not read fast enough only drops its own frames, according to
+/// its [`FanoutOverflow`], and neither stalls the data callback nor affects the other receivers.
+///
+/// The number of receivers is bounded when building the fanout, so that their rings are allocated
+/// up front and the data callback never allocates or locks.
+///
+/// The stream is paused when built; play it through [`stream`](Self::stream). Dropping the fanout
+/// drops the stream, after which the receivers only return the frames left in their rings.
+pub struct InputFanout<S> {
+    stream: S,
+    shared: Arc<Shared>,
+}
+
+impl<S> InputFanout<S> {
+    /// Builds an input stream on `device` with `config` whose samples go to up to
+    /// `max_receivers` receivers at a time.
+    pub fn new<D, E>(
+        device: &D,
+        config: &StreamConfig,
+        max_receivers: usize,
+        error_callback: E,
+    ) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S> + ?Sized,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let frames = config.sample_rate.frames_in_rounded(FANOUT_DURATION) as usize;
+        let shared = Arc::new(Shared::new(config.channels, frames, max_receivers));
+        let data_shared = shared.clone();
+        let stream = device.build_input_stream::<f32, _, _>(
+            config,
+            move |data, _| data_shared.capture(data),
+            error_callback,
+        )?;
+        Ok(InputFanout { stream, shared })
+    }
+
+    /// Adds a receiver of the frames captured from now on, or returns `None` if there are already
+    /// as many receivers as the fanout was built for.
+    pub fn subscribe(&self, overflow: FanoutOverflow) -> Option<InputReceiver> {
+        Shared::subscribe(&self.shared, overflow)
+    }
+
+    /// The number of receivers currently subscribed.
+    pub fn receivers(&self) -> usize {
+        self.shared
+            .slots
+            .iter()
+            .filter(|slot| slot.state.load(Ordering::Acquire) != FREE)
+            .count()
+    }
+
+    /// The stream capturing the samples.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+/// A subscription to the samples of an [`InputFanout`], see [`InputFanout::subscribe`].
+///
+/// Dropping it unsubscribes, freeing its place for another receiver.
+pub struct InputReceiver {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl InputReceiver {
+    /// The number of channels of the interleaved samples.
+    pub fn channels(&self) -> ChannelCount {
+        self.shared.channels
+    }
+
+    /// The number of samples that can be read, always whole frames.
+    pub fn available(&self) -> usize {
+        self.slot().ring.len()
+    }
+
+    /// Reads as many whole frames as are available and fit into `samples`, returning the number of
+    /// samples read.
+    pub fn read(&mut self, samples: &mut [f32]) -> usize {
+        let channels = self.shared.channels as usize;
+        let len = samples.len() / channels * channels;
+        self.slot().ring.pop(&mut samples[..len])
+    }
+
+    /// The number of frames dropped so far because the ring of this receiver was full.
+    pub fn dropped_frames(&self) -> u64 {
+        self.slot().dropped.load(Ordering::Relaxed)
+    }
+
+    fn slot(&self) -> &Slot {
+        &self.shared.slots[self.index]
+    }
+}
+
+impl Drop for InputReceiver {
+    fn drop(&mut self) {
+        self.slot().state.store(FREE, Ordering::Release);
+    }
+}
+
+// The states of a slot: free for a new receiver, being set up by `subscribe`, and receiving.
+const FREE: u8 = 0;
+const CLAIMED: u8 = 1;
+const ACTIVE: u8 = 2;
+
+// The state shared by the data callback, the fanout and its receivers.
+struct Shared {
+    channels: ChannelCount,
+    slots: Box<[Slot]>,
+    // Incremented when the data callback starts and when it returns, so odd while it runs.
+    callbacks: AtomicUsize,
+}
+
+struct Slot {
+    state: AtomicU8,
+    drop_oldest: AtomicBool,
+    ring: Ring,
+    dropped: AtomicU64,
+}
+
+impl Shared {
+    fn new(channels: ChannelCount, frames: usize, max_receivers: usize) -> Self {
+        let slots = (0..max_receivers)
+            .map(|_| Slot {
+                state: AtomicU8::new(FREE),
+                drop_oldest: AtomicBool::new(false),
+                ring: Ring::new(frames.max(1) * channels as usize),
+                dropped: AtomicU64::new(0),
+            })
+            .collect();
+        Shared {
+            channels,
+            slots,
+            callbacks: AtomicUsize::new(0),
+        }
+    }
+
+    fn subscribe(this: &Arc<Self>, overflow: FanoutOverflow) -> Option<InputReceiver> {
+        let index = this.slots.iter().position(|slot| {
+            slot.state
+                .compare_exchange(FREE, CLAIMED, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        // A data callback that found the slot active before its previous receiver was dropped may
+        // still be writing to the ring.
+        this.wait_for_callback();
+        let slot = &this.slots[index];
+        slot.ring.clear();
+        slot.dropped.store(0, Ordering::Relaxed);
+        slot.drop_oldest
+            .store(overflow == FanoutOverflow::DropOldest, Ordering::Relaxed);
+        slot.state.store(ACTIVE, Ordering::SeqCst);
+        Some(InputReceiver {
+            shared: this.clone(),
+            index,
+        })
+    }
+
+    // Returns once the data callback running when called, if any, has returned.
+    fn wait_for_callback(&self) {
+        let callbacks = self.callbacks.load(Ordering::SeqCst);
+        if callbacks % 2 == 1 {
+            while self.callbacks.load(Ordering::SeqCst) == callbacks {
+                thread::yield_now();
+            }
+        }
+    }
+
+    // Called by the data callback.
+    fn capture(&self, data: &[f32]) {
+        self.callbacks.fetch_add(1, Ordering::SeqCst);
+        let channels = self.channels as usize;
+        for slot in self.slots.iter() {
+            if slot.state.load(Ordering::SeqCst) != ACTIVE {
+                continue;
+            }
+            let dropped = if slot.drop_oldest.load(Ordering::Relaxed) {
+                slot.ring.push_overwrite(data, channels)
+            } else {
+                // Pushing whole frames only keeps the frames in the ring aligned.
+                let len = data.len().min(slot.ring.free() / channels * channels);
+                data.len() - slot.ring.push(&data[..len])
+            };
+            if dropped > 0 {
+                slot.dropped
+                    .fetch_add((dropped / channels) as u64, Ordering::Relaxed);
+            }
+        }
+        self.callbacks.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FanoutOverflow, Shared};
+    use std::sync::Arc;
+
+    // Frames of 2 channels whose samples are the index of the frame.
+    fn frames(range: std::ops::Range<usize>) -> Vec<f32> {
+        range.flat_map(|frame| [frame as f32; 2]).collect()
+    }
+
+    #[test]
+    fn test_receivers_drop_by_their_own_policy() {
+        let shared = Arc::new(Shared::new(2, 4, 2));
+        let mut newest = Shared::subscribe(&shared, FanoutOverflow::DropNewest).unwrap();
+        let mut oldest = Shared::subscribe(&shared, FanoutOverflow::DropOldest).unwrap();
+        assert!(Shared::subscribe(&shared, FanoutOverflow::DropNewest).is_none());
+        shared.capture(&frames(0..3));
+        shared.capture(&frames(3..6));
+
+        let mut samples = [0.0; 16];
+        assert_eq!(newest.read(&mut samples), 8);
+        assert_eq!(samples[..8], frames(0..4));
+        assert_eq!(newest.dropped_frames(), 2);
+        assert_eq!(oldest.read(&mut samples), 8);
+        assert_eq!(samples[..8], frames(2..6));
+        assert_eq!(oldest.dropped_frames(), 2);
+
+        // Only whole frames are read.
+        shared.capture(&frames(6..8));
+        assert_eq!(newest.available(), 4);
+        assert_eq!(newest.read(&mut samples[..3]), 2);
+        assert_eq!(samples[..2], frames(6..7));
+    }
+
+    #[test]
+    fn test_dropped_receiver_frees_its_slot() {
+        let shared = Arc::new(Shared::new(2, 4, 1));
+        let receiver = Shared::subscribe(&shared, FanoutOverflow::DropNewest).unwrap();
+        shared.capture(&frames(0..2));
+        drop(receiver);
+        shared.capture(&frames(2..4));
+
+        let mut receiver = Shared::subscribe(&shared, FanoutOverflow::DropNewest).unwrap();
+        assert_eq!(receiver.available(), 0);
+        shared.capture(&frames(4..5));
+        let mut samples = [0.0; 8];
+        assert_eq!(receiver.read(&mut samples), 2);
+        assert_eq!(samples[..2], frames(4..5));
+        assert_eq!(receiver.dropped_frames(), 0);
+    }
+}
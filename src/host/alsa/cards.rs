@@ -0,0 +1,194 @@
+//! Structured enumeration of the sound cards known to ALSA and of their PCM devices.
+
+use super::alsa;
+use super::alsa_sys;
+use super::libc;
+use super::parking_lot::Mutex;
+use super::{Device, DeviceHandles, Host};
+use crate::DevicesError;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::ptr;
+
+/// ALSA specific extensions to the host.
+pub trait AlsaHostExt {
+    /// The sound cards known to ALSA, along with their PCM devices.
+    ///
+    /// Unlike `HostTrait::devices`, which lists the PCMs defined by the ALSA configuration, this
+    /// describes the hardware, e.g. a card "HDA Intel PCH" with PCM devices "ALC892 Analog" and
+    /// "HDMI 0" to "HDMI 3".
+    fn cards(&self) -> Result<Vec<CardInfo>, DevicesError>;
+
+    /// The device for PCM device `device` of card `card`, as listed by `cards`.
+    ///
+    /// With `subdevice` set to `None` ALSA picks a free subdevice when a stream is opened. The
+    /// device is opened through the `plughw` plugin, which converts to and from the sample
+    /// formats, rates and channel counts the hardware supports.
+    fn pcm_device(
+        &self,
+        card: u32,
+        device: u32,
+        subdevice: Option<u32>,
+    ) -> Result<Device, DevicesError>;
+}
+
+/// A sound card, as listed by `AlsaHostExt::cards`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CardInfo {
+    /// The index of the card, as in `hw:0`.
+    pub index: u32,
+    /// The identifier of the card, as in `hw:CARD=PCH`.
+    pub id: String,
+    pub name: String,
+    pub longname: String,
+    pub pcm_devices: Vec<PcmInfo>,
+}
+
+/// One direction of a PCM device of a sound card.
+///
+/// A PCM device supporting both playback and capture is listed once for each direction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PcmInfo {
+    /// The index of the PCM device on its card, as in `hw:0,3`.
+    pub device: u32,
+    pub subdevice_count: u32,
+    pub name: String,
+    pub direction: PcmDirection,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PcmDirection {
+    Playback,
+    Capture,
+}
+
+impl AlsaHostExt for Host {
+    fn cards(&self) -> Result<Vec<CardInfo>, DevicesError> {
+        let mut cards = Vec::new();
+        for card in alsa::card::Iter::new() {
+            let card = card?;
+            let ctl = alsa::ctl::Ctl::from_card(&card, false)?;
+            let card_info = ctl.card_info()?;
+            cards.push(CardInfo {
+                index: card.get_index() as u32,
+                id: card_info.get_id()?.to_owned(),
+                name: card_info.get_name()?.to_owned(),
+                longname: card_info.get_longname()?.to_owned(),
+                pcm_devices: pcm_devices(card.get_index())?,
+            });
+        }
+        Ok(cards)
+    }
+
+    fn pcm_device(
+        &self,
+        card: u32,
+        device: u32,
+        subdevice: Option<u32>,
+    ) -> Result<Device, DevicesError> {
+        let name = pcm_device_name(card, device, subdevice);
+        let handles = DeviceHandles::open(&name)?;
+        Ok(Device {
+            name,
+            handles: Mutex::new(handles),
+        })
+    }
+}
+
+fn pcm_device_name(card: u32, device: u32, subdevice: Option<u32>) -> String {
+    match subdevice {
+        Some(subdevice) => format!("plughw:{},{},{}", card, device, subdevice),
+        None => format!("plughw:{},{}", card, device),
+    }
+}
+
+fn check(func: &'static str, result: c_int) -> Result<(), alsa::Error> {
+    if result < 0 {
+        Err(alsa::Error::new(func, -result))
+    } else {
+        Ok(())
+    }
+}
+
+// The `alsa` crate does not expose the PCM part of the control interface.
+struct Ctl(*mut alsa_sys::snd_ctl_t);
+
+impl Drop for Ctl {
+    fn drop(&mut self) {
+        unsafe { alsa_sys::snd_ctl_close(self.0) };
+    }
+}
+
+struct PcmInfoPtr(*mut alsa_sys::snd_pcm_info_t);
+
+impl Drop for PcmInfoPtr {
+    fn drop(&mut self) {
+        unsafe { alsa_sys::snd_pcm_info_free(self.0) };
+    }
+}
+
+fn pcm_devices(card: c_int) -> Result<Vec<PcmInfo>, alsa::Error> {
+    let name = CString::new(format!("hw:{}", card)).unwrap();
+    let mut ctl = ptr::null_mut();
+    check("snd_ctl_open", unsafe {
+        alsa_sys::snd_ctl_open(&mut ctl, name.as_ptr(), 0)
+    })?;
+    let ctl = Ctl(ctl);
+    let mut info = ptr::null_mut();
+    check("snd_pcm_info_malloc", unsafe {
+        alsa_sys::snd_pcm_info_malloc(&mut info)
+    })?;
+    let info = PcmInfoPtr(info);
+
+    let mut pcm_devices = Vec::new();
+    let mut device = -1;
+    loop {
+        check("snd_ctl_pcm_next_device", unsafe {
+            alsa_sys::snd_ctl_pcm_next_device(ctl.0, &mut device)
+        })?;
+        if device < 0 {
+            return Ok(pcm_devices);
+        }
+        for &(direction, stream) in &[
+            (PcmDirection::Playback, alsa_sys::SND_PCM_STREAM_PLAYBACK),
+            (PcmDirection::Capture, alsa_sys::SND_PCM_STREAM_CAPTURE),
+        ] {
+            unsafe {
+                alsa_sys::snd_pcm_info_set_device(info.0, device as u32);
+                alsa_sys::snd_pcm_info_set_subdevice(info.0, 0);
+                alsa_sys::snd_pcm_info_set_stream(info.0, stream);
+            }
+            let result = unsafe { alsa_sys::snd_ctl_pcm_info(ctl.0, info.0) };
+            if result < 0 {
+                // `ENOENT` means the device does not support this direction.
+                if result != -libc::ENOENT {
+                    debug!(
+                        card,
+                        device,
+                        err = %alsa::Error::new("snd_ctl_pcm_info", -result),
+                        "skipping PCM device",
+                    );
+                }
+                continue;
+            }
+            let name = unsafe { CStr::from_ptr(alsa_sys::snd_pcm_info_get_name(info.0)) };
+            pcm_devices.push(PcmInfo {
+                device: device as u32,
+                subdevice_count: unsafe { alsa_sys::snd_pcm_info_get_subdevices_count(info.0) },
+                name: name.to_string_lossy().into_owned(),
+                direction,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::pcm_device_name;
+
+    #[test]
+    fn test_pcm_device_name() {
+        assert_eq!(pcm_device_name(1, 3, None), "plughw:1,3");
+        assert_eq!(pcm_device_name(0, 7, Some(2)), "plughw:0,7,2");
+    }
+}
@@ -1,7 +1,7 @@
 use super::alsa;
 use super::parking_lot::Mutex;
 use super::{Device, DeviceHandles};
-use crate::{BackendSpecificError, DevicesError};
+use crate::{BackendSpecificError, DeviceId, DevicesError};
 
 /// ALSA's implementation for `Devices`.
 pub struct Devices {
@@ -46,6 +46,23 @@ impl Iterator for Devices {
     }
 }
 
+/// Lists the PCMs that `Devices` would try to open, without opening them.
+pub fn device_names() -> Result<impl Iterator<Item = (DeviceId, String)>, DevicesError> {
+    let hint_iter = alsa::device_name::HintIter::new_str(None, "pcm")?;
+    Ok(hint_iter
+        .filter_map(|hint| hint.name)
+        .filter(|name| name != "null")
+        .map(|name| (DeviceId::new(name.clone()), name)))
+}
+
+/// The PCM named by `id`. It is only opened once it is used.
+pub fn device_by_id(id: &DeviceId) -> Option<Device> {
+    Some(Device {
+        name: id.as_str().to_owned(),
+        handles: Mutex::new(Default::default()),
+    })
+}
+
 #[inline]
 pub fn default_input_device() -> Option<Device> {
     Some(Device {
@@ -1,28 +1,42 @@
 extern crate alsa;
+extern crate alsa_sys;
 extern crate libc;
 extern crate parking_lot;
 
 use self::alsa::poll::Descriptors;
 use self::parking_lot::Mutex;
-use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
+use super::audio_thread::{AudioThread, DROP_TIMEOUT};
+use super::callback_guard;
+use super::channel_mode;
+use super::fade::{self, Fade};
+use super::keep_alive::{OutputMode, PauseState};
+use super::watchdog;
+use crate::samples_formats::{fill_silence, sign_extend_i24};
+use crate::traits::{check_buffer_size, watchdog_spawn_error, DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, ChannelCount, Data,
-    DefaultStreamConfigError, DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo,
-    PauseStreamError, PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError,
-    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
-    SupportedStreamConfigsError,
+    BackendSpecificError, BtProfile, BufferSize, BuildStreamError, ChannelCount, Data,
+    DefaultStreamConfigError, DeviceDiagnostics, DeviceId, DeviceNameError, DevicesError,
+    ErrorCallback, FrameCount, InputCallback, InputCallbackInfo, KeepAlive, KeepAliveError,
+    OutputCallback, OutputCallbackInfo, PauseStreamError, PlayStreamError, SampleFormat,
+    SampleRate, StreamConfig, StreamDirection, StreamError, StreamOptions, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
 };
-use std::cmp;
 use std::convert::TryInto;
-use std::sync::Arc;
-use std::thread::{self, JoinHandle};
+use std::ffi::CStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::IntoIter as VecIntoIter;
 
-pub use self::enumerate::{default_input_device, default_output_device, Devices};
+pub use self::cards::{AlsaHostExt, CardInfo, PcmDirection, PcmInfo};
+pub use self::enumerate::{
+    default_input_device, default_output_device, device_by_id, device_names, Devices,
+};
 
 pub type SupportedInputConfigs = VecIntoIter<SupportedStreamConfigRange>;
 pub type SupportedOutputConfigs = VecIntoIter<SupportedStreamConfigRange>;
 
+mod cards;
 mod enumerate;
 
 /// The default linux, dragonfly and freebsd host type.
@@ -48,6 +62,19 @@ impl HostTrait for Host {
         Devices::new()
     }
 
+    fn device_names(&self) -> Result<impl Iterator<Item = (DeviceId, String)>, DevicesError> {
+        device_names()
+    }
+
+    fn device_by_id(&self, id: &DeviceId) -> Option<Self::Device> {
+        device_by_id(id)
+    }
+
+    fn backend_version(&self) -> Option<String> {
+        let version = unsafe { CStr::from_ptr(alsa_sys::snd_asoundlib_version()) };
+        Some(format!("alsa-lib {}", version.to_string_lossy()))
+    }
+
     fn default_input_device(&self) -> Option<Self::Device> {
         default_input_device()
     }
@@ -86,6 +113,29 @@ impl DeviceTrait for Device {
         Device::default_output_config(self)
     }
 
+    fn physical_channels(&self, direction: StreamDirection) -> Option<ChannelCount> {
+        Device::physical_channels(self, direction)
+    }
+
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        Device::native_sample_format(self, direction)
+    }
+
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        bluealsa_profile(&self.name)
+    }
+
+    fn is_virtual(&self) -> bool {
+        self.card_driver().as_deref() == Some(LOOPBACK_DRIVER)
+    }
+
+    fn diagnostics(&self) -> DeviceDiagnostics {
+        DeviceDiagnostics {
+            driver: self.card_driver(),
+            ..DeviceDiagnostics::new(self)
+        }
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         conf: &StreamConfig,
@@ -97,6 +147,8 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
         let stream_inner =
             self.build_stream_inner(conf, sample_format, alsa::Direction::Capture)?;
         let stream = Stream::new_input(Arc::new(stream_inner), data_callback, error_callback);
@@ -114,11 +166,101 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         let stream_inner =
             self.build_stream_inner(conf, sample_format, alsa::Direction::Playback)?;
         let stream = Stream::new_output(Arc::new(stream_inner), data_callback, error_callback);
         Ok(stream)
     }
+
+    fn build_input_stream_raw_with_options<D, E>(
+        &self,
+        conf: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let conf = check_buffer_size(self, StreamDirection::Input, conf, options)?;
+        let (conf, data_callback) = channel_mode::apply(
+            self,
+            &conf,
+            sample_format,
+            options.channel_mode,
+            data_callback,
+        )?;
+        let (data_callback, error_callback): (Box<InputCallback>, Box<ErrorCallback>) =
+            match options.callback_watchdog {
+                None => (Box::new(data_callback), Box::new(error_callback)),
+                Some(timeout) => {
+                    let (heartbeat, error_callback) =
+                        watchdog::spawn(timeout, error_callback).map_err(watchdog_spawn_error)?;
+                    let data_callback = watchdog::watch_input(heartbeat, data_callback);
+                    (Box::new(data_callback), Box::new(error_callback))
+                }
+            };
+        if !options.defer_open {
+            return self.build_input_stream_raw(
+                &conf,
+                sample_format,
+                data_callback,
+                error_callback,
+            );
+        }
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
+        let callbacks = Callbacks::Input(Box::new(data_callback), Box::new(error_callback));
+        self.build_deferred(&conf, sample_format, callbacks)
+    }
+
+    fn build_output_stream_raw_with_options<D, E>(
+        &self,
+        conf: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let conf = check_buffer_size(self, StreamDirection::Output, conf, options)?;
+        let (fade, data_callback): (_, Box<OutputCallback>) = match options.fade {
+            None => (None, Box::new(data_callback)),
+            Some(duration) => {
+                let channels = conf.channels as usize;
+                let (fade, data_callback) =
+                    fade::apply(duration, channels, conf.sample_rate, data_callback);
+                (Some(fade), Box::new(data_callback))
+            }
+        };
+        let (data_callback, error_callback): (Box<OutputCallback>, Box<ErrorCallback>) =
+            match options.callback_watchdog {
+                None => (Box::new(data_callback), Box::new(error_callback)),
+                Some(timeout) => {
+                    let (heartbeat, error_callback) =
+                        watchdog::spawn(timeout, error_callback).map_err(watchdog_spawn_error)?;
+                    let data_callback = watchdog::watch_output(heartbeat, data_callback);
+                    (Box::new(data_callback), Box::new(error_callback))
+                }
+            };
+        let mut stream = if !options.defer_open {
+            self.build_output_stream_raw(&conf, sample_format, data_callback, error_callback)?
+        } else {
+            let (data_callback, error_callback) =
+                callback_guard::guard_output(data_callback, error_callback);
+            let callbacks = Callbacks::Output(Box::new(data_callback), Box::new(error_callback));
+            self.build_deferred(&conf, sample_format, callbacks)?
+        };
+        stream.fade = fade;
+        Ok(stream)
+    }
 }
 
 struct TriggerSender(libc::c_int);
@@ -221,6 +363,14 @@ impl DeviceHandles {
     fn take(&mut self, name: &str, stream_type: alsa::Direction) -> Result<alsa::PCM, alsa::Error> {
         Ok(self.try_open(name, stream_type)?.take().unwrap())
     }
+
+    /// Close the `alsa::PCM` handle for a specific `stream_type`, if it is open.
+    fn close(&mut self, stream_type: alsa::Direction) {
+        match stream_type {
+            alsa::Direction::Playback => self.playback = None,
+            alsa::Direction::Capture => self.capture = None,
+        }
+    }
 }
 
 pub struct Device {
@@ -242,7 +392,10 @@ impl Device {
             .map_err(|e| (e, e.errno()));
 
         let handle = match handle_result {
-            Err((_, nix::errno::Errno::EBUSY)) => return Err(BuildStreamError::DeviceNotAvailable),
+            Err((_, nix::errno::Errno::EBUSY)) => {
+                debug!(device = %self.name, "device is busy");
+                return Err(BuildStreamError::DeviceNotAvailable);
+            }
             Err((_, nix::errno::Errno::EINVAL)) => return Err(BuildStreamError::InvalidArgument),
             Err((e, _)) => return Err(e.into()),
             Ok(handle) => handle,
@@ -250,12 +403,29 @@ impl Device {
         let can_pause = set_hw_params_from_format(&handle, conf, sample_format)?;
         let period_len = set_sw_params_from_format(&handle, conf, stream_type)?;
 
+        // The hardware may have picked the nearest supported rate rather than the requested one.
+        let sample_rate = SampleRate(handle.hw_params_current()?.get_rate()?);
+        if sample_rate != conf.sample_rate {
+            warn!(
+                requested = conf.sample_rate.0,
+                negotiated = sample_rate.0,
+                "device did not accept the requested sample rate",
+            );
+        }
+        let conf = StreamConfig {
+            sample_rate,
+            ..conf.clone()
+        };
+
         handle.prepare()?;
 
         let num_descriptors = handle.count();
         if num_descriptors == 0 {
             let description = "poll descriptor count for stream was 0".to_string();
-            let err = BackendSpecificError { description };
+            let err = BackendSpecificError {
+                description,
+                code: None,
+            };
             return Err(err.into());
         }
 
@@ -275,15 +445,61 @@ impl Device {
             channel: handle,
             sample_format,
             num_descriptors,
-            conf: conf.clone(),
+            conf,
             period_len,
             can_pause,
             creation_instant,
+            output_pause: match stream_type {
+                alsa::Direction::Playback => Some(PauseState::new(true)),
+                alsa::Direction::Capture => None,
+            },
         };
 
         Ok(stream_inner)
     }
 
+    // Builds a stream for `StreamOptions::defer_open`, which opens the device when played.
+    fn build_deferred(
+        &self,
+        conf: &StreamConfig,
+        sample_format: SampleFormat,
+        callbacks: Callbacks,
+    ) -> Result<Stream, BuildStreamError> {
+        let direction = callbacks.direction();
+        let supported = match direction {
+            alsa::Direction::Capture => {
+                DeviceTrait::supported_configs(self, StreamDirection::Input)
+            }
+            alsa::Direction::Playback => {
+                DeviceTrait::supported_configs(self, StreamDirection::Output)
+            }
+        };
+        let supported = supported.map_err(|err| match err {
+            SupportedStreamConfigsError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+            SupportedStreamConfigsError::InvalidArgument => BuildStreamError::InvalidArgument,
+            SupportedStreamConfigsError::BackendSpecific { err } => err.into(),
+        })?;
+        if !supported.contains(conf, sample_format) {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        // Leave the device to others until the stream is played.
+        self.handles.lock().close(direction);
+        let (returned, callbacks_rx) = mpsc::channel();
+        returned.send(callbacks).expect("the receiver is alive");
+        Ok(Stream {
+            running: Mutex::new(None),
+            deferred: Some(DeferredOpen {
+                device: self.name.clone(),
+                direction,
+                callbacks: Mutex::new(callbacks_rx),
+                returned,
+            }),
+            fade: None,
+            conf: conf.clone(),
+            sample_format,
+        })
+    }
+
     #[inline]
     fn name(&self) -> Result<String, DeviceNameError> {
         Ok(self.name.clone())
@@ -312,14 +528,14 @@ impl Device {
         let hw_params = alsa::pcm::HwParams::any(handle)?;
 
         // TODO: check endianness
-        const FORMATS: [(SampleFormat, alsa::pcm::Format); 3] = [
+        const FORMATS: [(SampleFormat, alsa::pcm::Format); 5] = [
             //SND_PCM_FORMAT_S8,
             //SND_PCM_FORMAT_U8,
             (SampleFormat::I16, alsa::pcm::Format::S16LE),
             //SND_PCM_FORMAT_S16_BE,
             (SampleFormat::U16, alsa::pcm::Format::U16LE),
             //SND_PCM_FORMAT_U16_BE,
-            //SND_PCM_FORMAT_S24_LE,
+            (SampleFormat::I24, alsa::pcm::Format::S24LE),
             //SND_PCM_FORMAT_S24_BE,
             //SND_PCM_FORMAT_U24_LE,
             //SND_PCM_FORMAT_U24_BE,
@@ -339,7 +555,7 @@ impl Device {
             //SND_PCM_FORMAT_MPEG,
             //SND_PCM_FORMAT_GSM,
             //SND_PCM_FORMAT_SPECIAL,
-            //SND_PCM_FORMAT_S24_3LE,
+            (SampleFormat::I24Packed, alsa::pcm::Format::S243LE),
             //SND_PCM_FORMAT_S24_3BE,
             //SND_PCM_FORMAT_U24_3LE,
             //SND_PCM_FORMAT_U24_3BE,
@@ -385,19 +601,29 @@ impl Device {
             }
         };
 
-        let min_channels = hw_params.get_channels_min()?;
-        let max_channels = hw_params.get_channels_max()?;
-
-        let max_channels = cmp::min(max_channels, 32); // TODO: limiting to 32 channels or too much stuff is returned
-        let supported_channels = (min_channels..max_channels + 1)
-            .filter_map(|num| {
-                if hw_params.test_channels(num).is_ok() {
-                    Some(num as ChannelCount)
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+        // Converting plugins accept any channel count, so the hardware's is looked up to list it.
+        let hardware_channels = if hw_params.get_channels_max()? > MAX_HARDWARE_CHANNELS {
+            hardware_channels(&self.name, handle, stream_t)
+        } else {
+            None
+        };
+        let all_channels = supported_channels(&hw_params, hardware_channels)?;
+
+        // The available channel counts may depend on the sample rate, e.g. ADAT interfaces halve
+        // their channel count at 88.2 and 96 kHz. When the device reports a discrete set of rates,
+        // each rate is probed separately so that only valid combinations are returned. This costs
+        // one `test_channels` call per channel count for every supported rate.
+        let mut rate_channels = Vec::with_capacity(sample_rates.len());
+        for &(min_rate, max_rate) in sample_rates.iter() {
+            let channels = if min_rate == max_rate && sample_rates.len() > 1 {
+                let rate_params = hw_params.clone();
+                rate_params.set_rate(min_rate, alsa::ValueOr::Nearest)?;
+                supported_channels(&rate_params, hardware_channels)?
+            } else {
+                all_channels.clone()
+            };
+            rate_channels.push((min_rate, max_rate, channels));
+        }
 
         let min_buffer_size = hw_params.get_buffer_size_min()?;
         let max_buffer_size = hw_params.get_buffer_size_max()?;
@@ -407,16 +633,18 @@ impl Device {
             max: max_buffer_size as u32,
         };
 
-        let mut output = Vec::with_capacity(
-            supported_formats.len() * supported_channels.len() * sample_rates.len(),
-        );
+        let mut output =
+            Vec::with_capacity(supported_formats.len() * all_channels.len() * sample_rates.len());
         for &sample_format in supported_formats.iter() {
-            for &channels in supported_channels.iter() {
-                for &(min_rate, max_rate) in sample_rates.iter() {
+            for &channels in all_channels.iter() {
+                for (min_rate, max_rate, _) in rate_channels
+                    .iter()
+                    .filter(|(_, _, rate_channels)| rate_channels.contains(&channels))
+                {
                     output.push(SupportedStreamConfigRange {
                         channels,
-                        min_sample_rate: SampleRate(min_rate as u32),
-                        max_sample_rate: SampleRate(max_rate as u32),
+                        min_sample_rate: SampleRate(*min_rate as u32),
+                        max_sample_rate: SampleRate(*max_rate as u32),
                         buffer_size: buffer_size_range.clone(),
                         sample_format,
                     });
@@ -471,6 +699,10 @@ impl Device {
                 let mut format = f.with_max_sample_rate();
                 const HZ_44100: SampleRate = SampleRate(44_100);
                 if min_r <= HZ_44100 && HZ_44100 <= max_r {
+                    debug!(
+                        max_sample_rate = max_r.0,
+                        "device supports 44100 Hz, preferring it over the maximum rate",
+                    );
                     format.sample_rate = HZ_44100;
                 }
                 Ok(format)
@@ -486,6 +718,86 @@ impl Device {
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
         self.default_config(alsa::Direction::Playback)
     }
+
+    // The channel count of a hardware PCM is what the card offers, whereas plugins such as `plug`
+    // or `dmix` may convert to any count. For the latter, the hardware PCM they write to is opened
+    // separately, which fails while another client, possibly this device, holds it exclusively.
+    fn physical_channels(&self, direction: StreamDirection) -> Option<ChannelCount> {
+        let stream_t = match direction {
+            StreamDirection::Input => alsa::Direction::Capture,
+            StreamDirection::Output => alsa::Direction::Playback,
+        };
+        let mut guard = self.handles.lock();
+        let handle = guard.get_mut(&self.name, stream_t).ok()?;
+        hardware_channels(&self.name, handle, stream_t)?
+            .try_into()
+            .ok()
+    }
+
+    // Plugins such as `plug` convert to any format, so this is the format of the hardware PCM
+    // behind them, as with `physical_channels`.
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let stream_t = match direction {
+            StreamDirection::Input => alsa::Direction::Capture,
+            StreamDirection::Output => alsa::Direction::Playback,
+        };
+        let mut guard = self.handles.lock();
+        let handle = guard.get_mut(&self.name, stream_t).ok()?;
+        with_hardware_pcm(&self.name, handle, stream_t, |pcm| {
+            let hw_params = alsa::pcm::HwParams::any(pcm).ok()?;
+            // Widest first, so that streams of the reported format lose no resolution.
+            [
+                SampleFormat::I24,
+                SampleFormat::I24Packed,
+                SampleFormat::F32,
+                SampleFormat::I16,
+                SampleFormat::U16,
+            ]
+            .iter()
+            .copied()
+            .find(|&format| hw_params.test_format(alsa_format(format)).is_ok())
+        })
+    }
+
+    // The kernel driver of the card the device is on, if any. Plugins not backed by a card, e.g.
+    // routing to a sound server, have none.
+    fn card_driver(&self) -> Option<String> {
+        let mut guard = self.handles.lock();
+        let card = [alsa::Direction::Playback, alsa::Direction::Capture]
+            .iter()
+            .find_map(|&stream_t| {
+                let handle = guard.get_mut(&self.name, stream_t).ok()?;
+                Some(handle.info().ok()?.get_card())
+            })?;
+        if card < 0 {
+            return None;
+        }
+        let ctl = alsa::ctl::Ctl::from_card(&alsa::card::Card::new(card), false).ok()?;
+        let card_info = ctl.card_info().ok()?;
+        card_info.get_driver().ok().map(str::to_owned)
+    }
+}
+
+// The driver of the `snd-aloop` card, whose PCMs loop what is played back to capture.
+const LOOPBACK_DRIVER: &str = "Loopback";
+
+// BlueALSA devices select the profile with an argument, e.g. `bluealsa:DEV=<address>,PROFILE=sco`.
+// Without one, BlueALSA defaults to A2DP.
+fn bluealsa_profile(name: &str) -> Option<BtProfile> {
+    let args = match name.strip_prefix("bluealsa") {
+        Some("") => return Some(BtProfile::A2dp),
+        Some(args) => args.strip_prefix(':')?,
+        None => return None,
+    };
+    let profile = args
+        .split(',')
+        .find_map(|arg| arg.strip_prefix("PROFILE="))
+        .unwrap_or("a2dp");
+    match profile.to_ascii_lowercase().as_str() {
+        "a2dp" => Some(BtProfile::A2dp),
+        "sco" => Some(BtProfile::HandsFree),
+        _ => Some(BtProfile::Unknown),
+    }
 }
 
 struct StreamInner {
@@ -519,6 +831,9 @@ struct StreamInner {
     // If this field is `None` then the elapsed duration between `get_trigger_htstamp` and
     // `get_htstamp` is used.
     creation_instant: Option<std::time::Instant>,
+
+    // Pause handling for output streams, which support `KeepAlive`. `None` for input streams.
+    output_pause: Option<PauseState>,
 }
 
 // Assume that the ALSA library is built with thread safe option.
@@ -531,9 +846,23 @@ enum StreamType {
 }
 
 pub struct Stream {
+    /// The open stream, which deferred streams only have while playing.
+    running: Mutex<Option<RunningStream>>,
+
+    /// How to open the device of a stream built with `StreamOptions::defer_open`.
+    deferred: Option<DeferredOpen>,
+
+    /// The ramp of an output stream built with `StreamOptions::fade`.
+    fade: Option<Arc<Fade>>,
+
+    /// The requested config, which the device may have adjusted when opened.
+    conf: StreamConfig,
+    sample_format: SampleFormat,
+}
+
+struct RunningStream {
     /// The high-priority audio processing thread calling callbacks.
-    /// Option used for moving out in destructor.
-    thread: Option<JoinHandle<()>>,
+    thread: AudioThread,
 
     /// Handle to the underlying stream for playback controls.
     inner: Arc<StreamInner>,
@@ -542,10 +871,36 @@ pub struct Stream {
     trigger: TriggerSender,
 }
 
+// The callbacks of a deferred stream, moved into the worker thread of each device open.
+enum Callbacks {
+    Input(Box<InputCallback>, Box<ErrorCallback>),
+    Output(Box<OutputCallback>, Box<ErrorCallback>),
+}
+
+impl Callbacks {
+    fn direction(&self) -> alsa::Direction {
+        match self {
+            Callbacks::Input(..) => alsa::Direction::Capture,
+            Callbacks::Output(..) => alsa::Direction::Playback,
+        }
+    }
+}
+
+struct DeferredOpen {
+    device: String,
+    direction: alsa::Direction,
+    // The callbacks while the device is closed. A worker takes them when the device is opened and
+    // sends them back through `returned` when it stops.
+    callbacks: Mutex<mpsc::Receiver<Callbacks>>,
+    returned: mpsc::Sender<Callbacks>,
+}
+
 #[derive(Default)]
 struct StreamWorkerContext {
     descriptors: Vec<libc::pollfd>,
     buffer: Vec<u8>,
+    // The number of frames read or written so far, which serves as the device clock.
+    frames_transferred: u64,
 }
 
 fn input_stream_worker(
@@ -567,6 +922,7 @@ fn input_stream_worker(
                 continue;
             }
             PollDescriptorsFlow::XRun => {
+                warn!("xrun, re-preparing the stream");
                 if let Err(err) = stream.channel.prepare() {
                     error_callback(err.into());
                 }
@@ -584,13 +940,9 @@ fn input_stream_worker(
                     StreamType::Input,
                     "expected input stream, but polling descriptors indicated output",
                 );
-                if let Err(err) = process_input(
-                    stream,
-                    &mut ctxt.buffer,
-                    status,
-                    delay_frames,
-                    data_callback,
-                ) {
+                if let Err(err) =
+                    process_input(stream, &mut ctxt, status, delay_frames, data_callback)
+                {
                     error_callback(err.into());
                 }
             }
@@ -615,6 +967,7 @@ fn output_stream_worker(
         match flow {
             PollDescriptorsFlow::Continue => continue,
             PollDescriptorsFlow::XRun => {
+                warn!("xrun, re-preparing the stream");
                 if let Err(err) = stream.channel.prepare() {
                     error_callback(err.into());
                 }
@@ -634,7 +987,7 @@ fn output_stream_worker(
                 );
                 if let Err(err) = process_output(
                     stream,
-                    &mut ctxt.buffer,
+                    &mut ctxt,
                     status,
                     avail_frames,
                     delay_frames,
@@ -669,6 +1022,7 @@ fn poll_descriptors_and_prepare_buffer(
     let StreamWorkerContext {
         ref mut descriptors,
         ref mut buffer,
+        ..
     } = *ctxt;
 
     descriptors.clear();
@@ -697,7 +1051,10 @@ fn poll_descriptors_and_prepare_buffer(
     let res = alsa::poll::poll(descriptors, -1)?;
     if res == 0 {
         let description = String::from("`alsa::poll()` spuriously returned");
-        return Err(BackendSpecificError { description });
+        return Err(BackendSpecificError {
+            description,
+            code: None,
+        });
     }
 
     if descriptors[0].revents != 0 {
@@ -749,13 +1106,27 @@ fn poll_descriptors_and_prepare_buffer(
 // Read input data from ALSA and deliver it to the user.
 fn process_input(
     stream: &StreamInner,
-    buffer: &mut [u8],
+    ctxt: &mut StreamWorkerContext,
     status: alsa::pcm::Status,
     delay_frames: usize,
     data_callback: &mut (dyn FnMut(&Data, &InputCallbackInfo) + Send + 'static),
 ) -> Result<(), BackendSpecificError> {
-    stream.channel.io_bytes().readi(buffer)?;
+    let StreamWorkerContext {
+        ref mut buffer,
+        ref mut frames_transferred,
+        ..
+    } = *ctxt;
+    let frames_read = stream.channel.io_bytes().readi(buffer)?;
+    let device_timestamp = Some(crate::DeviceTimestamp {
+        samples: *frames_transferred,
+        sample_rate: stream.conf.sample_rate,
+    });
+    *frames_transferred += frames_read as u64;
     let sample_format = stream.sample_format;
+    // `S24_LE` leaves the upper byte of each sample unspecified.
+    if sample_format == SampleFormat::I24 {
+        sign_extend_i24(buffer);
+    }
     let data = buffer.as_mut_ptr() as *mut ();
     let len = buffer.len() / sample_format.sample_size();
     let data = unsafe { Data::from_parts(data, len, sample_format) };
@@ -765,7 +1136,11 @@ fn process_input(
         .sub(delay_duration)
         .expect("`capture` is earlier than representation supported by `StreamInstant`");
     let timestamp = crate::InputStreamTimestamp { callback, capture };
-    let info = crate::InputCallbackInfo { timestamp };
+    let info = crate::InputCallbackInfo {
+        timestamp,
+        device_timestamp,
+        channel_mix: crate::ChannelMix::None,
+    };
     data_callback(&data, &info);
 
     Ok(())
@@ -776,14 +1151,25 @@ fn process_input(
 // Returns `true`
 fn process_output(
     stream: &StreamInner,
-    buffer: &mut [u8],
+    ctxt: &mut StreamWorkerContext,
     status: alsa::pcm::Status,
     available_frames: usize,
     delay_frames: usize,
     data_callback: &mut (dyn FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static),
     error_callback: &mut dyn FnMut(StreamError),
 ) -> Result<(), BackendSpecificError> {
-    {
+    let StreamWorkerContext {
+        ref mut buffer,
+        ref mut frames_transferred,
+        ..
+    } = *ctxt;
+    let paused = stream
+        .output_pause
+        .as_ref()
+        .is_some_and(|state| state.output_mode() != OutputMode::Callback);
+    if paused {
+        fill_silence(buffer, stream.sample_format);
+    } else {
         // We're now sure that we're ready to write data.
         let sample_format = stream.sample_format;
         let data = buffer.as_mut_ptr() as *mut ();
@@ -795,7 +1181,14 @@ fn process_output(
             .add(delay_duration)
             .expect("`playback` occurs beyond representation supported by `StreamInstant`");
         let timestamp = crate::OutputStreamTimestamp { callback, playback };
-        let info = crate::OutputCallbackInfo { timestamp };
+        let device_timestamp = Some(crate::DeviceTimestamp {
+            samples: *frames_transferred,
+            sample_rate: stream.conf.sample_rate,
+        });
+        let info = crate::OutputCallbackInfo {
+            timestamp,
+            device_timestamp,
+        };
         data_callback(&mut data, &info);
     }
     loop {
@@ -803,6 +1196,7 @@ fn process_output(
             Err(err) if err.errno() == nix::errno::Errno::EPIPE => {
                 // buffer underrun
                 // TODO: Notify the user of this.
+                warn!("underrun while writing, recovering the stream");
                 let _ = stream.channel.try_recover(err, false);
             }
             Err(err) => {
@@ -815,10 +1209,17 @@ fn process_output(
                      result {} (this should never happen)",
                     available_frames, result,
                 );
-                error_callback(BackendSpecificError { description }.into());
+                error_callback(
+                    BackendSpecificError {
+                        description,
+                        code: None,
+                    }
+                    .into(),
+                );
                 continue;
             }
             _ => {
+                *frames_transferred += available_frames as u64;
                 break;
             }
         }
@@ -876,104 +1277,419 @@ fn frames_to_duration(frames: usize, rate: crate::SampleRate) -> std::time::Dura
     std::time::Duration::new(secs, nanos)
 }
 
-impl Stream {
-    fn new_input<D, E>(
-        inner: Arc<StreamInner>,
-        mut data_callback: D,
-        mut error_callback: E,
-    ) -> Stream
+impl RunningStream {
+    // Starts the worker thread `name`, which runs `worker` until triggered to stop.
+    fn spawn<W>(inner: Arc<StreamInner>, name: &str, worker: W) -> RunningStream
     where
-        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
-        E: FnMut(StreamError) + Send + 'static,
+        W: FnOnce(TriggerReceiver, &StreamInner) + Send + 'static,
     {
         let (tx, rx) = trigger();
         // Clone the handle for passing into worker thread.
         let stream = inner.clone();
-        let thread = thread::Builder::new()
-            .name("cpal_alsa_in".to_owned())
-            .spawn(move || {
-                input_stream_worker(rx, &*stream, &mut data_callback, &mut error_callback);
-            })
-            .unwrap();
-        Stream {
-            thread: Some(thread),
+        let thread = AudioThread::spawn(thread::Builder::new().name(name.to_owned()), move || {
+            worker(rx, &stream)
+        })
+        .unwrap();
+        RunningStream {
+            thread,
             inner,
             trigger: tx,
         }
     }
 
+    fn new_input<D, E>(
+        inner: Arc<StreamInner>,
+        mut data_callback: D,
+        mut error_callback: E,
+    ) -> RunningStream
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        RunningStream::spawn(inner, "cpal_alsa_in", move |rx, stream| {
+            input_stream_worker(rx, stream, &mut data_callback, &mut error_callback);
+        })
+    }
+
     fn new_output<D, E>(
         inner: Arc<StreamInner>,
         mut data_callback: D,
         mut error_callback: E,
-    ) -> Stream
+    ) -> RunningStream
     where
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
-        let (tx, rx) = trigger();
-        // Clone the handle for passing into worker thread.
-        let stream = inner.clone();
-        let thread = thread::Builder::new()
-            .name("cpal_alsa_out".to_owned())
-            .spawn(move || {
-                output_stream_worker(rx, &*stream, &mut data_callback, &mut error_callback);
-            })
-            .unwrap();
-        Stream {
-            thread: Some(thread),
+        RunningStream::spawn(inner, "cpal_alsa_out", move |rx, stream| {
+            output_stream_worker(rx, stream, &mut data_callback, &mut error_callback);
+        })
+    }
+
+    fn stop(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        self.trigger.wakeup();
+        self.thread.join_timeout(timeout)
+    }
+}
+
+impl DeferredOpen {
+    // Opens the device and starts a worker calling the callbacks, paused if it is for output.
+    fn open(
+        &self,
+        conf: &StreamConfig,
+        sample_format: SampleFormat,
+    ) -> Result<RunningStream, BuildStreamError> {
+        let device = Device {
+            name: self.device.clone(),
+            handles: Mutex::new(Default::default()),
+        };
+        let inner = device.build_stream_inner(conf, sample_format, self.direction)?;
+        let inner = Arc::new(inner);
+        // The previous worker still has the callbacks if stopping it timed out.
+        let callbacks = self.callbacks.lock().try_recv().map_err(|_| {
+            let description = format!(
+                "the data callback did not return within {:?} of pausing the stream",
+                DROP_TIMEOUT
+            );
+            BackendSpecificError {
+                description,
+                code: None,
+            }
+        })?;
+        let returned = self.returned.clone();
+        let running = match callbacks {
+            Callbacks::Input(mut data_callback, mut error_callback) => {
+                RunningStream::spawn(inner, "cpal_alsa_in", move |rx, stream| {
+                    input_stream_worker(rx, stream, &mut *data_callback, &mut *error_callback);
+                    let _ = returned.send(Callbacks::Input(data_callback, error_callback));
+                })
+            }
+            Callbacks::Output(mut data_callback, mut error_callback) => {
+                RunningStream::spawn(inner, "cpal_alsa_out", move |rx, stream| {
+                    output_stream_worker(rx, stream, &mut *data_callback, &mut *error_callback);
+                    let _ = returned.send(Callbacks::Output(data_callback, error_callback));
+                })
+            }
+        };
+        Ok(running)
+    }
+}
+
+impl Stream {
+    fn new_input<D, E>(inner: Arc<StreamInner>, data_callback: D, error_callback: E) -> Stream
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        Stream::from_running(RunningStream::new_input(
             inner,
-            trigger: tx,
+            data_callback,
+            error_callback,
+        ))
+    }
+
+    fn new_output<D, E>(inner: Arc<StreamInner>, data_callback: D, error_callback: E) -> Stream
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        Stream::from_running(RunningStream::new_output(
+            inner,
+            data_callback,
+            error_callback,
+        ))
+    }
+
+    fn from_running(running: RunningStream) -> Stream {
+        Stream {
+            conf: running.inner.conf.clone(),
+            sample_format: running.inner.sample_format,
+            running: Mutex::new(Some(running)),
+            deferred: None,
+            fade: None,
+        }
+    }
+
+    fn stop(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        match self.running.get_mut().take() {
+            Some(mut running) => running.stop(timeout),
+            None => Ok(()),
+        }
+    }
+
+    // Fades a playing stream built with `StreamOptions::fade` out, then waits for the device to
+    // play the frames already written, which stopping the stream would cut off.
+    fn fade_out(&mut self, timeout: Duration) {
+        let fade = match self.fade {
+            Some(ref fade) => fade,
+            None => return,
+        };
+        let inner = match *self.running.get_mut() {
+            Some(ref running) => &running.inner,
+            None => return,
+        };
+        let playing = inner
+            .output_pause
+            .as_ref()
+            .is_some_and(|state| state.output_mode() == OutputMode::Callback);
+        let started = Instant::now();
+        if !playing || !fade.fade_out(timeout) {
+            return;
         }
+        let delay = inner.channel.delay().unwrap_or(0).max(0) as usize;
+        let remaining = timeout.saturating_sub(started.elapsed());
+        thread::sleep(frames_to_duration(delay, inner.conf.sample_rate).min(remaining));
+    }
+}
+
+// The error of pausing a deferred stream, whose worker failed to stop with `err`.
+fn deferred_pause_error(err: StreamError) -> PauseStreamError {
+    match err {
+        StreamError::DeviceNotAvailable => PauseStreamError::DeviceNotAvailable,
+        StreamError::CloseTimedOut => {
+            let description = format!(
+                "the data callback did not return within {:?} of pausing the stream; the device \
+                 is closed once it returns",
+                DROP_TIMEOUT
+            );
+            BackendSpecificError {
+                description,
+                code: None,
+            }
+            .into()
+        }
+        StreamError::BackendSpecific { err } => err.into(),
+        err => BackendSpecificError {
+            description: err.to_string(),
+            code: None,
+        }
+        .into(),
     }
 }
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        self.trigger.wakeup();
-        self.thread.take().unwrap().join().unwrap();
+        let _span = debug_span!("drop_stream");
+        // `drop` has no way of reporting a timeout; callers who care use `close`.
+        let _ = self.stop(DROP_TIMEOUT);
     }
 }
 
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
-        self.inner.channel.pause(false).ok();
-        Ok(())
+        let mut running = self.running.lock();
+        if running.is_none() {
+            // Only deferred streams are ever without a running stream.
+            if let Some(ref deferred) = self.deferred {
+                let opened =
+                    deferred
+                        .open(&self.conf, self.sample_format)
+                        .map_err(|err| match err {
+                            BuildStreamError::DeviceNotAvailable => {
+                                PlayStreamError::DeviceNotAvailable
+                            }
+                            BuildStreamError::BackendSpecific { err } => err.into(),
+                            err => BackendSpecificError {
+                                description: err.to_string(),
+                                code: None,
+                            }
+                            .into(),
+                        })?;
+                *running = Some(opened);
+            }
+        }
+        let inner = match *running {
+            Some(ref running) => &running.inner,
+            None => return Ok(()),
+        };
+        let resume = || {
+            inner.channel.pause(false).ok();
+            Ok(())
+        };
+        match inner.output_pause {
+            Some(ref state) => state.play(resume),
+            None => resume(),
+        }
     }
     fn pause(&self) -> Result<(), PauseStreamError> {
-        self.inner.channel.pause(true).ok();
+        let mut running = self.running.lock();
+        if self.deferred.is_some() {
+            // Closes the device, which is opened again when next played.
+            if let Some(mut running) = running.take() {
+                running.stop(DROP_TIMEOUT).map_err(deferred_pause_error)?;
+            }
+        } else {
+            let inner = match *running {
+                Some(ref running) => &running.inner,
+                None => return Ok(()),
+            };
+            let pause = || {
+                inner.channel.pause(true).ok();
+                Ok::<_, PauseStreamError>(())
+            };
+            match inner.output_pause {
+                Some(ref state) => state.pause(pause)?,
+                None => pause()?,
+            }
+        }
+        // Fade in again when next played.
+        if let Some(ref fade) = self.fade {
+            fade.rearm();
+        }
         Ok(())
     }
+    fn sample_rate(&self) -> SampleRate {
+        match *self.running.lock() {
+            Some(ref running) => running.inner.conf.sample_rate,
+            None => self.conf.sample_rate,
+        }
+    }
+    fn channels(&self) -> ChannelCount {
+        self.conf.channels
+    }
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    fn set_keep_alive(&self, keep_alive: KeepAlive) -> Result<(), KeepAliveError> {
+        if self.deferred.is_some() {
+            return match keep_alive {
+                KeepAlive::Off => Ok(()),
+                _ => Err(KeepAliveError::NotSupported),
+            };
+        }
+        let running = self.running.lock();
+        let inner = match *running {
+            Some(ref running) => &running.inner,
+            None => return Err(KeepAliveError::NotSupported),
+        };
+        match (&inner.output_pause, keep_alive) {
+            (None, KeepAlive::Off) => Ok(()),
+            (Some(state), KeepAlive::Off) | (Some(state), KeepAlive::Silence) => state
+                .set_keep_alive(keep_alive, || {
+                    inner.channel.pause(true).ok();
+                    Ok(())
+                }),
+            _ => Err(KeepAliveError::NotSupported),
+        }
+    }
+
+    fn close(mut self, timeout: Duration) -> Result<(), StreamError> {
+        let started = Instant::now();
+        self.fade_out(timeout);
+        self.stop(timeout.saturating_sub(started.elapsed()))
+    }
 }
 
-fn set_hw_params_from_format(
-    pcm_handle: &alsa::pcm::PCM,
-    config: &StreamConfig,
-    sample_format: SampleFormat,
-) -> Result<bool, BackendSpecificError> {
-    let hw_params = alsa::pcm::HwParams::any(pcm_handle)?;
-    hw_params.set_access(alsa::pcm::Access::RWInterleaved)?;
+// The channel counts allowed by the given hardware parameters.
+// Devices accepting more channels than this are taken to be converting plugins such as `plug`,
+// which accept thousands of channels.
+const MAX_HARDWARE_CHANNELS: u32 = 256;
+// The channel counts listed for converting plugins, in addition to those of the hardware behind.
+const MAX_PLUGIN_CHANNELS: u32 = 32;
+
+// The channel counts worth testing for a device accepting `min..=max` channels, backed by hardware
+// with `hardware_channels` channels if known.
+fn channel_candidates(
+    min: u32,
+    max: u32,
+    hardware_channels: Option<u32>,
+) -> impl Iterator<Item = u32> {
+    let probed_max = if max > MAX_HARDWARE_CHANNELS {
+        MAX_PLUGIN_CHANNELS
+    } else {
+        max
+    };
+    let hardware_channels =
+        hardware_channels.filter(|&channels| channels > probed_max && channels <= max);
+    (min..=probed_max).chain(hardware_channels)
+}
+
+fn supported_channels(
+    hw_params: &alsa::pcm::HwParams,
+    hardware_channels: Option<u32>,
+) -> Result<Vec<ChannelCount>, alsa::Error> {
+    let min_channels = hw_params.get_channels_min()?;
+    let max_channels = hw_params.get_channels_max()?;
+    let supported_channels = channel_candidates(min_channels, max_channels, hardware_channels)
+        .filter(|&num| hw_params.test_channels(num).is_ok())
+        .filter_map(|num| ChannelCount::try_from(num).ok())
+        .collect::<Vec<_>>();
+    Ok(supported_channels)
+}
+
+// The maximum channel count of the hardware PCM that `pcm` is, or is a plugin for.
+fn hardware_channels(name: &str, pcm: &alsa::PCM, stream_t: alsa::Direction) -> Option<u32> {
+    with_hardware_pcm(name, pcm, stream_t, |pcm| {
+        alsa::pcm::HwParams::any(pcm)
+            .and_then(|hw_params| hw_params.get_channels_max())
+            .ok()
+    })
+}
+
+// Calls `f` with the hardware PCM that `pcm` is, or is a plugin for. Plugins not backed by a card,
+// e.g. routing to a sound server, have none.
+fn with_hardware_pcm<T, F>(
+    name: &str,
+    pcm: &alsa::PCM,
+    stream_t: alsa::Direction,
+    f: F,
+) -> Option<T>
+where
+    F: FnOnce(&alsa::PCM) -> Option<T>,
+{
+    if name.starts_with("hw:") {
+        f(pcm)
+    } else {
+        let info = pcm.info().ok()?;
+        if info.get_card() < 0 {
+            return None;
+        }
+        let hw_name = format!("hw:{},{}", info.get_card(), info.get_device());
+        let hw_pcm = alsa::PCM::new(&hw_name, stream_t, true).ok()?;
+        f(&hw_pcm)
+    }
+}
 
-    let sample_format = if cfg!(target_endian = "big") {
+// The ALSA format of a sample format, in native byte order.
+fn alsa_format(sample_format: SampleFormat) -> alsa::pcm::Format {
+    if cfg!(target_endian = "big") {
         match sample_format {
             SampleFormat::I16 => alsa::pcm::Format::S16BE,
             SampleFormat::U16 => alsa::pcm::Format::U16BE,
+            SampleFormat::I24 => alsa::pcm::Format::S24BE,
+            SampleFormat::I24Packed => alsa::pcm::Format::S243BE,
             SampleFormat::F32 => alsa::pcm::Format::FloatBE,
         }
     } else {
         match sample_format {
             SampleFormat::I16 => alsa::pcm::Format::S16LE,
             SampleFormat::U16 => alsa::pcm::Format::U16LE,
+            SampleFormat::I24 => alsa::pcm::Format::S24LE,
+            SampleFormat::I24Packed => alsa::pcm::Format::S243LE,
             SampleFormat::F32 => alsa::pcm::Format::FloatLE,
         }
-    };
+    }
+}
 
-    hw_params.set_format(sample_format)?;
+fn set_hw_params_from_format(
+    pcm_handle: &alsa::pcm::PCM,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+) -> Result<bool, BuildStreamError> {
+    let hw_params = alsa::pcm::HwParams::any(pcm_handle)?;
+    hw_params.set_access(alsa::pcm::Access::RWInterleaved)?;
+
+    hw_params.set_format(alsa_format(sample_format))?;
     hw_params.set_rate(config.sample_rate.0, alsa::ValueOr::Nearest)?;
     hw_params.set_channels(config.channels as u32)?;
 
     match config.buffer_size {
         BufferSize::Fixed(v) => {
+            let buffer_size_range = SupportedBufferSize::Range {
+                min: hw_params.get_buffer_size_min()? as FrameCount,
+                max: hw_params.get_buffer_size_max()? as FrameCount,
+            };
+            buffer_size_range.check(&config.buffer_size)?;
             hw_params.set_period_size_near((v / 4) as alsa::pcm::Frames, alsa::ValueOr::Nearest)?;
             hw_params.set_buffer_size(v as alsa::pcm::Frames)?;
         }
@@ -1002,6 +1718,7 @@ fn set_sw_params_from_format(
         if buffer == 0 {
             return Err(BackendSpecificError {
                 description: "initialization resulted in a null buffer".to_string(),
+                code: None,
             });
         }
         sw_params.set_avail_min(period as alsa::pcm::Frames)?;
@@ -1036,8 +1753,10 @@ fn set_sw_params_from_format(
 
 impl From<alsa::Error> for BackendSpecificError {
     fn from(err: alsa::Error) -> Self {
+        debug!(errno = err.errno() as i32, %err, "ALSA call failed");
         BackendSpecificError {
             description: err.to_string(),
+            code: Some(err.errno() as i32),
         }
     }
 }
@@ -1076,3 +1795,131 @@ impl From<alsa::Error> for StreamError {
         err.into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{bluealsa_profile, channel_candidates, deferred_pause_error, Device};
+    use crate::traits::{DeviceTrait, StreamTrait};
+    use crate::{
+        BtProfile, BufferSize, Data, OutputCallbackInfo, PauseStreamError, SampleFormat,
+        SampleRate, StreamConfig, StreamError, StreamOptions,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Waits until `calls` exceeds `count`, failing the test after a second.
+    fn wait_for_calls(calls: &AtomicUsize, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while calls.load(Ordering::SeqCst) <= count {
+            assert!(
+                Instant::now() < deadline,
+                "the data callback was not called"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_deferred_stream_opens_while_playing() {
+        // The `null` plugin of alsa-lib accepts any config and consumes the frames written to it.
+        let device = Device {
+            name: "null".to_string(),
+            handles: Default::default(),
+        };
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            buffer_size: BufferSize::Fixed(256),
+        };
+        let options = StreamOptions {
+            defer_open: true,
+            ..StreamOptions::default()
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stream = {
+            let calls = calls.clone();
+            device
+                .build_output_stream_raw_with_options(
+                    &config,
+                    SampleFormat::F32,
+                    move |_: &mut Data, _: &OutputCallbackInfo| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                    },
+                    |err| panic!("{}", err),
+                    &options,
+                )
+                .unwrap()
+        };
+        assert!(stream.running.lock().is_none());
+
+        stream.play().unwrap();
+        assert!(stream.running.lock().is_some());
+        wait_for_calls(&calls, 0);
+
+        stream.pause().unwrap();
+        assert!(stream.running.lock().is_none());
+        let paused = calls.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(calls.load(Ordering::SeqCst), paused);
+
+        // Playing again opens the device again, with the same callbacks.
+        stream.play().unwrap();
+        assert!(stream.running.lock().is_some());
+        wait_for_calls(&calls, paused);
+        stream.close(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn test_deferred_pause_error() {
+        let err = deferred_pause_error(StreamError::CloseTimedOut);
+        let PauseStreamError::BackendSpecific { err } = err else {
+            panic!("unexpected error: {:?}", err);
+        };
+        assert!(err.description.contains("did not return"));
+        assert!(matches!(
+            deferred_pause_error(StreamError::DeviceNotAvailable),
+            PauseStreamError::DeviceNotAvailable
+        ));
+    }
+
+    #[test]
+    fn test_bluealsa_profile() {
+        assert_eq!(bluealsa_profile("default"), None);
+        assert_eq!(bluealsa_profile("bluealsaX"), None);
+        assert_eq!(bluealsa_profile("bluealsa"), Some(BtProfile::A2dp));
+        assert_eq!(
+            bluealsa_profile("bluealsa:DEV=00:11:22:33:44:55"),
+            Some(BtProfile::A2dp)
+        );
+        assert_eq!(
+            bluealsa_profile("bluealsa:DEV=00:11:22:33:44:55,PROFILE=sco"),
+            Some(BtProfile::HandsFree)
+        );
+        assert_eq!(
+            bluealsa_profile("bluealsa:PROFILE=a2dp"),
+            Some(BtProfile::A2dp)
+        );
+        assert_eq!(
+            bluealsa_profile("bluealsa:PROFILE=other"),
+            Some(BtProfile::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_channel_candidates() {
+        let candidates =
+            |min, max, hardware| channel_candidates(min, max, hardware).collect::<Vec<_>>();
+        // Hardware with a fixed count above what converting plugins list.
+        assert_eq!(candidates(64, 64, None), vec![64]);
+        let multichannel: Vec<u32> = (1..=64).collect();
+        assert_eq!(candidates(1, 64, None), multichannel);
+        // A `plug` device in front of 32 and 64 channel hardware.
+        let plugin: Vec<u32> = (1..=32).collect();
+        assert_eq!(candidates(1, 10000, Some(32)), plugin);
+        let plugin_and_hardware: Vec<u32> = (1..=32).chain(Some(64)).collect();
+        assert_eq!(candidates(1, 10000, Some(64)), plugin_and_hardware);
+        assert_eq!(candidates(1, 10000, None), plugin);
+    }
+}
@@ -4,14 +4,19 @@ pub type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>
 
 use super::parking_lot::Mutex;
 use super::sys;
+use crate::host::config_cache::ConfigCache;
+use crate::traits::DeviceTrait;
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Once};
 use BackendSpecificError;
+use BuildStreamError;
 use DefaultStreamConfigError;
 use DeviceNameError;
 use DevicesError;
 use SampleFormat;
 use SampleRate;
+use StreamDirection;
 use SupportedBufferSize;
 use SupportedStreamConfig;
 use SupportedStreamConfigRange;
@@ -59,34 +64,11 @@ impl Device {
     pub fn supported_input_configs(
         &self,
     ) -> Result<SupportedInputConfigs, SupportedStreamConfigsError> {
-        // Retrieve the default config for the total supported channels and supported sample
-        // format.
-        let mut f = match self.default_input_config() {
-            Err(_) => return Err(SupportedStreamConfigsError::DeviceNotAvailable),
-            Ok(f) => f,
-        };
-
-        // Collect a config for every combination of supported sample rate and number of channels.
         let mut supported_configs = vec![];
-        for &rate in ::COMMON_SAMPLE_RATES {
-            if !self
-                .driver
-                .can_sample_rate(rate.0.into())
-                .ok()
-                .unwrap_or(false)
-            {
-                continue;
-            }
-            for channels in 1..f.channels + 1 {
-                supported_configs.push(SupportedStreamConfigRange {
-                    channels,
-                    min_sample_rate: rate,
-                    max_sample_rate: rate,
-                    buffer_size: f.buffer_size.clone(),
-                    sample_format: f.sample_format.clone(),
-                })
-            }
-        }
+        self.supported_input_configs_incremental(|config| {
+            supported_configs.push(config);
+            ControlFlow::Continue(())
+        })?;
         Ok(supported_configs.into_iter())
     }
 
@@ -96,15 +78,83 @@ impl Device {
     pub fn supported_output_configs(
         &self,
     ) -> Result<SupportedOutputConfigs, SupportedStreamConfigsError> {
-        // Retrieve the default config for the total supported channels and supported sample
-        // format.
-        let mut f = match self.default_output_config() {
-            Err(_) => return Err(SupportedStreamConfigsError::DeviceNotAvailable),
-            Ok(f) => f,
-        };
-
-        // Collect a config for every combination of supported sample rate and number of channels.
         let mut supported_configs = vec![];
+        self.supported_output_configs_incremental(|config| {
+            supported_configs.push(config);
+            ControlFlow::Continue(())
+        })?;
+        Ok(supported_configs.into_iter())
+    }
+
+    /// Passes the supported input configs to `callback` as each sample rate is probed.
+    pub fn supported_input_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
+        self.cached_configs(StreamDirection::Input, callback, |callback| {
+            // Retrieve the default config for the total supported channels and supported sample
+            // format.
+            let f = match self.default_input_config() {
+                Err(_) => return Err(SupportedStreamConfigsError::DeviceNotAvailable),
+                Ok(f) => f,
+            };
+            self.probe_sample_rates(&f, callback);
+            Ok(())
+        })
+    }
+
+    /// Passes the supported output configs to `callback` as each sample rate is probed.
+    pub fn supported_output_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
+        self.cached_configs(StreamDirection::Output, callback, |callback| {
+            // Retrieve the default config for the total supported channels and supported sample
+            // format.
+            let f = match self.default_output_config() {
+                Err(_) => return Err(SupportedStreamConfigsError::DeviceNotAvailable),
+                Ok(f) => f,
+            };
+            self.probe_sample_rates(&f, callback);
+            Ok(())
+        })
+    }
+
+    // Passes the configs found by `probe` to `callback`, or the ones it found before if the
+    // driver has not requested to be reset since, as it does when its settings change.
+    fn cached_configs<F, P>(
+        &self,
+        direction: StreamDirection,
+        callback: F,
+        probe: P,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        P: FnOnce(
+            &mut dyn FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        ) -> Result<(), SupportedStreamConfigsError>,
+    {
+        // The reset callbacks outlive the driver they are added with, and are not told which
+        // driver requested the reset, so one callback clears the configs of every driver.
+        static WATCH_RESETS: Once = Once::new();
+        WATCH_RESETS.call_once(|| {
+            self.driver.add_reset_callback(|| CONFIGS.clear_all());
+        });
+        CONFIGS.supported_configs(self.id().ok(), direction, callback, probe)
+    }
+
+    // Yields a config for every combination of supported sample rate and number of channels, up
+    // to the channels of the default config `f`.
+    fn probe_sample_rates<F>(&self, f: &SupportedStreamConfig, mut callback: F)
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
         for &rate in ::COMMON_SAMPLE_RATES {
             if !self
                 .driver
@@ -115,16 +165,24 @@ impl Device {
                 continue;
             }
             for channels in 1..f.channels + 1 {
-                supported_configs.push(SupportedStreamConfigRange {
+                let config = SupportedStreamConfigRange {
                     channels,
                     min_sample_rate: rate,
                     max_sample_rate: rate,
                     buffer_size: f.buffer_size.clone(),
-                    sample_format: f.sample_format.clone(),
-                })
+                    sample_format: f.sample_format,
+                };
+                if let ControlFlow::Break(()) = callback(config) {
+                    return;
+                }
             }
         }
-        Ok(supported_configs.into_iter())
+    }
+
+    /// Returns the buffer size the driver prefers, which is used with `BufferSize::Default`.
+    pub fn preferred_buffer_size(&self) -> Result<u32, DefaultStreamConfigError> {
+        let buffer_sizes = self.driver.buffer_sizes().map_err(default_config_err)?;
+        Ok(buffer_sizes.pref as u32)
     }
 
     /// Returns the default input config
@@ -167,8 +225,20 @@ impl Device {
             sample_format,
         })
     }
+
+    /// The sample format of the driver's channels, if CPAL can use it without conversion.
+    pub fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let data_type = match direction {
+            StreamDirection::Input => self.driver.input_data_type(),
+            StreamDirection::Output => self.driver.output_data_type(),
+        };
+        native_data_type(&data_type.ok()?)
+    }
 }
 
+// The configs of the drivers, found by asking them about each of the common sample rates.
+static CONFIGS: ConfigCache = ConfigCache::new();
+
 impl Devices {
     pub fn new(asio: Arc<sys::Asio>) -> Result<Self, DevicesError> {
         let drivers = asio.driver_names().into_iter();
@@ -203,10 +273,26 @@ impl Iterator for Devices {
     }
 }
 
+// The sample format that is exactly `ty`, unlike `convert_data_type` which also maps the types
+// that the stream callback converts.
+fn native_data_type(ty: &sys::AsioSampleType) -> Option<SampleFormat> {
+    match *ty {
+        sys::AsioSampleType::ASIOSTInt16LSB => Some(SampleFormat::I16),
+        sys::AsioSampleType::ASIOSTInt24LSB => Some(SampleFormat::I24Packed),
+        sys::AsioSampleType::ASIOSTInt32LSB24 => Some(SampleFormat::I24),
+        sys::AsioSampleType::ASIOSTFloat32LSB => Some(SampleFormat::F32),
+        _ => None,
+    }
+}
+
 pub(crate) fn convert_data_type(ty: &sys::AsioSampleType) -> Option<SampleFormat> {
     let fmt = match *ty {
         sys::AsioSampleType::ASIOSTInt16MSB => SampleFormat::I16,
         sys::AsioSampleType::ASIOSTInt16LSB => SampleFormat::I16,
+        sys::AsioSampleType::ASIOSTInt24MSB => SampleFormat::I24Packed,
+        sys::AsioSampleType::ASIOSTInt24LSB => SampleFormat::I24Packed,
+        sys::AsioSampleType::ASIOSTInt32MSB24 => SampleFormat::I24,
+        sys::AsioSampleType::ASIOSTInt32LSB24 => SampleFormat::I24,
         sys::AsioSampleType::ASIOSTFloat32MSB => SampleFormat::F32,
         sys::AsioSampleType::ASIOSTFloat32LSB => SampleFormat::F32,
         // NOTE: While ASIO does not support these formats directly, the stream callback created by
@@ -216,11 +302,37 @@ pub(crate) fn convert_data_type(ty: &sys::AsioSampleType) -> Option<SampleFormat
         // format.
         sys::AsioSampleType::ASIOSTInt32MSB => SampleFormat::I16,
         sys::AsioSampleType::ASIOSTInt32LSB => SampleFormat::I16,
+        // Other formats with more than 16 valid bits are converted to `F32` by default so that the
+        // additional resolution is not thrown away.
+        sys::AsioSampleType::ASIOSTInt32MSB16
+        | sys::AsioSampleType::ASIOSTInt32MSB18
+        | sys::AsioSampleType::ASIOSTInt32MSB20
+        | sys::AsioSampleType::ASIOSTInt32LSB16
+        | sys::AsioSampleType::ASIOSTInt32LSB18
+        | sys::AsioSampleType::ASIOSTInt32LSB20
+        | sys::AsioSampleType::ASIOSTFloat64MSB
+        | sys::AsioSampleType::ASIOSTFloat64LSB => SampleFormat::F32,
         _ => return None,
     };
     Some(fmt)
 }
 
+/// Whether or not the stream callback can convert between the given ASIO sample type and the
+/// given CPAL sample format.
+pub(crate) fn can_convert_data_type(ty: &sys::AsioSampleType, sample_format: SampleFormat) -> bool {
+    convert_data_type(ty).is_some() && sample_format != SampleFormat::U16
+}
+
+/// The error returned when a driver uses a sample type that CPAL cannot convert.
+pub(crate) fn unsupported_data_type_err(ty: &sys::AsioSampleType) -> BuildStreamError {
+    let description = format!("the ASIO sample type {:?} is not supported by CPAL", ty);
+    BackendSpecificError {
+        description,
+        code: None,
+    }
+    .into()
+}
+
 fn default_config_err(e: sys::AsioError) -> DefaultStreamConfigError {
     match e {
         sys::AsioError::NoDrivers | sys::AsioError::HardwareMalfunction => {
@@ -229,7 +341,8 @@ fn default_config_err(e: sys::AsioError) -> DefaultStreamConfigError {
         sys::AsioError::NoRate => DefaultStreamConfigError::StreamTypeNotSupported,
         err => {
             let description = format!("{}", err);
-            BackendSpecificError { description }.into()
+            let code = err.code();
+            BackendSpecificError { description, code }.into()
         }
     }
 }
@@ -1,20 +1,33 @@
 extern crate asio_sys as sys;
 extern crate parking_lot;
 
+use crate::host::callback_guard;
 use crate::{
-    BuildStreamError, Data, DefaultStreamConfigError, DeviceNameError, DevicesError,
-    InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError, SampleFormat,
-    StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigsError,
+    BuildStreamError, ChannelCount, Data, DefaultStreamConfigError, DeviceDiagnostics,
+    DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
+    PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamDirection, StreamError,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
 };
 use traits::{DeviceTrait, HostTrait, StreamTrait};
 
 pub use self::device::{Device, Devices, SupportedInputConfigs, SupportedOutputConfigs};
 pub use self::stream::Stream;
+use std::ops::ControlFlow;
 use std::sync::Arc;
 
 mod device;
 mod stream;
 
+/// ASIO specific extensions to the device.
+pub trait DeviceExt {
+    /// The buffer size, in frames, that the driver prefers. Streams built with
+    /// `BufferSize::Default` use it.
+    ///
+    /// Many drivers perform badly, or refuse to open a stream, at any other size, so applications
+    /// letting the user pick a buffer size should pre-select this one.
+    fn preferred_buffer_size(&self) -> Result<u32, DefaultStreamConfigError>;
+}
+
 /// The host for ASIO.
 #[derive(Debug)]
 pub struct Host {
@@ -62,6 +75,14 @@ impl DeviceTrait for Device {
         Device::name(self)
     }
 
+    fn diagnostics(&self) -> DeviceDiagnostics {
+        DeviceDiagnostics {
+            driver: Some(self.driver.name().to_string()),
+            driver_version: Some(self.driver.version().to_string()),
+            ..DeviceDiagnostics::new(self)
+        }
+    }
+
     fn supported_input_configs(
         &self,
     ) -> Result<Self::SupportedInputConfigs, SupportedStreamConfigsError> {
@@ -74,6 +95,26 @@ impl DeviceTrait for Device {
         Device::supported_output_configs(self)
     }
 
+    fn supported_input_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        Device::supported_input_configs_incremental(self, callback)
+    }
+
+    fn supported_output_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        Device::supported_output_configs_incremental(self, callback)
+    }
+
     fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
         Device::default_input_config(self)
     }
@@ -82,6 +123,10 @@ impl DeviceTrait for Device {
         Device::default_output_config(self)
     }
 
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        Device::native_sample_format(self, direction)
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         config: &StreamConfig,
@@ -93,6 +138,8 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
         Device::build_input_stream_raw(self, config, sample_format, data_callback, error_callback)
     }
 
@@ -107,10 +154,18 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         Device::build_output_stream_raw(self, config, sample_format, data_callback, error_callback)
     }
 }
 
+impl DeviceExt for Device {
+    fn preferred_buffer_size(&self) -> Result<u32, DefaultStreamConfigError> {
+        Device::preferred_buffer_size(self)
+    }
+}
+
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
         Stream::play(self)
@@ -119,4 +174,16 @@ impl StreamTrait for Stream {
     fn pause(&self) -> Result<(), PauseStreamError> {
         Stream::pause(self)
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        Stream::sample_rate(self)
+    }
+
+    fn channels(&self) -> ChannelCount {
+        Stream::channels(self)
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        Stream::sample_format(self)
+    }
 }
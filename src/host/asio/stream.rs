@@ -1,13 +1,11 @@
 extern crate asio_sys as sys;
-extern crate num_traits;
 
-use self::num_traits::PrimInt;
 use super::parking_lot::Mutex;
 use super::Device;
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, Data, InputCallbackInfo,
-    OutputCallbackInfo, PauseStreamError, PlayStreamError, Sample, SampleFormat, StreamConfig,
-    StreamError,
+    BackendSpecificError, BufferSize, BuildStreamError, ChannelCount, ChannelMix, Data, FrameCount,
+    InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError, Sample, SampleFormat,
+    SampleRate, StreamConfig, StreamError, SupportedBufferSize,
 };
 use std;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -24,6 +22,34 @@ trait AsioSample: Clone + Copy + Silence + std::ops::Add<Self, Output = Self> {
     fn from_cpal_sample<T: Sample>(_: &T) -> Self;
 }
 
+/// Byte order conversions for the ASIO sample types.
+trait Endianness: Sized {
+    fn from_le(x: Self) -> Self;
+    fn from_be(x: Self) -> Self;
+    fn to_le(self) -> Self {
+        Self::from_le(self)
+    }
+    fn to_be(self) -> Self {
+        Self::from_be(self)
+    }
+}
+
+/// A signed integer sample right-aligned within a 32-bit container, as used by the
+/// `ASIOSTInt32*16`, `ASIOSTInt32*18`, `ASIOSTInt32*20` and `ASIOSTInt32*24` sample types.
+///
+/// `BITS` is the number of valid bits. The remaining high bits hold the sign extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+struct I32Aligned<const BITS: u32>(i32);
+
+/// A packed 24-bit signed integer sample, as used by the `ASIOSTInt24*` sample types.
+///
+/// The bytes are always stored in little-endian order. Use `Endianness` to convert to and from
+/// the byte order of the driver's buffers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+struct I24Packed([u8; 3]);
+
 // Used to keep track of whether or not the current asio stream buffer requires
 // being silencing before summing audio.
 #[derive(Default)]
@@ -38,6 +64,9 @@ pub struct Stream {
     driver: Arc<sys::Driver>,
     asio_streams: Arc<Mutex<sys::AsioStreams>>,
     callback_id: sys::CallbackId,
+    // The configuration and sample format with which the stream was created.
+    config: StreamConfig,
+    sample_format: SampleFormat,
 }
 
 impl Stream {
@@ -50,6 +79,18 @@ impl Stream {
         self.playing.store(false, Ordering::SeqCst);
         Ok(())
     }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate
+    }
+
+    pub fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
 }
 
 impl Device {
@@ -67,9 +108,10 @@ impl Device {
         let stream_type = self.driver.input_data_type().map_err(build_stream_err)?;
 
         // Ensure that the desired sample type is supported.
-        let expected_sample_format = super::device::convert_data_type(&stream_type)
-            .ok_or(BuildStreamError::StreamConfigNotSupported)?;
-        if sample_format != expected_sample_format {
+        if super::device::convert_data_type(&stream_type).is_none() {
+            return Err(super::device::unsupported_data_type_err(&stream_type));
+        }
+        if !super::device::can_convert_data_type(&stream_type, sample_format) {
             return Err(BuildStreamError::StreamConfigNotSupported);
         }
 
@@ -87,6 +129,7 @@ impl Device {
 
         // Set the input callback.
         // This is most performance critical part of the ASIO bindings.
+        let stream_config = config.clone();
         let config = config.clone();
         let callback_id = self.driver.add_callback(move |callback_info| unsafe {
             // If not playing return early.
@@ -138,87 +181,90 @@ impl Device {
                     .sub(delay)
                     .expect("`capture` occurs before origin of alsa `StreamInstant`");
                 let timestamp = crate::InputStreamTimestamp { callback, capture };
-                let info = InputCallbackInfo { timestamp };
+                // The buffer was captured over the period leading up to the buffer switch.
+                let position = asio_samples_to_u64(asio_info.sample_position);
+                let device_timestamp = Some(crate::DeviceTimestamp {
+                    samples: position.saturating_sub(n_frames as u64),
+                    sample_rate,
+                });
+                let info = InputCallbackInfo {
+                    timestamp,
+                    device_timestamp,
+                    channel_mix: ChannelMix::None,
+                };
                 data_callback(&data, &info);
             }
 
-            match (&stream_type, sample_format) {
-                (&sys::AsioSampleType::ASIOSTInt16LSB, SampleFormat::I16) => {
-                    process_input_callback::<i16, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        from_le,
-                    );
-                }
-                (&sys::AsioSampleType::ASIOSTInt16MSB, SampleFormat::I16) => {
-                    process_input_callback::<i16, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        from_be,
-                    );
-                }
-
-                // TODO: Handle endianness conversion for floats? We currently use the `PrimInt`
-                // trait for the `to_le` and `to_be` methods, but this does not support floats.
-                (&sys::AsioSampleType::ASIOSTFloat32LSB, SampleFormat::F32)
-                | (&sys::AsioSampleType::ASIOSTFloat32MSB, SampleFormat::F32) => {
-                    process_input_callback::<f32, f32, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        std::convert::identity::<f32>,
-                    );
-                }
-
-                // TODO: Add support for the following sample formats to CPAL and simplify the
-                // `process_output_callback` function above by removing the unnecessary sample
-                // conversion function.
-                (&sys::AsioSampleType::ASIOSTInt32LSB, SampleFormat::I16) => {
-                    process_input_callback::<i32, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        from_le,
-                    );
-                }
-                (&sys::AsioSampleType::ASIOSTInt32MSB, SampleFormat::I16) => {
-                    process_input_callback::<i32, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        from_be,
-                    );
-                }
-                // TODO: Handle endianness conversion for floats? We currently use the `PrimInt`
-                // trait for the `to_le` and `to_be` methods, but this does not support floats.
-                (&sys::AsioSampleType::ASIOSTFloat64LSB, SampleFormat::F32)
-                | (&sys::AsioSampleType::ASIOSTFloat64MSB, SampleFormat::F32) => {
-                    process_input_callback::<f64, f32, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        std::convert::identity::<f64>,
-                    );
-                }
+            // Deliver the driver's sample type `$A` as the sample format requested by the user.
+            macro_rules! process_input {
+                ($A:ty, $from_endianness:expr) => {
+                    match sample_format {
+                        SampleFormat::I16 => process_input_callback::<$A, i16, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $from_endianness,
+                        ),
+                        SampleFormat::F32 => process_input_callback::<$A, f32, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $from_endianness,
+                        ),
+                        SampleFormat::I24 => process_input_callback::<$A, crate::I24, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $from_endianness,
+                        ),
+                        SampleFormat::I24Packed => {
+                            process_input_callback::<$A, crate::I24Packed, _, _>(
+                                &mut data_callback,
+                                &mut interleaved,
+                                asio_stream,
+                                callback_info,
+                                config.sample_rate,
+                                $from_endianness,
+                            )
+                        }
+                        SampleFormat::U16 => unreachable!(
+                            "`build_input_stream_raw` should have returned with unsupported \
+                             format {:?}",
+                            sample_format
+                        ),
+                    }
+                };
+            }
 
-                unsupported_format_pair => unreachable!(
+            match &stream_type {
+                sys::AsioSampleType::ASIOSTInt16LSB => process_input!(i16, from_le),
+                sys::AsioSampleType::ASIOSTInt16MSB => process_input!(i16, from_be),
+                sys::AsioSampleType::ASIOSTInt24LSB => process_input!(I24Packed, from_le),
+                sys::AsioSampleType::ASIOSTInt24MSB => process_input!(I24Packed, from_be),
+                sys::AsioSampleType::ASIOSTInt32LSB => process_input!(i32, from_le),
+                sys::AsioSampleType::ASIOSTInt32MSB => process_input!(i32, from_be),
+                sys::AsioSampleType::ASIOSTInt32LSB16 => process_input!(I32Aligned<16>, from_le),
+                sys::AsioSampleType::ASIOSTInt32MSB16 => process_input!(I32Aligned<16>, from_be),
+                sys::AsioSampleType::ASIOSTInt32LSB18 => process_input!(I32Aligned<18>, from_le),
+                sys::AsioSampleType::ASIOSTInt32MSB18 => process_input!(I32Aligned<18>, from_be),
+                sys::AsioSampleType::ASIOSTInt32LSB20 => process_input!(I32Aligned<20>, from_le),
+                sys::AsioSampleType::ASIOSTInt32MSB20 => process_input!(I32Aligned<20>, from_be),
+                sys::AsioSampleType::ASIOSTInt32LSB24 => process_input!(I32Aligned<24>, from_le),
+                sys::AsioSampleType::ASIOSTInt32MSB24 => process_input!(I32Aligned<24>, from_be),
+                sys::AsioSampleType::ASIOSTFloat32LSB => process_input!(f32, from_le),
+                sys::AsioSampleType::ASIOSTFloat32MSB => process_input!(f32, from_be),
+                sys::AsioSampleType::ASIOSTFloat64LSB => process_input!(f64, from_le),
+                sys::AsioSampleType::ASIOSTFloat64MSB => process_input!(f64, from_be),
+                unsupported_format => unreachable!(
                     "`build_input_stream_raw` should have returned with unsupported \
                      format {:?}",
-                    unsupported_format_pair
+                    unsupported_format
                 ),
             }
         });
@@ -234,6 +280,8 @@ impl Device {
             driver,
             asio_streams,
             callback_id,
+            config: stream_config,
+            sample_format,
         })
     }
 
@@ -251,9 +299,10 @@ impl Device {
         let stream_type = self.driver.output_data_type().map_err(build_stream_err)?;
 
         // Ensure that the desired sample type is supported.
-        let expected_sample_format = super::device::convert_data_type(&stream_type)
-            .ok_or(BuildStreamError::StreamConfigNotSupported)?;
-        if sample_format != expected_sample_format {
+        if super::device::convert_data_type(&stream_type).is_none() {
+            return Err(super::device::unsupported_data_type_err(&stream_type));
+        }
+        if !super::device::can_convert_data_type(&stream_type, sample_format) {
             return Err(BuildStreamError::StreamConfigNotSupported);
         }
 
@@ -270,6 +319,7 @@ impl Device {
         let playing = Arc::clone(&stream_playing);
         let asio_streams = self.asio_streams.clone();
 
+        let stream_config = config.clone();
         let config = config.clone();
         let callback_id = self.driver.add_callback(move |callback_info| unsafe {
             // If not playing, return early.
@@ -336,7 +386,15 @@ impl Device {
                     .add(delay)
                     .expect("`playback` occurs beyond representation supported by `StreamInstant`");
                 let timestamp = crate::OutputStreamTimestamp { callback, playback };
-                let info = OutputCallbackInfo { timestamp };
+                let position = asio_samples_to_u64(asio_info.sample_position);
+                let device_timestamp = Some(crate::DeviceTimestamp {
+                    samples: position + n_frames as u64,
+                    sample_rate,
+                });
+                let info = OutputCallbackInfo {
+                    timestamp,
+                    device_timestamp,
+                };
                 data_callback(&mut data, &info);
 
                 // 2. Silence ASIO channels if necessary.
@@ -362,89 +420,80 @@ impl Device {
                 }
             }
 
-            match (sample_format, &stream_type) {
-                (SampleFormat::I16, &sys::AsioSampleType::ASIOSTInt16LSB) => {
-                    process_output_callback::<i16, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        to_le,
-                    );
-                }
-                (SampleFormat::I16, &sys::AsioSampleType::ASIOSTInt16MSB) => {
-                    process_output_callback::<i16, i16, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        to_be,
-                    );
-                }
-
-                // TODO: Handle endianness conversion for floats? We currently use the `PrimInt`
-                // trait for the `to_le` and `to_be` methods, but this does not support floats.
-                (SampleFormat::F32, &sys::AsioSampleType::ASIOSTFloat32LSB)
-                | (SampleFormat::F32, &sys::AsioSampleType::ASIOSTFloat32MSB) => {
-                    process_output_callback::<f32, f32, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        std::convert::identity::<f32>,
-                    );
-                }
-
-                // TODO: Add support for the following sample formats to CPAL and simplify the
-                // `process_output_callback` function above by removing the unnecessary sample
-                // conversion function.
-                (SampleFormat::I16, &sys::AsioSampleType::ASIOSTInt32LSB) => {
-                    process_output_callback::<i16, i32, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        to_le,
-                    );
-                }
-                (SampleFormat::I16, &sys::AsioSampleType::ASIOSTInt32MSB) => {
-                    process_output_callback::<i16, i32, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        to_be,
-                    );
-                }
-                // TODO: Handle endianness conversion for floats? We currently use the `PrimInt`
-                // trait for the `to_le` and `to_be` methods, but this does not support floats.
-                (SampleFormat::F32, &sys::AsioSampleType::ASIOSTFloat64LSB)
-                | (SampleFormat::F32, &sys::AsioSampleType::ASIOSTFloat64MSB) => {
-                    process_output_callback::<f32, f64, _, _>(
-                        &mut data_callback,
-                        &mut interleaved,
-                        silence,
-                        asio_stream,
-                        callback_info,
-                        config.sample_rate,
-                        std::convert::identity::<f64>,
-                    );
-                }
+            // Render the sample format requested by the user into the driver's sample type `$B`.
+            macro_rules! process_output {
+                ($B:ty, $to_endianness:expr) => {
+                    match sample_format {
+                        SampleFormat::I16 => process_output_callback::<i16, $B, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            silence,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $to_endianness,
+                        ),
+                        SampleFormat::F32 => process_output_callback::<f32, $B, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            silence,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $to_endianness,
+                        ),
+                        SampleFormat::I24 => process_output_callback::<crate::I24, $B, _, _>(
+                            &mut data_callback,
+                            &mut interleaved,
+                            silence,
+                            asio_stream,
+                            callback_info,
+                            config.sample_rate,
+                            $to_endianness,
+                        ),
+                        SampleFormat::I24Packed => {
+                            process_output_callback::<crate::I24Packed, $B, _, _>(
+                                &mut data_callback,
+                                &mut interleaved,
+                                silence,
+                                asio_stream,
+                                callback_info,
+                                config.sample_rate,
+                                $to_endianness,
+                            )
+                        }
+                        SampleFormat::U16 => unreachable!(
+                            "`build_output_stream_raw` should have returned with unsupported \
+                             format {:?}",
+                            sample_format
+                        ),
+                    }
+                };
+            }
 
-                unsupported_format_pair => unreachable!(
+            match &stream_type {
+                sys::AsioSampleType::ASIOSTInt16LSB => process_output!(i16, to_le),
+                sys::AsioSampleType::ASIOSTInt16MSB => process_output!(i16, to_be),
+                sys::AsioSampleType::ASIOSTInt24LSB => process_output!(I24Packed, to_le),
+                sys::AsioSampleType::ASIOSTInt24MSB => process_output!(I24Packed, to_be),
+                sys::AsioSampleType::ASIOSTInt32LSB => process_output!(i32, to_le),
+                sys::AsioSampleType::ASIOSTInt32MSB => process_output!(i32, to_be),
+                sys::AsioSampleType::ASIOSTInt32LSB16 => process_output!(I32Aligned<16>, to_le),
+                sys::AsioSampleType::ASIOSTInt32MSB16 => process_output!(I32Aligned<16>, to_be),
+                sys::AsioSampleType::ASIOSTInt32LSB18 => process_output!(I32Aligned<18>, to_le),
+                sys::AsioSampleType::ASIOSTInt32MSB18 => process_output!(I32Aligned<18>, to_be),
+                sys::AsioSampleType::ASIOSTInt32LSB20 => process_output!(I32Aligned<20>, to_le),
+                sys::AsioSampleType::ASIOSTInt32MSB20 => process_output!(I32Aligned<20>, to_be),
+                sys::AsioSampleType::ASIOSTInt32LSB24 => process_output!(I32Aligned<24>, to_le),
+                sys::AsioSampleType::ASIOSTInt32MSB24 => process_output!(I32Aligned<24>, to_be),
+                sys::AsioSampleType::ASIOSTFloat32LSB => process_output!(f32, to_le),
+                sys::AsioSampleType::ASIOSTFloat32MSB => process_output!(f32, to_be),
+                sys::AsioSampleType::ASIOSTFloat64LSB => process_output!(f64, to_le),
+                sys::AsioSampleType::ASIOSTFloat64MSB => process_output!(f64, to_be),
+                unsupported_format => unreachable!(
                     "`build_output_stream_raw` should have returned with unsupported \
                      format {:?}",
-                    unsupported_format_pair
+                    unsupported_format
                 ),
             }
         });
@@ -460,6 +509,8 @@ impl Device {
             driver,
             asio_streams,
             callback_id,
+            config: stream_config,
+            sample_format,
         })
     }
 
@@ -622,11 +673,143 @@ impl AsioSample for f64 {
     }
 }
 
+impl<const BITS: u32> I32Aligned<BITS> {
+    const MIN: i32 = -(1 << (BITS - 1));
+    const MAX: i32 = (1 << (BITS - 1)) - 1;
+}
+
+impl<const BITS: u32> Silence for I32Aligned<BITS> {
+    const SILENCE: Self = I32Aligned(0);
+}
+
+impl<const BITS: u32> std::ops::Add for I32Aligned<BITS> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        let sum = self.0.saturating_add(rhs.0);
+        I32Aligned(sum.clamp(Self::MIN, Self::MAX))
+    }
+}
+
+impl<const BITS: u32> AsioSample for I32Aligned<BITS> {
+    fn to_cpal_sample<T: Sample>(&self) -> T {
+        let f = if self.0 < 0 {
+            self.0 as f64 / -(Self::MIN as f64)
+        } else {
+            self.0 as f64 / Self::MAX as f64
+        };
+        (f as f32).to_cpal_sample()
+    }
+    fn from_cpal_sample<T: Sample>(t: &T) -> Self {
+        let f = f32::from_cpal_sample(t).clamp(-1.0, 1.0) as f64;
+        let s = if f >= 0.0 {
+            f * Self::MAX as f64
+        } else {
+            f * -(Self::MIN as f64)
+        };
+        I32Aligned(s.round() as i32)
+    }
+}
+
+impl I24Packed {
+    const MIN: i32 = -(1 << 23);
+    const MAX: i32 = (1 << 23) - 1;
+
+    fn from_i32(s: i32) -> Self {
+        let [b0, b1, b2, _] = s.clamp(Self::MIN, Self::MAX).to_le_bytes();
+        I24Packed([b0, b1, b2])
+    }
+
+    fn to_i32(self) -> i32 {
+        let [b0, b1, b2] = self.0;
+        // Shift the sign bit into place to sign extend.
+        i32::from_le_bytes([0, b0, b1, b2]) >> 8
+    }
+}
+
+impl Silence for I24Packed {
+    const SILENCE: Self = I24Packed([0; 3]);
+}
+
+impl std::ops::Add for I24Packed {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        I24Packed::from_i32(self.to_i32() + rhs.to_i32())
+    }
+}
+
+impl AsioSample for I24Packed {
+    fn to_cpal_sample<T: Sample>(&self) -> T {
+        I32Aligned::<24>(self.to_i32()).to_cpal_sample()
+    }
+    fn from_cpal_sample<T: Sample>(t: &T) -> Self {
+        I24Packed::from_i32(I32Aligned::<24>::from_cpal_sample(t).0)
+    }
+}
+
+impl Endianness for i16 {
+    fn from_le(x: Self) -> Self {
+        i16::from_le(x)
+    }
+    fn from_be(x: Self) -> Self {
+        i16::from_be(x)
+    }
+}
+
+impl Endianness for i32 {
+    fn from_le(x: Self) -> Self {
+        i32::from_le(x)
+    }
+    fn from_be(x: Self) -> Self {
+        i32::from_be(x)
+    }
+}
+
+impl Endianness for f32 {
+    fn from_le(x: Self) -> Self {
+        f32::from_bits(u32::from_le(x.to_bits()))
+    }
+    fn from_be(x: Self) -> Self {
+        f32::from_bits(u32::from_be(x.to_bits()))
+    }
+}
+
+impl Endianness for f64 {
+    fn from_le(x: Self) -> Self {
+        f64::from_bits(u64::from_le(x.to_bits()))
+    }
+    fn from_be(x: Self) -> Self {
+        f64::from_bits(u64::from_be(x.to_bits()))
+    }
+}
+
+impl<const BITS: u32> Endianness for I32Aligned<BITS> {
+    fn from_le(x: Self) -> Self {
+        I32Aligned(i32::from_le(x.0))
+    }
+    fn from_be(x: Self) -> Self {
+        I32Aligned(i32::from_be(x.0))
+    }
+}
+
+impl Endianness for I24Packed {
+    fn from_le(x: Self) -> Self {
+        x
+    }
+    fn from_be(x: Self) -> Self {
+        let [b0, b1, b2] = x.0;
+        I24Packed([b2, b1, b0])
+    }
+}
+
 fn asio_ns_to_double(val: sys::bindings::asio_import::ASIOTimeStamp) -> f64 {
     let two_raised_to_32 = 4294967296.0;
     val.lo as f64 + val.hi as f64 * two_raised_to_32
 }
 
+fn asio_samples_to_u64(val: sys::bindings::asio_import::ASIOSamples) -> u64 {
+    (val.hi as u64) << 32 | val.lo as u64
+}
+
 /// Asio retrieves system time via `timeGetTime` which returns the time in milliseconds.
 fn system_time_to_stream_instant(
     system_time: sys::bindings::asio_import::ASIOTimeStamp,
@@ -675,15 +858,62 @@ fn check_config(
     }
     // unsigned formats are not supported by asio
     match sample_format {
-        SampleFormat::I16 | SampleFormat::F32 => (),
+        SampleFormat::I16 | SampleFormat::I24 | SampleFormat::I24Packed | SampleFormat::F32 => (),
         SampleFormat::U16 => return Err(BuildStreamError::StreamConfigNotSupported),
     }
     if *channels > num_asio_channels {
         return Err(BuildStreamError::StreamConfigNotSupported);
     }
+    // Drivers only accept sizes in steps of their granularity; say which instead of failing later.
+    if let BufferSize::Fixed(frames) = *buffer_size {
+        let buffer_sizes = driver.buffer_sizes().map_err(build_stream_err)?;
+        let buffer_size_range = SupportedBufferSize::Range {
+            min: buffer_sizes.min.max(0) as FrameCount,
+            max: buffer_sizes.max.max(0) as FrameCount,
+        };
+        buffer_size_range.check(buffer_size)?;
+        let supported = i32::try_from(frames).is_ok_and(|frames| buffer_sizes.supports(frames));
+        if !supported {
+            let description = format!(
+                "a buffer size of {} frames is not supported by the driver, which accepts {} \
+                 (preferred: {})",
+                frames,
+                describe_buffer_sizes(&buffer_sizes),
+                buffer_sizes.pref,
+            );
+            return Err(BackendSpecificError {
+                description,
+                code: None,
+            }
+            .into());
+        }
+    }
     Ok(())
 }
 
+/// Lists the buffer sizes accepted by the driver, for error messages.
+fn describe_buffer_sizes(buffer_sizes: &sys::BufferSizes) -> String {
+    let sys::BufferSizes {
+        min, max, grans, ..
+    } = *buffer_sizes;
+    if min == max {
+        return min.to_string();
+    }
+    match grans {
+        -1 => {
+            let mut sizes = Vec::new();
+            let mut size = min;
+            while size > 0 && size <= max {
+                sizes.push(size.to_string());
+                size *= 2;
+            }
+            sizes.join(", ")
+        }
+        grans if grans > 0 => format!("{} to {} in steps of {}", min, max, grans),
+        _ => format!("{} to {}", min, max),
+    }
+}
+
 /// Cast a byte slice into a mutable slice of desired type.
 ///
 /// Safety: it's up to the caller to ensure that the input slice has valid bit representations.
@@ -693,22 +923,22 @@ unsafe fn cast_slice_mut<T>(v: &mut [u8]) -> &mut [T] {
 }
 
 /// Helper function to convert to little endianness.
-fn to_le<T: PrimInt>(t: T) -> T {
+fn to_le<T: Endianness>(t: T) -> T {
     t.to_le()
 }
 
 /// Helper function to convert to big endianness.
-fn to_be<T: PrimInt>(t: T) -> T {
+fn to_be<T: Endianness>(t: T) -> T {
     t.to_be()
 }
 
 /// Helper function to convert from little endianness.
-fn from_le<T: PrimInt>(t: T) -> T {
+fn from_le<T: Endianness>(t: T) -> T {
     T::from_le(t)
 }
 
-/// Helper function to convert from little endianness.
-fn from_be<T: PrimInt>(t: T) -> T {
+/// Helper function to convert from big endianness.
+fn from_be<T: Endianness>(t: T) -> T {
     T::from_be(t)
 }
 
@@ -746,7 +976,136 @@ fn build_stream_err(e: sys::AsioError) -> BuildStreamError {
         sys::AsioError::InvalidInput | sys::AsioError::BadMode => BuildStreamError::InvalidArgument,
         err => {
             let description = format!("{}", err);
-            BackendSpecificError { description }.into()
+            let code = err.code();
+            BackendSpecificError { description, code }.into()
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{describe_buffer_sizes, sys, AsioSample, Endianness, I24Packed, I32Aligned};
+
+    #[test]
+    fn buffer_size_granularity() {
+        let powers_of_two = sys::BufferSizes {
+            min: 64,
+            max: 1024,
+            pref: 256,
+            grans: -1,
+        };
+        assert!(powers_of_two.supports(128));
+        assert!(!powers_of_two.supports(192));
+        assert!(!powers_of_two.supports(2048));
+        assert_eq!(
+            describe_buffer_sizes(&powers_of_two),
+            "64, 128, 256, 512, 1024"
+        );
+        let linear = sys::BufferSizes {
+            min: 48,
+            max: 480,
+            pref: 96,
+            grans: 48,
+        };
+        assert!(linear.supports(144));
+        assert!(!linear.supports(100));
+        assert_eq!(describe_buffer_sizes(&linear), "48 to 480 in steps of 48");
+        let fixed = sys::BufferSizes {
+            min: 512,
+            max: 512,
+            pref: 512,
+            grans: 0,
+        };
+        assert!(fixed.supports(512));
+        assert_eq!(describe_buffer_sizes(&fixed), "512");
+    }
+
+    #[test]
+    fn i32_aligned_to_cpal() {
+        assert_eq!(I32Aligned::<24>(0x7F_FFFF).to_cpal_sample::<f32>(), 1.0);
+        assert_eq!(I32Aligned::<24>(-0x80_0000).to_cpal_sample::<f32>(), -1.0);
+        assert_eq!(I32Aligned::<24>(-0x40_0000).to_cpal_sample::<f32>(), -0.5);
+        assert_eq!(I32Aligned::<24>(0).to_cpal_sample::<i16>(), 0);
+        assert_eq!(
+            I32Aligned::<24>(0x7F_FFFF).to_cpal_sample::<i16>(),
+            i16::MAX
+        );
+        assert_eq!(
+            I32Aligned::<24>(-0x80_0000).to_cpal_sample::<i16>(),
+            i16::MIN
+        );
+        assert_eq!(I32Aligned::<20>(0x7_FFFF).to_cpal_sample::<f32>(), 1.0);
+        assert_eq!(I32Aligned::<20>(-0x4_0000).to_cpal_sample::<f32>(), -0.5);
+        assert_eq!(I32Aligned::<18>(0x1_FFFF).to_cpal_sample::<f32>(), 1.0);
+        assert_eq!(I32Aligned::<18>(-0x2_0000).to_cpal_sample::<f32>(), -1.0);
+        assert_eq!(I32Aligned::<16>(0x7FFF).to_cpal_sample::<i16>(), i16::MAX);
+        assert_eq!(I32Aligned::<16>(-0x8000).to_cpal_sample::<i16>(), i16::MIN);
+        assert_eq!(I32Aligned::<16>(0x1234).to_cpal_sample::<i16>(), 0x1234);
+    }
+
+    #[test]
+    fn i32_aligned_from_cpal() {
+        assert_eq!(I32Aligned::<24>::from_cpal_sample(&1.0f32).0, 0x7F_FFFF);
+        assert_eq!(I32Aligned::<24>::from_cpal_sample(&-1.0f32).0, -0x80_0000);
+        assert_eq!(I32Aligned::<24>::from_cpal_sample(&2.0f32).0, 0x7F_FFFF);
+        assert_eq!(I32Aligned::<24>::from_cpal_sample(&i16::MIN).0, -0x80_0000);
+        assert_eq!(I32Aligned::<20>::from_cpal_sample(&-0.5f32).0, -0x4_0000);
+        assert_eq!(I32Aligned::<18>::from_cpal_sample(&i16::MAX).0, 0x1_FFFF);
+        assert_eq!(I32Aligned::<16>::from_cpal_sample(&0x1234i16).0, 0x1234);
+    }
+
+    #[test]
+    fn i32_aligned_add_saturates() {
+        let max = I32Aligned::<20>(0x7_FFFF);
+        assert_eq!((max + max).0, 0x7_FFFF);
+        let min = I32Aligned::<20>(-0x8_0000);
+        assert_eq!((min + min).0, -0x8_0000);
+        assert_eq!((I32Aligned::<20>(3) + I32Aligned::<20>(-5)).0, -2);
+    }
+
+    #[test]
+    fn i32_aligned_endianness() {
+        let s = I32Aligned::<24>(0x12_3456);
+        assert_eq!(s.to_be().0.to_ne_bytes(), [0x00, 0x12, 0x34, 0x56]);
+        assert_eq!(s.to_le().0.to_ne_bytes(), [0x56, 0x34, 0x12, 0x00]);
+        assert_eq!(Endianness::from_be(s.to_be()), s);
+        assert_eq!(Endianness::from_le(s.to_le()), s);
+    }
+
+    #[test]
+    fn i24_packed() {
+        assert_eq!(I24Packed([0xFF, 0xFF, 0x7F]).to_i32(), 0x7F_FFFF);
+        assert_eq!(I24Packed([0x00, 0x00, 0x80]).to_i32(), -0x80_0000);
+        assert_eq!(I24Packed([0xFF, 0xFF, 0xFF]).to_i32(), -1);
+        assert_eq!(I24Packed::from_i32(-1).0, [0xFF, 0xFF, 0xFF]);
+        assert_eq!(I24Packed::from_i32(0x12_3456).0, [0x56, 0x34, 0x12]);
+        assert_eq!(I24Packed::from_i32(0x100_0000).0, [0xFF, 0xFF, 0x7F]);
+        assert_eq!(I24Packed([0xFF, 0xFF, 0x7F]).to_cpal_sample::<f32>(), 1.0);
+        assert_eq!(
+            I24Packed([0x00, 0x00, 0x80]).to_cpal_sample::<i16>(),
+            i16::MIN
+        );
+        assert_eq!(I24Packed::from_cpal_sample(&-1.0f32).0, [0x00, 0x00, 0x80]);
+        let sum = I24Packed::from_i32(0x7F_0000) + I24Packed::from_i32(0x7F_0000);
+        assert_eq!(sum.to_i32(), 0x7F_FFFF);
+    }
+
+    #[test]
+    fn i24_packed_endianness() {
+        let le = I24Packed([0x56, 0x34, 0x12]);
+        assert_eq!(Endianness::from_le(le), le);
+        assert_eq!(le.to_be().0, [0x12, 0x34, 0x56]);
+        assert_eq!(Endianness::from_be(I24Packed([0x12, 0x34, 0x56])), le);
+    }
+
+    #[test]
+    fn float_endianness() {
+        let f = 0.25f32;
+        assert_eq!(f.to_be().to_bits(), u32::from_ne_bytes(f.to_be_bytes()));
+        assert_eq!(f.to_le().to_bits(), u32::from_ne_bytes(f.to_le_bytes()));
+        assert_eq!(Endianness::from_be(f.to_be()), f);
+        let d = -0.125f64;
+        assert_eq!(d.to_be().to_bits(), u64::from_ne_bytes(d.to_be_bytes()));
+        assert_eq!(Endianness::from_be(d.to_be()), d);
+    }
+}
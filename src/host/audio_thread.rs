@@ -0,0 +1,129 @@
+//! A handle to a backend's audio processing thread that can be joined with a timeout.
+
+use crate::{BackendSpecificError, StreamError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long dropping a stream waits for its audio thread to finish before detaching it.
+pub(crate) const DROP_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub(crate) struct AudioThread {
+    handle: Option<JoinHandle<()>>,
+    // Disconnected once the thread has returned or unwound.
+    done: Receiver<()>,
+}
+
+impl AudioThread {
+    pub(crate) fn spawn<F>(builder: thread::Builder, f: F) -> std::io::Result<Self>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (done_tx, done) = mpsc::channel::<()>();
+        let handle = builder.spawn(move || {
+            // Dropped when `f` returns or panics, which disconnects `done`.
+            let _done_tx = done_tx;
+            f();
+        })?;
+        Ok(AudioThread {
+            handle: Some(handle),
+            done,
+        })
+    }
+
+    /// Waits at most `timeout` for the thread to finish.
+    ///
+    /// The thread must already have been asked to stop. If it has not finished in time it is
+    /// detached, and keeps ownership of everything it captured until it eventually returns.
+    /// Returns immediately if the thread has already been joined or detached.
+    pub(crate) fn join_timeout(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        match self.done.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    ?timeout,
+                    "audio thread did not finish in time, detaching it"
+                );
+                Err(StreamError::CloseTimedOut)
+            }
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => handle.join().map_err(|_| {
+                warn!("audio thread panicked");
+                let description = "the audio thread panicked".to_string();
+                BackendSpecificError {
+                    description,
+                    code: None,
+                }
+                .into()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AudioThread;
+    use crate::StreamError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_join_waits_for_callback_exit() {
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let exited = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut thread = {
+            let exited = exited.clone();
+            let stop = stop.clone();
+            AudioThread::spawn(thread::Builder::new(), move || {
+                entered_tx.send(()).unwrap();
+                while !stop.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                thread::sleep(Duration::from_millis(20));
+                exited.store(true, Ordering::SeqCst);
+            })
+            .unwrap()
+        };
+        entered_rx.recv().unwrap();
+        stop.store(true, Ordering::SeqCst);
+        assert!(thread.join_timeout(Duration::from_secs(10)).is_ok());
+        assert!(exited.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_join_times_out_on_blocked_callback() {
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (exited_tx, exited_rx) = mpsc::channel();
+        let mut thread = AudioThread::spawn(thread::Builder::new(), move || {
+            entered_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+            exited_tx.send(()).unwrap();
+        })
+        .unwrap();
+        entered_rx.recv().unwrap();
+        assert!(matches!(
+            thread.join_timeout(Duration::from_millis(20)),
+            Err(StreamError::CloseTimedOut)
+        ));
+        // The detached thread still runs to completion once unblocked.
+        drop(release_tx);
+        exited_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn test_join_reports_panic() {
+        let mut thread =
+            AudioThread::spawn(thread::Builder::new(), || panic!("callback panicked")).unwrap();
+        assert!(matches!(
+            thread.join_timeout(Duration::from_secs(10)),
+            Err(StreamError::BackendSpecific { .. })
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+//! Catching panics in the user's callbacks before they unwind into a host's audio thread.
+//!
+//! Every host wraps the callbacks passed to `build_*_stream_raw` with `guard_input` or
+//! `guard_output` before handing them to the backend. A panic in the data callback is reported
+//! once to the error callback as `StreamError::CallbackPanicked`, after which the data callback is
+//! never called again: output buffers are filled with silence and input buffers are dropped. A
+//! panic in the error callback discards it, and later errors are ignored.
+
+use crate::samples_formats::fill_silence;
+use crate::{Data, InputCallbackInfo, OutputCallbackInfo, StreamError};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+struct Shared<E> {
+    // Set once the data callback has panicked.
+    poisoned: AtomicBool,
+    // `None` once the error callback has panicked.
+    error_callback: Mutex<Option<E>>,
+}
+
+impl<E> Shared<E>
+where
+    E: FnMut(StreamError),
+{
+    fn new(error_callback: E) -> Arc<Self> {
+        Arc::new(Shared {
+            poisoned: AtomicBool::new(false),
+            error_callback: Mutex::new(Some(error_callback)),
+        })
+    }
+
+    fn report_error(&self, err: StreamError) {
+        // The lock is never held while unwinding, but don't rely on it.
+        let mut error_callback = self
+            .error_callback
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(callback) = error_callback.as_mut() {
+            if panic::catch_unwind(AssertUnwindSafe(|| callback(err))).is_err() {
+                warn!("error callback panicked, ignoring further stream errors");
+                *error_callback = None;
+            }
+        }
+    }
+
+    /// Calls the data callback through `call` unless it has panicked before.
+    ///
+    /// Returns `false` if the data callback was not called or panicked.
+    fn call_data_callback(&self, call: impl FnOnce()) -> bool {
+        if self.poisoned.load(Ordering::Acquire) {
+            return false;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(call)) {
+            Ok(()) => true,
+            Err(payload) => {
+                self.poisoned.store(true, Ordering::Release);
+                let message = panic_message(&*payload);
+                warn!(%message, "data callback panicked");
+                self.report_error(StreamError::CallbackPanicked { message });
+                false
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Wraps the callbacks of an input stream.
+pub(crate) fn guard_input<D, E>(
+    mut data_callback: D,
+    error_callback: E,
+) -> (
+    impl FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+    impl FnMut(StreamError) + Send + 'static,
+)
+where
+    D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let shared = Shared::new(error_callback);
+    let data_shared = shared.clone();
+    let data_callback = move |data: &Data, info: &InputCallbackInfo| {
+        data_shared.call_data_callback(|| data_callback(data, info));
+    };
+    let error_callback = move |err| shared.report_error(err);
+    (data_callback, error_callback)
+}
+
+/// Wraps the callbacks of an output stream.
+pub(crate) fn guard_output<D, E>(
+    mut data_callback: D,
+    error_callback: E,
+) -> (
+    impl FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+    impl FnMut(StreamError) + Send + 'static,
+)
+where
+    D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let shared = Shared::new(error_callback);
+    let data_shared = shared.clone();
+    let data_callback = move |data: &mut Data, info: &OutputCallbackInfo| {
+        if !data_shared.call_data_callback(|| data_callback(data, info)) {
+            // The callback may have written part of the buffer before panicking.
+            let sample_format = data.sample_format();
+            fill_silence(data.bytes_mut(), sample_format);
+        }
+    };
+    let error_callback = move |err| shared.report_error(err);
+    (data_callback, error_callback)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{guard_input, guard_output};
+    use crate::mock::{input_info, output_info};
+    use crate::{Data, InputCallbackInfo, OutputCallbackInfo, SampleFormat, StreamError};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_output_panic_is_reported_once_and_silenced() {
+        let (err_tx, err_rx) = mpsc::channel();
+        let mut calls = 0;
+        let (mut data_callback, _error_callback) = guard_output(
+            move |data: &mut Data, _: &OutputCallbackInfo| {
+                calls += 1;
+                data.as_slice_mut::<u16>().unwrap().fill(1);
+                assert!(calls < 2, "callback panicked");
+            },
+            move |err| err_tx.send(err).unwrap(),
+        );
+        let mut buffer = [0u16; 4];
+        for _ in 0..5 {
+            let mut data =
+                unsafe { Data::from_parts(buffer.as_mut_ptr() as *mut (), 4, SampleFormat::U16) };
+            data_callback(&mut data, &output_info());
+        }
+        assert_eq!(buffer, [0x8000; 4]);
+        let errors: Vec<_> = err_rx.try_iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            StreamError::CallbackPanicked { message } if message == "callback panicked"
+        ));
+        drop(data_callback);
+    }
+
+    #[test]
+    fn test_input_panic_stops_calling_callback() {
+        let (err_tx, err_rx) = mpsc::channel();
+        let (calls_tx, calls_rx) = mpsc::channel();
+        let (mut data_callback, _error_callback) = guard_input(
+            move |_: &Data, _: &InputCallbackInfo| {
+                calls_tx.send(()).unwrap();
+                panic!("callback panicked");
+            },
+            move |err| err_tx.send(err).unwrap(),
+        );
+        let mut buffer = [0.0f32; 4];
+        for _ in 0..5 {
+            let data =
+                unsafe { Data::from_parts(buffer.as_mut_ptr() as *mut (), 4, SampleFormat::F32) };
+            data_callback(&data, &input_info());
+        }
+        assert_eq!(calls_rx.try_iter().count(), 1);
+        assert_eq!(err_rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_error_callback_panic_is_contained() {
+        let (calls_tx, calls_rx) = mpsc::channel();
+        let (_data_callback, mut error_callback) = guard_input(
+            |_: &Data, _: &InputCallbackInfo| {},
+            move |_| {
+                calls_tx.send(()).unwrap();
+                panic!("error callback panicked");
+            },
+        );
+        error_callback(StreamError::DeviceNotAvailable);
+        error_callback(StreamError::DeviceNotAvailable);
+        assert_eq!(calls_rx.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,230 @@
+//! Presenting an input stream as mono or stereo, see `StreamOptions::channel_mode`.
+//!
+//! When the device supports the channel count of the requested mode, the stream is opened with it
+//! and only reports whether the device's physical channels suggest that the backend mixes them.
+//! Otherwise the stream is opened with the other count, and the data callback is wrapped to
+//! average or duplicate the channels into a buffer of its own. The buffer is allocated when the
+//! stream is built with a fixed buffer size, and on the first call otherwise.
+
+use crate::traits::DeviceTrait;
+use crate::{
+    BufferSize, BuildStreamError, ChannelMix, ChannelMode, Data, I24Packed, InputCallbackInfo,
+    Sample, SampleFormat, StreamConfig, StreamDirection, SupportedStreamConfigsError, I24,
+};
+
+/// The config to open `device` with for an input stream of `config` in `mode`, along with the
+/// data callback to pass to the host, which reports the resulting `ChannelMix`.
+pub(crate) fn apply<T, D>(
+    device: &T,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    mode: ChannelMode,
+    data_callback: D,
+) -> Result<
+    (
+        StreamConfig,
+        impl FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+    ),
+    BuildStreamError,
+>
+where
+    T: DeviceTrait + ?Sized,
+    D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+{
+    let (device_config, mix) = plan(device, config, sample_format, mode)?;
+    let data_callback = mix_input(config, sample_format, mix, data_callback);
+    Ok((device_config, data_callback))
+}
+
+fn plan<T>(
+    device: &T,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    mode: ChannelMode,
+) -> Result<(StreamConfig, ChannelMix), BuildStreamError>
+where
+    T: DeviceTrait + ?Sized,
+{
+    let (channels, fallback, mix) = match mode {
+        ChannelMode::AsReported => return Ok((config.clone(), ChannelMix::None)),
+        ChannelMode::ForceMono => (1, 2, ChannelMix::Downmix),
+        ChannelMode::ForceStereo => (2, 1, ChannelMix::Upmix),
+    };
+    if config.channels != channels {
+        return Err(BuildStreamError::StreamConfigNotSupported);
+    }
+    let supported = device
+        .supported_configs(StreamDirection::Input)
+        .map_err(|err| match err {
+            SupportedStreamConfigsError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+            SupportedStreamConfigsError::InvalidArgument => BuildStreamError::InvalidArgument,
+            SupportedStreamConfigsError::BackendSpecific { err } => err.into(),
+        })?;
+    if supported.contains(config, sample_format) {
+        let mix = match device.physical_channels(StreamDirection::Input) {
+            Some(physical) if physical > channels => ChannelMix::BackendDownmix,
+            Some(physical) if physical < channels => ChannelMix::BackendUpmix,
+            _ => ChannelMix::None,
+        };
+        return Ok((config.clone(), mix));
+    }
+    let device_config = StreamConfig {
+        channels: fallback,
+        ..config.clone()
+    };
+    if !supported.contains(&device_config, sample_format) {
+        return Err(BuildStreamError::StreamConfigNotSupported);
+    }
+    Ok((device_config, mix))
+}
+
+// Wraps the data callback of a stream of `config` to report `mix`, and to perform it if it is up
+// to CPAL.
+fn mix_input<D>(
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    mix: ChannelMix,
+    mut data_callback: D,
+) -> impl FnMut(&Data, &InputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+{
+    let capacity = match (mix, &config.buffer_size) {
+        (ChannelMix::Downmix | ChannelMix::Upmix, BufferSize::Fixed(frames)) => {
+            *frames as usize * config.channels as usize
+        }
+        _ => 0,
+    };
+    let mut buffer = Buffer::new(sample_format, capacity);
+    move |data: &Data, info: &InputCallbackInfo| {
+        if mix == ChannelMix::None {
+            return data_callback(data, info);
+        }
+        let info = InputCallbackInfo {
+            channel_mix: mix,
+            ..info.clone()
+        };
+        match mix {
+            ChannelMix::Downmix | ChannelMix::Upmix => {
+                let data = buffer.mix(data, mix);
+                data_callback(&data, &info);
+            }
+            _ => data_callback(data, &info),
+        }
+    }
+}
+
+// The samples of a stream whose channels CPAL mixes.
+enum Buffer {
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I24(Vec<I24>),
+    I24Packed(Vec<I24Packed>),
+    F32(Vec<f32>),
+}
+
+impl Buffer {
+    fn new(sample_format: SampleFormat, capacity: usize) -> Self {
+        match sample_format {
+            SampleFormat::I16 => Buffer::I16(Vec::with_capacity(capacity)),
+            SampleFormat::U16 => Buffer::U16(Vec::with_capacity(capacity)),
+            SampleFormat::I24 => Buffer::I24(Vec::with_capacity(capacity)),
+            SampleFormat::I24Packed => Buffer::I24Packed(Vec::with_capacity(capacity)),
+            SampleFormat::F32 => Buffer::F32(Vec::with_capacity(capacity)),
+        }
+    }
+
+    // Mixes the samples of `data` into the buffer, returning the `Data` of the buffer.
+    fn mix(&mut self, data: &Data, mix: ChannelMix) -> Data {
+        match self {
+            Buffer::I16(buffer) => mix_samples(data, buffer, mix),
+            Buffer::U16(buffer) => mix_samples(data, buffer, mix),
+            Buffer::I24(buffer) => mix_samples(data, buffer, mix),
+            Buffer::I24Packed(buffer) => mix_samples(data, buffer, mix),
+            Buffer::F32(buffer) => mix_samples(data, buffer, mix),
+        }
+    }
+}
+
+fn mix_samples<T: Sample>(data: &Data, buffer: &mut Vec<T>, mix: ChannelMix) -> Data {
+    let samples = data
+        .as_slice::<T>()
+        .expect("host supplied incorrect sample type");
+    buffer.clear();
+    if mix == ChannelMix::Upmix {
+        buffer.extend(samples.iter().flat_map(|&sample| [sample, sample]));
+    } else {
+        buffer.extend(samples.chunks_exact(2).map(|frame| {
+            let average = (frame[0].to_f32() + frame[1].to_f32()) / 2.0;
+            <T as Sample>::from(&average)
+        }));
+    }
+    // Safety: the buffer holds `buffer.len()` samples of `T::FORMAT`, and is not touched again
+    // until the returned `Data` has been passed to the data callback.
+    unsafe { Data::from_parts(buffer.as_mut_ptr() as *mut (), buffer.len(), T::FORMAT) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::mix_input;
+    use crate::mock::input_info;
+    use crate::{
+        BufferSize, ChannelMix, Data, InputCallbackInfo, SampleFormat, SampleRate, StreamConfig,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn config(channels: u16) -> StreamConfig {
+        StreamConfig {
+            channels,
+            sample_rate: SampleRate(48_000),
+            buffer_size: BufferSize::Fixed(2),
+        }
+    }
+
+    // Passes `samples` through a data callback wrapped to perform `mix`, returning the samples and
+    // mix it received.
+    fn call(
+        config: &StreamConfig,
+        mix: ChannelMix,
+        mut samples: Vec<f32>,
+    ) -> (Vec<f32>, ChannelMix) {
+        let received = Arc::new(Mutex::new((Vec::new(), ChannelMix::None)));
+        let received_by_callback = received.clone();
+        let mut data_callback = mix_input(
+            config,
+            SampleFormat::F32,
+            mix,
+            move |data: &Data, info: &InputCallbackInfo| {
+                let samples = data.as_slice::<f32>().unwrap().to_vec();
+                *received_by_callback.lock().unwrap() = (samples, info.channel_mix());
+            },
+        );
+        let len = samples.len();
+        let data =
+            unsafe { Data::from_parts(samples.as_mut_ptr() as *mut (), len, SampleFormat::F32) };
+        data_callback(&data, &input_info());
+        let received = received.lock().unwrap();
+        received.clone()
+    }
+
+    #[test]
+    fn test_downmix() {
+        let (samples, mix) = call(&config(1), ChannelMix::Downmix, vec![0.5, 0.25, -1.0, 0.0]);
+        assert_eq!(samples, [0.375, -0.5]);
+        assert_eq!(mix, ChannelMix::Downmix);
+    }
+
+    #[test]
+    fn test_upmix() {
+        let (samples, mix) = call(&config(2), ChannelMix::Upmix, vec![0.5, -0.25]);
+        assert_eq!(samples, [0.5, 0.5, -0.25, -0.25]);
+        assert_eq!(mix, ChannelMix::Upmix);
+    }
+
+    #[test]
+    fn test_backend_mix_is_only_reported() {
+        let (samples, mix) = call(&config(2), ChannelMix::BackendUpmix, vec![0.5, 0.5]);
+        assert_eq!(samples, [0.5, 0.5]);
+        assert_eq!(mix, ChannelMix::BackendUpmix);
+    }
+}
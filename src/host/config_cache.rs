@@ -0,0 +1,224 @@
+//! Caching the configs that devices support, for the hosts that can only find them by probing the
+//! device, see `DeviceTrait::supported_output_configs_incremental`.
+//!
+//! Only complete enumerations are cached, so that a callback breaking early does not hide the
+//! configs it was not passed from later calls. Hosts clear the configs of a device when notified
+//! that it changed.
+
+use crate::{DeviceId, StreamDirection, SupportedStreamConfigRange, SupportedStreamConfigsError};
+use std::ops::ControlFlow;
+use std::sync::{Mutex, PoisonError};
+
+type Configs = Vec<SupportedStreamConfigRange>;
+
+/// The configs supported by each device in each direction, keyed by the ID of the device.
+pub(crate) struct ConfigCache {
+    configs: Mutex<Vec<(DeviceId, StreamDirection, Configs)>>,
+}
+
+impl ConfigCache {
+    pub(crate) const fn new() -> Self {
+        ConfigCache {
+            configs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Passes the configs of the device `id` in `direction` to `callback`: the cached ones if
+    /// there are, and otherwise those passed to the callback of `probe`, which are cached if
+    /// `callback` let `probe` pass all of them. A device whose ID cannot be read is always probed.
+    pub(crate) fn supported_configs<F, P>(
+        &self,
+        id: Option<DeviceId>,
+        direction: StreamDirection,
+        mut callback: F,
+        probe: P,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        P: FnOnce(
+            &mut dyn FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        ) -> Result<(), SupportedStreamConfigsError>,
+    {
+        let Some(id) = id else {
+            return probe(&mut callback);
+        };
+        // The lock is not held while calling back, which may enumerate again.
+        if let Some(configs) = self.cached(&id, direction) {
+            for config in configs {
+                if callback(config).is_break() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let mut configs = Vec::new();
+        let mut complete = true;
+        probe(&mut |config| {
+            configs.push(config.clone());
+            let flow = callback(config);
+            complete &= flow.is_continue();
+            flow
+        })?;
+        if complete {
+            let mut cached = self.lock();
+            cached.retain(|(cached, dir, _)| *cached != id || *dir != direction);
+            cached.push((id, direction, configs));
+        }
+        Ok(())
+    }
+
+    /// Forgets the configs of the device `id`, in both directions.
+    pub(crate) fn clear(&self, id: &DeviceId) {
+        self.lock().retain(|(cached, _, _)| cached != id);
+    }
+
+    /// Forgets the configs of all devices.
+    pub(crate) fn clear_all(&self) {
+        self.lock().clear();
+    }
+
+    fn cached(&self, id: &DeviceId, direction: StreamDirection) -> Option<Configs> {
+        self.lock()
+            .iter()
+            .find(|(cached, dir, _)| cached == id && *dir == direction)
+            .map(|(_, _, configs)| configs.clone())
+    }
+
+    // The cache is only ever replaced or cleared as a whole entry, so a panic while it was locked
+    // leaves it consistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(DeviceId, StreamDirection, Configs)>> {
+        self.configs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigCache;
+    use crate::{
+        DeviceId, SampleFormat, SampleRate, StreamDirection, SupportedBufferSize,
+        SupportedStreamConfigRange, SupportedStreamConfigsError,
+    };
+    use std::cell::Cell;
+    use std::ops::ControlFlow;
+
+    fn config(channels: u16) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange {
+            channels,
+            min_sample_rate: SampleRate(48_000),
+            max_sample_rate: SampleRate(48_000),
+            buffer_size: SupportedBufferSize::Unknown,
+            sample_format: SampleFormat::F32,
+        }
+    }
+
+    // Enumerates the configs of `id` with a callback breaking after `limit` of them, returning
+    // the configs passed to it.
+    fn enumerate(
+        cache: &ConfigCache,
+        id: Option<&str>,
+        limit: usize,
+        probes: &Cell<usize>,
+    ) -> Vec<SupportedStreamConfigRange> {
+        let mut passed = Vec::new();
+        cache
+            .supported_configs(
+                id.map(DeviceId::new),
+                StreamDirection::Output,
+                |config| {
+                    passed.push(config);
+                    if passed.len() == limit {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                },
+                |callback| {
+                    probes.set(probes.get() + 1);
+                    for channels in 1..=4 {
+                        if callback(config(channels)).is_break() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+        passed
+    }
+
+    #[test]
+    fn test_complete_enumerations_are_replayed() {
+        let cache = ConfigCache::new();
+        let probes = Cell::new(0);
+        let probed = enumerate(&cache, Some("speakers"), usize::MAX, &probes);
+        assert_eq!(probed.len(), 4);
+        assert_eq!(
+            enumerate(&cache, Some("speakers"), usize::MAX, &probes),
+            probed
+        );
+        assert_eq!(probes.get(), 1);
+
+        // Replaying honours the callback breaking.
+        assert_eq!(enumerate(&cache, Some("speakers"), 2, &probes), probed[..2]);
+        assert_eq!(probes.get(), 1);
+    }
+
+    #[test]
+    fn test_partial_enumerations_are_not_cached() {
+        let cache = ConfigCache::new();
+        let probes = Cell::new(0);
+        assert_eq!(enumerate(&cache, Some("speakers"), 2, &probes).len(), 2);
+        assert_eq!(
+            enumerate(&cache, Some("speakers"), usize::MAX, &probes).len(),
+            4
+        );
+        assert_eq!(probes.get(), 2);
+    }
+
+    #[test]
+    fn test_configs_are_cached_per_device_until_cleared() {
+        let cache = ConfigCache::new();
+        let probes = Cell::new(0);
+        enumerate(&cache, Some("speakers"), usize::MAX, &probes);
+        enumerate(&cache, Some("headset"), usize::MAX, &probes);
+        enumerate(&cache, None, usize::MAX, &probes);
+        enumerate(&cache, None, usize::MAX, &probes);
+        assert_eq!(probes.get(), 4);
+
+        cache.clear(&DeviceId::new("speakers"));
+        enumerate(&cache, Some("speakers"), usize::MAX, &probes);
+        enumerate(&cache, Some("headset"), usize::MAX, &probes);
+        assert_eq!(probes.get(), 5);
+
+        cache.clear_all();
+        enumerate(&cache, Some("headset"), usize::MAX, &probes);
+        assert_eq!(probes.get(), 6);
+    }
+
+    #[test]
+    fn test_failed_enumerations_are_not_cached() {
+        let cache = ConfigCache::new();
+        let id = || Some(DeviceId::new("speakers"));
+        let result = cache.supported_configs(
+            id(),
+            StreamDirection::Input,
+            |_| ControlFlow::Continue(()),
+            |_| Err(SupportedStreamConfigsError::DeviceNotAvailable),
+        );
+        assert!(matches!(
+            result,
+            Err(SupportedStreamConfigsError::DeviceNotAvailable)
+        ));
+        let probes = Cell::new(0);
+        let _ = cache.supported_configs(
+            id(),
+            StreamDirection::Input,
+            |_| ControlFlow::Continue(()),
+            |_| {
+                probes.set(probes.get() + 1);
+                Ok(())
+            },
+        );
+        assert_eq!(probes.get(), 1);
+    }
+}
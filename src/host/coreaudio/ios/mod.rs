@@ -19,14 +19,17 @@ use self::coreaudio::sys::{
     AudioStreamBasicDescription,
 };
 
-use super::{asbd_from_config, frames_to_duration, host_time_to_stream_instant};
+use super::{asbd_from_config, device_timestamp, frames_to_duration, host_time_to_stream_instant};
+use crate::host::callback_guard;
+use crate::host::frame_align;
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, Data, DefaultStreamConfigError,
-    DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
-    PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError, SupportedBufferSize,
-    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+    BackendSpecificError, BufferSize, BuildStreamError, ChannelCount, ChannelMix, Data,
+    DefaultStreamConfigError, DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo,
+    PauseStreamError, PermissionStatus, PlayStreamError, SampleFormat, SampleRate, StreamConfig,
+    StreamError, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
 };
 
 use self::enumerate::{
@@ -70,6 +73,17 @@ impl HostTrait for Host {
     fn default_output_device(&self) -> Option<Self::Device> {
         default_output_device()
     }
+
+    fn input_permission_status(&self) -> PermissionStatus {
+        super::permission::input_permission_status()
+    }
+
+    fn request_input_permission<F>(&self, callback: F)
+    where
+        F: FnOnce(PermissionStatus) + Send + 'static,
+    {
+        super::permission::request_input_permission(Box::new(callback))
+    }
 }
 
 impl Device {
@@ -172,13 +186,17 @@ impl DeviceTrait for Device {
         &self,
         config: &StreamConfig,
         sample_format: SampleFormat,
-        mut data_callback: D,
-        mut error_callback: E,
+        data_callback: D,
+        error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
     where
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, mut error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
+        let mut data_callback =
+            frame_align::align_input(config.channels, sample_format, data_callback);
         // The scope and element for working with a device's input stream.
         let scope = Scope::Output;
         let element = Element::Input;
@@ -236,7 +254,12 @@ impl DeviceTrait for Device {
                 .expect("`capture` occurs before origin of alsa `StreamInstant`");
             let timestamp = crate::InputStreamTimestamp { callback, capture };
 
-            let info = InputCallbackInfo { timestamp };
+            let device_timestamp = device_timestamp(&args.time_stamp, sample_rate);
+            let info = InputCallbackInfo {
+                timestamp,
+                device_timestamp,
+                channel_mix: ChannelMix::None,
+            };
             data_callback(&data, &info);
             Ok(())
         })?;
@@ -246,6 +269,8 @@ impl DeviceTrait for Device {
         Ok(Stream::new(StreamInner {
             playing: true,
             audio_unit,
+            config: config.clone(),
+            sample_format,
         }))
     }
 
@@ -254,13 +279,17 @@ impl DeviceTrait for Device {
         &self,
         config: &StreamConfig,
         sample_format: SampleFormat,
-        mut data_callback: D,
-        mut error_callback: E,
+        data_callback: D,
+        error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
     where
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, mut error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
+        let mut data_callback =
+            frame_align::align_output(config.channels, sample_format, data_callback);
         match config.buffer_size {
             BufferSize::Fixed(_) => {
                 return Err(BuildStreamError::StreamConfigNotSupported);
@@ -312,7 +341,11 @@ impl DeviceTrait for Device {
                 .expect("`playback` occurs beyond representation supported by `StreamInstant`");
             let timestamp = crate::OutputStreamTimestamp { callback, playback };
 
-            let info = OutputCallbackInfo { timestamp };
+            let device_timestamp = device_timestamp(&args.time_stamp, sample_rate);
+            let info = OutputCallbackInfo {
+                timestamp,
+                device_timestamp,
+            };
             data_callback(&mut data, &info);
             Ok(())
         })?;
@@ -322,6 +355,8 @@ impl DeviceTrait for Device {
         Ok(Stream::new(StreamInner {
             playing: true,
             audio_unit,
+            config: config.clone(),
+            sample_format,
         }))
     }
 }
@@ -345,7 +380,8 @@ impl StreamTrait for Stream {
         if !stream.playing {
             if let Err(e) = stream.audio_unit.start() {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let code = Some(e.as_os_status());
+                let err = BackendSpecificError { description, code };
                 return Err(err.into());
             }
             stream.playing = true;
@@ -359,7 +395,8 @@ impl StreamTrait for Stream {
         if stream.playing {
             if let Err(e) = stream.audio_unit.stop() {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let code = Some(e.as_os_status());
+                let err = BackendSpecificError { description, code };
                 return Err(err.into());
             }
 
@@ -367,11 +404,26 @@ impl StreamTrait for Stream {
         }
         Ok(())
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.borrow().config.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.inner.borrow().config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.inner.borrow().sample_format
+    }
 }
 
 struct StreamInner {
     playing: bool,
     audio_unit: AudioUnit,
+    // The configuration and sample format with which the stream was created.
+    config: StreamConfig,
+    sample_format: SampleFormat,
 }
 
 fn create_audio_unit() -> Result<AudioUnit, coreaudio::Error> {
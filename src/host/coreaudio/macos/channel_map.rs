@@ -0,0 +1,114 @@
+//! Channels disabled through `DeviceExt::set_channel_enabled`, and the channel maps that leave
+//! them out of streams.
+//!
+//! The disabled channels are kept per device for the whole process, so that they apply to every
+//! `Device` referring to it. Streams pass them to their audio unit as
+//! `kAudioOutputUnitProperty_ChannelMap`, after which the audio unit only moves the enabled
+//! channels of the device.
+
+use super::coreaudio::sys::AudioDeviceID;
+use crate::{ChannelCount, StreamDirection};
+use std::sync::Mutex;
+
+// The disabled channels of all devices.
+static DISABLED: Mutex<Vec<(AudioDeviceID, StreamDirection, ChannelCount)>> =
+    Mutex::new(Vec::new());
+
+pub(super) fn is_enabled(
+    device: AudioDeviceID,
+    direction: StreamDirection,
+    channel: ChannelCount,
+) -> bool {
+    !DISABLED
+        .lock()
+        .unwrap()
+        .contains(&(device, direction, channel))
+}
+
+pub(super) fn set_enabled(
+    device: AudioDeviceID,
+    direction: StreamDirection,
+    channel: ChannelCount,
+    enabled: bool,
+) {
+    let mut disabled = DISABLED.lock().unwrap();
+    disabled.retain(|&entry| entry != (device, direction, channel));
+    if !enabled {
+        disabled.push((device, direction, channel));
+    }
+}
+
+/// The enabled channels among the first `device_channels` of the device, or `None` if all of
+/// them are.
+pub(super) fn enabled_channels(
+    device: AudioDeviceID,
+    direction: StreamDirection,
+    device_channels: ChannelCount,
+) -> Option<Vec<ChannelCount>> {
+    let disabled = DISABLED.lock().unwrap();
+    let enabled: Vec<_> = (0..device_channels)
+        .filter(|&channel| !disabled.contains(&(device, direction, channel)))
+        .collect();
+    if enabled.len() == device_channels as usize {
+        None
+    } else {
+        Some(enabled)
+    }
+}
+
+/// The value of `kAudioOutputUnitProperty_ChannelMap` passing only the `enabled` channels of a
+/// device with `device_channels` channels to or from a stream.
+///
+/// The map of an input stream holds the device channel for each of the stream's channels. The map
+/// of an output stream holds the stream channel for each of the device's channels, or -1 for
+/// none.
+pub(super) fn channel_map(
+    direction: StreamDirection,
+    enabled: &[ChannelCount],
+    device_channels: ChannelCount,
+) -> Vec<i32> {
+    match direction {
+        StreamDirection::Input => enabled.iter().map(|&channel| channel as i32).collect(),
+        StreamDirection::Output => {
+            let mut map = vec![-1; device_channels as usize];
+            for (stream_channel, &channel) in enabled.iter().enumerate() {
+                map[channel as usize] = stream_channel as i32;
+            }
+            map
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{channel_map, enabled_channels, is_enabled, set_enabled};
+    use crate::StreamDirection;
+
+    #[test]
+    fn test_enabled_channels() {
+        // No other test uses this device ID.
+        let device = 0xc4a1;
+        assert_eq!(enabled_channels(device, StreamDirection::Input, 4), None);
+        set_enabled(device, StreamDirection::Input, 1, false);
+        assert!(!is_enabled(device, StreamDirection::Input, 1));
+        assert!(is_enabled(device, StreamDirection::Output, 1));
+        assert_eq!(
+            enabled_channels(device, StreamDirection::Input, 4),
+            Some(vec![0, 2, 3])
+        );
+        set_enabled(device, StreamDirection::Input, 1, true);
+        assert_eq!(enabled_channels(device, StreamDirection::Input, 4), None);
+    }
+
+    #[test]
+    fn test_channel_map() {
+        assert_eq!(
+            channel_map(StreamDirection::Input, &[0, 2, 3], 4),
+            [0, 2, 3]
+        );
+        assert_eq!(
+            channel_map(StreamDirection::Output, &[0, 2, 3], 4),
+            [0, -1, 1, 2]
+        );
+    }
+}
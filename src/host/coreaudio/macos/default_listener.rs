@@ -0,0 +1,109 @@
+extern crate coreaudio;
+
+use self::coreaudio::sys::{
+    kAudioHardwarePropertyDefaultInputDevice, kAudioHardwarePropertyDefaultOutputDevice,
+    kAudioObjectPropertyElementMaster, kAudioObjectPropertyScopeGlobal, kAudioObjectSystemObject,
+    AudioObjectAddPropertyListener, AudioObjectID, AudioObjectPropertyAddress,
+    AudioObjectPropertySelector, AudioObjectRemovePropertyListener, OSStatus,
+};
+use super::{default_input_device, default_output_device, Device};
+use crate::{BackendSpecificError, DefaultDeviceEvent, StreamDirection};
+use std::os::raw::c_void;
+use std::sync::Mutex;
+
+type Callback = dyn FnMut(DefaultDeviceEvent<Device>) + Send;
+
+struct Context {
+    direction: StreamDirection,
+    callback: Mutex<Box<Callback>>,
+}
+
+/// Listens for changes to the system's default device in one direction and passes the new
+/// default to the user's callback. The listener is removed on drop.
+pub(super) struct DefaultListener {
+    selector: AudioObjectPropertySelector,
+    // Owned by the listener. Boxed so that its address, passed to CoreAudio, is stable.
+    context: *mut Context,
+}
+
+// The context is only accessed through shared references, and its fields are thread-safe.
+unsafe impl Send for DefaultListener {}
+
+impl DefaultListener {
+    pub(super) fn new<F>(
+        direction: StreamDirection,
+        callback: F,
+    ) -> Result<Self, BackendSpecificError>
+    where
+        F: FnMut(DefaultDeviceEvent<Device>) + Send + 'static,
+    {
+        let selector = match direction {
+            StreamDirection::Input => kAudioHardwarePropertyDefaultInputDevice,
+            StreamDirection::Output => kAudioHardwarePropertyDefaultOutputDevice,
+        };
+        let context = Box::into_raw(Box::new(Context {
+            direction,
+            callback: Mutex::new(Box::new(callback)),
+        }));
+        let status = unsafe {
+            AudioObjectAddPropertyListener(
+                kAudioObjectSystemObject,
+                &property_address(selector) as *const _,
+                Some(property_listener),
+                context as *mut c_void,
+            )
+        };
+        if status != 0 {
+            unsafe { drop(Box::from_raw(context)) };
+            return Err(BackendSpecificError {
+                description: format!(
+                    "failed to listen for changes to the default device (OSStatus {})",
+                    status
+                ),
+                code: Some(status),
+            });
+        }
+        Ok(DefaultListener { selector, context })
+    }
+}
+
+impl Drop for DefaultListener {
+    fn drop(&mut self) {
+        unsafe {
+            AudioObjectRemovePropertyListener(
+                kAudioObjectSystemObject,
+                &property_address(self.selector) as *const _,
+                Some(property_listener),
+                self.context as *mut c_void,
+            );
+            drop(Box::from_raw(self.context));
+        }
+    }
+}
+
+fn property_address(selector: AudioObjectPropertySelector) -> AudioObjectPropertyAddress {
+    AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    }
+}
+
+// Called by CoreAudio on one of its own threads whenever the default device changes.
+unsafe extern "C" fn property_listener(
+    _object_id: AudioObjectID,
+    _n_addresses: u32,
+    _addresses: *const AudioObjectPropertyAddress,
+    context: *mut c_void,
+) -> OSStatus {
+    let context = &*(context as *const Context);
+    let device = match context.direction {
+        StreamDirection::Input => default_input_device(),
+        StreamDirection::Output => default_output_device(),
+    };
+    // A poisoned lock means the callback panicked before; don't call it again.
+    if let Ok(mut callback) = context.callback.lock() {
+        callback(DefaultDeviceEvent::DefaultChanged { device });
+    }
+    0
+}
@@ -0,0 +1,290 @@
+extern crate coreaudio;
+
+use self::coreaudio::sys::{
+    kAudioDeviceProcessorOverload, kAudioDevicePropertyDeviceIsAlive,
+    kAudioDevicePropertyNominalSampleRate, kAudioObjectPropertyElementMaster,
+    kAudioObjectPropertyScopeGlobal, AudioDeviceID, AudioObjectAddPropertyListener,
+    AudioObjectGetPropertyData, AudioObjectID, AudioObjectPropertyAddress,
+    AudioObjectPropertySelector, AudioObjectRemovePropertyListener, OSStatus,
+};
+use crate::{BackendSpecificError, BtProfile, StreamError};
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr::null;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The user's error callback, shared between the audio unit's callback and the device listener.
+pub(super) type ErrorCallback = Arc<Mutex<dyn FnMut(StreamError) + Send>>;
+
+/// Passes `err` to the user's error callback.
+pub(super) fn report_error(error_callback: &ErrorCallback, err: StreamError) {
+    // A poisoned lock means the callback panicked before; don't call it again.
+    if let Ok(mut error_callback) = error_callback.lock() {
+        error_callback(err);
+    }
+}
+
+// The device properties a stream listens to.
+const SELECTORS: [AudioObjectPropertySelector; 3] = [
+    kAudioDevicePropertyDeviceIsAlive,
+    kAudioDeviceProcessorOverload,
+    // The nominal sample rate of a Bluetooth device changes with its profile.
+    kAudioDevicePropertyNominalSampleRate,
+];
+
+struct Context {
+    error_callback: ErrorCallback,
+    // Cleared once the device has been reported dead.
+    device_alive: AtomicBool,
+    // The Bluetooth profile of the device when it was last checked.
+    bluetooth_profile: Mutex<Option<BtProfile>>,
+}
+
+/// Listens for the removal of, processor overloads on, and Bluetooth profile switches of the
+/// device a stream runs on and reports them to the stream's error callback. The listeners are
+/// removed on drop.
+pub(super) struct DeviceListener {
+    device_id: AudioDeviceID,
+    // Owned by the listener. Boxed so that its address, passed to CoreAudio, is stable.
+    context: *mut Context,
+}
+
+// The context is only accessed through shared references, and its fields are thread-safe.
+unsafe impl Send for DeviceListener {}
+
+impl DeviceListener {
+    pub(super) fn new(
+        device_id: AudioDeviceID,
+        error_callback: ErrorCallback,
+    ) -> Result<Self, coreaudio::Error> {
+        let context = Box::into_raw(Box::new(Context {
+            error_callback,
+            device_alive: AtomicBool::new(true),
+            bluetooth_profile: Mutex::new(super::bluetooth_profile(device_id)),
+        }));
+        let listener = DeviceListener { device_id, context };
+        for &selector in SELECTORS.iter() {
+            let status = unsafe {
+                AudioObjectAddPropertyListener(
+                    device_id,
+                    &property_address(selector) as *const _,
+                    Some(property_listener),
+                    context as *mut c_void,
+                )
+            };
+            // Dropping `listener` removes the listeners added so far. Removing one that was never
+            // added is a harmless error.
+            coreaudio::Error::from_os_status(status)?;
+        }
+        Ok(listener)
+    }
+
+    /// Whether the device has not been reported dead.
+    pub(super) fn device_is_alive(&self) -> bool {
+        unsafe { (*self.context).device_alive.load(Ordering::Acquire) }
+    }
+}
+
+impl Drop for DeviceListener {
+    fn drop(&mut self) {
+        for &selector in SELECTORS.iter() {
+            unsafe {
+                AudioObjectRemovePropertyListener(
+                    self.device_id,
+                    &property_address(selector) as *const _,
+                    Some(property_listener),
+                    self.context as *mut c_void,
+                );
+            }
+        }
+        unsafe { drop(Box::from_raw(self.context)) };
+    }
+}
+
+fn property_address(selector: AudioObjectPropertySelector) -> AudioObjectPropertyAddress {
+    AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    }
+}
+
+// Called by CoreAudio on one of its own threads whenever a property we listen to changes.
+unsafe extern "C" fn property_listener(
+    device_id: AudioObjectID,
+    n_addresses: u32,
+    addresses: *const AudioObjectPropertyAddress,
+    context: *mut c_void,
+) -> OSStatus {
+    let context = &*(context as *const Context);
+    let addresses = slice::from_raw_parts(addresses, n_addresses as usize);
+    dispatch(
+        addresses,
+        &context.device_alive,
+        &context.bluetooth_profile,
+        || query_device_is_alive(device_id),
+        || super::bluetooth_profile(device_id),
+        |err| report_error(&context.error_callback, err),
+    );
+    0
+}
+
+/// Turns a property change notification into calls to `error_callback`.
+///
+/// Device removal is reported once, as `StreamError::DeviceNotAvailable`, and nothing is reported
+/// after it. A change of the nominal sample rate is reported as
+/// `StreamError::BluetoothProfileChanged` if the profile differs from `bluetooth_profile`.
+fn dispatch(
+    addresses: &[AudioObjectPropertyAddress],
+    device_alive: &AtomicBool,
+    bluetooth_profile: &Mutex<Option<BtProfile>>,
+    is_alive: impl Fn() -> bool,
+    query_profile: impl Fn() -> Option<BtProfile>,
+    mut error_callback: impl FnMut(StreamError),
+) {
+    for address in addresses {
+        if !device_alive.load(Ordering::Acquire) {
+            return;
+        }
+        match address.mSelector {
+            kAudioDevicePropertyDeviceIsAlive => {
+                if !is_alive() {
+                    debug!("device is no longer alive");
+                    device_alive.store(false, Ordering::Release);
+                    error_callback(StreamError::DeviceNotAvailable);
+                }
+            }
+            kAudioDeviceProcessorOverload => {
+                let description =
+                    "the device reported a processor overload: a buffer was not delivered in time"
+                        .to_string();
+                error_callback(
+                    BackendSpecificError {
+                        description,
+                        code: None,
+                    }
+                    .into(),
+                );
+            }
+            kAudioDevicePropertyNominalSampleRate => {
+                let profile = query_profile();
+                let mut last = bluetooth_profile
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                if profile != *last {
+                    *last = profile;
+                    drop(last);
+                    if let Some(profile) = profile {
+                        debug!(?profile, "the Bluetooth profile of the device changed");
+                        error_callback(StreamError::BluetoothProfileChanged { profile });
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn query_device_is_alive(device_id: AudioDeviceID) -> bool {
+    let property_address = property_address(kAudioDevicePropertyDeviceIsAlive);
+    let is_alive: u32 = 0;
+    let data_size = mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        AudioObjectGetPropertyData(
+            device_id,
+            &property_address as *const _,
+            0,
+            null(),
+            &data_size as *const _ as *mut _,
+            &is_alive as *const _ as *mut _,
+        )
+    };
+    // A device that can no longer be queried is gone.
+    status == 0 && is_alive != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notify(
+        selectors: &[AudioObjectPropertySelector],
+        device_alive: &AtomicBool,
+        is_alive: bool,
+    ) -> Vec<StreamError> {
+        notify_profile(selectors, device_alive, &Mutex::new(None), is_alive, None)
+    }
+
+    fn notify_profile(
+        selectors: &[AudioObjectPropertySelector],
+        device_alive: &AtomicBool,
+        bluetooth_profile: &Mutex<Option<BtProfile>>,
+        is_alive: bool,
+        profile: Option<BtProfile>,
+    ) -> Vec<StreamError> {
+        let addresses: Vec<_> = selectors.iter().map(|&s| property_address(s)).collect();
+        let mut errors = Vec::new();
+        dispatch(
+            &addresses,
+            device_alive,
+            bluetooth_profile,
+            || is_alive,
+            || profile,
+            |err| errors.push(err),
+        );
+        errors
+    }
+
+    #[test]
+    fn device_removal_is_reported_once() {
+        let device_alive = AtomicBool::new(true);
+        let removed = [kAudioDevicePropertyDeviceIsAlive];
+        let errors = notify(&removed, &device_alive, false);
+        assert!(matches!(errors[..], [StreamError::DeviceNotAvailable]));
+        assert!(!device_alive.load(Ordering::Acquire));
+        assert!(notify(&removed, &device_alive, false).is_empty());
+        assert!(notify(&[kAudioDeviceProcessorOverload], &device_alive, false).is_empty());
+    }
+
+    #[test]
+    fn alive_device_is_not_reported() {
+        let device_alive = AtomicBool::new(true);
+        assert!(notify(&[kAudioDevicePropertyDeviceIsAlive], &device_alive, true).is_empty());
+        assert!(device_alive.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn processor_overload_is_reported() {
+        let device_alive = AtomicBool::new(true);
+        let errors = notify(&[kAudioDeviceProcessorOverload, 0], &device_alive, true);
+        assert!(matches!(errors[..], [StreamError::BackendSpecific { .. }]));
+    }
+
+    #[test]
+    fn bluetooth_profile_switch_is_reported() {
+        let device_alive = AtomicBool::new(true);
+        let last = Mutex::new(Some(BtProfile::A2dp));
+        let rate = [kAudioDevicePropertyNominalSampleRate];
+        let switch_to = |profile| notify_profile(&rate, &device_alive, &last, true, profile);
+        assert!(switch_to(Some(BtProfile::A2dp)).is_empty());
+        let errors = switch_to(Some(BtProfile::HandsFree));
+        assert!(matches!(
+            errors[..],
+            [StreamError::BluetoothProfileChanged {
+                profile: BtProfile::HandsFree
+            }]
+        ));
+        assert!(switch_to(Some(BtProfile::HandsFree)).is_empty());
+        assert_eq!(switch_to(Some(BtProfile::A2dp)).len(), 1);
+    }
+
+    #[test]
+    fn rate_change_of_wired_device_is_not_reported() {
+        let device_alive = AtomicBool::new(true);
+        let last = Mutex::new(None);
+        let rate = [kAudioDevicePropertyNominalSampleRate];
+        assert!(notify_profile(&rate, &device_alive, &last, true, None).is_empty());
+    }
+}
@@ -66,7 +66,8 @@ impl Devices {
                 Ok(devices) => devices,
                 Err(os_status) => {
                     let description = format!("{}", os_status);
-                    let err = BackendSpecificError { description };
+                    let code = Some(os_status);
+                    let err = BackendSpecificError { description, code };
                     return Err(err.into());
                 }
             }
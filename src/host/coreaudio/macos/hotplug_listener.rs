@@ -0,0 +1,255 @@
+extern crate coreaudio;
+
+use self::coreaudio::sys::{
+    kAudioDevicePropertyVolumeScalar, kAudioHardwarePropertyDefaultInputDevice,
+    kAudioHardwarePropertyDefaultOutputDevice, kAudioHardwarePropertyDevices,
+    kAudioObjectPropertyElementMaster, kAudioObjectPropertyScopeGlobal, kAudioObjectSystemObject,
+    AudioObjectAddPropertyListener, AudioObjectID, AudioObjectPropertyAddress,
+    AudioObjectPropertySelector, AudioObjectRemovePropertyListener, OSStatus,
+};
+use super::{default_input_device, default_output_device, Device, Devices};
+use crate::host::device_events::{with_ids, KnownDevices};
+use crate::{BackendSpecificError, DeviceEvent, DeviceId, DevicesError};
+use std::mem;
+use std::os::raw::c_void;
+use std::slice;
+use std::sync::{Mutex, PoisonError};
+
+type Callback = dyn FnMut(DeviceEvent<Device>) + Send;
+
+// The properties of the system object listened to.
+const SELECTORS: [AudioObjectPropertySelector; 3] = [
+    kAudioHardwarePropertyDevices,
+    kAudioHardwarePropertyDefaultInputDevice,
+    kAudioHardwarePropertyDefaultOutputDevice,
+];
+
+struct Context {
+    known: Mutex<KnownDevices>,
+    // The listed devices with a volume control, along with the addresses of their volume controls
+    // listened to.
+    volumes: Mutex<Vec<(DeviceId, Device, Vec<AudioObjectPropertyAddress>)>>,
+    callback: Mutex<Box<Callback>>,
+}
+
+/// Listens for devices being added or removed, for changes to both default devices and for
+/// changes to the volume of each device, and passes them to the user's callback. The listeners
+/// are removed on drop.
+pub(super) struct HotplugListener {
+    // Owned by the listener. Boxed so that its address, passed to CoreAudio, is stable.
+    context: *mut Context,
+}
+
+// The context is only accessed through shared references, and its fields are thread-safe.
+unsafe impl Send for HotplugListener {}
+
+impl HotplugListener {
+    pub(super) fn new<F>(callback: F) -> Result<Self, BackendSpecificError>
+    where
+        F: FnMut(DeviceEvent<Device>) + Send + 'static,
+    {
+        let devices = Devices::new().map_err(|err| match err {
+            DevicesError::BackendSpecific { err } => err,
+        })?;
+        let devices = with_ids(devices);
+        let listed: Vec<_> = devices
+            .iter()
+            .map(|(id, device)| (id.clone(), device.clone()))
+            .collect();
+        let context = Box::into_raw(Box::new(Context {
+            known: Mutex::new(KnownDevices::new(devices)),
+            volumes: Mutex::new(Vec::new()),
+            callback: Mutex::new(Box::new(callback)),
+        }));
+        for (added, &selector) in SELECTORS.iter().enumerate() {
+            let status = unsafe {
+                AudioObjectAddPropertyListener(
+                    kAudioObjectSystemObject,
+                    &property_address(selector) as *const _,
+                    Some(property_listener),
+                    context as *mut c_void,
+                )
+            };
+            if status != 0 {
+                unsafe { remove_listeners(&SELECTORS[..added], context) };
+                return Err(BackendSpecificError {
+                    description: format!(
+                        "failed to listen for changes to the devices (OSStatus {})",
+                        status
+                    ),
+                    code: Some(status),
+                });
+            }
+        }
+        for (id, device) in listed {
+            unsafe { watch_volume(id, device, context) };
+        }
+        Ok(HotplugListener { context })
+    }
+}
+
+impl Drop for HotplugListener {
+    fn drop(&mut self) {
+        unsafe { remove_listeners(&SELECTORS, self.context) };
+    }
+}
+
+// Removes the listeners on `selectors` and on the volume of the devices, and frees `context`.
+unsafe fn remove_listeners(selectors: &[AudioObjectPropertySelector], context: *mut Context) {
+    for &selector in selectors {
+        AudioObjectRemovePropertyListener(
+            kAudioObjectSystemObject,
+            &property_address(selector) as *const _,
+            Some(property_listener),
+            context as *mut c_void,
+        );
+    }
+    let volumes = mem::take(
+        &mut *(*context)
+            .volumes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+    for (_, device, addresses) in volumes {
+        remove_volume_listeners(device.audio_device_id, &addresses, context);
+    }
+    drop(Box::from_raw(context));
+}
+
+// Listens for changes to the volume of `device`, if it has a volume control.
+unsafe fn watch_volume(id: DeviceId, device: Device, context: *mut Context) {
+    let Some(addresses) = device.volume_controls() else {
+        return;
+    };
+    for (added, address) in addresses.iter().enumerate() {
+        let status = AudioObjectAddPropertyListener(
+            device.audio_device_id,
+            address as *const _,
+            Some(property_listener),
+            context as *mut c_void,
+        );
+        if status != 0 {
+            remove_volume_listeners(device.audio_device_id, &addresses[..added], context);
+            return;
+        }
+    }
+    (*context)
+        .volumes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push((id, device, addresses));
+}
+
+// Stops listening for changes to the volume of the device with ID `id`.
+unsafe fn unwatch_volume(id: &DeviceId, context: *mut Context) {
+    let removed: Vec<_> = {
+        let mut volumes = (*context)
+            .volumes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (removed, kept) = mem::take(&mut *volumes)
+            .into_iter()
+            .partition(|(watched, _, _)| watched == id);
+        *volumes = kept;
+        removed
+    };
+    for (_, device, addresses) in removed {
+        remove_volume_listeners(device.audio_device_id, &addresses, context);
+    }
+}
+
+unsafe fn remove_volume_listeners(
+    object_id: AudioObjectID,
+    addresses: &[AudioObjectPropertyAddress],
+    context: *mut Context,
+) {
+    for address in addresses {
+        AudioObjectRemovePropertyListener(
+            object_id,
+            address as *const _,
+            Some(property_listener),
+            context as *mut c_void,
+        );
+    }
+}
+
+fn property_address(selector: AudioObjectPropertySelector) -> AudioObjectPropertyAddress {
+    AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    }
+}
+
+// Called by CoreAudio on one of its own threads with the properties of `object_id` that changed,
+// either the system object or a device whose volume is listened to.
+unsafe extern "C" fn property_listener(
+    object_id: AudioObjectID,
+    n_addresses: u32,
+    addresses: *const AudioObjectPropertyAddress,
+    context: *mut c_void,
+) -> OSStatus {
+    let context_ptr = context as *mut Context;
+    let context = &*context_ptr;
+    let addresses = slice::from_raw_parts(addresses, n_addresses as usize);
+    let mut events = Vec::new();
+    let mut volume_changed = false;
+    for address in addresses {
+        match address.mSelector {
+            kAudioHardwarePropertyDevices => {
+                // The next change reports the devices if they cannot be listed now.
+                let (Ok(devices), Ok(mut known)) = (Devices::new(), context.known.lock()) else {
+                    continue;
+                };
+                let changes = known.update(with_ids(devices));
+                drop(known);
+                for event in &changes {
+                    match event {
+                        DeviceEvent::Removed { id } => unwatch_volume(id, context_ptr),
+                        DeviceEvent::Added { device } => {
+                            if let Ok(id) = device.id() {
+                                watch_volume(id, device.clone(), context_ptr);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                events.extend(changes);
+            }
+            // Devices without a main volume control notify once for each channel.
+            kAudioDevicePropertyVolumeScalar => volume_changed = true,
+            kAudioHardwarePropertyDefaultInputDevice => {
+                events.push(DeviceEvent::DefaultInputChanged {
+                    device: default_input_device(),
+                });
+            }
+            kAudioHardwarePropertyDefaultOutputDevice => {
+                events.push(DeviceEvent::DefaultOutputChanged {
+                    device: default_output_device(),
+                });
+            }
+            _ => {}
+        }
+    }
+    if volume_changed {
+        let device = context
+            .volumes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(_, device, _)| device.audio_device_id == object_id)
+            .map(|(_, device, _)| device.clone());
+        if let Some(device) = device {
+            if let Some(volume) = device.volume() {
+                events.push(DeviceEvent::VolumeChanged { device, volume });
+            }
+        }
+    }
+    // A poisoned lock means the callback panicked before; don't call it again.
+    if let Ok(mut callback) = context.callback.lock() {
+        for event in events {
+            callback(event);
+        }
+    }
+    0
+}
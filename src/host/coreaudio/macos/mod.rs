@@ -1,49 +1,103 @@
 extern crate core_foundation_sys;
 extern crate coreaudio;
 
-use super::{asbd_from_config, check_os_status, frames_to_duration, host_time_to_stream_instant};
+use super::{
+    asbd_from_config, check_os_status, device_timestamp, frames_to_duration,
+    host_time_to_stream_instant,
+};
 
 use self::core_foundation_sys::string::{CFStringGetCString, CFStringGetCStringPtr, CFStringRef};
 use self::coreaudio::audio_unit::render_callback::{self, data};
 use self::coreaudio::audio_unit::{AudioUnit, Element, Scope};
 use self::coreaudio::sys::{
-    kAudioDevicePropertyAvailableNominalSampleRates, kAudioDevicePropertyBufferFrameSize,
-    kAudioDevicePropertyBufferFrameSizeRange, kAudioDevicePropertyDeviceNameCFString,
+    kAudioDevicePermissionsError, kAudioDevicePropertyAvailableNominalSampleRates,
+    kAudioDevicePropertyBufferFrameSize, kAudioDevicePropertyBufferFrameSizeRange,
+    kAudioDevicePropertyDeviceNameCFString, kAudioDevicePropertyDeviceUID,
     kAudioDevicePropertyNominalSampleRate, kAudioDevicePropertyScopeOutput,
     kAudioDevicePropertyStreamConfiguration, kAudioDevicePropertyStreamFormat,
+    kAudioDevicePropertyStreams, kAudioDevicePropertyTransportType,
+    kAudioDevicePropertyVolumeDecibels, kAudioDevicePropertyVolumeScalar,
+    kAudioDeviceTransportTypeBluetooth, kAudioDeviceTransportTypeBluetoothLE,
+    kAudioDeviceTransportTypeVirtual, kAudioFormatFlagIsAlignedHigh, kAudioFormatFlagIsFloat,
+    kAudioFormatFlagIsPacked, kAudioFormatFlagIsSignedInteger, kAudioFormatLinearPCM,
     kAudioObjectPropertyElementMaster, kAudioObjectPropertyScopeGlobal,
     kAudioObjectPropertyScopeInput, kAudioObjectPropertyScopeOutput,
-    kAudioOutputUnitProperty_CurrentDevice, kAudioOutputUnitProperty_EnableIO,
-    kAudioUnitProperty_StreamFormat, kCFStringEncodingUTF8, AudioBuffer, AudioBufferList,
-    AudioDeviceID, AudioObjectAddPropertyListener, AudioObjectGetPropertyData,
-    AudioObjectGetPropertyDataSize, AudioObjectID, AudioObjectPropertyAddress,
-    AudioObjectPropertyScope, AudioObjectRemovePropertyListener, AudioObjectSetPropertyData,
-    AudioStreamBasicDescription, AudioValueRange, OSStatus,
+    kAudioOutputUnitProperty_ChannelMap, kAudioOutputUnitProperty_CurrentDevice,
+    kAudioOutputUnitProperty_EnableIO, kAudioStreamPropertyPhysicalFormat,
+    kAudioUnitProperty_StreamFormat, kCFStringEncodingUTF8,
+    kLinearPCMFormatFlagsSampleFractionMask, kLinearPCMFormatFlagsSampleFractionShift, AudioBuffer,
+    AudioBufferList, AudioDeviceID, AudioObjectAddPropertyListener, AudioObjectGetPropertyData,
+    AudioObjectGetPropertyDataSize, AudioObjectHasProperty, AudioObjectID,
+    AudioObjectIsPropertySettable, AudioObjectPropertyAddress, AudioObjectPropertyScope,
+    AudioObjectRemovePropertyListener, AudioObjectSetPropertyData, AudioStreamBasicDescription,
+    AudioStreamID, AudioUnitSetProperty, AudioValueRange, Boolean, OSStatus,
 };
+use self::default_listener::DefaultListener;
+use self::device_listener::{report_error, DeviceListener, ErrorCallback};
+use self::hotplug_listener::HotplugListener;
+use crate::host::audio_thread::{AudioThread, DROP_TIMEOUT};
+use crate::host::callback_guard;
+use crate::host::frame_align;
+use crate::host::keep_alive::{OutputMode, PauseState};
+use crate::samples_formats::fill_silence;
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, ChannelCount, Data,
-    DefaultStreamConfigError, DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo,
-    PauseStreamError, PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError,
-    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
-    SupportedStreamConfigsError,
+    BackendSpecificError, BtProfile, BufferSize, BuildStreamError, ChannelCount, ChannelMix, Data,
+    DefaultDeviceEvent, DefaultStreamConfigError, DefaultWatcher, DeviceEvent, DeviceId,
+    DeviceNameError, DeviceSampleRateError, DeviceVolumeError, DeviceWatcher, DevicesError,
+    FrameCount, InputCallbackInfo, KeepAlive, KeepAliveError, OutputCallbackInfo, PauseStreamError,
+    PermissionStatus, PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamDirection,
+    StreamError, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError, WatchDefaultError, WatchDevicesError,
 };
 use std::cell::RefCell;
 use std::ffi::CStr;
 use std::fmt;
-use std::mem;
+use std::mem::{self, ManuallyDrop};
 use std::os::raw::c_char;
 use std::ptr::null;
 use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub use self::enumerate::{
     default_input_device, default_output_device, Devices, SupportedInputConfigs,
     SupportedOutputConfigs,
 };
 
+mod channel_map;
+mod default_listener;
+mod device_listener;
 pub mod enumerate;
+mod hotplug_listener;
+
+/// CoreAudio specific extensions to the device.
+pub trait DeviceExt {
+    /// Whether `channel` of the device, counted from 0, is enabled in `direction`. Channels are
+    /// enabled unless disabled through [`set_channel_enabled`](DeviceExt::set_channel_enabled).
+    fn channel_enabled(&self, direction: StreamDirection, channel: ChannelCount) -> bool;
+
+    /// Enables or disables `channel` of the device, counted from 0, for the streams of this
+    /// process in `direction`.
+    ///
+    /// Streams only move the enabled channels of the device, which lowers the load of devices
+    /// with many channels when only some of them are needed, and may allow smaller buffers.
+    /// The setting is kept for the device until the process exits, and applies to streams
+    /// built afterwards.
+    ///
+    /// Disabling channels changes the channels of the device's configs and of its streams: they
+    /// have one channel per enabled channel, in the order of the device's channels, so the
+    /// indices seen by the data callback shift past every disabled channel. Enumerate the
+    /// supported configs again after a change.
+    fn set_channel_enabled(
+        &self,
+        direction: StreamDirection,
+        channel: ChannelCount,
+        enabled: bool,
+    ) -> Result<(), BackendSpecificError>;
+}
 
 /// Coreaudio host, the default host on macOS.
 #[derive(Debug)]
@@ -75,6 +129,37 @@ impl HostTrait for Host {
     fn default_output_device(&self) -> Option<Self::Device> {
         default_output_device()
     }
+
+    fn watch_default<F>(
+        &self,
+        direction: StreamDirection,
+        callback: F,
+    ) -> Result<DefaultWatcher, WatchDefaultError>
+    where
+        F: FnMut(DefaultDeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let listener = DefaultListener::new(direction, callback)?;
+        Ok(DefaultWatcher::new(listener))
+    }
+
+    fn watch_devices<F>(&self, callback: F) -> Result<DeviceWatcher, WatchDevicesError>
+    where
+        F: FnMut(DeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let listener = HotplugListener::new(callback)?;
+        Ok(DeviceWatcher::new(listener))
+    }
+
+    fn input_permission_status(&self) -> PermissionStatus {
+        super::permission::input_permission_status()
+    }
+
+    fn request_input_permission<F>(&self, callback: F)
+    where
+        F: FnOnce(PermissionStatus) + Send + 'static,
+    {
+        super::permission::request_input_permission(Box::new(callback))
+    }
 }
 
 impl DeviceTrait for Device {
@@ -86,6 +171,10 @@ impl DeviceTrait for Device {
         Device::name(self)
     }
 
+    fn id(&self) -> Result<DeviceId, DeviceNameError> {
+        Device::id(self)
+    }
+
     fn supported_input_configs(
         &self,
     ) -> Result<Self::SupportedInputConfigs, SupportedStreamConfigsError> {
@@ -106,6 +195,38 @@ impl DeviceTrait for Device {
         Device::default_output_config(self)
     }
 
+    fn physical_channels(&self, direction: StreamDirection) -> Option<ChannelCount> {
+        Device::physical_channels(self, direction)
+    }
+
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        Device::native_sample_format(self, direction)
+    }
+
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        Device::bluetooth_profile(self)
+    }
+
+    fn is_virtual(&self) -> bool {
+        Device::is_virtual(self)
+    }
+
+    fn volume(&self) -> Option<f32> {
+        Device::volume(self)
+    }
+
+    fn input_sensitivity(&self) -> Option<f32> {
+        Device::input_sensitivity(self)
+    }
+
+    fn set_volume(&self, volume: f32) -> Result<(), DeviceVolumeError> {
+        Device::set_volume(self, volume)
+    }
+
+    fn set_sample_rate(&self, rate: SampleRate) -> Result<SampleRate, DeviceSampleRateError> {
+        Device::set_sample_rate(self, rate)
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         config: &StreamConfig,
@@ -117,6 +238,9 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
+        let data_callback = frame_align::align_input(config.channels, sample_format, data_callback);
         Device::build_input_stream_raw(self, config, sample_format, data_callback, error_callback)
     }
 
@@ -131,6 +255,10 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
+        let data_callback =
+            frame_align::align_output(config.channels, sample_format, data_callback);
         Device::build_output_stream_raw(self, config, sample_format, data_callback, error_callback)
     }
 }
@@ -143,12 +271,29 @@ pub struct Device {
 
 impl Device {
     fn name(&self) -> Result<String, DeviceNameError> {
-        let property_address = AudioObjectPropertyAddress {
+        self.string_property(AudioObjectPropertyAddress {
             mSelector: kAudioDevicePropertyDeviceNameCFString,
             mScope: kAudioDevicePropertyScopeOutput,
             mElement: kAudioObjectPropertyElementMaster,
-        };
-        let device_name: CFStringRef = null();
+        })
+    }
+
+    // The UID persists across reboots for the same device, unlike its `AudioDeviceID`.
+    fn id(&self) -> Result<DeviceId, DeviceNameError> {
+        self.string_property(AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyDeviceUID,
+            mScope: kAudioObjectPropertyScopeGlobal,
+            mElement: kAudioObjectPropertyElementMaster,
+        })
+        .map(DeviceId::new)
+    }
+
+    // Reads a `CFString` property of the device.
+    fn string_property(
+        &self,
+        property_address: AudioObjectPropertyAddress,
+    ) -> Result<String, DeviceNameError> {
+        let string: CFStringRef = null();
         let data_size = mem::size_of::<CFStringRef>();
         let c_str = unsafe {
             let status = AudioObjectGetPropertyData(
@@ -157,11 +302,11 @@ impl Device {
                 0,
                 null(),
                 &data_size as *const _ as *mut _,
-                &device_name as *const _ as *mut _,
+                &string as *const _ as *mut _,
             );
             check_os_status(status)?;
 
-            let c_string: *const c_char = CFStringGetCStringPtr(device_name, kCFStringEncodingUTF8);
+            let c_string: *const c_char = CFStringGetCStringPtr(string, kCFStringEncodingUTF8);
             if c_string.is_null() {
                 let status = AudioObjectGetPropertyData(
                     self.audio_device_id,
@@ -169,24 +314,27 @@ impl Device {
                     0,
                     null(),
                     &data_size as *const _ as *mut _,
-                    &device_name as *const _ as *mut _,
+                    &string as *const _ as *mut _,
                 );
                 check_os_status(status)?;
                 let mut buf: [i8; 255] = [0; 255];
                 let result = CFStringGetCString(
-                    device_name,
+                    string,
                     buf.as_mut_ptr(),
                     buf.len() as _,
                     kCFStringEncodingUTF8,
                 );
                 if result == 0 {
                     let description =
-                        "core foundation failed to return device name string".to_string();
-                    let err = BackendSpecificError { description };
+                        "core foundation failed to return the device property string".to_string();
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
                     return Err(err.into());
                 }
-                let name: &CStr = CStr::from_ptr(buf.as_ptr());
-                return Ok(name.to_str().unwrap().to_owned());
+                let c_str: &CStr = CStr::from_ptr(buf.as_ptr());
+                return Ok(c_str.to_str().unwrap().to_owned());
             }
             CStr::from_ptr(c_string as *mut _)
         };
@@ -244,6 +392,14 @@ impl Device {
             for buffer in buffers {
                 n_channels += buffer.mNumberChannels as usize;
             }
+            // Streams only have the enabled channels, see `DeviceExt::set_channel_enabled`.
+            if let Some(enabled) = channel_map::enabled_channels(
+                self.audio_device_id,
+                direction_of_scope(scope),
+                n_channels as ChannelCount,
+            ) {
+                n_channels = enabled.len();
+            }
 
             // TODO: macOS should support U8, I16, I32, F32 and F64. This should allow for using
             // I16 but just use F32 for now as it's the default anyway.
@@ -333,7 +489,8 @@ impl Device {
                 }
                 err => {
                     let description = format!("{}", err);
-                    let err = BackendSpecificError { description };
+                    let code = Some(err.as_os_status());
+                    let err = BackendSpecificError { description, code };
                     Err(err.into())
                 }
             }
@@ -358,33 +515,25 @@ impl Device {
             );
             default_config_error_from_os_status(status)?;
 
-            let sample_format = {
-                let audio_format = coreaudio::audio_unit::AudioFormat::from_format_and_flag(
-                    asbd.mFormatID,
-                    Some(asbd.mFormatFlags),
-                );
-                let flags = match audio_format {
-                    Some(coreaudio::audio_unit::AudioFormat::LinearPCM(flags)) => flags,
-                    _ => return Err(DefaultStreamConfigError::StreamTypeNotSupported),
-                };
-                let maybe_sample_format =
-                    coreaudio::audio_unit::SampleFormat::from_flags_and_bytes_per_frame(
-                        flags,
-                        asbd.mBytesPerFrame,
-                    );
-                match maybe_sample_format {
-                    Some(coreaudio::audio_unit::SampleFormat::F32) => SampleFormat::F32,
-                    Some(coreaudio::audio_unit::SampleFormat::I16) => SampleFormat::I16,
-                    _ => return Err(DefaultStreamConfigError::StreamTypeNotSupported),
-                }
-            };
+            let sample_format = sample_format_from_asbd(&asbd)
+                .ok_or(DefaultStreamConfigError::StreamTypeNotSupported)?;
 
             let audio_unit = audio_unit_from_device(self, true)?;
             let buffer_size = get_io_buffer_frame_size_range(&audio_unit)?;
 
+            // Streams only have the enabled channels, see `DeviceExt::set_channel_enabled`.
+            let channels = match channel_map::enabled_channels(
+                self.audio_device_id,
+                direction_of_scope(scope),
+                asbd.mChannelsPerFrame as _,
+            ) {
+                Some(enabled) => enabled.len() as ChannelCount,
+                None => asbd.mChannelsPerFrame as _,
+            };
+
             let config = SupportedStreamConfig {
                 sample_rate: SampleRate(asbd.mSampleRate as _),
-                channels: asbd.mChannelsPerFrame as _,
+                channels,
                 buffer_size,
                 sample_format,
             };
@@ -399,6 +548,358 @@ impl Device {
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
         self.default_config(kAudioObjectPropertyScopeOutput)
     }
+
+    /// The sum of the channels of the physical formats of the device's streams in the scope.
+    ///
+    /// The virtual format of a stream, which clients see, may differ from the physical format the
+    /// hardware runs at.
+    fn physical_channels(&self, direction: StreamDirection) -> Option<ChannelCount> {
+        let channels: u32 = self
+            .physical_formats(direction)?
+            .iter()
+            .map(|asbd| asbd.mChannelsPerFrame)
+            .sum();
+        channels.try_into().ok()
+    }
+
+    /// The physical format of the device's first stream in the scope, if it is a `SampleFormat`.
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let formats = self.physical_formats(direction)?;
+        physical_sample_format(formats.first()?)
+    }
+
+    /// The bits of each sample in a stream of `sample_format` that reach the device, at the
+    /// resolution of the physical formats of its streams in the scope.
+    fn effective_bit_depth(&self, direction: StreamDirection, sample_format: SampleFormat) -> u8 {
+        let bits = u32::from(sample_format.bits());
+        let device_bits = self
+            .physical_formats(direction)
+            .and_then(|formats| formats.iter().map(|asbd| asbd.mBitsPerChannel).max());
+        match device_bits {
+            Some(device_bits) if device_bits != 0 => bits.min(device_bits) as u8,
+            _ => bits as u8,
+        }
+    }
+
+    /// The physical formats of the device's streams in the scope.
+    fn physical_formats(
+        &self,
+        direction: StreamDirection,
+    ) -> Option<Vec<AudioStreamBasicDescription>> {
+        let scope = match direction {
+            StreamDirection::Input => kAudioObjectPropertyScopeInput,
+            StreamDirection::Output => kAudioObjectPropertyScopeOutput,
+        };
+        let property_address = AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyStreams,
+            mScope: scope,
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        unsafe {
+            let data_size = 0u32;
+            let status = AudioObjectGetPropertyDataSize(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+            );
+            check_os_status(status).ok()?;
+            let n_streams = data_size as usize / mem::size_of::<AudioStreamID>();
+            let mut streams: Vec<AudioStreamID> = vec![0; n_streams];
+            let status = AudioObjectGetPropertyData(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+                streams.as_mut_ptr() as *mut _,
+            );
+            check_os_status(status).ok()?;
+
+            let mut formats = Vec::with_capacity(streams.len());
+            for &stream in &streams {
+                let property_address = AudioObjectPropertyAddress {
+                    mSelector: kAudioStreamPropertyPhysicalFormat,
+                    mScope: kAudioObjectPropertyScopeGlobal,
+                    mElement: kAudioObjectPropertyElementMaster,
+                };
+                let asbd: AudioStreamBasicDescription = mem::zeroed();
+                let data_size = mem::size_of::<AudioStreamBasicDescription>() as u32;
+                let status = AudioObjectGetPropertyData(
+                    stream,
+                    &property_address as *const _,
+                    0,
+                    null(),
+                    &data_size as *const _ as *mut _,
+                    &asbd as *const _ as *mut _,
+                );
+                check_os_status(status).ok()?;
+                formats.push(asbd);
+            }
+            Some(formats)
+        }
+    }
+
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        bluetooth_profile(self.audio_device_id)
+    }
+
+    fn is_virtual(&self) -> bool {
+        let transport_type: Option<u32> =
+            unsafe { get_global_property(self.audio_device_id, kAudioDevicePropertyTransportType) };
+        transport_type == Some(kAudioDeviceTransportTypeVirtual)
+    }
+
+    /// The addresses of the device's volume controls.
+    ///
+    /// This is the main volume control of the output scope, or else of the input scope. Devices
+    /// without a main control are controlled through the volume of each of their channels.
+    fn volume_controls(&self) -> Option<Vec<AudioObjectPropertyAddress>> {
+        let has_property = |address: &AudioObjectPropertyAddress| unsafe {
+            AudioObjectHasProperty(self.audio_device_id, address as *const _) != 0
+        };
+        for &scope in &[
+            kAudioObjectPropertyScopeOutput,
+            kAudioObjectPropertyScopeInput,
+        ] {
+            let address = |element| AudioObjectPropertyAddress {
+                mSelector: kAudioDevicePropertyVolumeScalar,
+                mScope: scope,
+                mElement: element,
+            };
+            let main = address(kAudioObjectPropertyElementMaster);
+            if has_property(&main) {
+                return Some(vec![main]);
+            }
+            // Channels are numbered from 1.
+            let channels: Vec<_> = (1..).map(address).take_while(has_property).collect();
+            if !channels.is_empty() {
+                return Some(channels);
+            }
+        }
+        None
+    }
+
+    fn volume(&self) -> Option<f32> {
+        let controls = self.volume_controls()?;
+        let mut sum = 0.0;
+        for address in &controls {
+            let volume: f32 = 0.0;
+            let data_size = mem::size_of::<f32>() as u32;
+            let status = unsafe {
+                AudioObjectGetPropertyData(
+                    self.audio_device_id,
+                    address as *const _,
+                    0,
+                    null(),
+                    &data_size as *const _ as *mut _,
+                    &volume as *const _ as *mut _,
+                )
+            };
+            check_os_status(status).ok()?;
+            sum += volume;
+        }
+        Some(sum / controls.len() as f32)
+    }
+
+    /// The volume of the main element of the input scope, in dB.
+    fn input_sensitivity(&self) -> Option<f32> {
+        let property_address = AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyVolumeDecibels,
+            mScope: kAudioObjectPropertyScopeInput,
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        if unsafe { AudioObjectHasProperty(self.audio_device_id, &property_address as *const _) }
+            == 0
+        {
+            return None;
+        }
+        let level_db: f32 = 0.0;
+        let data_size = mem::size_of::<f32>() as u32;
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+                &level_db as *const _ as *mut _,
+            )
+        };
+        check_os_status(status).ok()?;
+        Some(level_db)
+    }
+
+    /// Sets the volume, of every channel if the device has no main volume control.
+    fn set_volume(&self, volume: f32) -> Result<(), DeviceVolumeError> {
+        let controls = self
+            .volume_controls()
+            .ok_or(DeviceVolumeError::NotSupported)?;
+        let volume = volume.clamp(0.0, 1.0);
+        for address in &controls {
+            let status = unsafe {
+                AudioObjectSetPropertyData(
+                    self.audio_device_id,
+                    address as *const _,
+                    0,
+                    null(),
+                    mem::size_of::<f32>() as u32,
+                    &volume as *const _ as *const _,
+                )
+            };
+            check_os_status(status)?;
+        }
+        Ok(())
+    }
+
+    /// The ranges of nominal sample rates supported by the device.
+    fn available_sample_rates(&self) -> Result<Vec<AudioValueRange>, BackendSpecificError> {
+        let property_address = AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyAvailableNominalSampleRates,
+            mScope: kAudioObjectPropertyScopeGlobal,
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        let data_size = 0u32;
+        let status = unsafe {
+            AudioObjectGetPropertyDataSize(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+            )
+        };
+        check_os_status(status)?;
+        let n_ranges = data_size as usize / mem::size_of::<AudioValueRange>();
+        let mut ranges = vec![
+            AudioValueRange {
+                mMinimum: 0.0,
+                mMaximum: 0.0,
+            };
+            n_ranges
+        ];
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+                ranges.as_mut_ptr() as *mut _,
+            )
+        };
+        check_os_status(status)?;
+        ranges.truncate(data_size as usize / mem::size_of::<AudioValueRange>());
+        Ok(ranges)
+    }
+
+    /// Sets the nominal sample rate, waiting up to a second for the device to apply it.
+    fn set_sample_rate(&self, rate: SampleRate) -> Result<SampleRate, DeviceSampleRateError> {
+        let property_address = AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyNominalSampleRate,
+            mScope: kAudioObjectPropertyScopeGlobal,
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        // Devices that follow the clock of another device, such as those in an aggregate device,
+        // don't allow setting their rate.
+        let mut settable: Boolean = 0;
+        let status = unsafe {
+            AudioObjectIsPropertySettable(
+                self.audio_device_id,
+                &property_address as *const _,
+                &mut settable as *mut _,
+            )
+        };
+        check_os_status(status)?;
+        if settable == 0 {
+            return Err(DeviceSampleRateError::SampleRateLocked);
+        }
+
+        let requested = rate.0 as f64;
+        let supported = self
+            .available_sample_rates()?
+            .iter()
+            .any(|range| range.mMinimum <= requested && requested <= range.mMaximum);
+        if !supported {
+            return Err(DeviceSampleRateError::SampleRateNotSupported);
+        }
+
+        let status = unsafe {
+            AudioObjectSetPropertyData(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                mem::size_of::<f64>() as u32,
+                &requested as *const _ as *const _,
+            )
+        };
+        // Another process has the device in hog mode.
+        if status == kAudioDevicePermissionsError as OSStatus {
+            return Err(DeviceSampleRateError::SampleRateLocked);
+        }
+        check_os_status(status)?;
+
+        // The device applies the new rate asynchronously, and may settle on another one.
+        let start = Instant::now();
+        loop {
+            let current: f64 = unsafe {
+                get_global_property(self.audio_device_id, kAudioDevicePropertyNominalSampleRate)
+            }
+            .ok_or_else(|| BackendSpecificError {
+                description: "failed to read the nominal sample rate of the device".into(),
+                code: None,
+            })?;
+            if current as u32 == rate.0 || start.elapsed() > Duration::from_secs(1) {
+                return Ok(SampleRate(current as u32));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+// Reads a global property of a fixed-size type `T` from `object`.
+unsafe fn get_global_property<T: Default>(object: AudioObjectID, selector: u32) -> Option<T> {
+    let property_address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+    let value = T::default();
+    let data_size = mem::size_of::<T>() as u32;
+    let status = AudioObjectGetPropertyData(
+        object,
+        &property_address as *const _,
+        0,
+        null(),
+        &data_size as *const _ as *mut _,
+        &value as *const _ as *mut _,
+    );
+    check_os_status(status).ok()?;
+    Some(value)
+}
+
+// CoreAudio doesn't expose the profile of a Bluetooth device, but a headset in the Hands-Free
+// profile runs at a nominal sample rate of 8 or 16 kHz, whereas A2DP runs at 44.1 or 48 kHz.
+fn bluetooth_profile(device_id: AudioDeviceID) -> Option<BtProfile> {
+    let transport_type: u32 =
+        unsafe { get_global_property(device_id, kAudioDevicePropertyTransportType)? };
+    if transport_type != kAudioDeviceTransportTypeBluetooth
+        && transport_type != kAudioDeviceTransportTypeBluetoothLE
+    {
+        return None;
+    }
+    let sample_rate: f64 =
+        match unsafe { get_global_property(device_id, kAudioDevicePropertyNominalSampleRate) } {
+            Some(sample_rate) => sample_rate,
+            None => return Some(BtProfile::Unknown),
+        };
+    if sample_rate <= 16_000.0 {
+        Some(BtProfile::HandsFree)
+    } else {
+        Some(BtProfile::A2dp)
+    }
 }
 
 impl fmt::Debug for Device {
@@ -412,13 +913,57 @@ impl fmt::Debug for Device {
 
 struct StreamInner {
     playing: bool,
-    audio_unit: AudioUnit,
+    // Disposed of by `Stream::dispose`, which may have to do so on another thread.
+    audio_unit: ManuallyDrop<AudioUnit>,
     // Track the device with which the audio unit was spawned.
     //
     // We must do this so that we can avoid changing the device sample rate if there is already
     // a stream associated with the device.
     #[allow(dead_code)]
     device_id: AudioDeviceID,
+    // The configuration and sample format with which the stream was created.
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    // The bits of each sample that reach the device, for `StreamTrait::effective_bit_depth`.
+    bit_depth: u8,
+    // The frames of the current or last render call of an output stream, for
+    // `StreamTrait::next_block_frames`. `None` for input streams.
+    next_block_frames: Option<Arc<AtomicU32>>,
+}
+
+impl DeviceExt for Device {
+    fn channel_enabled(&self, direction: StreamDirection, channel: ChannelCount) -> bool {
+        channel_map::is_enabled(self.audio_device_id, direction, channel)
+    }
+
+    fn set_channel_enabled(
+        &self,
+        direction: StreamDirection,
+        channel: ChannelCount,
+        enabled: bool,
+    ) -> Result<(), BackendSpecificError> {
+        let channels = self.stream_channels(direction)?;
+        if channel >= channels {
+            let description = format!(
+                "channel {} is out of range for a device with {} {:?} channels",
+                channel, channels, direction
+            );
+            return Err(BackendSpecificError {
+                description,
+                code: None,
+            });
+        }
+        channel_map::set_enabled(self.audio_device_id, direction, channel, enabled);
+        Ok(())
+    }
+}
+
+fn direction_of_scope(scope: AudioObjectPropertyScope) -> StreamDirection {
+    if scope == kAudioObjectPropertyScopeInput {
+        StreamDirection::Input
+    } else {
+        StreamDirection::Output
+    }
 }
 
 fn audio_unit_from_device(device: &Device, input: bool) -> Result<AudioUnit, coreaudio::Error> {
@@ -460,6 +1005,87 @@ fn audio_unit_from_device(device: &Device, input: bool) -> Result<AudioUnit, cor
 }
 
 impl Device {
+    /// The number of channels of the device's streams in `direction`, before any are disabled.
+    #[allow(clippy::cast_ptr_alignment)]
+    fn stream_channels(
+        &self,
+        direction: StreamDirection,
+    ) -> Result<ChannelCount, BackendSpecificError> {
+        let property_address = AudioObjectPropertyAddress {
+            mSelector: kAudioDevicePropertyStreamConfiguration,
+            mScope: match direction {
+                StreamDirection::Input => kAudioObjectPropertyScopeInput,
+                StreamDirection::Output => kAudioObjectPropertyScopeOutput,
+            },
+            mElement: kAudioObjectPropertyElementMaster,
+        };
+        unsafe {
+            let data_size = 0u32;
+            let status = AudioObjectGetPropertyDataSize(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+            );
+            check_os_status(status)?;
+            if (data_size as usize) < mem::size_of::<AudioBufferList>() {
+                return Ok(0);
+            }
+            let mut audio_buffer_list: Vec<u8> = vec![0; data_size as usize];
+            let status = AudioObjectGetPropertyData(
+                self.audio_device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &data_size as *const _ as *mut _,
+                audio_buffer_list.as_mut_ptr() as *mut _,
+            );
+            check_os_status(status)?;
+            let audio_buffer_list = audio_buffer_list.as_ptr() as *const AudioBufferList;
+            let n_buffers = (*audio_buffer_list).mNumberBuffers as usize;
+            let first: *const AudioBuffer = (*audio_buffer_list).mBuffers.as_ptr();
+            let buffers = slice::from_raw_parts(first, n_buffers);
+            Ok(buffers
+                .iter()
+                .map(|buffer| buffer.mNumberChannels as ChannelCount)
+                .sum())
+        }
+    }
+
+    // Leaves the channels disabled through `DeviceExt::set_channel_enabled` out of the stream.
+    fn set_channel_map(
+        &self,
+        audio_unit: &AudioUnit,
+        direction: StreamDirection,
+        config: &StreamConfig,
+        scope: Scope,
+        element: Element,
+    ) -> Result<(), BuildStreamError> {
+        let device_channels = self.stream_channels(direction)?;
+        let enabled =
+            match channel_map::enabled_channels(self.audio_device_id, direction, device_channels) {
+                Some(enabled) => enabled,
+                None => return Ok(()),
+            };
+        if config.channels as usize != enabled.len() {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        let map = channel_map::channel_map(direction, &enabled, device_channels);
+        let status = unsafe {
+            AudioUnitSetProperty(
+                *audio_unit.as_ref(),
+                kAudioOutputUnitProperty_ChannelMap,
+                scope as u32,
+                element as u32,
+                map.as_ptr() as *const _,
+                mem::size_of_val(map.as_slice()) as u32,
+            )
+        };
+        check_os_status(status)?;
+        Ok(())
+    }
+
     #[allow(clippy::cast_ptr_alignment)]
     #[allow(clippy::while_immutable_condition)]
     #[allow(clippy::float_cmp)]
@@ -468,7 +1094,7 @@ impl Device {
         config: &StreamConfig,
         sample_format: SampleFormat,
         mut data_callback: D,
-        mut error_callback: E,
+        error_callback: E,
     ) -> Result<Stream, BuildStreamError>
     where
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
@@ -500,6 +1126,11 @@ impl Device {
 
             // If the requested sample rate is different to the device sample rate, update the device.
             if sample_rate as u32 != config.sample_rate.0 {
+                debug!(
+                    from = sample_rate,
+                    to = config.sample_rate.0,
+                    "changing the device sample rate",
+                );
                 // Get available sample rate ranges.
                 property_address.mSelector = kAudioDevicePropertyAvailableNominalSampleRates;
                 let data_size = 0u32;
@@ -600,7 +1231,10 @@ impl Device {
                     if timer.elapsed() > Duration::from_secs(1) {
                         let description =
                             "timeout waiting for sample rate update for device".into();
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: None,
+                        };
                         return Err(err.into());
                     }
                     thread::sleep(Duration::from_millis(5));
@@ -622,25 +1256,20 @@ impl Device {
         // Set the stream in interleaved mode.
         let asbd = asbd_from_config(config, sample_format);
         audio_unit.set_property(kAudioUnitProperty_StreamFormat, scope, element, Some(&asbd))?;
+        self.set_channel_map(&audio_unit, StreamDirection::Input, config, scope, element)?;
 
         // Set the buffersize
         match config.buffer_size {
             BufferSize::Fixed(v) => {
                 let buffer_size_range = get_io_buffer_frame_size_range(&audio_unit)?;
-                match buffer_size_range {
-                    SupportedBufferSize::Range { min, max } => {
-                        if v >= min && v <= max {
-                            audio_unit.set_property(
-                                kAudioDevicePropertyBufferFrameSize,
-                                scope,
-                                element,
-                                Some(&v),
-                            )?
-                        } else {
-                            return Err(BuildStreamError::StreamConfigNotSupported);
-                        }
-                    }
-                    SupportedBufferSize::Unknown => (),
+                buffer_size_range.check(&config.buffer_size)?;
+                if let SupportedBufferSize::Range { .. } = buffer_size_range {
+                    audio_unit.set_property(
+                        kAudioDevicePropertyBufferFrameSize,
+                        scope,
+                        element,
+                        Some(&v),
+                    )?
                 }
             }
             BufferSize::Default => (),
@@ -650,6 +1279,8 @@ impl Device {
         // fed to the audio buffer.
         let bytes_per_channel = sample_format.sample_size();
         let sample_rate = config.sample_rate;
+        let error_callback: ErrorCallback = Arc::new(Mutex::new(error_callback));
+        let listener = DeviceListener::new(self.audio_device_id, error_callback.clone())?;
         type Args = render_callback::Args<data::Raw>;
         audio_unit.set_input_callback(move |args: Args| unsafe {
             let ptr = (*args.data.data).mBuffers.as_ptr() as *const AudioBuffer;
@@ -670,7 +1301,7 @@ impl Device {
             // TODO: Need a better way to get delay, for now we assume a double-buffer offset.
             let callback = match host_time_to_stream_instant(args.time_stamp.mHostTime) {
                 Err(err) => {
-                    error_callback(err.into());
+                    report_error(&error_callback, err.into());
                     return Err(());
                 }
                 Ok(cb) => cb,
@@ -682,18 +1313,31 @@ impl Device {
                 .expect("`capture` occurs before origin of alsa `StreamInstant`");
             let timestamp = crate::InputStreamTimestamp { callback, capture };
 
-            let info = InputCallbackInfo { timestamp };
+            let device_timestamp = device_timestamp(&args.time_stamp, sample_rate);
+            let info = InputCallbackInfo {
+                timestamp,
+                device_timestamp,
+                channel_mix: ChannelMix::None,
+            };
             data_callback(&data, &info);
             Ok(())
         })?;
 
         audio_unit.start()?;
 
-        Ok(Stream::new(StreamInner {
-            playing: true,
-            audio_unit,
-            device_id: self.audio_device_id,
-        }))
+        Ok(Stream::new(
+            StreamInner {
+                playing: true,
+                audio_unit: ManuallyDrop::new(audio_unit),
+                device_id: self.audio_device_id,
+                config: config.clone(),
+                sample_format,
+                bit_depth: self.effective_bit_depth(StreamDirection::Input, sample_format),
+                next_block_frames: None,
+            },
+            None,
+            listener,
+        ))
     }
 
     fn build_output_stream_raw<D, E>(
@@ -701,7 +1345,7 @@ impl Device {
         config: &StreamConfig,
         sample_format: SampleFormat,
         mut data_callback: D,
-        mut error_callback: E,
+        error_callback: E,
     ) -> Result<Stream, BuildStreamError>
     where
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
@@ -716,25 +1360,20 @@ impl Device {
         // Set the stream in interleaved mode.
         let asbd = asbd_from_config(config, sample_format);
         audio_unit.set_property(kAudioUnitProperty_StreamFormat, scope, element, Some(&asbd))?;
+        self.set_channel_map(&audio_unit, StreamDirection::Output, config, scope, element)?;
 
         // Set the buffersize
         match config.buffer_size {
             BufferSize::Fixed(v) => {
                 let buffer_size_range = get_io_buffer_frame_size_range(&audio_unit)?;
-                match buffer_size_range {
-                    SupportedBufferSize::Range { min, max } => {
-                        if v >= min && v <= max {
-                            audio_unit.set_property(
-                                kAudioDevicePropertyBufferFrameSize,
-                                scope,
-                                element,
-                                Some(&v),
-                            )?
-                        } else {
-                            return Err(BuildStreamError::StreamConfigNotSupported);
-                        }
-                    }
-                    SupportedBufferSize::Unknown => (),
+                buffer_size_range.check(&config.buffer_size)?;
+                if let SupportedBufferSize::Range { .. } = buffer_size_range {
+                    audio_unit.set_property(
+                        kAudioDevicePropertyBufferFrameSize,
+                        scope,
+                        element,
+                        Some(&v),
+                    )?
                 }
             }
             BufferSize::Default => (),
@@ -744,6 +1383,17 @@ impl Device {
         // fed to the audio buffer.
         let bytes_per_channel = sample_format.sample_size();
         let sample_rate = config.sample_rate;
+        let error_callback: ErrorCallback = Arc::new(Mutex::new(error_callback));
+        let listener = DeviceListener::new(self.audio_device_id, error_callback.clone())?;
+        // The audio unit is started below, before the stream is returned.
+        let output_pause = Arc::new(PauseState::new(true));
+        let pause_state = output_pause.clone();
+        // Render calls usually cover the device's IO buffer.
+        let buffer_frames: u32 = audio_unit
+            .get_property(kAudioDevicePropertyBufferFrameSize, Scope::Global, element)
+            .unwrap_or(0);
+        let next_block_frames = Arc::new(AtomicU32::new(buffer_frames));
+        let block_frames = next_block_frames.clone();
         type Args = render_callback::Args<data::Raw>;
         audio_unit.set_render_callback(move |args: Args| unsafe {
             // If `run()` is currently running, then a callback will be available from this list.
@@ -755,13 +1405,20 @@ impl Device {
                 mData: data,
             } = (*args.data.data).mBuffers[0];
 
+            if pause_state.output_mode() != OutputMode::Callback {
+                let bytes = slice::from_raw_parts_mut(data as *mut u8, data_byte_size as usize);
+                fill_silence(bytes, sample_format);
+                return Ok(());
+            }
+            block_frames.store(args.num_frames as u32, Ordering::Relaxed);
+
             let data = data as *mut ();
             let len = (data_byte_size as usize / bytes_per_channel) as usize;
             let mut data = Data::from_parts(data, len, sample_format);
 
             let callback = match host_time_to_stream_instant(args.time_stamp.mHostTime) {
                 Err(err) => {
-                    error_callback(err.into());
+                    report_error(&error_callback, err.into());
                     return Err(());
                 }
                 Ok(cb) => cb,
@@ -774,41 +1431,110 @@ impl Device {
                 .expect("`playback` occurs beyond representation supported by `StreamInstant`");
             let timestamp = crate::OutputStreamTimestamp { callback, playback };
 
-            let info = OutputCallbackInfo { timestamp };
+            let device_timestamp = device_timestamp(&args.time_stamp, sample_rate);
+            let info = OutputCallbackInfo {
+                timestamp,
+                device_timestamp,
+            };
             data_callback(&mut data, &info);
             Ok(())
         })?;
 
         audio_unit.start()?;
 
-        Ok(Stream::new(StreamInner {
-            playing: true,
-            audio_unit,
-            device_id: self.audio_device_id,
-        }))
+        Ok(Stream::new(
+            StreamInner {
+                playing: true,
+                audio_unit: ManuallyDrop::new(audio_unit),
+                device_id: self.audio_device_id,
+                config: config.clone(),
+                sample_format,
+                bit_depth: self.effective_bit_depth(StreamDirection::Output, sample_format),
+                next_block_frames: Some(next_block_frames),
+            },
+            Some(output_pause),
+            listener,
+        ))
     }
 }
 
 pub struct Stream {
     inner: RefCell<StreamInner>,
+    // Pause handling for output streams, which support `KeepAlive`. `None` for input streams.
+    output_pause: Option<Arc<PauseState>>,
+    // Reports removal of the device to the error callback. `None` once the stream is disposed of.
+    listener: Option<DeviceListener>,
 }
 
 impl Stream {
-    fn new(inner: StreamInner) -> Self {
+    fn new(
+        inner: StreamInner,
+        output_pause: Option<Arc<PauseState>>,
+        listener: DeviceListener,
+    ) -> Self {
         Self {
             inner: RefCell::new(inner),
+            output_pause,
+            listener: Some(listener),
+        }
+    }
+
+    /// Removes the device listeners and disposes of the audio unit, which stops it.
+    ///
+    /// `AudioOutputUnitStop` can block indefinitely on a device that has been removed, so in that
+    /// case the audio unit is disposed of on another thread, waiting at most `timeout` for it.
+    fn dispose(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        let listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => return Ok(()),
+        };
+        let device_alive = listener.device_is_alive();
+        drop(listener);
+        // Safety: the audio unit is taken only once, guarded by `listener`, and is not used again.
+        let audio_unit = unsafe { ManuallyDrop::take(&mut self.inner.get_mut().audio_unit) };
+        if device_alive {
+            drop(audio_unit);
+            return Ok(());
         }
+        debug!("disposing of the audio unit of a removed device");
+        let audio_unit = DisposedAudioUnit(audio_unit);
+        let builder = thread::Builder::new().name("cpal_coreaudio_dispose".to_owned());
+        // If the thread can't be spawned the audio unit is dropped on this thread instead.
+        let mut thread = AudioThread::spawn(builder, move || drop(audio_unit)).map_err(|err| {
+            let description = format!("failed to spawn the dispose thread: {}", err);
+            StreamError::from(BackendSpecificError {
+                description,
+                code: None,
+            })
+        })?;
+        thread.join_timeout(timeout)
     }
 }
 
-impl StreamTrait for Stream {
-    fn play(&self) -> Result<(), PlayStreamError> {
+// An audio unit being moved to another thread to be disposed of.
+struct DisposedAudioUnit(AudioUnit);
+
+// Safety: the audio unit is no longer used by the stream once it is disposed of, and CoreAudio
+// allows disposing of an audio unit from any thread.
+unsafe impl Send for DisposedAudioUnit {}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _span = debug_span!("coreaudio_drop_stream");
+        // `drop` has no way of reporting a timeout; callers who care use `close`.
+        let _ = self.dispose(DROP_TIMEOUT);
+    }
+}
+
+impl Stream {
+    fn start(&self) -> Result<(), PlayStreamError> {
         let mut stream = self.inner.borrow_mut();
 
         if !stream.playing {
             if let Err(e) = stream.audio_unit.start() {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let code = Some(e.as_os_status());
+                let err = BackendSpecificError { description, code };
                 return Err(err.into());
             }
             stream.playing = true;
@@ -816,13 +1542,14 @@ impl StreamTrait for Stream {
         Ok(())
     }
 
-    fn pause(&self) -> Result<(), PauseStreamError> {
+    fn stop(&self) -> Result<(), PauseStreamError> {
         let mut stream = self.inner.borrow_mut();
 
         if stream.playing {
             if let Err(e) = stream.audio_unit.stop() {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let code = Some(e.as_os_status());
+                let err = BackendSpecificError { description, code };
                 return Err(err.into());
             }
 
@@ -832,6 +1559,117 @@ impl StreamTrait for Stream {
     }
 }
 
+impl StreamTrait for Stream {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        match self.output_pause {
+            Some(ref state) => state.play(|| self.start()),
+            None => self.start(),
+        }
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        match self.output_pause {
+            Some(ref state) => state.pause(|| self.stop()),
+            None => self.stop(),
+        }
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.borrow().config.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.inner.borrow().config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.inner.borrow().sample_format
+    }
+
+    fn effective_bit_depth(&self) -> u8 {
+        self.inner.borrow().bit_depth
+    }
+
+    fn next_block_frames(&self) -> Option<FrameCount> {
+        let inner = self.inner.borrow();
+        // Zero if the buffer size could not be read and no render call has happened yet.
+        match inner.next_block_frames.as_ref()?.load(Ordering::Relaxed) {
+            0 => None,
+            frames => Some(frames),
+        }
+    }
+
+    fn set_keep_alive(&self, keep_alive: KeepAlive) -> Result<(), KeepAliveError> {
+        match (&self.output_pause, keep_alive) {
+            (None, KeepAlive::Off) => Ok(()),
+            (Some(state), KeepAlive::Off) | (Some(state), KeepAlive::Silence) => state
+                .set_keep_alive(keep_alive, || {
+                    self.stop().map_err(|err| {
+                        let description = err.to_string();
+                        let code = err.raw_os_error();
+                        KeepAliveError::from(BackendSpecificError { description, code })
+                    })
+                }),
+            _ => Err(KeepAliveError::NotSupported),
+        }
+    }
+
+    fn close(mut self, timeout: Duration) -> Result<(), StreamError> {
+        self.dispose(timeout)
+    }
+}
+
+/// The sample format exposed for a device whose native stream format is described by `asbd`.
+///
+/// The HAL output unit converts between the device's native format and the client format set via
+/// `asbd_from_config`. Native formats without an exact CPAL equivalent, such as 32-bit integers,
+/// 24-bit integers aligned high and 8.24 fixed-point, are exposed as `F32` so that no resolution
+/// is lost.
+fn sample_format_from_asbd(asbd: &AudioStreamBasicDescription) -> Option<SampleFormat> {
+    if asbd.mFormatID != kAudioFormatLinearPCM {
+        return None;
+    }
+    let flags = asbd.mFormatFlags;
+    let bits = asbd.mBitsPerChannel;
+    if flags & kAudioFormatFlagIsFloat as u32 != 0 {
+        return match bits {
+            32 | 64 => Some(SampleFormat::F32),
+            _ => None,
+        };
+    }
+    let signed = flags & kAudioFormatFlagIsSignedInteger as u32 != 0;
+    let fraction_bits = (flags & kLinearPCMFormatFlagsSampleFractionMask as u32)
+        >> kLinearPCMFormatFlagsSampleFractionShift as u32;
+    let packed = flags & kAudioFormatFlagIsPacked as u32 != 0;
+    let aligned_high = flags & kAudioFormatFlagIsAlignedHigh as u32 != 0;
+    match (signed, bits, fraction_bits) {
+        (true, 16, 0) => Some(SampleFormat::I16),
+        (false, 16, 0) => Some(SampleFormat::U16),
+        (true, 24, 0) if packed => Some(SampleFormat::I24Packed),
+        (true, 24, 0) if !aligned_high => Some(SampleFormat::I24),
+        (_, 8..=32, _) => Some(SampleFormat::F32),
+        _ => None,
+    }
+}
+
+/// The sample format that is exactly the physical format described by `asbd`, unlike
+/// `sample_format_from_asbd` which also maps the formats the HAL output unit converts.
+fn physical_sample_format(asbd: &AudioStreamBasicDescription) -> Option<SampleFormat> {
+    let format = sample_format_from_asbd(asbd)?;
+    let exact = match format {
+        SampleFormat::I16 | SampleFormat::U16 => asbd.mBitsPerChannel == 16,
+        SampleFormat::I24 | SampleFormat::I24Packed => asbd.mBitsPerChannel == 24,
+        SampleFormat::F32 => {
+            asbd.mFormatFlags & kAudioFormatFlagIsFloat as u32 != 0 && asbd.mBitsPerChannel == 32
+        }
+    };
+    if exact {
+        Some(format)
+    } else {
+        None
+    }
+}
+
 fn get_io_buffer_frame_size_range(
     audio_unit: &AudioUnit,
 ) -> Result<SupportedBufferSize, coreaudio::Error> {
@@ -846,3 +1684,68 @@ fn get_io_buffer_frame_size_range(
         max: buffer_size_range.mMaximum as u32,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn asbd(format_flags: u32, bits_per_channel: u32) -> AudioStreamBasicDescription {
+        AudioStreamBasicDescription {
+            mFormatID: kAudioFormatLinearPCM,
+            mFormatFlags: format_flags,
+            mBitsPerChannel: bits_per_channel,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn native_sample_formats() {
+        let float = kAudioFormatFlagIsFloat as u32;
+        let signed = kAudioFormatFlagIsSignedInteger as u32;
+        let packed = kAudioFormatFlagIsPacked as u32;
+        let aligned_high = kAudioFormatFlagIsAlignedHigh as u32;
+        let fixed_8_24 = signed | (24 << kLinearPCMFormatFlagsSampleFractionShift as u32);
+        let cases = [
+            (float, 32, Some(SampleFormat::F32)),
+            (float, 64, Some(SampleFormat::F32)),
+            (float, 16, None),
+            (signed, 16, Some(SampleFormat::I16)),
+            (0, 16, Some(SampleFormat::U16)),
+            (signed | packed, 24, Some(SampleFormat::I24Packed)),
+            (signed, 24, Some(SampleFormat::I24)),
+            (signed | aligned_high, 24, Some(SampleFormat::F32)),
+            (signed, 32, Some(SampleFormat::F32)),
+            (fixed_8_24, 32, Some(SampleFormat::F32)),
+            (signed, 8, Some(SampleFormat::F32)),
+            (signed, 48, None),
+        ];
+        for &(flags, bits, expected) in cases.iter() {
+            assert_eq!(sample_format_from_asbd(&asbd(flags, bits)), expected);
+        }
+
+        let not_pcm = AudioStreamBasicDescription {
+            mFormatID: 0,
+            ..asbd(float, 32)
+        };
+        assert_eq!(sample_format_from_asbd(&not_pcm), None);
+    }
+
+    #[test]
+    fn physical_sample_formats() {
+        let float = kAudioFormatFlagIsFloat as u32;
+        let signed = kAudioFormatFlagIsSignedInteger as u32;
+        let packed = kAudioFormatFlagIsPacked as u32;
+        let cases = [
+            (float, 32, Some(SampleFormat::F32)),
+            (float, 64, None),
+            (signed, 16, Some(SampleFormat::I16)),
+            (0, 16, Some(SampleFormat::U16)),
+            (signed | packed, 24, Some(SampleFormat::I24Packed)),
+            (signed, 24, Some(SampleFormat::I24)),
+            (signed, 32, None),
+        ];
+        for &(flags, bits, expected) in cases.iter() {
+            assert_eq!(physical_sample_format(&asbd(flags, bits)), expected);
+        }
+    }
+}
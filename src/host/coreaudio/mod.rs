@@ -1,8 +1,9 @@
 extern crate coreaudio;
 
 use self::coreaudio::sys::{
-    kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked, kAudioFormatLinearPCM,
-    AudioStreamBasicDescription, OSStatus,
+    kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked, kAudioFormatFlagIsSignedInteger,
+    kAudioFormatLinearPCM, kAudioTimeStampSampleTimeValid, AudioStreamBasicDescription,
+    AudioTimeStamp, OSStatus,
 };
 
 use crate::DefaultStreamConfigError;
@@ -14,6 +15,7 @@ use crate::{BackendSpecificError, SampleFormat, StreamConfig};
 mod ios;
 #[cfg(target_os = "macos")]
 mod macos;
+mod permission;
 
 #[cfg(target_os = "ios")]
 pub use self::ios::{
@@ -24,7 +26,7 @@ pub use self::ios::{
 #[cfg(target_os = "macos")]
 pub use self::macos::{
     enumerate::{Devices, SupportedInputConfigs, SupportedOutputConfigs},
-    Device, Host, Stream,
+    Device, DeviceExt, Host, Stream,
 };
 
 /// Common helper methods used by both macOS and iOS
@@ -34,7 +36,10 @@ fn check_os_status(os_status: OSStatus) -> Result<(), BackendSpecificError> {
         Ok(()) => Ok(()),
         Err(err) => {
             let description = err.to_string();
-            Err(BackendSpecificError { description })
+            Err(BackendSpecificError {
+                description,
+                code: Some(os_status),
+            })
         }
     }
 }
@@ -47,13 +52,21 @@ fn asbd_from_config(
     let n_channels = config.channels as usize;
     let sample_rate = config.sample_rate.0;
     let bytes_per_channel = sample_format.sample_size();
-    let bits_per_channel = bytes_per_channel * 8;
+    // `I24` samples are aligned low in a 4-byte container.
+    let bits_per_channel = match sample_format {
+        SampleFormat::I24 => 24,
+        _ => bytes_per_channel * 8,
+    };
     let bytes_per_frame = n_channels * bytes_per_channel;
     let frames_per_packet = 1;
     let bytes_per_packet = frames_per_packet * bytes_per_frame;
     let format_flags = match sample_format {
         SampleFormat::F32 => (kAudioFormatFlagIsFloat | kAudioFormatFlagIsPacked) as u32,
-        _ => kAudioFormatFlagIsPacked as u32,
+        SampleFormat::I16 | SampleFormat::I24Packed => {
+            (kAudioFormatFlagIsSignedInteger | kAudioFormatFlagIsPacked) as u32
+        }
+        SampleFormat::I24 => kAudioFormatFlagIsSignedInteger as u32,
+        SampleFormat::U16 => kAudioFormatFlagIsPacked as u32,
     };
     AudioStreamBasicDescription {
         mBitsPerChannel: bits_per_channel as _,
@@ -74,12 +87,28 @@ fn host_time_to_stream_instant(
     let mut info: mach::mach_time::mach_timebase_info = Default::default();
     let res = unsafe { mach::mach_time::mach_timebase_info(&mut info) };
     check_os_status(res)?;
-    let nanos = m_host_time * info.numer as u64 / info.denom as u64;
+    // Widened so that the multiplication can't overflow however long the host has been up.
+    let nanos = (m_host_time as u128 * info.numer as u128 / info.denom as u128) as u64;
     let secs = nanos / 1_000_000_000;
     let subsec_nanos = nanos - secs * 1_000_000_000;
     Ok(crate::StreamInstant::new(secs as i64, subsec_nanos as u32))
 }
 
+// The device clock position carried by an audio unit callback's time stamp, if it has one.
+fn device_timestamp(
+    time_stamp: &AudioTimeStamp,
+    sample_rate: crate::SampleRate,
+) -> Option<crate::DeviceTimestamp> {
+    let valid = time_stamp.mFlags & kAudioTimeStampSampleTimeValid != 0;
+    if !valid || time_stamp.mSampleTime < 0.0 {
+        return None;
+    }
+    Some(crate::DeviceTimestamp {
+        samples: time_stamp.mSampleTime as u64,
+        sample_rate,
+    })
+}
+
 // Convert the given duration in frames at the given sample rate to a `std::time::Duration`.
 fn frames_to_duration(frames: usize, rate: crate::SampleRate) -> std::time::Duration {
     let secsf = frames as f64 / rate.0 as f64;
@@ -91,6 +120,7 @@ fn frames_to_duration(frames: usize, rate: crate::SampleRate) -> std::time::Dura
 // TODO need stronger error identification
 impl From<coreaudio::Error> for BuildStreamError {
     fn from(err: coreaudio::Error) -> BuildStreamError {
+        debug!(?err, "CoreAudio call failed");
         match err {
             coreaudio::Error::RenderCallbackBufferFormatDoesNotMatchAudioUnitStreamFormat
             | coreaudio::Error::NoKnownSubtype
@@ -104,8 +134,10 @@ impl From<coreaudio::Error> for BuildStreamError {
 
 impl From<coreaudio::Error> for SupportedStreamConfigsError {
     fn from(err: coreaudio::Error) -> SupportedStreamConfigsError {
+        debug!(?err, "CoreAudio call failed");
         let description = format!("{}", err);
-        let err = BackendSpecificError { description };
+        let code = Some(err.as_os_status());
+        let err = BackendSpecificError { description, code };
         // Check for possible DeviceNotAvailable variant
         SupportedStreamConfigsError::BackendSpecific { err }
     }
@@ -113,8 +145,10 @@ impl From<coreaudio::Error> for SupportedStreamConfigsError {
 
 impl From<coreaudio::Error> for DefaultStreamConfigError {
     fn from(err: coreaudio::Error) -> DefaultStreamConfigError {
+        debug!(?err, "CoreAudio call failed");
         let description = format!("{}", err);
-        let err = BackendSpecificError { description };
+        let code = Some(err.as_os_status());
+        let err = BackendSpecificError { description, code };
         // Check for possible DeviceNotAvailable variant
         DefaultStreamConfigError::BackendSpecific { err }
     }
@@ -0,0 +1,131 @@
+//! Microphone permission, which both macOS (10.14 and later) and iOS require before an
+//! application can capture audio.
+//!
+//! The permission is managed by AVFoundation's `AVCaptureDevice`; it is queried through the
+//! Objective-C runtime so as not to depend on an Objective-C bindings crate.
+
+use crate::PermissionStatus;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+use std::sync::Mutex;
+
+type Id = *const c_void;
+type Sel = *const c_void;
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    static AVMediaTypeAudio: Id;
+}
+
+#[link(name = "objc")]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    // Must be cast to the signature of the method being called.
+    fn objc_msgSend();
+}
+
+extern "C" {
+    static _NSConcreteGlobalBlock: c_void;
+}
+
+// `AVAuthorizationStatus` values.
+const AUTHORIZATION_NOT_DETERMINED: c_long = 0;
+const AUTHORIZATION_RESTRICTED: c_long = 1;
+const AUTHORIZATION_DENIED: c_long = 2;
+const AUTHORIZATION_AUTHORIZED: c_long = 3;
+
+pub(super) fn input_permission_status() -> PermissionStatus {
+    unsafe {
+        let class = objc_getClass(c"AVCaptureDevice".as_ptr());
+        if class.is_null() {
+            return PermissionStatus::NotApplicable;
+        }
+        let selector = sel_registerName(c"authorizationStatusForMediaType:".as_ptr());
+        let send: unsafe extern "C" fn(Id, Sel, Id) -> c_long =
+            mem::transmute(objc_msgSend as *const c_void);
+        match send(class, selector, AVMediaTypeAudio) {
+            AUTHORIZATION_AUTHORIZED => PermissionStatus::Granted,
+            AUTHORIZATION_DENIED | AUTHORIZATION_RESTRICTED => PermissionStatus::Denied,
+            AUTHORIZATION_NOT_DETERMINED => PermissionStatus::Undetermined,
+            _ => PermissionStatus::NotApplicable,
+        }
+    }
+}
+
+type PermissionCallback = Box<dyn FnOnce(PermissionStatus) + Send>;
+
+// Callbacks waiting for the user to answer the permission prompt. The prompt is only shown once,
+// so they all receive the same answer.
+static PENDING: Mutex<Vec<PermissionCallback>> = Mutex::new(Vec::new());
+
+/// Prompts the user for permission if they have not been asked yet, then calls `callback`.
+///
+/// The system calls the completion handler, and thereby `callback`, on an arbitrary thread.
+pub(super) fn request_input_permission(callback: PermissionCallback) {
+    let status = input_permission_status();
+    if status != PermissionStatus::Undetermined {
+        return callback(status);
+    }
+    let mut pending = PENDING.lock().unwrap();
+    pending.push(callback);
+    if pending.len() > 1 {
+        // A request is already in flight.
+        return;
+    }
+    drop(pending);
+    unsafe {
+        let class = objc_getClass(c"AVCaptureDevice".as_ptr());
+        let selector = sel_registerName(c"requestAccessForMediaType:completionHandler:".as_ptr());
+        let send: unsafe extern "C" fn(Id, Sel, Id, *const Block) =
+            mem::transmute(objc_msgSend as *const c_void);
+        send(class, selector, AVMediaTypeAudio, &COMPLETION_HANDLER);
+    }
+}
+
+// The completion handler, a `void (^)(BOOL granted)` block. It captures nothing, so it can be a
+// global block, which the system neither copies nor frees.
+#[repr(C)]
+struct Block {
+    isa: *const c_void,
+    flags: c_int,
+    reserved: c_int,
+    invoke: unsafe extern "C" fn(*const Block, i8),
+    descriptor: *const BlockDescriptor,
+}
+
+#[repr(C)]
+struct BlockDescriptor {
+    reserved: c_ulong,
+    size: c_ulong,
+}
+
+// Safety: the block is immutable.
+unsafe impl Sync for Block {}
+
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+
+static COMPLETION_HANDLER_DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    reserved: 0,
+    size: mem::size_of::<Block>() as c_ulong,
+};
+
+static COMPLETION_HANDLER: Block = Block {
+    isa: unsafe { &_NSConcreteGlobalBlock as *const c_void },
+    flags: BLOCK_IS_GLOBAL,
+    reserved: 0,
+    invoke: permission_answered,
+    descriptor: &COMPLETION_HANDLER_DESCRIPTOR,
+};
+
+unsafe extern "C" fn permission_answered(_block: *const Block, granted: i8) {
+    let status = if granted != 0 {
+        PermissionStatus::Granted
+    } else {
+        PermissionStatus::Denied
+    };
+    let pending = mem::take(&mut *PENDING.lock().unwrap());
+    for callback in pending {
+        callback(status);
+    }
+}
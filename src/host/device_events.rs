@@ -0,0 +1,89 @@
+//! Turning a host's notifications about its list of devices into `DeviceEvent`s.
+//!
+//! Hosts notify that their list of devices changed, or about a single device whose state changed,
+//! which does not always mean that it was added to or removed from the devices listed by
+//! `HostTrait::devices`. `KnownDevices` remembers the listed devices, so that only actual changes
+//! to the list are reported, and so that removed devices can be reported by their ID.
+
+use crate::traits::DeviceTrait;
+use crate::{DeviceEvent, DeviceId};
+
+/// Pairs each device with its ID, leaving out the devices whose ID cannot be read.
+pub(crate) fn with_ids<D: DeviceTrait>(devices: impl IntoIterator<Item = D>) -> Vec<(DeviceId, D)> {
+    devices
+        .into_iter()
+        .filter_map(|device| Some((device.id().ok()?, device)))
+        .collect()
+}
+
+/// The IDs of the devices a host listed last.
+pub(crate) struct KnownDevices {
+    ids: Vec<DeviceId>,
+}
+
+impl KnownDevices {
+    pub(crate) fn new<D>(devices: Vec<(DeviceId, D)>) -> Self {
+        KnownDevices {
+            ids: devices.into_iter().map(|(id, _)| id).collect(),
+        }
+    }
+
+    /// Remembers `devices` as the devices now listed, and returns the events turning the devices
+    /// listed before into them: the removed devices first, then the added ones.
+    pub(crate) fn update<D>(&mut self, devices: Vec<(DeviceId, D)>) -> Vec<DeviceEvent<D>> {
+        let mut events: Vec<DeviceEvent<D>> = self
+            .ids
+            .iter()
+            .filter(|id| !devices.iter().any(|(listed, _)| listed == *id))
+            .map(|id| DeviceEvent::Removed { id: id.clone() })
+            .collect();
+        let ids = devices.iter().map(|(id, _)| id.clone()).collect();
+        let known = std::mem::replace(&mut self.ids, ids);
+        events.extend(
+            devices
+                .into_iter()
+                .filter(|(id, _)| !known.contains(id))
+                .map(|(_, device)| DeviceEvent::Added { device }),
+        );
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{with_ids, KnownDevices};
+    use crate::host::null;
+    use crate::{DeviceEvent, DeviceId};
+
+    fn devices(names: &[&'static str]) -> Vec<(DeviceId, &'static str)> {
+        names
+            .iter()
+            .map(|name| (DeviceId::new(*name), *name))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_reports_changes_to_the_list() {
+        let mut known = KnownDevices::new(devices(&["speakers", "mic"]));
+        assert!(known.update(devices(&["mic", "speakers"])).is_empty());
+
+        let events = known.update(devices(&["speakers", "headset"]));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], DeviceEvent::Removed { id } if id.as_str() == "mic"));
+        assert!(matches!(
+            events[1],
+            DeviceEvent::Added { device: "headset" }
+        ));
+
+        let events = known.update(devices(&["speakers"]));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], DeviceEvent::Removed { id } if id.as_str() == "headset"));
+    }
+
+    #[test]
+    fn test_with_ids_pairs_devices_with_their_id() {
+        let devices = with_ids(Some(null::Device));
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].0.as_str(), "null");
+    }
+}
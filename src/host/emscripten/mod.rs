@@ -7,12 +7,13 @@ use stdweb::web::set_timeout;
 use stdweb::web::TypedArray;
 use stdweb::Reference;
 
+use crate::host::callback_guard;
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BufferSize, BuildStreamError, Data, DefaultStreamConfigError, DeviceNameError, DevicesError,
-    InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError, SampleFormat,
-    SampleRate, StreamConfig, StreamError, SupportedBufferSize, SupportedStreamConfig,
-    SupportedStreamConfigRange, SupportedStreamConfigsError,
+    BufferSize, BuildStreamError, ChannelCount, Data, DefaultStreamConfigError, DeviceNameError,
+    DevicesError, InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError,
+    SampleFormat, SampleRate, StreamConfig, StreamError, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
 };
 
 // The emscripten backend currently works by instantiating an `AudioContext` object per `Stream`.
@@ -32,6 +33,8 @@ pub struct Device;
 pub struct Stream {
     // A reference to an `AudioContext` object.
     audio_ctxt_ref: Reference,
+    config: StreamConfig,
+    sample_format: SampleFormat,
 }
 
 // Index within the `streams` array of the events loop.
@@ -190,6 +193,8 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         if !valid_config(config, sample_format) {
             return Err(BuildStreamError::StreamConfigNotSupported);
         }
@@ -207,7 +212,11 @@ impl DeviceTrait for Device {
 
         // Create the stream.
         let audio_ctxt_ref = js!(return new AudioContext()).into_reference().unwrap();
-        let stream = Stream { audio_ctxt_ref };
+        let stream = Stream {
+            audio_ctxt_ref,
+            config: config.clone(),
+            sample_format,
+        };
 
         // Specify the callback.
         let mut user_data = (self, data_callback, error_callback);
@@ -247,6 +256,18 @@ impl StreamTrait for Stream {
         js!(@{audio_ctxt}.suspend());
         Ok(())
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
 }
 
 // The first argument of the callback function (a `void*`) is a cast pointer to `self`
@@ -291,7 +312,10 @@ fn audio_callback_fn<D, E>(
                 .add(buffer_duration)
                 .expect("`playback` occurs beyond representation supported by `StreamInstant`");
             let timestamp = crate::OutputStreamTimestamp { callback, playback };
-            let info = OutputCallbackInfo { timestamp };
+            let info = OutputCallbackInfo {
+                timestamp,
+                device_timestamp: None,
+            };
             data_cb(&mut data, &info);
         }
 
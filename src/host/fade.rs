@@ -0,0 +1,209 @@
+//! Ramping the gain of an output stream as it starts and before it is closed, see
+//! `StreamOptions::fade`.
+//!
+//! The data callback is wrapped so that it scales each frame the user renders by the gain of the
+//! ramp. The gain rises from zero over the fade when the stream starts, and again after each
+//! `pause`. `Fade::fade_out` has it fall back to zero, and waits until the callback has rendered
+//! the last frame of the ramp.
+
+use crate::{
+    Data, FrameCount, I24Packed, OutputCallbackInfo, Sample, SampleFormat, SampleRate, I24,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The controls of a fading stream, shared with its data callback.
+pub(crate) struct Fade {
+    // Set by `rearm`, so that the next call ramps up from zero again.
+    rearm: AtomicBool,
+    // Set by `fade_out`, so that the gain falls to zero.
+    out: AtomicBool,
+    // Whether the gain has fallen to zero since `out` was set.
+    silent: Mutex<bool>,
+    silent_changed: Condvar,
+}
+
+impl Fade {
+    /// Fades the stream in again from its next call, for streams resuming after a pause.
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd")),
+        allow(dead_code)
+    )]
+    pub(crate) fn rearm(&self) {
+        self.rearm.store(true, Ordering::Release);
+    }
+
+    /// Ramps the gain down to zero, and waits until the data callback has rendered the end of the
+    /// ramp. Returns `false` if that took longer than `timeout`.
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd")),
+        allow(dead_code)
+    )]
+    pub(crate) fn fade_out(&self, timeout: Duration) -> bool {
+        self.out.store(true, Ordering::Release);
+        let deadline = Instant::now() + timeout;
+        let mut silent = self.silent.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*silent {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => remaining,
+                None => return false,
+            };
+            silent = self
+                .silent_changed
+                .wait_timeout(silent, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+}
+
+/// Wraps `data_callback` so that it fades in over `duration`, returning the controls of the fade
+/// along with the wrapped callback.
+pub(crate) fn apply<D>(
+    duration: Duration,
+    channels: usize,
+    sample_rate: SampleRate,
+    mut data_callback: D,
+) -> (
+    Arc<Fade>,
+    impl FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+)
+where
+    D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+{
+    let fade = Arc::new(Fade {
+        rearm: AtomicBool::new(false),
+        out: AtomicBool::new(false),
+        silent: Mutex::new(false),
+        silent_changed: Condvar::new(),
+    });
+    let frames = (duration.as_secs_f64() * f64::from(sample_rate.0)).round() as FrameCount;
+    let mut ramp = Ramp {
+        frames: frames.max(1),
+        position: 0,
+    };
+    let controls = fade.clone();
+    let data_callback = move |data: &mut Data, info: &OutputCallbackInfo| {
+        data_callback(data, info);
+        if fade.rearm.swap(false, Ordering::Acquire) {
+            ramp.position = 0;
+        }
+        let falling = fade.out.load(Ordering::Acquire);
+        match data.sample_format() {
+            SampleFormat::I16 => ramp.apply::<i16>(data, channels, falling),
+            SampleFormat::U16 => ramp.apply::<u16>(data, channels, falling),
+            SampleFormat::I24 => ramp.apply::<I24>(data, channels, falling),
+            SampleFormat::I24Packed => ramp.apply::<I24Packed>(data, channels, falling),
+            SampleFormat::F32 => ramp.apply::<f32>(data, channels, falling),
+        }
+        if falling && ramp.position == 0 {
+            let mut silent = fade.silent.lock().unwrap_or_else(PoisonError::into_inner);
+            if !*silent {
+                *silent = true;
+                fade.silent_changed.notify_all();
+            }
+        }
+    };
+    (controls, data_callback)
+}
+
+// A linear ramp of the gain over `frames` frames, at `position`.
+struct Ramp {
+    frames: FrameCount,
+    position: FrameCount,
+}
+
+impl Ramp {
+    // Scales the frames of `data` by the gain, moving the ramp towards zero if `falling` and
+    // towards one otherwise. Rising starts at a gain of zero, and falling ends at it.
+    fn apply<T: Sample>(&mut self, data: &mut Data, channels: usize, falling: bool) {
+        let samples = match data.as_slice_mut::<T>() {
+            Some(samples) => samples,
+            None => return,
+        };
+        for frame in samples.chunks_mut(channels) {
+            if falling {
+                self.position = self.position.saturating_sub(1);
+            } else if self.position == self.frames {
+                // Fully faded in; the rest of the buffer is left as rendered.
+                return;
+            }
+            let gain = self.position as f32 / self.frames as f32;
+            for sample in frame.iter_mut() {
+                *sample = T::from(&(sample.to_f32() * gain));
+            }
+            if !falling {
+                self.position += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::apply;
+    use crate::mock::output_info;
+    use crate::{Data, OutputCallbackInfo, SampleFormat, SampleRate};
+    use std::time::Duration;
+
+    // Renders 64 stereo frames through `data_callback`.
+    fn call(data_callback: &mut impl FnMut(&mut Data, &OutputCallbackInfo)) -> Vec<f32> {
+        let mut buffer = vec![0.0f32; 128];
+        let mut data = unsafe {
+            Data::from_parts(
+                buffer.as_mut_ptr() as *mut (),
+                buffer.len(),
+                SampleFormat::F32,
+            )
+        };
+        data_callback(&mut data, &output_info());
+        buffer
+    }
+
+    fn full_scale(data: &mut Data, _: &OutputCallbackInfo) {
+        data.as_slice_mut::<f32>().unwrap().fill(1.0);
+    }
+
+    #[test]
+    fn test_fade_in_and_out() {
+        // 100 frames at 1kHz.
+        let duration = Duration::from_millis(100);
+        let (fade, mut data_callback) = apply(duration, 2, SampleRate(1_000), full_scale);
+
+        let first = call(&mut data_callback);
+        assert_eq!(&first[..2], &[0.0, 0.0]);
+        assert!(first.windows(2).all(|pair| pair[0] <= pair[1]));
+        let second = call(&mut data_callback);
+        assert!(second[0] > first[127]);
+        assert_eq!(second[127], 1.0);
+
+        // The ramp falls from where it rose to, and ends on a silent frame.
+        assert!(!fade.fade_out(Duration::ZERO));
+        let mut tail = call(&mut data_callback);
+        tail.extend(call(&mut data_callback));
+        assert!(fade.fade_out(Duration::ZERO));
+        let end = tail.iter().position(|&sample| sample == 0.0).unwrap();
+        assert_eq!(end, 198);
+        assert!(tail[..end].windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(tail[end..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_rearmed_fade_starts_from_zero() {
+        let duration = Duration::from_millis(10);
+        let (fade, mut data_callback) = apply(duration, 2, SampleRate(1_000), full_scale);
+        call(&mut data_callback);
+        assert_eq!(call(&mut data_callback)[0], 1.0);
+        fade.rearm();
+        assert_eq!(call(&mut data_callback)[0], 0.0);
+    }
+
+    #[test]
+    fn test_fade_out_times_out_without_calls() {
+        let (fade, _data_callback) =
+            apply(Duration::from_millis(10), 2, SampleRate(1_000), full_scale);
+        assert!(!fade.fade_out(Duration::from_millis(10)));
+    }
+}
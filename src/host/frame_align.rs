@@ -0,0 +1,186 @@
+//! Passing buffers to data callbacks in whole frames.
+//!
+//! Hosts that size their buffers in bytes, such as CoreAudio, may hand over a number of samples
+//! that is not a multiple of the channel count. Passing such a buffer on as it is would split the
+//! channels of a frame across two calls, and shift the channels of every call after it. The
+//! wrappers here carry the incomplete frame over to the next call instead.
+//!
+//! Buffers of whole frames are passed on as they are while no frame is being carried over. Other
+//! buffers are copied through a buffer of the wrapper, which is allocated on first use.
+
+use crate::samples_formats::fill_silence;
+use crate::{ChannelCount, Data, InputCallbackInfo, OutputCallbackInfo, SampleFormat};
+use std::slice;
+
+/// Wraps the data callback of an input stream so that it only receives whole frames.
+///
+/// The samples of an incomplete frame at the end of a buffer are passed at the start of the next
+/// call instead.
+pub(crate) fn align_input<D>(
+    channels: ChannelCount,
+    sample_format: SampleFormat,
+    mut data_callback: D,
+) -> impl FnMut(&Data, &InputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+{
+    let frame_bytes = channels as usize * sample_format.sample_size();
+    // The bytes of the incomplete frame at the end of the previous buffer.
+    let mut partial: Vec<u8> = Vec::new();
+    let mut buffer = AlignedBuffer::default();
+    move |data: &Data, info: &InputCallbackInfo| {
+        let bytes = data.bytes();
+        if partial.is_empty() && bytes.len().is_multiple_of(frame_bytes) {
+            return data_callback(data, info);
+        }
+        let len = partial.len() + bytes.len();
+        let whole = len - len % frame_bytes;
+        let frames = buffer.bytes_mut(len);
+        frames[..partial.len()].copy_from_slice(&partial);
+        frames[partial.len()..].copy_from_slice(bytes);
+        partial.clear();
+        partial.extend_from_slice(&frames[whole..]);
+        if whole == 0 {
+            return;
+        }
+        let len = whole / sample_format.sample_size();
+        let data = unsafe { Data::from_parts(buffer.as_mut_ptr(), len, sample_format) };
+        data_callback(&data, info);
+    }
+}
+
+/// Wraps the data callback of an output stream so that it only fills whole frames.
+///
+/// When a buffer ends within a frame, the callback fills the whole frame, and the samples that
+/// do not fit are written at the start of the next buffer.
+pub(crate) fn align_output<D>(
+    channels: ChannelCount,
+    sample_format: SampleFormat,
+    mut data_callback: D,
+) -> impl FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+{
+    let frame_bytes = channels as usize * sample_format.sample_size();
+    // The bytes of the last frame filled that did not fit into the previous buffer.
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buffer = AlignedBuffer::default();
+    move |data: &mut Data, info: &OutputCallbackInfo| {
+        if pending.is_empty() && data.bytes().len().is_multiple_of(frame_bytes) {
+            return data_callback(data, info);
+        }
+        let output = data.bytes_mut();
+        let carried = pending.len().min(output.len());
+        output[..carried].copy_from_slice(&pending[..carried]);
+        pending.drain(..carried);
+        let rest = output.len() - carried;
+        if rest == 0 {
+            return;
+        }
+        let len = rest.div_ceil(frame_bytes) * frame_bytes;
+        fill_silence(buffer.bytes_mut(len), sample_format);
+        let mut frames = unsafe {
+            Data::from_parts(
+                buffer.as_mut_ptr(),
+                len / sample_format.sample_size(),
+                sample_format,
+            )
+        };
+        data_callback(&mut frames, info);
+        let frames = buffer.bytes_mut(len);
+        output[carried..].copy_from_slice(&frames[..rest]);
+        pending.extend_from_slice(&frames[rest..]);
+    }
+}
+
+// A byte buffer aligned for the samples of every format.
+#[derive(Default)]
+struct AlignedBuffer(Vec<u64>);
+
+impl AlignedBuffer {
+    // The first `len` bytes of the buffer, which grows to hold them if needed.
+    fn bytes_mut(&mut self, len: usize) -> &mut [u8] {
+        let words = len.div_ceil(8);
+        if self.0.len() < words {
+            self.0.resize(words, 0);
+        }
+        unsafe { slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, len) }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut () {
+        self.0.as_mut_ptr() as *mut ()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{align_input, align_output};
+    use crate::mock::{input_info, output_info};
+    use crate::{Data, SampleFormat};
+    use std::sync::{Arc, Mutex};
+
+    // Buffers of 5 samples for 2 channels, which end within a frame every other call.
+    const BUFFER_LEN: usize = 5;
+
+    #[test]
+    fn test_input_frames_are_not_split() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_by_callback = received.clone();
+        let mut data_callback = align_input(2, SampleFormat::F32, move |data: &Data, _: &_| {
+            let samples = data.as_slice::<f32>().unwrap();
+            assert_eq!(samples.len() % 2, 0);
+            received_by_callback.lock().unwrap().push(samples.to_vec());
+        });
+        // Left channels are even and right channels odd.
+        let mut samples: Vec<f32> = (0..4 * BUFFER_LEN).map(|i| i as f32).collect();
+        for buffer in samples.chunks_mut(BUFFER_LEN) {
+            let data = unsafe {
+                Data::from_parts(
+                    buffer.as_mut_ptr() as *mut (),
+                    BUFFER_LEN,
+                    SampleFormat::F32,
+                )
+            };
+            data_callback(&data, &input_info());
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(
+            received.iter().map(Vec::len).collect::<Vec<_>>(),
+            [4, 6, 4, 6]
+        );
+        let all: Vec<f32> = received.concat();
+        assert!(all
+            .iter()
+            .enumerate()
+            .all(|(i, &sample)| sample == i as f32));
+    }
+
+    #[test]
+    fn test_output_frames_are_not_split() {
+        let mut frame = 0;
+        let mut data_callback =
+            align_output(2, SampleFormat::F32, move |data: &mut Data, _: &_| {
+                let samples = data.as_slice_mut::<f32>().unwrap();
+                assert_eq!(samples.len() % 2, 0);
+                for channels in samples.chunks_mut(2) {
+                    channels[0] = frame as f32;
+                    channels[1] = -(frame as f32);
+                    frame += 1;
+                }
+            });
+        let mut output = [f32::NAN; 4 * BUFFER_LEN];
+        for buffer in output.chunks_mut(BUFFER_LEN) {
+            let mut data = unsafe {
+                Data::from_parts(
+                    buffer.as_mut_ptr() as *mut (),
+                    BUFFER_LEN,
+                    SampleFormat::F32,
+                )
+            };
+            data_callback(&mut data, &output_info());
+        }
+        for (frame, channels) in output.chunks(2).enumerate() {
+            assert_eq!(channels, [frame as f32, -(frame as f32)]);
+        }
+    }
+}
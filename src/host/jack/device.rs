@@ -1,8 +1,9 @@
+use crate::host::callback_guard;
 use crate::traits::DeviceTrait;
 use crate::{
     BackendSpecificError, BuildStreamError, Data, DefaultStreamConfigError, DeviceNameError,
-    InputCallbackInfo, OutputCallbackInfo, SampleFormat, SampleRate, StreamConfig, StreamError,
-    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    InputCallbackInfo, OutputCallbackInfo, SampleFormat, SampleRate, StreamConfig, StreamDirection,
+    StreamError, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
     SupportedStreamConfigsError,
 };
 use std::hash::{Hash, Hasher};
@@ -171,6 +172,18 @@ impl DeviceTrait for Device {
         self.default_config()
     }
 
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let matches_direction = match direction {
+            StreamDirection::Input => self.is_input(),
+            StreamDirection::Output => self.is_output(),
+        };
+        if matches_direction {
+            Some(JACK_SAMPLE_FORMAT)
+        } else {
+            None
+        }
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         conf: &StreamConfig,
@@ -182,6 +195,8 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
         if let DeviceType::OutputDevice = &self.device_type {
             // Trying to create an input stream from an output device
             return Err(BuildStreamError::StreamConfigNotSupported);
@@ -196,7 +211,10 @@ impl DeviceTrait for Device {
             Ok(c) => client = c,
             Err(e) => {
                 return Err(BuildStreamError::BackendSpecific {
-                    err: BackendSpecificError { description: e },
+                    err: BackendSpecificError {
+                        description: e,
+                        code: None,
+                    },
                 })
             }
         };
@@ -220,6 +238,8 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         if let DeviceType::InputDevice = &self.device_type {
             // Trying to create an output stream from an input device
             return Err(BuildStreamError::StreamConfigNotSupported);
@@ -235,7 +255,10 @@ impl DeviceTrait for Device {
             Ok(c) => client = c,
             Err(e) => {
                 return Err(BuildStreamError::BackendSpecific {
-                    err: BackendSpecificError { description: e },
+                    err: BackendSpecificError {
+                        description: e,
+                        code: None,
+                    },
                 })
             }
         };
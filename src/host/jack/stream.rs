@@ -5,7 +5,7 @@ use std::sync::{Arc, Mutex};
 
 use crate::{
     BackendSpecificError, Data, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
-    PlayStreamError, SampleRate, StreamError,
+    PlayStreamError, SampleFormat, SampleRate, StreamError,
 };
 
 use super::JACK_SAMPLE_FORMAT;
@@ -19,6 +19,9 @@ pub struct Stream {
     // Port names are stored in order to connect them to other ports in jack automatically
     input_port_names: Vec<String>,
     output_port_names: Vec<String>,
+    sample_rate: SampleRate,
+    // The number of ports that were successfully registered.
+    channels: ChannelCount,
 }
 
 impl Stream {
@@ -52,6 +55,7 @@ impl Stream {
                     error_callback(
                         BackendSpecificError {
                             description: e.to_string(),
+                            code: None,
                         }
                         .into(),
                     );
@@ -60,13 +64,15 @@ impl Stream {
         }
 
         let playing = Arc::new(AtomicBool::new(true));
+        let sample_rate = SampleRate(client.sample_rate() as u32);
+        let channels = ports.len() as ChannelCount;
 
         let error_callback_ptr = Arc::new(Mutex::new(error_callback)) as ErrorCallbackPtr;
 
         let input_process_handler = LocalProcessHandler::new(
             vec![],
             ports,
-            SampleRate(client.sample_rate() as u32),
+            sample_rate,
             client.buffer_size() as usize,
             Some(Box::new(data_callback)),
             None,
@@ -85,6 +91,8 @@ impl Stream {
             async_client,
             input_port_names: port_names,
             output_port_names: vec![],
+            sample_rate,
+            channels,
         }
     }
 
@@ -117,6 +125,7 @@ impl Stream {
                     error_callback(
                         BackendSpecificError {
                             description: e.to_string(),
+                            code: None,
                         }
                         .into(),
                     );
@@ -125,13 +134,15 @@ impl Stream {
         }
 
         let playing = Arc::new(AtomicBool::new(true));
+        let sample_rate = SampleRate(client.sample_rate() as u32);
+        let channels = ports.len() as ChannelCount;
 
         let error_callback_ptr = Arc::new(Mutex::new(error_callback)) as ErrorCallbackPtr;
 
         let output_process_handler = LocalProcessHandler::new(
             ports,
             vec![],
-            SampleRate(client.sample_rate() as u32),
+            sample_rate,
             client.buffer_size() as usize,
             None,
             Some(Box::new(data_callback)),
@@ -150,6 +161,8 @@ impl Stream {
             async_client,
             input_port_names: vec![],
             output_port_names: port_names,
+            sample_rate,
+            channels,
         }
     }
 
@@ -216,6 +229,18 @@ impl StreamTrait for Stream {
         self.playing.store(false, Ordering::SeqCst);
         Ok(())
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        JACK_SAMPLE_FORMAT
+    }
 }
 
 struct LocalProcessHandler {
@@ -233,6 +258,8 @@ struct LocalProcessHandler {
     temp_output_buffer: Vec<f32>,
     playing: Arc<AtomicBool>,
     creation_timestamp: std::time::Instant,
+    /// The JACK frame time of the last cycle, extended to 64 bits.
+    frame_time: Option<u64>,
     /// This should not be called on `process`, only on `buffer_size` because it can block.
     error_callback_ptr: ErrorCallbackPtr,
 }
@@ -265,6 +292,7 @@ impl LocalProcessHandler {
             temp_output_buffer,
             playing,
             creation_timestamp: std::time::Instant::now(),
+            frame_time: None,
             error_callback_ptr,
         }
     }
@@ -301,6 +329,8 @@ impl jack::ProcessHandler for LocalProcessHandler {
             }
         };
         let start_cycle_instant = micros_to_stream_instant(current_start_usecs);
+        let frame_time = extend_frame_time(self.frame_time, process_scope.last_frame_time());
+        self.frame_time = Some(frame_time);
         let start_callback_instant = start_cycle_instant
             .add(frames_to_duration(
                 process_scope.frames_since_cycle_start() as usize,
@@ -335,7 +365,15 @@ impl jack::ProcessHandler for LocalProcessHandler {
                 .expect("`playback` occurs beyond representation supported by `StreamInstant`");
             let capture = start_callback_instant;
             let timestamp = crate::InputStreamTimestamp { callback, capture };
-            let info = crate::InputCallbackInfo { timestamp };
+            let device_timestamp = Some(crate::DeviceTimestamp {
+                samples: frame_time,
+                sample_rate: self.sample_rate,
+            });
+            let info = crate::InputCallbackInfo {
+                timestamp,
+                device_timestamp,
+                channel_mix: crate::ChannelMix::None,
+            };
             input_callback(&data, &info);
         }
 
@@ -359,7 +397,14 @@ impl jack::ProcessHandler for LocalProcessHandler {
                 .add(buffer_duration)
                 .expect("`playback` occurs beyond representation supported by `StreamInstant`");
             let timestamp = crate::OutputStreamTimestamp { callback, playback };
-            let info = crate::OutputCallbackInfo { timestamp };
+            let device_timestamp = Some(crate::DeviceTimestamp {
+                samples: frame_time + current_frame_count as u64,
+                sample_rate: self.sample_rate,
+            });
+            let info = crate::OutputCallbackInfo {
+                timestamp,
+                device_timestamp,
+            };
             output_callback(&mut data, &info);
 
             // Deinterlace
@@ -388,7 +433,11 @@ impl jack::ProcessHandler for LocalProcessHandler {
             let description = format!("buffer size changed to: {}", new_size);
             if let Ok(mut mutex_guard) = self.error_callback_ptr.lock() {
                 let err = &mut *mutex_guard;
-                err(BackendSpecificError { description }.into());
+                err(BackendSpecificError {
+                    description,
+                    code: None,
+                }
+                .into());
             }
         }
 
@@ -403,6 +452,14 @@ fn micros_to_stream_instant(micros: u64) -> crate::StreamInstant {
     crate::StreamInstant::new(secs as i64, subsec_nanos as u32)
 }
 
+// JACK frame times are 32 bits wide and wrap around after a day or so at common sample rates.
+fn extend_frame_time(previous: Option<u64>, frame_time: jack::Frames) -> u64 {
+    match previous {
+        Some(previous) => previous + frame_time.wrapping_sub(previous as jack::Frames) as u64,
+        None => frame_time as u64,
+    }
+}
+
 // Convert the given duration in frames at the given sample rate to a `std::time::Duration`.
 fn frames_to_duration(frames: usize, rate: crate::SampleRate) -> std::time::Duration {
     let secsf = frames as f64 / rate.0 as f64;
@@ -430,7 +487,11 @@ impl JackNotificationHandler {
         // This thread isn't the audio thread, it's fine to block
         if let Ok(mut mutex_guard) = self.error_callback_ptr.lock() {
             let err = &mut *mutex_guard;
-            err(BackendSpecificError { description }.into());
+            err(BackendSpecificError {
+                description,
+                code: None,
+            }
+            .into());
         }
     }
 }
@@ -461,3 +522,16 @@ impl jack::NotificationHandler for JackNotificationHandler {
         jack::Control::Continue
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::extend_frame_time;
+
+    #[test]
+    fn test_extend_frame_time_across_wrap() {
+        let before_wrap = extend_frame_time(None, u32::MAX - 99);
+        assert_eq!(before_wrap, u32::MAX as u64 - 99);
+        let after_wrap = extend_frame_time(Some(before_wrap), 156);
+        assert_eq!(after_wrap, u32::MAX as u64 + 157);
+    }
+}
@@ -0,0 +1,170 @@
+//! Shared handling of `pause`/`play` for hosts that support `KeepAlive` on output streams.
+
+use crate::KeepAlive;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// What the audio thread should do with the next output buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum OutputMode {
+    /// Call the user's data callback.
+    Callback,
+    /// The stream is paused: fill the buffer with silence.
+    Silence,
+    /// The stream is paused under `KeepAlive::Hardware`: hand the buffer back without writing to
+    /// it, letting the host produce silence.
+    Idle,
+}
+
+/// The user-visible paused state of a stream, and whether its backend stream is actually running.
+///
+/// With `KeepAlive::Off`, pausing stops the backend stream. Otherwise the backend keeps running
+/// while paused and the audio thread stops calling the user's data callback.
+pub(crate) struct PauseState {
+    paused: AtomicBool,
+    keep_alive: AtomicU8,
+    // Only accessed by `play`, `pause` and `set_keep_alive`, which it also serialises.
+    backend_running: Mutex<bool>,
+}
+
+impl PauseState {
+    pub(crate) fn new(backend_running: bool) -> Self {
+        PauseState {
+            paused: AtomicBool::new(!backend_running),
+            keep_alive: AtomicU8::new(KeepAlive::Off as u8),
+            backend_running: Mutex::new(backend_running),
+        }
+    }
+
+    fn keep_alive(&self) -> KeepAlive {
+        match self.keep_alive.load(Ordering::Acquire) {
+            x if x == KeepAlive::Silence as u8 => KeepAlive::Silence,
+            x if x == KeepAlive::Hardware as u8 => KeepAlive::Hardware,
+            _ => KeepAlive::Off,
+        }
+    }
+
+    /// Resumes the user side of the stream, calling `start` if the backend stream is stopped.
+    pub(crate) fn play<E>(&self, start: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        let mut backend_running = self.backend_running.lock().unwrap();
+        if !*backend_running {
+            start()?;
+            *backend_running = true;
+        }
+        self.paused.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Pauses the user side of the stream, calling `stop` unless the device is kept alive.
+    pub(crate) fn pause<E>(&self, stop: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        let mut backend_running = self.backend_running.lock().unwrap();
+        self.paused.store(true, Ordering::Release);
+        if *backend_running && self.keep_alive() == KeepAlive::Off {
+            stop()?;
+            *backend_running = false;
+        }
+        Ok(())
+    }
+
+    /// Changes the keep-alive mode.
+    ///
+    /// Turning keep-alive off while paused calls `stop`. Turning it on never starts a stopped
+    /// backend stream; it takes effect from the next `pause`.
+    pub(crate) fn set_keep_alive<E>(
+        &self,
+        keep_alive: KeepAlive,
+        stop: impl FnOnce() -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut backend_running = self.backend_running.lock().unwrap();
+        self.keep_alive.store(keep_alive as u8, Ordering::Release);
+        if keep_alive == KeepAlive::Off && *backend_running && self.paused.load(Ordering::Acquire) {
+            stop()?;
+            *backend_running = false;
+        }
+        Ok(())
+    }
+
+    /// Called by the audio thread before producing each output buffer.
+    pub(crate) fn output_mode(&self) -> OutputMode {
+        if !self.paused.load(Ordering::Acquire) {
+            return OutputMode::Callback;
+        }
+        match self.keep_alive() {
+            KeepAlive::Hardware => OutputMode::Idle,
+            // With `Off` the backend is about to be stopped; don't call the user in the meantime.
+            KeepAlive::Silence | KeepAlive::Off => OutputMode::Silence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OutputMode, PauseState};
+    use crate::KeepAlive;
+    use std::cell::Cell;
+
+    fn count(transitions: &Cell<u32>) -> Result<(), ()> {
+        transitions.set(transitions.get() + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pause_without_keep_alive_stops_backend() {
+        let state = PauseState::new(true);
+        let transitions = Cell::new(0);
+        for _ in 0..10 {
+            state.pause(|| count(&transitions)).unwrap();
+            state.play(|| count(&transitions)).unwrap();
+        }
+        assert_eq!(transitions.get(), 20);
+    }
+
+    #[test]
+    fn test_rapid_pause_with_keep_alive_never_touches_backend() {
+        for &keep_alive in &[KeepAlive::Silence, KeepAlive::Hardware] {
+            let state = PauseState::new(true);
+            let transitions = Cell::new(0);
+            state
+                .set_keep_alive(keep_alive, || count(&transitions))
+                .unwrap();
+            for _ in 0..1000 {
+                state.pause(|| count(&transitions)).unwrap();
+                assert_ne!(state.output_mode(), OutputMode::Callback);
+                state.play(|| count(&transitions)).unwrap();
+                assert_eq!(state.output_mode(), OutputMode::Callback);
+            }
+            assert_eq!(transitions.get(), 0);
+        }
+    }
+
+    #[test]
+    fn test_output_mode_while_paused() {
+        let state = PauseState::new(true);
+        state
+            .set_keep_alive(KeepAlive::Silence, || Ok::<_, ()>(()))
+            .unwrap();
+        state.pause(|| Ok::<_, ()>(())).unwrap();
+        assert_eq!(state.output_mode(), OutputMode::Silence);
+        state
+            .set_keep_alive(KeepAlive::Hardware, || Ok::<_, ()>(()))
+            .unwrap();
+        assert_eq!(state.output_mode(), OutputMode::Idle);
+    }
+
+    #[test]
+    fn test_disabling_keep_alive_while_paused_stops_backend() {
+        let state = PauseState::new(true);
+        let transitions = Cell::new(0);
+        state
+            .set_keep_alive(KeepAlive::Silence, || count(&transitions))
+            .unwrap();
+        state.pause(|| count(&transitions)).unwrap();
+        assert_eq!(transitions.get(), 0);
+        state
+            .set_keep_alive(KeepAlive::Off, || count(&transitions))
+            .unwrap();
+        assert_eq!(transitions.get(), 1);
+        state.play(|| count(&transitions)).unwrap();
+        assert_eq!(transitions.get(), 2);
+    }
+}
@@ -2,19 +2,45 @@
 pub(crate) mod alsa;
 #[cfg(all(windows, feature = "asio"))]
 pub(crate) mod asio;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "macos",
+    windows
+))]
+pub(crate) mod audio_thread;
+pub(crate) mod callback_guard;
+pub(crate) mod channel_mode;
+#[cfg(any(windows, test))]
+pub(crate) mod config_cache;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub(crate) mod coreaudio;
+#[cfg(any(target_os = "macos", windows, test))]
+pub(crate) mod device_events;
 #[cfg(target_os = "emscripten")]
 pub(crate) mod emscripten;
+pub(crate) mod fade;
+#[cfg(any(target_os = "macos", target_os = "ios", test))]
+pub(crate) mod frame_align;
 #[cfg(all(
     any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"),
     feature = "jack"
 ))]
 pub(crate) mod jack;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "macos",
+    windows
+))]
+pub(crate) mod keep_alive;
 pub(crate) mod null;
 #[cfg(target_os = "android")]
 pub(crate) mod oboe;
 #[cfg(windows)]
 pub(crate) mod wasapi;
+pub(crate) mod watchdog;
 #[cfg(all(target_arch = "wasm32", feature = "wasm-bindgen"))]
 pub(crate) mod webaudio;
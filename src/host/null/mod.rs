@@ -1,8 +1,8 @@
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BuildStreamError, Data, DefaultStreamConfigError, DeviceNameError, DevicesError,
+    BuildStreamError, ChannelCount, Data, DefaultStreamConfigError, DeviceNameError, DevicesError,
     InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError, SampleFormat,
-    StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigRange,
+    SampleRate, StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigRange,
     SupportedStreamConfigsError,
 };
 
@@ -126,6 +126,18 @@ impl StreamTrait for Stream {
     fn pause(&self) -> Result<(), PauseStreamError> {
         unimplemented!()
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        unimplemented!()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        unimplemented!()
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        unimplemented!()
+    }
 }
 
 impl Iterator for Devices {
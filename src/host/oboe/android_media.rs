@@ -1,9 +1,13 @@
-use std::sync::Arc;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
 extern crate jni;
 
-use self::jni::Executor;
+use self::jni::objects::{GlobalRef, JClass, JObject, JValue};
+use self::jni::sys::{jint, jlong};
 use self::jni::{errors::Result as JResult, JNIEnv, JavaVM};
+use self::jni::{Executor, NativeMethod};
 
 // constants from android.media.AudioFormat
 pub const ENCODING_PCM_16BIT: i32 = 2;
@@ -11,6 +15,17 @@ pub const ENCODING_PCM_FLOAT: i32 = 4;
 pub const CHANNEL_OUT_MONO: i32 = 4;
 pub const CHANNEL_OUT_STEREO: i32 = 12;
 
+// constants from android.media.AudioManager
+pub const AUDIOFOCUS_GAIN: i32 = 1;
+pub const AUDIOFOCUS_GAIN_TRANSIENT: i32 = 2;
+pub const AUDIOFOCUS_GAIN_TRANSIENT_MAY_DUCK: i32 = 3;
+pub const AUDIOFOCUS_GAIN_TRANSIENT_EXCLUSIVE: i32 = 4;
+pub const AUDIOFOCUS_LOSS: i32 = -1;
+pub const AUDIOFOCUS_LOSS_TRANSIENT: i32 = -2;
+pub const AUDIOFOCUS_LOSS_TRANSIENT_CAN_DUCK: i32 = -3;
+const AUDIOFOCUS_REQUEST_GRANTED: i32 = 1;
+const STREAM_MUSIC: i32 = 3;
+
 fn with_attached<F, R>(closure: F) -> JResult<R>
 where
     F: FnOnce(&JNIEnv) -> JResult<R>,
@@ -60,3 +75,234 @@ pub fn get_audio_record_min_buffer_size(sample_rate: i32, channel_mask: i32, for
         format,
     )
 }
+
+fn with_audio_manager<F, R>(closure: F) -> JResult<R>
+where
+    F: FnOnce(&JNIEnv, JObject) -> JResult<R>,
+{
+    with_attached(|env| {
+        let context = JObject::from(ndk_context::android_context().context() as jni::sys::jobject);
+        let audio_service = env
+            .get_static_field(
+                "android/content/Context",
+                "AUDIO_SERVICE",
+                "Ljava/lang/String;",
+            )?
+            .l()?;
+        let audio_manager = env
+            .call_method(
+                context,
+                "getSystemService",
+                "(Ljava/lang/String;)Ljava/lang/Object;",
+                &[audio_service.into()],
+            )?
+            .l()?;
+        closure(env, audio_manager)
+    })
+}
+
+/// Request audio focus for the application, returning whether it was granted.
+///
+/// Changes of the focus are reported to `listener`. Without one, focus is requested on behalf of
+/// the whole `AudioManager` rather than a particular stream.
+pub fn request_audio_focus(duration_hint: i32, listener: Option<&FocusListener>) -> JResult<bool> {
+    with_audio_manager(|env, audio_manager| {
+        let listener = listener.map_or_else(JObject::null, |listener| listener.object.as_obj());
+        let result = env
+            .call_method(
+                audio_manager,
+                "requestAudioFocus",
+                "(Landroid/media/AudioManager$OnAudioFocusChangeListener;II)I",
+                &[listener.into(), STREAM_MUSIC.into(), duration_hint.into()],
+            )?
+            .i()?;
+        Ok(result == AUDIOFOCUS_REQUEST_GRANTED)
+    })
+}
+
+/// Abandon audio focus previously acquired via `request_audio_focus` with the same `listener`.
+pub fn abandon_audio_focus(listener: Option<&FocusListener>) -> JResult<()> {
+    with_audio_manager(|env, audio_manager| {
+        let listener = listener.map_or_else(JObject::null, |listener| listener.object.as_obj());
+        env.call_method(
+            audio_manager,
+            "abandonAudioFocus",
+            "(Landroid/media/AudioManager$OnAudioFocusChangeListener;)I",
+            &[listener.into()],
+        )?;
+        Ok(())
+    })
+}
+
+/// An `AudioManager.OnAudioFocusChangeListener`, calling a closure with the `AUDIOFOCUS_*` value
+/// of each change of the focus until dropped.
+pub struct FocusListener {
+    object: GlobalRef,
+    handle: jlong,
+}
+
+type FocusChangeCallback = Arc<Mutex<dyn FnMut(i32) + Send>>;
+
+// The callbacks of the live listeners, by the `handle` field of their Java object.
+static FOCUS_LISTENERS: Mutex<Vec<(jlong, FocusChangeCallback)>> = Mutex::new(Vec::new());
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(0);
+
+// The listener class once loaded, or `None` within if it could not be.
+static LISTENER_CLASS: Mutex<Option<Option<GlobalRef>>> = Mutex::new(None);
+
+impl FocusListener {
+    /// Returns `None` if the listener class cannot be loaded, which requires Android 8.0 (API
+    /// level 26) or later.
+    pub fn new<F>(on_change: F) -> Option<Self>
+    where
+        F: FnMut(i32) + Send + 'static,
+    {
+        let created = with_attached(|env| {
+            let class = match listener_class(env) {
+                Some(class) => class,
+                None => return Ok(None),
+            };
+            let object = env.alloc_object(JClass::from(class.as_obj()))?;
+            let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+            env.set_field(object, "handle", "J", JValue::Long(handle))?;
+            let object = env.new_global_ref(object)?;
+            Ok(Some(FocusListener { object, handle }))
+        });
+        let listener = created.ok().flatten()?;
+        FOCUS_LISTENERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((listener.handle, Arc::new(Mutex::new(on_change))));
+        Some(listener)
+    }
+}
+
+impl Drop for FocusListener {
+    fn drop(&mut self) {
+        FOCUS_LISTENERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|&(handle, _)| handle != self.handle);
+    }
+}
+
+// Loads the listener class from `LISTENER_DEX` and binds its native method, on first use.
+fn listener_class(env: &JNIEnv) -> Option<GlobalRef> {
+    let mut class = LISTENER_CLASS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if class.is_none() {
+        let loaded = load_listener_class(env);
+        if loaded.is_err() {
+            // E.g. `InMemoryDexClassLoader` not being found before Android 8.0.
+            let _ = env.exception_clear();
+        }
+        *class = Some(loaded.ok());
+    }
+    class.clone().flatten()
+}
+
+fn load_listener_class(env: &JNIEnv) -> JResult<GlobalRef> {
+    // The class loader reads the dex file from the buffer for as long as it lives.
+    let dex = Box::leak(LISTENER_DEX.to_vec().into_boxed_slice());
+    let buffer = env.new_direct_byte_buffer(dex)?;
+    let context = JObject::from(ndk_context::android_context().context() as jni::sys::jobject);
+    let parent = env
+        .call_method(context, "getClassLoader", "()Ljava/lang/ClassLoader;", &[])?
+        .l()?;
+    let loader = env.new_object(
+        "dalvik/system/InMemoryDexClassLoader",
+        "(Ljava/nio/ByteBuffer;Ljava/lang/ClassLoader;)V",
+        &[JObject::from(buffer).into(), parent.into()],
+    )?;
+    let name = env.new_string("cpal.AudioFocusListener")?;
+    let class = env
+        .call_method(
+            loader,
+            "loadClass",
+            "(Ljava/lang/String;)Ljava/lang/Class;",
+            &[JObject::from(name).into()],
+        )?
+        .l()?;
+    env.register_native_methods(
+        JClass::from(class),
+        &[NativeMethod {
+            name: "onAudioFocusChange".into(),
+            sig: "(I)V".into(),
+            fn_ptr: on_audio_focus_change as *mut c_void,
+        }],
+    )?;
+    env.new_global_ref(class)
+}
+
+// `AudioFocusListener.onAudioFocusChange`, called on the main thread of the application.
+extern "system" fn on_audio_focus_change(env: JNIEnv, listener: JObject, focus_change: jint) {
+    let handle = match env
+        .get_field(listener, "handle", "J")
+        .and_then(|handle| handle.j())
+    {
+        Ok(handle) => handle,
+        Err(_) => return,
+    };
+    // Released before calling back, which may drop the listener.
+    let on_change = FOCUS_LISTENERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .find(|&&(listener, _)| listener == handle)
+        .map(|(_, on_change)| on_change.clone());
+    if let Some(on_change) = on_change {
+        (on_change.lock().unwrap_or_else(PoisonError::into_inner))(focus_change);
+    }
+}
+
+// A dex file defining the listener class, so that applications need not ship any Java code for
+// it. It is equivalent to
+//
+// ```java
+// package cpal;
+//
+// public final class AudioFocusListener implements AudioManager.OnAudioFocusChangeListener {
+//     private long handle;
+//
+//     public native void onAudioFocusChange(int focusChange);
+// }
+// ```
+//
+// without a constructor, as instances are only allocated through JNI, which sets `handle`.
+static LISTENER_DEX: [u8; 540] = [
+    0x64, 0x65, 0x78, 0x0a, 0x30, 0x33, 0x35, 0x00, 0xdf, 0x54, 0x24, 0x76, 0x05, 0x91, 0x80, 0x75,
+    0x0b, 0x3c, 0x95, 0x1c, 0x24, 0x84, 0xcd, 0x82, 0x1a, 0x59, 0xcd, 0xb5, 0x8e, 0x77, 0xd2, 0xe4,
+    0x1c, 0x02, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x78, 0x56, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x94, 0x01, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00,
+    0x06, 0x00, 0x00, 0x00, 0x94, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xac, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00, 0xb8, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00, 0xc8, 0x00, 0x00, 0x00, 0x34, 0x01, 0x00, 0x00, 0xe8, 0x00, 0x00, 0x00,
+    0xf8, 0x00, 0x00, 0x00, 0xfb, 0x00, 0x00, 0x00, 0xfe, 0x00, 0x00, 0x00, 0x37, 0x01, 0x00, 0x00,
+    0x52, 0x01, 0x00, 0x00, 0x66, 0x01, 0x00, 0x00, 0x69, 0x01, 0x00, 0x00, 0x6d, 0x01, 0x00, 0x00,
+    0x75, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+    0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+    0x05, 0x00, 0x00, 0x00, 0xe8, 0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x07, 0x00, 0x00, 0x00,
+    0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00,
+    0x04, 0x00, 0x00, 0x00, 0xf0, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+    0x89, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x49, 0x00, 0x01, 0x4a, 0x00, 0x37, 0x4c,
+    0x61, 0x6e, 0x64, 0x72, 0x6f, 0x69, 0x64, 0x2f, 0x6d, 0x65, 0x64, 0x69, 0x61, 0x2f, 0x41, 0x75,
+    0x64, 0x69, 0x6f, 0x4d, 0x61, 0x6e, 0x61, 0x67, 0x65, 0x72, 0x24, 0x4f, 0x6e, 0x41, 0x75, 0x64,
+    0x69, 0x6f, 0x46, 0x6f, 0x63, 0x75, 0x73, 0x43, 0x68, 0x61, 0x6e, 0x67, 0x65, 0x4c, 0x69, 0x73,
+    0x74, 0x65, 0x6e, 0x65, 0x72, 0x3b, 0x00, 0x19, 0x4c, 0x63, 0x70, 0x61, 0x6c, 0x2f, 0x41, 0x75,
+    0x64, 0x69, 0x6f, 0x46, 0x6f, 0x63, 0x75, 0x73, 0x4c, 0x69, 0x73, 0x74, 0x65, 0x6e, 0x65, 0x72,
+    0x3b, 0x00, 0x12, 0x4c, 0x6a, 0x61, 0x76, 0x61, 0x2f, 0x6c, 0x61, 0x6e, 0x67, 0x2f, 0x4f, 0x62,
+    0x6a, 0x65, 0x63, 0x74, 0x3b, 0x00, 0x01, 0x56, 0x00, 0x02, 0x56, 0x49, 0x00, 0x06, 0x68, 0x61,
+    0x6e, 0x64, 0x6c, 0x65, 0x00, 0x12, 0x6f, 0x6e, 0x41, 0x75, 0x64, 0x69, 0x6f, 0x46, 0x6f, 0x63,
+    0x75, 0x73, 0x43, 0x68, 0x61, 0x6e, 0x67, 0x65, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x02, 0x00,
+    0x81, 0x02, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x94, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00, 0xac, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    0xb8, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00,
+    0x06, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xc8, 0x00, 0x00, 0x00, 0x01, 0x10, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0xe8, 0x00, 0x00, 0x00, 0x02, 0x20, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00,
+    0xf8, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x89, 0x01, 0x00, 0x00,
+    0x00, 0x10, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x94, 0x01, 0x00, 0x00,
+];
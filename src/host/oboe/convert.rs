@@ -33,6 +33,7 @@ impl From<oboe::Error> for StreamError {
             Disconnected | Unavailable | Closed => Self::DeviceNotAvailable,
             e => (BackendSpecificError {
                 description: e.to_string(),
+                code: Some(e as i32),
             })
             .into(),
         }
@@ -46,6 +47,7 @@ impl From<oboe::Error> for PlayStreamError {
             Disconnected | Unavailable | Closed => Self::DeviceNotAvailable,
             e => (BackendSpecificError {
                 description: e.to_string(),
+                code: Some(e as i32),
             })
             .into(),
         }
@@ -59,6 +61,7 @@ impl From<oboe::Error> for PauseStreamError {
             Disconnected | Unavailable | Closed => Self::DeviceNotAvailable,
             e => (BackendSpecificError {
                 description: e.to_string(),
+                code: Some(e as i32),
             })
             .into(),
         }
@@ -75,6 +78,7 @@ impl From<oboe::Error> for BuildStreamError {
             IllegalArgument => Self::InvalidArgument,
             e => (BackendSpecificError {
                 description: e.to_string(),
+                code: Some(e as i32),
             })
             .into(),
         }
@@ -4,7 +4,7 @@ use std::time::Instant;
 extern crate oboe;
 
 use super::convert::{stream_instant, to_stream_instant};
-use crate::{Data, InputCallbackInfo, InputStreamTimestamp, Sample, StreamError};
+use crate::{ChannelMix, Data, InputCallbackInfo, InputStreamTimestamp, Sample, StreamError};
 
 pub struct CpalInputCallback<I, C> {
     data_cb: Box<dyn FnMut(&Data, &InputCallbackInfo) + Send + 'static>,
@@ -38,6 +38,8 @@ impl<I, C> CpalInputCallback<I, C> {
                 callback: to_stream_instant(self.created.elapsed()),
                 capture: stream_instant(audio_stream),
             },
+            device_timestamp: None,
+            channel_mix: ChannelMix::None,
         }
     }
 }
@@ -1,17 +1,19 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp;
 use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
 use std::vec::IntoIter as VecIntoIter;
 
 extern crate oboe;
 
+use crate::host::callback_guard;
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, Data, DefaultStreamConfigError,
-    DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
-    PlayStreamError, Sample, SampleFormat, SampleRate, StreamConfig, StreamError,
-    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
-    SupportedStreamConfigsError,
+    BackendSpecificError, BtProfile, BufferSize, BuildStreamError, ChannelCount, Data,
+    DefaultStreamConfigError, DeviceNameError, DevicesError, FocusGain, FocusRequest,
+    InputCallbackInfo, OutputCallbackInfo, PauseStreamError, PlayStreamError, RequestFocusError,
+    Sample, SampleFormat, SampleRate, StreamConfig, StreamError, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
 };
 
 mod android_media;
@@ -19,7 +21,9 @@ mod convert;
 mod input_callback;
 mod output_callback;
 
-use self::android_media::{get_audio_record_min_buffer_size, get_audio_track_min_buffer_size};
+use self::android_media::{
+    get_audio_record_min_buffer_size, get_audio_track_min_buffer_size, FocusListener,
+};
 use self::input_callback::CpalInputCallback;
 use self::oboe::{AudioInputStream, AudioOutputStream};
 use self::output_callback::CpalOutputCallback;
@@ -37,7 +41,19 @@ const SAMPLE_RATES: [i32; 13] = [
 
 pub struct Host;
 pub struct Device(Option<oboe::AudioDeviceInfo>);
-pub enum Stream {
+pub struct Stream {
+    inner: StreamInner,
+    // Whether audio focus was granted via `request_focus`, in which case it is abandoned on drop.
+    has_focus: Cell<bool>,
+    // Reports the loss of the focus to the error callback, where the listener could be created.
+    focus_listener: RefCell<Option<FocusListener>>,
+    // Shared with the oboe callback, so that the focus listener can report errors too.
+    error_callback: Arc<Mutex<dyn FnMut(StreamError) + Send>>,
+    // The configuration and sample format with which the stream was created.
+    config: StreamConfig,
+    sample_format: SampleFormat,
+}
+enum StreamInner {
     Input(Box<RefCell<dyn AudioInputStream>>),
     Output(Box<RefCell<dyn AudioOutputStream>>),
 }
@@ -240,13 +256,19 @@ where
     E: FnMut(StreamError) + Send + 'static,
 {
     let builder = configure_for_device(builder, device, config);
+    let error_callback = Arc::new(Mutex::new(error_callback));
+    let stream_error_callback = error_callback.clone();
     let stream = builder
-        .set_callback(CpalInputCallback::<T, C>::new(
-            data_callback,
-            error_callback,
-        ))
+        .set_callback(CpalInputCallback::<T, C>::new(data_callback, move |err| {
+            (stream_error_callback.lock().unwrap())(err)
+        }))
         .open_stream()?;
-    Ok(Stream::Input(Box::new(RefCell::new(stream))))
+    Ok(Stream::new(
+        StreamInner::Input(Box::new(RefCell::new(stream))),
+        config.clone(),
+        T::FORMAT,
+        error_callback,
+    ))
 }
 
 fn build_output_stream<D, E, C, T>(
@@ -264,13 +286,19 @@ where
     E: FnMut(StreamError) + Send + 'static,
 {
     let builder = configure_for_device(builder, device, config);
+    let error_callback = Arc::new(Mutex::new(error_callback));
+    let stream_error_callback = error_callback.clone();
     let stream = builder
-        .set_callback(CpalOutputCallback::<T, C>::new(
-            data_callback,
-            error_callback,
-        ))
+        .set_callback(CpalOutputCallback::<T, C>::new(data_callback, move |err| {
+            (stream_error_callback.lock().unwrap())(err)
+        }))
         .open_stream()?;
-    Ok(Stream::Output(Box::new(RefCell::new(stream))))
+    Ok(Stream::new(
+        StreamInner::Output(Box::new(RefCell::new(stream))),
+        config.clone(),
+        T::FORMAT,
+        error_callback,
+    ))
 }
 
 impl DeviceTrait for Device {
@@ -327,6 +355,14 @@ impl DeviceTrait for Device {
         Ok(config)
     }
 
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        match self.0.as_ref()?.device_type {
+            oboe::AudioDeviceType::BluetoothA2DP => Some(BtProfile::A2dp),
+            oboe::AudioDeviceType::BluetoothSCO => Some(BtProfile::HandsFree),
+            _ => None,
+        }
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         config: &StreamConfig,
@@ -338,6 +374,8 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
         match sample_format {
             SampleFormat::I16 => {
                 let builder = oboe::AudioStreamBuilder::default()
@@ -362,6 +400,7 @@ impl DeviceTrait for Device {
                 } else {
                     Err(BackendSpecificError {
                         description: "More than 2 channels are not supported by Oboe.".to_owned(),
+                        code: None,
                     }
                     .into())
                 }
@@ -389,14 +428,18 @@ impl DeviceTrait for Device {
                 } else {
                     Err(BackendSpecificError {
                         description: "More than 2 channels are not supported by Oboe.".to_owned(),
+                        code: None,
                     }
                     .into())
                 }
             }
-            SampleFormat::U16 => Err(BackendSpecificError {
-                description: "U16 format is not supported on Android.".to_owned(),
+            SampleFormat::U16 | SampleFormat::I24 | SampleFormat::I24Packed => {
+                Err(BackendSpecificError {
+                    description: format!("{:?} format is not supported on Android.", sample_format),
+                    code: None,
+                }
+                .into())
             }
-            .into()),
         }
     }
 
@@ -411,6 +454,8 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         match sample_format {
             SampleFormat::I16 => {
                 let builder = oboe::AudioStreamBuilder::default()
@@ -435,6 +480,7 @@ impl DeviceTrait for Device {
                 } else {
                     Err(BackendSpecificError {
                         description: "More than 2 channels are not supported by Oboe.".to_owned(),
+                        code: None,
                     }
                     .into())
                 }
@@ -462,26 +508,48 @@ impl DeviceTrait for Device {
                 } else {
                     Err(BackendSpecificError {
                         description: "More than 2 channels are not supported by Oboe.".to_owned(),
+                        code: None,
                     }
                     .into())
                 }
             }
-            SampleFormat::U16 => Err(BackendSpecificError {
-                description: "U16 format is not supported on Android.".to_owned(),
+            SampleFormat::U16 | SampleFormat::I24 | SampleFormat::I24Packed => {
+                Err(BackendSpecificError {
+                    description: format!("{:?} format is not supported on Android.", sample_format),
+                    code: None,
+                }
+                .into())
             }
-            .into()),
+        }
+    }
+}
+
+impl Stream {
+    fn new(
+        inner: StreamInner,
+        config: StreamConfig,
+        sample_format: SampleFormat,
+        error_callback: Arc<Mutex<dyn FnMut(StreamError) + Send>>,
+    ) -> Self {
+        Stream {
+            inner,
+            has_focus: Cell::new(false),
+            focus_listener: RefCell::new(None),
+            error_callback,
+            config,
+            sample_format,
         }
     }
 }
 
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
-        match self {
-            Self::Input(stream) => stream
+        match &self.inner {
+            StreamInner::Input(stream) => stream
                 .borrow_mut()
                 .request_start()
                 .map_err(PlayStreamError::from),
-            Self::Output(stream) => stream
+            StreamInner::Output(stream) => stream
                 .borrow_mut()
                 .request_start()
                 .map_err(PlayStreamError::from),
@@ -489,15 +557,76 @@ impl StreamTrait for Stream {
     }
 
     fn pause(&self) -> Result<(), PauseStreamError> {
-        match self {
-            Self::Input(_) => Err(BackendSpecificError {
+        match &self.inner {
+            StreamInner::Input(_) => Err(BackendSpecificError {
                 description: "Pause called on the input stream.".to_owned(),
+                code: None,
             }
             .into()),
-            Self::Output(stream) => stream
+            StreamInner::Output(stream) => stream
                 .borrow_mut()
                 .request_pause()
                 .map_err(PauseStreamError::from),
         }
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    fn request_focus(&self, request: FocusRequest) -> Result<(), RequestFocusError> {
+        // Android can only let others duck while holding focus for a short time.
+        let duration_hint = match request.gain {
+            FocusGain::Transient if request.duck_others => {
+                android_media::AUDIOFOCUS_GAIN_TRANSIENT_MAY_DUCK
+            }
+            _ if request.duck_others => return Err(RequestFocusError::NotSupported),
+            FocusGain::Permanent => android_media::AUDIOFOCUS_GAIN,
+            FocusGain::Transient => android_media::AUDIOFOCUS_GAIN_TRANSIENT,
+            FocusGain::TransientExclusive => android_media::AUDIOFOCUS_GAIN_TRANSIENT_EXCLUSIVE,
+        };
+        let mut focus_listener = self.focus_listener.borrow_mut();
+        if focus_listener.is_none() && !self.has_focus.get() {
+            let error_callback = self.error_callback.clone();
+            *focus_listener = FocusListener::new(move |focus_change| {
+                let (transient, may_duck) = match focus_change {
+                    android_media::AUDIOFOCUS_LOSS => (false, false),
+                    android_media::AUDIOFOCUS_LOSS_TRANSIENT => (true, false),
+                    android_media::AUDIOFOCUS_LOSS_TRANSIENT_CAN_DUCK => (true, true),
+                    // Regaining the focus is not an error.
+                    _ => return,
+                };
+                (error_callback.lock().unwrap())(StreamError::FocusLost {
+                    transient,
+                    may_duck,
+                })
+            });
+        }
+        let granted = android_media::request_audio_focus(duration_hint, focus_listener.as_ref())
+            .map_err(|e| BackendSpecificError {
+                description: e.to_string(),
+                code: None,
+            })?;
+        if !granted {
+            return Err(RequestFocusError::Denied);
+        }
+        self.has_focus.set(true);
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if self.has_focus.get() {
+            let _ = android_media::abandon_audio_focus(self.focus_listener.get_mut().as_ref());
+        }
+    }
 }
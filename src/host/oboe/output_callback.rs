@@ -38,6 +38,7 @@ impl<I, C> CpalOutputCallback<I, C> {
                 callback: to_stream_instant(self.created.elapsed()),
                 playback: stream_instant(audio_stream),
             },
+            device_timestamp: None,
         }
     }
 }
@@ -1,18 +1,22 @@
+use crate::host::callback_guard;
+use crate::host::config_cache::ConfigCache;
 use crate::{
-    BackendSpecificError, BufferSize, Data, DefaultStreamConfigError, DeviceNameError,
-    DevicesError, InputCallbackInfo, OutputCallbackInfo, SampleFormat, SampleRate, StreamConfig,
+    BackendSpecificError, BtProfile, BufferSize, ChannelCount, Data, DefaultStreamConfigError,
+    DeviceId, DeviceNameError, DeviceRole, DeviceVolumeError, DevicesError, InputCallbackInfo,
+    OutputCallbackInfo, SampleFormat, SampleRate, StreamConfig, StreamDirection,
     SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
     SupportedStreamConfigsError, COMMON_SAMPLE_RATES,
 };
 use std;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::io::Error as IoError;
 use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::os::windows::ffi::OsStringExt;
+use std::ops::{ControlFlow, Deref, DerefMut};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
 use std::ptr;
 use std::slice;
+use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use super::check_result;
@@ -20,36 +24,45 @@ use super::check_result_backend_specific;
 use super::com;
 use super::winapi::ctypes::c_void;
 use super::winapi::shared::devpkey;
-use super::winapi::shared::guiddef::GUID;
+use super::winapi::shared::guiddef::{IsEqualGUID, GUID};
 use super::winapi::shared::ksmedia;
 use super::winapi::shared::minwindef::{DWORD, WORD};
 use super::winapi::shared::mmreg;
 use super::winapi::shared::winerror;
 use super::winapi::shared::wtypes;
+use super::winapi::shared::wtypesbase;
 use super::winapi::Interface;
 
 // https://msdn.microsoft.com/en-us/library/cc230355.aspx
 use super::winapi::um::audioclient::{
-    self, IAudioClient, IID_IAudioClient, AUDCLNT_E_DEVICE_INVALIDATED,
+    self, IAudioClient, IID_IAudioClient, AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED,
+    AUDCLNT_E_DEVICE_INVALIDATED,
 };
 use super::winapi::um::audiosessiontypes::{
-    AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK,
+    AUDCLNT_SHAREMODE_EXCLUSIVE, AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+    AUDCLNT_STREAMFLAGS_LOOPBACK,
 };
 use super::winapi::um::combaseapi::{
     CoCreateInstance, CoTaskMemFree, PropVariantClear, CLSCTX_ALL,
 };
 use super::winapi::um::coml2api;
+use super::winapi::um::endpointvolume::IAudioEndpointVolume;
 use super::winapi::um::mmdeviceapi::{
-    eAll, eCapture, eConsole, eRender, CLSID_MMDeviceEnumerator, EDataFlow, IMMDevice,
-    IMMDeviceCollection, IMMDeviceEnumerator, IMMEndpoint, DEVICE_STATE_ACTIVE,
+    eAll, eCapture, eCommunications, eConsole, eMultimedia, eRender, CLSID_MMDeviceEnumerator,
+    EDataFlow, IMMDevice, IMMDeviceCollection, IMMDeviceEnumerator, IMMEndpoint,
+    IMMNotificationClient, DEVICE_STATE_ACTIVE,
 };
+use super::winapi::um::propidl::PROPVARIANT;
 use super::winapi::um::winnt::{LPWSTR, WCHAR};
 
 use super::{
-    stream::{AudioClientFlow, Stream, StreamInner},
+    notification::Notifications,
+    stream::{AudioClientFlow, SilenceClient, Stream, StreamInner},
     winapi::um::synchapi,
+    DeviceExt, SrcQuality, WasapiConfig,
 };
-use crate::{traits::DeviceTrait, BuildStreamError, StreamError};
+use crate::traits::{self, DeviceTrait};
+use crate::{BuildStreamError, StreamError};
 
 pub type SupportedInputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
 pub type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
@@ -60,6 +73,47 @@ struct IAudioClientWrapper(*mut IAudioClient);
 unsafe impl Send for IAudioClientWrapper {}
 unsafe impl Sync for IAudioClientWrapper {}
 
+/// Releases the wrapped `IAudioEndpointVolume` on drop.
+pub(super) struct EndpointVolume(pub(super) *mut IAudioEndpointVolume);
+
+impl Drop for EndpointVolume {
+    fn drop(&mut self) {
+        unsafe { (*self.0).Release() };
+    }
+}
+
+fn device_volume_error(err: IoError) -> DeviceVolumeError {
+    if err.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) {
+        DeviceVolumeError::DeviceNotAvailable
+    } else {
+        let description = format!("{}", err);
+        BackendSpecificError {
+            description,
+            code: err.raw_os_error(),
+        }
+        .into()
+    }
+}
+
+fn silence_client_error(err: IoError) -> BuildStreamError {
+    if err.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) {
+        BuildStreamError::DeviceNotAvailable
+    } else {
+        let description = format!(
+            "failed to build the silence client of a loopback stream: {}",
+            err
+        );
+        BackendSpecificError {
+            description,
+            code: err.raw_os_error(),
+        }
+        .into()
+    }
+}
+
+// The duration of the buffer of a `SilenceClient`, in 100-nanosecond units.
+const SILENCE_BUFFER_DURATION: i64 = 1_000_000;
+
 /// An opaque type that identifies an end point.
 pub struct Device {
     device: *mut IMMDevice,
@@ -77,6 +131,10 @@ impl DeviceTrait for Device {
         Device::name(self)
     }
 
+    fn id(&self) -> Result<DeviceId, DeviceNameError> {
+        Device::id(self)
+    }
+
     fn supported_input_configs(
         &self,
     ) -> Result<Self::SupportedInputConfigs, SupportedStreamConfigsError> {
@@ -89,6 +147,26 @@ impl DeviceTrait for Device {
         Device::supported_output_configs(self)
     }
 
+    fn supported_input_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        Device::supported_input_configs_incremental(self, callback)
+    }
+
+    fn supported_output_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        Device::supported_output_configs_incremental(self, callback)
+    }
+
     fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
         Device::default_input_config(self)
     }
@@ -97,6 +175,30 @@ impl DeviceTrait for Device {
         Device::default_output_config(self)
     }
 
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        Device::native_sample_format(self, direction)
+    }
+
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        Device::bluetooth_profile(self)
+    }
+
+    fn is_loopback(&self) -> bool {
+        self.data_flow() == eRender
+    }
+
+    fn volume(&self) -> Option<f32> {
+        Device::volume(self).ok()
+    }
+
+    fn input_sensitivity(&self) -> Option<f32> {
+        Device::input_sensitivity(self).ok()
+    }
+
+    fn set_volume(&self, volume: f32) -> Result<(), DeviceVolumeError> {
+        Device::set_volume(self, volume)
+    }
+
     fn build_input_stream_raw<D, E>(
         &self,
         config: &StreamConfig,
@@ -108,7 +210,9 @@ impl DeviceTrait for Device {
         D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
-        let stream_inner = self.build_input_stream_raw_inner(config, sample_format)?;
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
+        let stream_inner = self.build_input_stream_raw_inner(config, sample_format, None)?;
         Ok(Stream::new_input(
             stream_inner,
             data_callback,
@@ -127,7 +231,87 @@ impl DeviceTrait for Device {
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
-        let stream_inner = self.build_output_stream_raw_inner(config, sample_format)?;
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
+        let stream_inner =
+            self.build_output_stream_raw_inner(config, sample_format, None, false)?;
+        Ok(Stream::new_output(
+            stream_inner,
+            data_callback,
+            error_callback,
+        ))
+    }
+
+    fn build_output_stream_low_latency<D, E>(
+        &self,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<(Self::Stream, StreamConfig), BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        // Exclusive mode bypasses the audio engine, so its latency is that of the device. Shared
+        // mode is the fallback, e.g. when another application holds the device or exclusive mode
+        // is disabled in the sound settings.
+        if let Some(stream_inner) = self.build_exclusive_output_stream_inner() {
+            let (data_callback, error_callback) =
+                callback_guard::guard_output(data_callback, error_callback);
+            let config = stream_inner.config.clone();
+            let stream = Stream::new_output(stream_inner, data_callback, error_callback);
+            return Ok((stream, config));
+        }
+        traits::try_low_latency_buffer_sizes(self, data_callback, error_callback)
+    }
+}
+
+impl DeviceExt for Device {
+    fn build_input_stream_raw_with_wasapi_config<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        wasapi_config: &WasapiConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let (data_callback, error_callback) =
+            callback_guard::guard_input(data_callback, error_callback);
+        let stream_inner = self.build_input_stream_raw_inner(
+            config,
+            sample_format,
+            Some(wasapi_config.src_quality),
+        )?;
+        Ok(Stream::new_input(
+            stream_inner,
+            data_callback,
+            error_callback,
+        ))
+    }
+
+    fn build_output_stream_raw_with_wasapi_config<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        wasapi_config: &WasapiConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let (data_callback, error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
+        let stream_inner = self.build_output_stream_raw_inner(
+            config,
+            sample_format,
+            Some(wasapi_config.src_quality),
+            false,
+        )?;
         Ok(Stream::new_output(
             stream_inner,
             data_callback,
@@ -136,6 +320,21 @@ impl DeviceTrait for Device {
     }
 }
 
+// TODO: These constants should be defined in winapi but are missing.
+const AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM: DWORD = 0x8000_0000;
+const AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY: DWORD = 0x0800_0000;
+
+// The stream flags asking the audio engine to convert to or from the mix format.
+fn conversion_stream_flags(src_quality: Option<SrcQuality>) -> DWORD {
+    match src_quality {
+        None => 0,
+        Some(SrcQuality::Default) => AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+        Some(SrcQuality::High) => {
+            AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY
+        }
+    }
+}
+
 struct Endpoint {
     endpoint: *mut IMMEndpoint,
 }
@@ -265,7 +464,10 @@ pub unsafe fn is_format_supported(
                 Err(SupportedStreamConfigsError::DeviceNotAvailable)
             }
             (_, Err(_)) => Ok(false),
-            (winerror::S_FALSE, _) => Ok(false),
+            (winerror::S_FALSE, _) => {
+                debug!("format is not supported exactly, the device suggested a close match");
+                Ok(false)
+            }
             (_, Ok(())) => Ok(true),
         }
     };
@@ -292,6 +494,26 @@ pub unsafe fn is_format_supported(
     }
 }
 
+// Given the audio client and format, returns whether the device can be opened with the format
+// in exclusive mode, which has no closest match to suggest.
+unsafe fn is_format_supported_exclusive(
+    client: *const IAudioClient,
+    waveformatex_ptr: *const mmreg::WAVEFORMATEX,
+) -> Result<bool, BuildStreamError> {
+    let result = (*client).IsFormatSupported(
+        AUDCLNT_SHAREMODE_EXCLUSIVE,
+        waveformatex_ptr,
+        ptr::null_mut(),
+    );
+    match check_result(result) {
+        Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
+            Err(BuildStreamError::DeviceNotAvailable)
+        }
+        Err(_) => Ok(false),
+        Ok(()) => Ok(result == winerror::S_OK),
+    }
+}
+
 // Get a cpal Format from a WAVEFORMATEX.
 unsafe fn format_from_waveformatex_ptr(
     waveformatex_ptr: *const mmreg::WAVEFORMATEX,
@@ -310,6 +532,8 @@ unsafe fn format_from_waveformatex_ptr(
             let sub = (*waveformatextensible_ptr).SubFormat;
             if n_bits == 16 && cmp_guid(&sub, &ksmedia::KSDATAFORMAT_SUBTYPE_PCM) {
                 SampleFormat::I16
+            } else if n_bits == 24 && cmp_guid(&sub, &ksmedia::KSDATAFORMAT_SUBTYPE_PCM) {
+                SampleFormat::I24Packed
             } else if n_bits == 32 && cmp_guid(&sub, &ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT) {
                 SampleFormat::F32
             } else {
@@ -346,7 +570,10 @@ impl Device {
                 &mut property_value,
             )) {
                 let description = format!("failed to retrieve name from property store: {}", err);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: err.raw_os_error(),
+                };
                 return Err(err.into());
             }
 
@@ -356,7 +583,10 @@ impl Device {
                     "property store produced invalid data: {:?}",
                     property_value.vt
                 );
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: None,
+                };
                 return Err(err.into());
             }
             let ptr_utf16 = *(&property_value.data as *const _ as *const *const u16);
@@ -383,6 +613,191 @@ impl Device {
     }
 
     #[inline]
+    pub fn id(&self) -> Result<DeviceId, DeviceNameError> {
+        unsafe {
+            let mut id: LPWSTR = ptr::null_mut();
+            // GetId only fails with E_OUTOFMEMORY.
+            check_result_backend_specific((*self.device).GetId(&mut id))?;
+
+            let mut len = 0;
+            while *id.offset(len) != 0 {
+                len += 1;
+            }
+            let id_slice = slice::from_raw_parts(id, len as usize);
+            let id_os_string: OsString = OsStringExt::from_wide(id_slice);
+            CoTaskMemFree(id as *mut c_void);
+            // Endpoint IDs are made of ASCII characters.
+            Ok(DeviceId::new(id_os_string.to_string_lossy()))
+        }
+    }
+
+    // `PKEY_AudioEngine_DeviceFormat`, the format the audio engine uses with the device itself,
+    // with the valid bits per sample if the format is a `WAVEFORMATEXTENSIBLE`.
+    fn device_format(&self) -> Option<(mmreg::WAVEFORMATEX, Option<WORD>)> {
+        // TODO: This constant should be defined in winapi but is missing.
+        const PKEY_AUDIO_ENGINE_DEVICE_FORMAT: wtypes::PROPERTYKEY = wtypes::PROPERTYKEY {
+            fmtid: GUID {
+                Data1: 0xf19f064d,
+                Data2: 0x082c,
+                Data3: 0x4e27,
+                Data4: [0xbc, 0x73, 0x68, 0x82, 0xa1, 0xbb, 0x8e, 0x4c],
+            },
+            pid: 0,
+        };
+        unsafe {
+            let mut property_store = ptr::null_mut();
+            check_result(
+                (*self.device).OpenPropertyStore(coml2api::STGM_READ, &mut property_store),
+            )
+            .ok()?;
+            let mut property_value = mem::zeroed();
+            let result =
+                (*property_store).GetValue(&PKEY_AUDIO_ENGINE_DEVICE_FORMAT, &mut property_value);
+            (*property_store).Release();
+            check_result(result).ok()?;
+
+            // The property holds a `WAVEFORMATEX`, or a `WAVEFORMATEXTENSIBLE` starting with one.
+            let mut device_format = None;
+            if property_value.vt == wtypes::VT_BLOB as _ {
+                let blob = *(&property_value.data as *const _ as *const wtypesbase::BLOB);
+                let size = blob.cbSize as usize;
+                if size >= mem::size_of::<mmreg::WAVEFORMATEX>() {
+                    let format = ptr::read_unaligned(blob.pBlobData as *const mmreg::WAVEFORMATEX);
+                    let valid_bits = if format.wFormatTag == mmreg::WAVE_FORMAT_EXTENSIBLE
+                        && size >= mem::size_of::<mmreg::WAVEFORMATEXTENSIBLE>()
+                    {
+                        let format = blob.pBlobData as *const mmreg::WAVEFORMATEXTENSIBLE;
+                        Some(ptr::read_unaligned(format).Samples)
+                    } else {
+                        None
+                    };
+                    device_format = Some((format, valid_bits));
+                }
+            }
+            PropVariantClear(&mut property_value);
+            device_format
+        }
+    }
+
+    // Reads the property `key` of the endpoint with `read`, or `None` if the endpoint doesn't have
+    // it.
+    fn read_property<T>(
+        &self,
+        key: &wtypes::PROPERTYKEY,
+        read: impl FnOnce(&PROPVARIANT) -> Option<T>,
+    ) -> Option<T> {
+        unsafe {
+            let mut property_store = ptr::null_mut();
+            check_result(
+                (*self.device).OpenPropertyStore(coml2api::STGM_READ, &mut property_store),
+            )
+            .ok()?;
+            let mut property_value = mem::zeroed();
+            let result = (*property_store).GetValue(key, &mut property_value);
+            (*property_store).Release();
+            check_result(result).ok()?;
+            let value = read(&property_value);
+            PropVariantClear(&mut property_value);
+            value
+        }
+    }
+
+    // `PKEY_Device_ContainerId`, which all the endpoints of a physical device share. The
+    // Hands-Free and A2DP endpoints of a headset are related through it.
+    fn container_id(&self) -> Option<GUID> {
+        let key = unsafe {
+            &*(&devpkey::DEVPKEY_Device_ContainerId as *const _ as *const wtypes::PROPERTYKEY)
+        };
+        self.read_property(key, |value| unsafe {
+            if value.vt != wtypes::VT_CLSID as _ {
+                return None;
+            }
+            let guid = *(&value.data as *const _ as *const *const GUID);
+            if guid.is_null() {
+                None
+            } else {
+                Some(*guid)
+            }
+        })
+    }
+
+    // The instance path of the adapter the endpoint belongs to, such as
+    // `{1}.BTHHFENUM\BthHFPAudio\...` for the Hands-Free endpoints of a headset.
+    fn adapter_path(&self) -> Option<String> {
+        // TODO: This constant should be defined in winapi but is missing.
+        const PKEY_AUDIO_ENDPOINT_ADAPTER_PATH: wtypes::PROPERTYKEY = wtypes::PROPERTYKEY {
+            fmtid: GUID {
+                Data1: 0xb3f8fa53,
+                Data2: 0x0004,
+                Data3: 0x438e,
+                Data4: [0x90, 0x03, 0x51, 0xa4, 0x6e, 0x13, 0x9b, 0xfc],
+            },
+            pid: 2,
+        };
+        self.read_property(&PKEY_AUDIO_ENDPOINT_ADAPTER_PATH, |value| unsafe {
+            if value.vt != wtypes::VT_LPWSTR as _ {
+                return None;
+            }
+            let ptr_utf16 = *(&value.data as *const _ as *const *const u16);
+            let mut len = 0;
+            while *ptr_utf16.offset(len) != 0 {
+                len += 1;
+            }
+            Some(String::from_utf16_lossy(slice::from_raw_parts(
+                ptr_utf16,
+                len as usize,
+            )))
+        })
+    }
+
+    // Endpoints whose adapter doesn't reveal the profile are related to the other endpoints of
+    // their device by container ID. A capture endpoint of a Bluetooth headset is Hands-Free, as
+    // A2DP has no input.
+    pub fn bluetooth_profile(&self) -> Option<BtProfile> {
+        let adapter_profile = |device: &Device| {
+            device
+                .adapter_path()
+                .and_then(|path| adapter_bluetooth_profile(&path))
+        };
+        if let Some(profile) = adapter_profile(self) {
+            return Some(profile);
+        }
+        let container_id = self.container_id()?;
+        let bluetooth = Devices::new().ok()?.any(|device| {
+            device
+                .container_id()
+                .map_or(false, |id| IsEqualGUID(&id, &container_id))
+                && adapter_profile(&device).is_some()
+        });
+        if !bluetooth {
+            None
+        } else if self.data_flow() == eCapture {
+            Some(BtProfile::HandsFree)
+        } else {
+            Some(BtProfile::Unknown)
+        }
+    }
+
+    fn device_format_channels(&self) -> Option<ChannelCount> {
+        self.device_format().map(|(format, _)| format.nChannels)
+    }
+
+    // The bits of each sample in a stream of `sample_format` that reach the device, after the
+    // audio engine converts the samples to the device format.
+    fn effective_bit_depth(&self, sample_format: SampleFormat) -> u8 {
+        let bits = WORD::from(sample_format.bits());
+        let device_bits = self.device_format().map(|(format, valid_bits)| {
+            // Some drivers leave the valid bits at zero.
+            valid_bits
+                .filter(|&valid_bits| valid_bits != 0)
+                .unwrap_or(format.wBitsPerSample)
+        });
+        match device_bits {
+            Some(device_bits) if device_bits != 0 => bits.min(device_bits) as u8,
+            _ => bits as u8,
+        }
+    }
+
     fn from_immdevice(device: *mut IMMDevice) -> Self {
         Device {
             device,
@@ -419,6 +834,50 @@ impl Device {
         Ok(lock)
     }
 
+    /// Activates the volume control of the endpoint.
+    pub(super) fn endpoint_volume(&self) -> Result<EndpointVolume, IoError> {
+        com::com_initialized();
+        unsafe {
+            let mut endpoint_volume = ptr::null_mut();
+            let hresult = (*self.device).Activate(
+                &IAudioEndpointVolume::uuidof(),
+                CLSCTX_ALL,
+                ptr::null_mut(),
+                &mut endpoint_volume,
+            );
+            check_result(hresult)?;
+            Ok(EndpointVolume(endpoint_volume as *mut _))
+        }
+    }
+
+    fn volume(&self) -> Result<f32, DeviceVolumeError> {
+        let endpoint_volume = self.endpoint_volume().map_err(device_volume_error)?;
+        let mut volume = 0.0;
+        let hresult = unsafe { (*endpoint_volume.0).GetMasterVolumeLevelScalar(&mut volume) };
+        check_result(hresult).map_err(device_volume_error)?;
+        Ok(volume)
+    }
+
+    /// The master volume level of a capture endpoint, in dB.
+    fn input_sensitivity(&self) -> Result<f32, DeviceVolumeError> {
+        if self.data_flow() != eCapture {
+            return Err(DeviceVolumeError::NotSupported);
+        }
+        let endpoint_volume = self.endpoint_volume().map_err(device_volume_error)?;
+        let mut level_db = 0.0;
+        let hresult = unsafe { (*endpoint_volume.0).GetMasterVolumeLevel(&mut level_db) };
+        check_result(hresult).map_err(device_volume_error)?;
+        Ok(level_db)
+    }
+
+    fn set_volume(&self, volume: f32) -> Result<(), DeviceVolumeError> {
+        let endpoint_volume = self.endpoint_volume().map_err(device_volume_error)?;
+        let volume = volume.clamp(0.0, 1.0);
+        let hresult =
+            unsafe { (*endpoint_volume.0).SetMasterVolumeLevelScalar(volume, ptr::null()) };
+        check_result(hresult).map_err(device_volume_error)
+    }
+
     /// Returns an uninitialized `IAudioClient`.
     #[inline]
     pub(crate) fn build_audioclient(&self) -> Result<*mut IAudioClient, IoError> {
@@ -428,6 +887,39 @@ impl Device {
         Ok(client)
     }
 
+    // Builds a client rendering silence to this output device in its mix format, see
+    // `SilenceClient`.
+    unsafe fn build_silence_client(&self) -> Result<SilenceClient, BuildStreamError> {
+        let audio_client = self.build_audioclient().map_err(silence_client_error)?;
+        // Releases the clients on error from here on.
+        let mut silence_client = SilenceClient {
+            audio_client,
+            render_client: ptr::null_mut(),
+            max_frames_in_buffer: 0,
+        };
+        let mut format_ptr = WaveFormatExPtr(ptr::null_mut());
+        check_result((*audio_client).GetMixFormat(&mut format_ptr.0))
+            .map_err(silence_client_error)?;
+        check_result((*audio_client).Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            0,
+            SILENCE_BUFFER_DURATION,
+            0,
+            format_ptr.0,
+            ptr::null(),
+        ))
+        .map_err(silence_client_error)?;
+        check_result((*audio_client).GetBufferSize(&mut silence_client.max_frames_in_buffer))
+            .map_err(silence_client_error)?;
+        check_result((*audio_client).GetService(
+            &audioclient::IID_IAudioRenderClient,
+            &mut silence_client.render_client as *mut *mut audioclient::IAudioRenderClient
+                as *mut _,
+        ))
+        .map_err(silence_client_error)?;
+        Ok(silence_client)
+    }
+
     // There is no way to query the list of all formats that are supported by the
     // audio processor, so instead we just trial some commonly supported formats.
     //
@@ -439,7 +931,12 @@ impl Device {
     // number of channels seems to be supported. Any, more or less returns an invalid
     // parameter error. Thus, we just assume that the default number of channels is the only
     // number supported.
-    fn supported_formats(&self) -> Result<SupportedInputConfigs, SupportedStreamConfigsError> {
+    //
+    // Each supported sample rate is passed to `callback` as soon as it has been trialed.
+    fn supported_formats<F>(&self, mut callback: F) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
         // initializing COM because we call `CoTaskMemFree` to release the format.
         com::com_initialized();
 
@@ -451,7 +948,10 @@ impl Device {
             }
             Err(e) => {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: e.raw_os_error(),
+                };
                 return Err(err.into());
             }
         };
@@ -467,7 +967,10 @@ impl Device {
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 }
             };
@@ -483,7 +986,22 @@ impl Device {
                 match WaveFormat::copy_from_waveformatex_ptr(default_waveformatex_ptr.0) {
                     Some(f) => f,
                     // If the format is neither EX nor EXTENSIBLE we don't know how to work with it.
-                    None => return Ok(vec![].into_iter()),
+                    None => return Ok(()),
+                }
+            };
+
+            // All supported configs share the default format apart from the sample rate.
+            let format = match format_from_waveformatex_ptr(default_waveformatex_ptr.0) {
+                Some(fmt) => fmt,
+                None => {
+                    let description =
+                        "could not create a `cpal::SupportedStreamConfig` from a `WAVEFORMATEX`"
+                            .to_string();
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
+                    return Err(err.into());
                 }
             };
 
@@ -492,74 +1010,164 @@ impl Device {
             // NOTE: We should really be testing for whole ranges here, but it is infeasible to
             // test every sample rate up to the overflow limit as the `IsFormatSupported` method is
             // quite slow.
-            let mut supported_sample_rates: Vec<u32> = Vec::new();
+            let config = |rate: u32| SupportedStreamConfigRange {
+                channels: format.channels,
+                min_sample_rate: SampleRate(rate as _),
+                max_sample_rate: SampleRate(rate as _),
+                buffer_size: format.buffer_size.clone(),
+                sample_format: format.sample_format,
+            };
+            let default_sr = (*default_waveformatex_ptr.0).nSamplesPerSec as _;
+            let mut default_sr_supported = false;
             for &rate in COMMON_SAMPLE_RATES {
                 let rate = rate.0 as DWORD;
                 test_format.nSamplesPerSec = rate;
                 test_format.nAvgBytesPerSec =
                     rate * u32::from((*default_waveformatex_ptr.0).nBlockAlign);
                 if is_format_supported(client, test_format.as_ptr())? {
-                    supported_sample_rates.push(rate);
+                    default_sr_supported |= rate == default_sr;
+                    if let ControlFlow::Break(()) = callback(config(rate)) {
+                        return Ok(());
+                    }
                 }
             }
 
             // If the common rates don't include the default one, add the default.
-            let default_sr = (*default_waveformatex_ptr.0).nSamplesPerSec as _;
-            if !supported_sample_rates.iter().any(|&r| r == default_sr) {
-                supported_sample_rates.push(default_sr);
+            if !default_sr_supported {
+                if let ControlFlow::Break(()) = callback(config(default_sr)) {
+                    return Ok(());
+                }
             }
 
-            // Reset the sample rate on the test format now that we're done.
-            test_format.nSamplesPerSec = (*default_waveformatex_ptr.0).nSamplesPerSec;
-            test_format.nAvgBytesPerSec = (*default_waveformatex_ptr.0).nAvgBytesPerSec;
-
-            // TODO: Test the different sample formats?
-
-            // Create the supported formats.
-            let format = match format_from_waveformatex_ptr(default_waveformatex_ptr.0) {
-                Some(fmt) => fmt,
-                None => {
-                    let description =
-                        "could not create a `cpal::SupportedStreamConfig` from a `WAVEFORMATEX`"
-                            .to_string();
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
+            // The mix format may have fewer channels than the device, e.g. when a multichannel
+            // interface is set up as stereo in the sound settings. Also probe the channel count
+            // the audio engine uses with the device itself.
+            if let Some(channels) = self.device_format_channels() {
+                if channels > format.channels {
+                    for &rate in COMMON_SAMPLE_RATES {
+                        let stream_config = StreamConfig {
+                            channels,
+                            sample_rate: rate,
+                            buffer_size: BufferSize::Default,
+                        };
+                        let test_format = match config_to_waveformatextensible(
+                            &stream_config,
+                            format.sample_format,
+                        ) {
+                            Some(f) => f,
+                            None => break,
+                        };
+                        if is_format_supported(client, &test_format.Format)? {
+                            let supported = SupportedStreamConfigRange {
+                                channels,
+                                ..config(rate.0)
+                            };
+                            if let ControlFlow::Break(()) = callback(supported) {
+                                return Ok(());
+                            }
+                        }
+                    }
                 }
-            };
-            let mut supported_formats = Vec::with_capacity(supported_sample_rates.len());
-            for rate in supported_sample_rates {
-                supported_formats.push(SupportedStreamConfigRange {
-                    channels: format.channels.clone(),
-                    min_sample_rate: SampleRate(rate as _),
-                    max_sample_rate: SampleRate(rate as _),
-                    buffer_size: format.buffer_size.clone(),
-                    sample_format: format.sample_format.clone(),
-                })
             }
-            Ok(supported_formats.into_iter())
+
+            // TODO: Test the different sample formats?
+            Ok(())
         }
     }
 
     pub fn supported_input_configs(
         &self,
     ) -> Result<SupportedInputConfigs, SupportedStreamConfigsError> {
-        if self.data_flow() == eCapture {
-            self.supported_formats()
-        // If it's an output device, assume no input formats.
-        } else {
-            Ok(vec![].into_iter())
-        }
+        let mut supported_configs = vec![];
+        self.supported_input_configs_incremental(|config| {
+            supported_configs.push(config);
+            ControlFlow::Continue(())
+        })?;
+        Ok(supported_configs.into_iter())
     }
 
     pub fn supported_output_configs(
         &self,
     ) -> Result<SupportedOutputConfigs, SupportedStreamConfigsError> {
-        if self.data_flow() == eRender {
-            self.supported_formats()
-        // If it's an input device, assume no output formats.
-        } else {
-            Ok(vec![].into_iter())
-        }
+        let mut supported_configs = vec![];
+        self.supported_output_configs_incremental(|config| {
+            supported_configs.push(config);
+            ControlFlow::Continue(())
+        })?;
+        Ok(supported_configs.into_iter())
+    }
+
+    pub fn supported_input_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
+        self.cached_configs(StreamDirection::Input, callback, |callback| {
+            if self.data_flow() == eCapture {
+                self.supported_formats(callback)
+            // Output devices are captured in loopback mode, in their mix format only.
+            } else {
+                let format = match self.default_format() {
+                    Ok(format) => format,
+                    Err(DefaultStreamConfigError::DeviceNotAvailable) => {
+                        return Err(SupportedStreamConfigsError::DeviceNotAvailable)
+                    }
+                    Err(DefaultStreamConfigError::StreamTypeNotSupported) => return Ok(()),
+                    Err(DefaultStreamConfigError::BackendSpecific { err }) => {
+                        return Err(err.into())
+                    }
+                };
+                let _ = callback(SupportedStreamConfigRange {
+                    channels: format.channels,
+                    min_sample_rate: format.sample_rate,
+                    max_sample_rate: format.sample_rate,
+                    buffer_size: format.buffer_size,
+                    sample_format: format.sample_format,
+                });
+                Ok(())
+            }
+        })
+    }
+
+    pub fn supported_output_configs_incremental<F>(
+        &self,
+        callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+    {
+        self.cached_configs(StreamDirection::Output, callback, |callback| {
+            if self.data_flow() == eRender {
+                self.supported_formats(callback)
+            // If it's an input device, assume no output formats.
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    // Passes the configs found by `probe` to `callback`, or the ones it found before if WASAPI
+    // has not notified about a change to the device since.
+    fn cached_configs<F, P>(
+        &self,
+        direction: StreamDirection,
+        callback: F,
+        probe: P,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        P: FnOnce(
+            &mut dyn FnMut(SupportedStreamConfigRange) -> ControlFlow<()>,
+        ) -> Result<(), SupportedStreamConfigsError>,
+    {
+        // Nothing is cached if the cache cannot be cleared.
+        let id = match *CONFIGS_WATCHER {
+            Some(_) => self.id().ok(),
+            None => None,
+        };
+        CONFIGS.supported_configs(id, direction, callback, probe)
     }
 
     // We always create voices in shared mode, therefore all samples go through an audio
@@ -577,7 +1185,10 @@ impl Device {
             }
             Err(e) => {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: e.raw_os_error(),
+                };
                 return Err(err.into());
             }
         };
@@ -591,7 +1202,10 @@ impl Device {
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 }
                 Ok(()) => (),
@@ -607,12 +1221,9 @@ impl Device {
         endpoint.data_flow()
     }
 
+    // Output devices are captured in loopback mode, in their mix format.
     pub fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
-        if self.data_flow() == eCapture {
-            self.default_format()
-        } else {
-            Err(DefaultStreamConfigError::StreamTypeNotSupported)
-        }
+        self.default_format()
     }
 
     pub fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
@@ -624,10 +1235,23 @@ impl Device {
         }
     }
 
+    // Streams are always created in shared mode, where the audio engine converts every stream to
+    // and from the mix format.
+    pub fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let config = match direction {
+            StreamDirection::Input => self.default_input_config(),
+            StreamDirection::Output => self.default_output_config(),
+        };
+        config.ok().map(|config| config.sample_format())
+    }
+
+    // With `src_quality`, the audio engine converts the stream from the mix format of the device
+    // with a sample rate converter of that quality. Without it, the format must be supported as is.
     pub(crate) fn build_input_stream_raw_inner(
         &self,
         config: &StreamConfig,
         sample_format: SampleFormat,
+        src_quality: Option<SrcQuality>,
     ) -> Result<StreamInner, BuildStreamError> {
         unsafe {
             // Making sure that COM is initialized.
@@ -642,7 +1266,10 @@ impl Device {
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 }
             };
@@ -657,11 +1284,21 @@ impl Device {
                 BufferSize::Default => (),
             };
 
-            let mut stream_flags: DWORD = AUDCLNT_STREAMFLAGS_EVENTCALLBACK;
+            let mut stream_flags: DWORD =
+                AUDCLNT_STREAMFLAGS_EVENTCALLBACK | conversion_stream_flags(src_quality);
 
-            if self.data_flow() == eRender {
+            let silence_client = if self.data_flow() == eRender {
                 stream_flags |= AUDCLNT_STREAMFLAGS_LOOPBACK;
-            }
+                match self.build_silence_client() {
+                    Ok(silence_client) => Some(silence_client),
+                    Err(err) => {
+                        (*audio_client).Release();
+                        return Err(err);
+                    }
+                }
+            } else {
+                None
+            };
 
             // Computing the format and initializing the device.
             let waveformatex = {
@@ -669,11 +1306,13 @@ impl Device {
                     .ok_or(BuildStreamError::StreamConfigNotSupported)?;
                 let share_mode = AUDCLNT_SHAREMODE_SHARED;
 
-                // Ensure the format is supported.
-                match super::device::is_format_supported(audio_client, &format_attempt.Format) {
-                    Ok(false) => return Err(BuildStreamError::StreamConfigNotSupported),
-                    Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
-                    _ => (),
+                // Ensure the format is supported, unless the audio engine converts it.
+                if src_quality.is_none() {
+                    match super::device::is_format_supported(audio_client, &format_attempt.Format) {
+                        Ok(false) => return Err(BuildStreamError::StreamConfigNotSupported),
+                        Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
+                        _ => (),
+                    }
                 }
 
                 // Finally, initializing the audio client
@@ -690,10 +1329,21 @@ impl Device {
                         (*audio_client).Release();
                         return Err(BuildStreamError::DeviceNotAvailable);
                     }
+                    // The audio engine rejects the conversion flags, e.g. before Windows 7.
+                    Err(ref e)
+                        if src_quality.is_some()
+                            && e.raw_os_error() == Some(winerror::E_INVALIDARG) =>
+                    {
+                        (*audio_client).Release();
+                        return Err(BuildStreamError::StreamConfigNotSupported);
+                    }
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -715,7 +1365,10 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -730,14 +1383,20 @@ impl Device {
                 if event.is_null() {
                     (*audio_client).Release();
                     let description = "failed to create event".to_string();
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
                     return Err(err.into());
                 }
 
                 if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
                     (*audio_client).Release();
                     let description = format!("failed to call SetEventHandle: {}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 }
 
@@ -760,7 +1419,10 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("failed to build capture client: {}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -788,29 +1450,66 @@ impl Device {
                 bytes_per_frame: waveformatex.nBlockAlign,
                 config: config.clone(),
                 sample_format,
+                bit_depth: self.effective_bit_depth(sample_format),
+                next_block_frames: Arc::new(AtomicU32::new(max_frames_in_buffer)),
+                silence_client,
+                realtime: false,
             })
         }
     }
 
+    // Builds the stream of `build_output_stream_low_latency` in exclusive mode, trying the default
+    // sample format and then the integer formats most devices accept.
+    fn build_exclusive_output_stream_inner(&self) -> Option<StreamInner> {
+        let default_config = self.default_output_config().ok()?;
+        let mut config = default_config.config();
+        config.buffer_size = BufferSize::Default;
+        let mut sample_formats = vec![default_config.sample_format()];
+        for sample_format in [SampleFormat::I24Packed, SampleFormat::I16] {
+            if !sample_formats.contains(&sample_format) {
+                sample_formats.push(sample_format);
+            }
+        }
+        for sample_format in sample_formats {
+            match self.build_output_stream_raw_inner(&config, sample_format, None, true) {
+                Ok(stream_inner) => return Some(stream_inner),
+                Err(err) => {
+                    debug!(%err, ?sample_format, "failed to open the device in exclusive mode");
+                }
+            }
+        }
+        None
+    }
+
+    // See `build_input_stream_raw_inner` for `src_quality`, which is ignored in exclusive mode.
+    //
+    // An exclusive stream runs at the device's minimum period, with a buffer of one period
+    // reported as its fixed buffer size, and its thread joins MMCSS.
     pub(crate) fn build_output_stream_raw_inner(
         &self,
         config: &StreamConfig,
         sample_format: SampleFormat,
+        src_quality: Option<SrcQuality>,
+        exclusive: bool,
     ) -> Result<StreamInner, BuildStreamError> {
+        let src_quality = src_quality.filter(|_| !exclusive);
         unsafe {
             // Making sure that COM is initialized.
             // It's not actually sure that this is required, but when in doubt do it.
             com::com_initialized();
 
             // Obtaining a `IAudioClient`.
-            let audio_client = match self.build_audioclient() {
+            let mut audio_client = match self.build_audioclient() {
                 Ok(client) => client,
                 Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
                     return Err(BuildStreamError::DeviceNotAvailable)
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 }
             };
@@ -829,34 +1528,105 @@ impl Device {
             let waveformatex = {
                 let format_attempt = config_to_waveformatextensible(config, sample_format)
                     .ok_or(BuildStreamError::StreamConfigNotSupported)?;
-                let share_mode = AUDCLNT_SHAREMODE_SHARED;
+                let share_mode = if exclusive {
+                    AUDCLNT_SHAREMODE_EXCLUSIVE
+                } else {
+                    AUDCLNT_SHAREMODE_SHARED
+                };
 
-                // Ensure the format is supported.
-                match super::device::is_format_supported(audio_client, &format_attempt.Format) {
-                    Ok(false) => return Err(BuildStreamError::StreamConfigNotSupported),
-                    Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
-                    _ => (),
+                // Ensure the format is supported, unless the audio engine converts it.
+                if exclusive {
+                    match is_format_supported_exclusive(audio_client, &format_attempt.Format) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            (*audio_client).Release();
+                            return Err(BuildStreamError::StreamConfigNotSupported);
+                        }
+                        Err(err) => {
+                            (*audio_client).Release();
+                            return Err(err);
+                        }
+                    }
+                } else if src_quality.is_none() {
+                    match super::device::is_format_supported(audio_client, &format_attempt.Format) {
+                        Ok(false) => return Err(BuildStreamError::StreamConfigNotSupported),
+                        Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
+                        _ => (),
+                    }
+                }
+
+                // Exclusive streams are event-driven too, so the buffer duration must equal the
+                // period. Shared streams leave both to the audio engine.
+                let mut period = 0;
+                if exclusive {
+                    let mut default_period = 0;
+                    let hresult = (*audio_client).GetDevicePeriod(&mut default_period, &mut period);
+                    if let Err(e) = check_result(hresult) {
+                        (*audio_client).Release();
+                        let description = format!("failed to obtain the device period: {}", e);
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
+                        return Err(err.into());
+                    }
                 }
 
                 // Finally, initializing the audio client
-                let hresult = (*audio_client).Initialize(
-                    share_mode,
-                    AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-                    0,
-                    0,
-                    &format_attempt.Format,
-                    ptr::null(),
-                );
+                let initialize = |audio_client: *mut IAudioClient, period| {
+                    (*audio_client).Initialize(
+                        share_mode,
+                        AUDCLNT_STREAMFLAGS_EVENTCALLBACK | conversion_stream_flags(src_quality),
+                        period,
+                        period,
+                        &format_attempt.Format,
+                        ptr::null(),
+                    )
+                };
+                let mut hresult = initialize(audio_client, period);
+
+                // The device only accepts periods of whole blocks of its own. The failed client
+                // reports the aligned buffer size, which a new client is initialized with.
+                if exclusive && hresult == AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED {
+                    let mut aligned_frames = 0u32;
+                    let result = (*audio_client).GetBufferSize(&mut aligned_frames);
+                    (*audio_client).Release();
+                    if let Err(e) = check_result(result) {
+                        let description = format!("failed to obtain buffer size: {}", e);
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
+                        return Err(err.into());
+                    }
+                    audio_client = self
+                        .build_audioclient()
+                        .map_err(|_| BuildStreamError::DeviceNotAvailable)?;
+                    period = (10_000_000.0 * aligned_frames as f64 / config.sample_rate.0 as f64)
+                        .round() as i64;
+                    hresult = initialize(audio_client, period);
+                }
 
                 match check_result(hresult) {
                     Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
                         (*audio_client).Release();
                         return Err(BuildStreamError::DeviceNotAvailable);
                     }
+                    // The audio engine rejects the conversion flags, e.g. before Windows 7.
+                    Err(ref e)
+                        if src_quality.is_some()
+                            && e.raw_os_error() == Some(winerror::E_INVALIDARG) =>
+                    {
+                        (*audio_client).Release();
+                        return Err(BuildStreamError::StreamConfigNotSupported);
+                    }
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -871,14 +1641,20 @@ impl Device {
                 if event.is_null() {
                     (*audio_client).Release();
                     let description = "failed to create event".to_string();
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
                     return Err(err.into());
                 }
 
                 if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
                     (*audio_client).Release();
                     let description = format!("failed to call SetEventHandle: {}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: e.raw_os_error(),
+                    };
                     return Err(err.into());
                 };
 
@@ -898,7 +1674,10 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("failed to obtain buffer size: {}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -923,7 +1702,10 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("failed to build render client: {}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError {
+                            description,
+                            code: e.raw_os_error(),
+                        };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -936,6 +1718,11 @@ impl Device {
             // `run()` method and added to the `RunContext`.
             let client_flow = AudioClientFlow::Render { render_client };
 
+            let mut config = config.clone();
+            if exclusive {
+                config.buffer_size = BufferSize::Fixed(max_frames_in_buffer);
+            }
+
             let audio_clock = get_audio_clock(audio_client).map_err(|err| {
                 (*audio_client).Release();
                 err
@@ -949,8 +1736,12 @@ impl Device {
                 playing: false,
                 max_frames_in_buffer,
                 bytes_per_frame: waveformatex.nBlockAlign,
-                config: config.clone(),
+                config,
                 sample_format,
+                bit_depth: self.effective_bit_depth(sample_format),
+                next_block_frames: Arc::new(AtomicU32::new(max_frames_in_buffer)),
+                silence_client: None,
+                realtime: exclusive,
             })
         }
     }
@@ -1068,7 +1859,17 @@ impl Endpoint {
     }
 }
 
+// The configs of the devices, found by probing many formats with `IsFormatSupported`.
+static CONFIGS: ConfigCache = ConfigCache::new();
+
 lazy_static! {
+    // Clears the configs of the devices WASAPI notifies about, until the process exits. `None` if
+    // it could not be registered.
+    static ref CONFIGS_WATCHER: Option<Mutex<Notifications>> =
+        Notifications::configs(|id| CONFIGS.clear(&id))
+            .ok()
+            .map(Mutex::new);
+
     static ref ENUMERATOR: Enumerator = {
         // COM initialization is thread local, but we only need to have COM initialized in the
         // thread we create the objects in
@@ -1176,10 +1977,30 @@ impl Iterator for Devices {
     }
 }
 
-fn default_device(data_flow: EDataFlow) -> Option<Device> {
+// The Bluetooth profile of an endpoint, from the instance path of its adapter. Windows enumerates
+// Hands-Free audio under `BTHHFENUM`, A2DP under `BTHENUM` and LE Audio under `BTHLEDEVICE`.
+fn adapter_bluetooth_profile(path: &str) -> Option<BtProfile> {
+    let path = path.to_ascii_uppercase();
+    if path.contains("BTHHFENUM") {
+        Some(BtProfile::HandsFree)
+    } else if path.contains("BTHENUM") {
+        Some(BtProfile::A2dp)
+    } else if path.contains("BTHLEDEVICE") {
+        Some(BtProfile::Unknown)
+    } else {
+        None
+    }
+}
+
+fn default_device(data_flow: EDataFlow, role: DeviceRole) -> Option<Device> {
+    let role = match role {
+        DeviceRole::Console => eConsole,
+        DeviceRole::Multimedia => eMultimedia,
+        DeviceRole::Communications => eCommunications,
+    };
     unsafe {
         let mut device = ptr::null_mut();
-        let hres = (*ENUMERATOR.0).GetDefaultAudioEndpoint(data_flow, eConsole, &mut device);
+        let hres = (*ENUMERATOR.0).GetDefaultAudioEndpoint(data_flow, role, &mut device);
         if let Err(_err) = check_result(hres) {
             return None; // TODO: check specifically for `E_NOTFOUND`, and panic otherwise
         }
@@ -1187,12 +2008,56 @@ fn default_device(data_flow: EDataFlow) -> Option<Device> {
     }
 }
 
+pub fn device_by_id(id: &DeviceId) -> Option<Device> {
+    let id: Vec<u16> = OsStr::new(id.as_str())
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    unsafe {
+        let mut device = ptr::null_mut();
+        // Fails with E_NOTFOUND for unknown IDs.
+        check_result((*ENUMERATOR.0).GetDevice(id.as_ptr(), &mut device)).ok()?;
+        let device = Device::from_immdevice(device);
+        // Disabled and unplugged endpoints keep their ID, but `Devices` skips them.
+        let mut state = 0;
+        check_result((*device.device).GetState(&mut state)).ok()?;
+        if state & DEVICE_STATE_ACTIVE == 0 {
+            return None;
+        }
+        Some(device)
+    }
+}
+
+/// Registers `client` to be notified of changes to the devices of the system.
+pub(super) fn register_notification_client(
+    client: *mut IMMNotificationClient,
+) -> Result<(), BackendSpecificError> {
+    unsafe {
+        check_result_backend_specific((*ENUMERATOR.0).RegisterEndpointNotificationCallback(client))
+    }
+}
+
+/// Stops notifying `client`, which must have been registered with `register_notification_client`.
+pub(super) fn unregister_notification_client(client: *mut IMMNotificationClient) {
+    unsafe {
+        (*ENUMERATOR.0).UnregisterEndpointNotificationCallback(client);
+    }
+}
+
 pub fn default_input_device() -> Option<Device> {
-    default_device(eCapture)
+    default_device(eCapture, DeviceRole::Console)
 }
 
 pub fn default_output_device() -> Option<Device> {
-    default_device(eRender)
+    default_device(eRender, DeviceRole::Console)
+}
+
+pub fn default_input_device_for_role(role: DeviceRole) -> Option<Device> {
+    default_device(eCapture, role)
+}
+
+pub fn default_output_device_for_role(role: DeviceRole) -> Option<Device> {
+    default_device(eRender, role)
 }
 
 /// Get the audio clock used to produce `StreamInstant`s.
@@ -1210,7 +2075,10 @@ unsafe fn get_audio_clock(
         }
         Err(e) => {
             let description = format!("failed to build audio clock: {}", e);
-            let err = BackendSpecificError { description };
+            let err = BackendSpecificError {
+                description,
+                code: e.raw_os_error(),
+            };
             return Err(err.into());
         }
         Ok(()) => (),
@@ -1225,10 +2093,14 @@ fn config_to_waveformatextensible(
     config: &StreamConfig,
     sample_format: SampleFormat,
 ) -> Option<mmreg::WAVEFORMATEXTENSIBLE> {
+    // `WAVE_FORMAT_PCM` is only valid for up to two channels.
     let format_tag = match sample_format {
-        SampleFormat::I16 => mmreg::WAVE_FORMAT_PCM,
-        SampleFormat::F32 => mmreg::WAVE_FORMAT_EXTENSIBLE,
-        SampleFormat::U16 => return None,
+        SampleFormat::I16 if config.channels <= 2 => mmreg::WAVE_FORMAT_PCM,
+        SampleFormat::I16 | SampleFormat::I24Packed | SampleFormat::F32 => {
+            mmreg::WAVE_FORMAT_EXTENSIBLE
+        }
+        // Windows aligns 24-bit samples in 32-bit containers to the most significant bits.
+        SampleFormat::U16 | SampleFormat::I24 => return None,
     };
     let channels = config.channels as WORD;
     let sample_rate = config.sample_rate.0 as DWORD;
@@ -1236,14 +2108,12 @@ fn config_to_waveformatextensible(
     let avg_bytes_per_sec = u32::from(channels) * sample_rate * u32::from(sample_bytes);
     let block_align = channels * sample_bytes;
     let bits_per_sample = 8 * sample_bytes;
-    let cb_size = match sample_format {
-        SampleFormat::I16 => 0,
-        SampleFormat::F32 => {
-            let extensible_size = mem::size_of::<mmreg::WAVEFORMATEXTENSIBLE>();
-            let ex_size = mem::size_of::<mmreg::WAVEFORMATEX>();
-            (extensible_size - ex_size) as WORD
-        }
-        SampleFormat::U16 => return None,
+    let cb_size = if format_tag == mmreg::WAVE_FORMAT_EXTENSIBLE {
+        let extensible_size = mem::size_of::<mmreg::WAVEFORMATEXTENSIBLE>();
+        let ex_size = mem::size_of::<mmreg::WAVEFORMATEX>();
+        (extensible_size - ex_size) as WORD
+    } else {
+        0
     };
     let waveformatex = mmreg::WAVEFORMATEX {
         wFormatTag: format_tag,
@@ -1261,9 +2131,9 @@ fn config_to_waveformatextensible(
     let channel_mask = KSAUDIO_SPEAKER_DIRECTOUT;
 
     let sub_format = match sample_format {
-        SampleFormat::I16 => ksmedia::KSDATAFORMAT_SUBTYPE_PCM,
+        SampleFormat::I16 | SampleFormat::I24Packed => ksmedia::KSDATAFORMAT_SUBTYPE_PCM,
         SampleFormat::F32 => ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
-        SampleFormat::U16 => return None,
+        SampleFormat::U16 | SampleFormat::I24 => return None,
     };
     let waveformatextensible = mmreg::WAVEFORMATEXTENSIBLE {
         Format: waveformatex,
@@ -1274,3 +2144,61 @@ fn config_to_waveformatextensible(
 
     Some(waveformatextensible)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{adapter_bluetooth_profile, config_to_waveformatextensible, mmreg};
+    use crate::{BtProfile, BufferSize, SampleFormat, SampleRate, StreamConfig};
+
+    #[test]
+    fn test_adapter_bluetooth_profile() {
+        let hands_free = r"{1}.BTHHFENUM\BthHFPAudio\8&2c0a2a4f&0&97";
+        let a2dp = r"{1}.BTHENUM\{0000110b-0000-1000-8000-00805f9b34fb}_VID&0002054c_PID&0ce0";
+        assert_eq!(
+            adapter_bluetooth_profile(hands_free),
+            Some(BtProfile::HandsFree)
+        );
+        assert_eq!(adapter_bluetooth_profile(a2dp), Some(BtProfile::A2dp));
+        assert_eq!(
+            adapter_bluetooth_profile(r"{1}.USB\VID_046D&PID_0A44"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_multichannel_waveformat() {
+        for &channels in &[2, 32, 64] {
+            for &sample_format in &[
+                SampleFormat::I16,
+                SampleFormat::I24Packed,
+                SampleFormat::F32,
+            ] {
+                let config = StreamConfig {
+                    channels,
+                    sample_rate: SampleRate(48_000),
+                    buffer_size: BufferSize::Default,
+                };
+                let format = config_to_waveformatextensible(&config, sample_format).unwrap();
+                // `WAVEFORMATEX` is packed, so its fields are copied out before comparing.
+                let mmreg::WAVEFORMATEX {
+                    wFormatTag: format_tag,
+                    nChannels: n_channels,
+                    nAvgBytesPerSec: avg_bytes_per_sec,
+                    nBlockAlign: block_align,
+                    cbSize: cb_size,
+                    ..
+                } = format.Format;
+                let frame_bytes = channels * sample_format.sample_size() as u16;
+                assert_eq!(n_channels, channels);
+                assert_eq!(block_align, frame_bytes);
+                assert_eq!(avg_bytes_per_sec, 48_000 * u32::from(frame_bytes));
+                if channels > 2 || sample_format != SampleFormat::I16 {
+                    assert_eq!(format_tag, mmreg::WAVE_FORMAT_EXTENSIBLE);
+                    assert_eq!(cb_size, 22);
+                } else {
+                    assert_eq!(format_tag, mmreg::WAVE_FORMAT_PCM);
+                }
+            }
+        }
+    }
+}
@@ -1,25 +1,108 @@
 extern crate winapi;
 
 pub use self::device::{
-    default_input_device, default_output_device, Device, Devices, SupportedInputConfigs,
+    default_input_device, default_input_device_for_role, default_output_device,
+    default_output_device_for_role, device_by_id, Device, Devices, SupportedInputConfigs,
     SupportedOutputConfigs,
 };
+use self::notification::Notifications;
 pub use self::stream::Stream;
-use self::winapi::um::winnt::HRESULT;
+use self::winapi::shared::ntdef::NTSTATUS;
+use self::winapi::um::winnt::{HRESULT, OSVERSIONINFOW};
 use crate::traits::HostTrait;
 use crate::BackendSpecificError;
+use crate::BuildStreamError;
+use crate::Data;
+use crate::DefaultDeviceEvent;
+use crate::DefaultWatcher;
+use crate::DeviceEvent;
+use crate::DeviceId;
+use crate::DeviceRole;
+use crate::DeviceWatcher;
 use crate::DevicesError;
+use crate::InputCallbackInfo;
+use crate::OutputCallbackInfo;
+use crate::SampleFormat;
+use crate::StreamConfig;
+use crate::StreamDirection;
+use crate::StreamError;
+use crate::WatchDefaultError;
+use crate::WatchDevicesError;
 use std::io::Error as IoError;
+use std::mem;
 
 mod com;
 mod device;
+mod notification;
 mod stream;
 
+/// The quality of the audio engine's sample rate converter, see [`WasapiConfig`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SrcQuality {
+    /// The converter the audio engine uses by default (`AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM`).
+    #[default]
+    Default,
+    /// A converter of higher quality, at a higher CPU cost
+    /// (`AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY`).
+    High,
+}
+
+/// WASAPI specific options for building a stream, see [`DeviceExt`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WasapiConfig {
+    /// The quality of the conversion when the sample rate of the stream differs from the mix
+    /// format of the device.
+    pub src_quality: SrcQuality,
+}
+
+/// WASAPI specific extensions to the device.
+pub trait DeviceExt {
+    /// Like `build_input_stream_raw`, but the audio engine converts the mix format of the device
+    /// to `config` and `sample_format`, see
+    /// [`build_output_stream_raw_with_wasapi_config`](DeviceExt::build_output_stream_raw_with_wasapi_config).
+    fn build_input_stream_raw_with_wasapi_config<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        wasapi_config: &WasapiConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static;
+
+    /// Like `build_output_stream_raw`, but the audio engine converts `config` and `sample_format`
+    /// to the mix format of the device.
+    ///
+    /// `build_output_stream_raw` only accepts the formats the device supports in shared mode,
+    /// which is usually just the sample rate of the mix format. Streams built here may use any
+    /// sample rate, channel count and sample format, converted with a sample rate converter of
+    /// `wasapi_config.src_quality`.
+    ///
+    /// The flags enabling the conversion are supported from Windows 7 on. If the audio engine
+    /// rejects them, this returns `BuildStreamError::StreamConfigNotSupported`.
+    fn build_output_stream_raw_with_wasapi_config<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        wasapi_config: &WasapiConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static;
+}
+
 /// The WASAPI host, the default windows host type.
 ///
 /// Note: If you use a WASAPI output device as an input device it will
 /// transparently enable loopback mode (see
 /// https://docs.microsoft.com/en-us/windows/win32/coreaudio/loopback-recording).
+/// Such devices are listed by `HostTrait::input_devices` and `HostTrait::loopback_devices`.
+/// While a loopback stream plays, it also plays silence to its device, so that it captures
+/// silence rather than nothing while no other application is playing.
 #[derive(Debug)]
 pub struct Host;
 
@@ -42,6 +125,10 @@ impl HostTrait for Host {
         Devices::new()
     }
 
+    fn device_by_id(&self, id: &DeviceId) -> Option<Self::Device> {
+        device_by_id(id)
+    }
+
     fn default_input_device(&self) -> Option<Self::Device> {
         default_input_device()
     }
@@ -49,11 +136,74 @@ impl HostTrait for Host {
     fn default_output_device(&self) -> Option<Self::Device> {
         default_output_device()
     }
+
+    fn default_input_device_for_role(&self, role: DeviceRole) -> Option<Self::Device> {
+        default_input_device_for_role(role)
+    }
+
+    fn default_output_device_for_role(&self, role: DeviceRole) -> Option<Self::Device> {
+        default_output_device_for_role(role)
+    }
+
+    fn watch_default<F>(
+        &self,
+        direction: StreamDirection,
+        callback: F,
+    ) -> Result<DefaultWatcher, WatchDefaultError>
+    where
+        F: FnMut(DefaultDeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let notifications = Notifications::default_device(direction, callback)?;
+        Ok(DefaultWatcher::new(notifications))
+    }
+
+    fn watch_devices<F>(&self, callback: F) -> Result<DeviceWatcher, WatchDevicesError>
+    where
+        F: FnMut(DeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let notifications = Notifications::devices(callback)?;
+        Ok(DeviceWatcher::new(notifications))
+    }
+
+    fn backend_version(&self) -> Option<String> {
+        windows_version().map(|version| {
+            format!(
+                "Windows {}.{}.{}",
+                version.dwMajorVersion, version.dwMinorVersion, version.dwBuildNumber
+            )
+        })
+    }
+
+    fn capabilities(&self) -> Vec<(&'static str, bool)> {
+        let windows_10 = windows_version().map_or(false, |version| version.dwMajorVersion >= 10);
+        vec![("IAudioClient3", windows_10)]
+    }
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    // TODO: This function should be declared in winapi but is missing.
+    fn RtlGetVersion(version_information: *mut OSVERSIONINFOW) -> NTSTATUS;
+}
+
+// The version of Windows. Unlike `GetVersionEx`, `RtlGetVersion` reports it regardless of the
+// compatibility manifest of the application.
+fn windows_version() -> Option<OSVERSIONINFOW> {
+    let mut version: OSVERSIONINFOW = unsafe { mem::zeroed() };
+    version.dwOSVersionInfoSize = mem::size_of::<OSVERSIONINFOW>() as u32;
+    if unsafe { RtlGetVersion(&mut version) } < 0 {
+        return None;
+    }
+    Some(version)
 }
 
 #[inline]
 fn check_result(result: HRESULT) -> Result<(), IoError> {
     if result < 0 {
+        debug!(
+            hresult = format_args!("{:#010x}", result),
+            "WASAPI call failed"
+        );
         Err(IoError::from_raw_os_error(result))
     } else {
         Ok(())
@@ -65,6 +215,7 @@ fn check_result_backend_specific(result: HRESULT) -> Result<(), BackendSpecificE
         Ok(()) => Ok(()),
         Err(err) => Err(BackendSpecificError {
             description: format!("{}", err),
+            code: err.raw_os_error(),
         }),
     }
 }
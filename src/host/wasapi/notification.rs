@@ -0,0 +1,486 @@
+//! Notifications about changes to the devices, see `HostTrait::watch_default` and
+//! `HostTrait::watch_devices`, and about the changes that make the cached configs of a device
+//! stale.
+//!
+//! WASAPI reports them to an `IMMNotificationClient`, and changes to the volume of a device to an
+//! `IAudioEndpointVolumeCallback`. Both are COM objects implemented here by hand: reference-counted
+//! structs starting with a pointer to a static vtable.
+
+use super::check_result;
+use super::device::{
+    default_input_device, default_output_device, register_notification_client,
+    unregister_notification_client, Device, Devices, EndpointVolume,
+};
+use super::winapi::ctypes::c_void;
+use super::winapi::shared::guiddef::{IsEqualIID, REFIID};
+use super::winapi::shared::minwindef::{DWORD, ULONG};
+use super::winapi::shared::winerror::{E_NOINTERFACE, E_POINTER, S_OK};
+use super::winapi::shared::wtypes::PROPERTYKEY;
+use super::winapi::um::endpointvolume::{
+    IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallbackVtbl, PAUDIO_VOLUME_NOTIFICATION_DATA,
+};
+use super::winapi::um::mmdeviceapi::{
+    eCapture, eConsole, eRender, EDataFlow, ERole, IMMNotificationClient, IMMNotificationClientVtbl,
+};
+use super::winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use super::winapi::um::winnt::{HRESULT, LPCWSTR};
+use super::winapi::Interface;
+use crate::host::device_events::{with_ids, KnownDevices};
+use crate::traits::DeviceTrait;
+use crate::{
+    BackendSpecificError, DefaultDeviceEvent, DeviceEvent, DeviceId, DevicesError, StreamDirection,
+};
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+type DefaultCallback = dyn FnMut(DefaultDeviceEvent<Device>) + Send;
+type DevicesCallback = dyn FnMut(DeviceEvent<Device>) + Send;
+type ConfigsCallback = dyn FnMut(DeviceId) + Send;
+
+// What a client reports, and to which of the user's callbacks.
+enum Handler {
+    // Changes to the default device of `flow`, for `watch_default`.
+    Default {
+        flow: EDataFlow,
+        callback: Box<DefaultCallback>,
+    },
+    // Changes to the list of devices, to both default devices and to the volume of each device,
+    // for `watch_devices`.
+    Devices {
+        known: KnownDevices,
+        // Shared with the volume clients, which report without locking the handler.
+        callback: Arc<Mutex<Box<DevicesCallback>>>,
+        // The volume clients of the listed devices, whose volume can be read.
+        volumes: Vec<(DeviceId, VolumeNotifications)>,
+    },
+    // Any change to a device, which may change the configs it supports, for `ConfigCache`.
+    Configs {
+        callback: Box<ConfigsCallback>,
+    },
+}
+
+// The layout of a COM object: the vtable pointer must come first.
+#[repr(C)]
+struct NotificationClient {
+    vtbl: *const IMMNotificationClientVtbl,
+    refs: AtomicU32,
+    handler: Mutex<Handler>,
+}
+
+static VTBL: IMMNotificationClientVtbl = IMMNotificationClientVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    OnDeviceStateChanged: on_device_state_changed,
+    OnDeviceAdded: on_device_added,
+    OnDeviceRemoved: on_device_removed,
+    OnDefaultDeviceChanged: on_default_device_changed,
+    OnPropertyValueChanged: on_property_value_changed,
+};
+
+/// Passes notifications to the user's callback, until dropped.
+pub(super) struct Notifications {
+    // Holds one reference to the client, released on drop.
+    client: *mut NotificationClient,
+}
+
+// The client is only accessed through COM, which synchronizes the reference count, and its
+// handler is behind a mutex.
+unsafe impl Send for Notifications {}
+
+impl Notifications {
+    /// Passes changes to the default device in one direction to `callback`.
+    pub(super) fn default_device<F>(
+        direction: StreamDirection,
+        callback: F,
+    ) -> Result<Self, BackendSpecificError>
+    where
+        F: FnMut(DefaultDeviceEvent<Device>) + Send + 'static,
+    {
+        let flow = match direction {
+            StreamDirection::Input => eCapture,
+            StreamDirection::Output => eRender,
+        };
+        Notifications::register(Handler::Default {
+            flow,
+            callback: Box::new(callback),
+        })
+    }
+
+    /// Passes devices being added or removed, and changes to both default devices, to `callback`.
+    pub(super) fn devices<F>(callback: F) -> Result<Self, BackendSpecificError>
+    where
+        F: FnMut(DeviceEvent<Device>) + Send + 'static,
+    {
+        let devices = Devices::new().map_err(|err| match err {
+            DevicesError::BackendSpecific { err } => err,
+        })?;
+        let devices = with_ids(devices);
+        let callback: Arc<Mutex<Box<DevicesCallback>>> = Arc::new(Mutex::new(Box::new(callback)));
+        let volumes = devices
+            .iter()
+            .filter_map(|(id, device)| {
+                Some((
+                    id.clone(),
+                    VolumeNotifications::register(device, &callback)?,
+                ))
+            })
+            .collect();
+        Notifications::register(Handler::Devices {
+            known: KnownDevices::new(devices),
+            callback,
+            volumes,
+        })
+    }
+
+    /// Passes the ID of each device whose state or properties change to `callback`.
+    pub(super) fn configs<F>(callback: F) -> Result<Self, BackendSpecificError>
+    where
+        F: FnMut(DeviceId) + Send + 'static,
+    {
+        Notifications::register(Handler::Configs {
+            callback: Box::new(callback),
+        })
+    }
+
+    fn register(handler: Handler) -> Result<Self, BackendSpecificError> {
+        let client = Box::into_raw(Box::new(NotificationClient {
+            vtbl: &VTBL,
+            refs: AtomicU32::new(1),
+            handler: Mutex::new(handler),
+        }));
+        if let Err(err) = register_notification_client(client as *mut IMMNotificationClient) {
+            // The client was not registered, so ours is its only reference.
+            unsafe { release(client as *mut IUnknown) };
+            return Err(err);
+        }
+        Ok(Notifications { client })
+    }
+}
+
+impl Drop for Notifications {
+    fn drop(&mut self) {
+        // WASAPI may still hold references to the client, which keep it alive until released.
+        unregister_notification_client(self.client as *mut IMMNotificationClient);
+        unsafe { release(self.client as *mut IUnknown) };
+    }
+}
+
+// Reports the changes to the list of devices since it was last listed, if the client watches
+// the devices, or else the device `device_id` if the client watches the configs. Called by
+// WASAPI on one of its own threads.
+unsafe fn devices_changed(this: *mut IMMNotificationClient, device_id: LPCWSTR) -> HRESULT {
+    let client = &*(this as *const NotificationClient);
+    if let Some(id) = device_id_from_wide(device_id) {
+        configs_changed(client, id);
+    }
+    // A poisoned lock means the callback panicked before; don't call it again.
+    let Ok(mut handler) = client.handler.lock() else {
+        return S_OK;
+    };
+    if let Handler::Devices {
+        known,
+        callback,
+        volumes,
+    } = &mut *handler
+    {
+        // The next notification reports the changes if the devices cannot be listed now.
+        let Ok(devices) = Devices::new() else {
+            return S_OK;
+        };
+        let events = known.update(with_ids(devices));
+        for event in &events {
+            match event {
+                DeviceEvent::Removed { id } => volumes.retain(|(watched, _)| watched != id),
+                DeviceEvent::Added { device } => {
+                    let id = device.id();
+                    let volume = VolumeNotifications::register(device, callback);
+                    if let (Ok(id), Some(volume)) = (id, volume) {
+                        volumes.push((id, volume));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // A poisoned lock means the callback panicked before; don't call it again.
+        if let Ok(mut callback) = callback.lock() {
+            for event in events {
+                callback(event);
+            }
+        }
+    }
+    S_OK
+}
+
+// Reports the device `id` if the client watches the configs.
+fn configs_changed(client: &NotificationClient, id: DeviceId) {
+    // A poisoned lock means the callback panicked before; don't call it again.
+    let Ok(mut handler) = client.handler.lock() else {
+        return;
+    };
+    if let Handler::Configs { callback } = &mut *handler {
+        callback(id);
+    }
+}
+
+// Reads a device ID passed by WASAPI, the way `Device::id` reads it.
+unsafe fn device_id_from_wide(id: LPCWSTR) -> Option<DeviceId> {
+    if id.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    while *id.offset(len) != 0 {
+        len += 1;
+    }
+    let id: OsString = OsStringExt::from_wide(slice::from_raw_parts(id, len as usize));
+    Some(DeviceId::new(id.to_string_lossy()))
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    if object.is_null() {
+        return E_POINTER;
+    }
+    if IsEqualIID(&*riid, &IUnknown::uuidof())
+        || IsEqualIID(&*riid, &IMMNotificationClient::uuidof())
+    {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const NotificationClient);
+    client.refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const NotificationClient);
+    let refs = client.refs.fetch_sub(1, Ordering::Release) - 1;
+    if refs == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this as *mut NotificationClient));
+    }
+    refs
+}
+
+// A device being plugged in or unplugged changes its state, while `OnDeviceAdded` and
+// `OnDeviceRemoved` are about installing and uninstalling it.
+unsafe extern "system" fn on_device_state_changed(
+    this: *mut IMMNotificationClient,
+    device_id: LPCWSTR,
+    _new_state: DWORD,
+) -> HRESULT {
+    devices_changed(this, device_id)
+}
+
+unsafe extern "system" fn on_device_added(
+    this: *mut IMMNotificationClient,
+    device_id: LPCWSTR,
+) -> HRESULT {
+    devices_changed(this, device_id)
+}
+
+unsafe extern "system" fn on_device_removed(
+    this: *mut IMMNotificationClient,
+    device_id: LPCWSTR,
+) -> HRESULT {
+    devices_changed(this, device_id)
+}
+
+// Called by WASAPI on one of its own threads, once for each role whose default changed.
+unsafe extern "system" fn on_default_device_changed(
+    this: *mut IMMNotificationClient,
+    flow: EDataFlow,
+    role: ERole,
+    _default_device_id: LPCWSTR,
+) -> HRESULT {
+    let client = &*(this as *const NotificationClient);
+    // `default_input_device` and `default_output_device` follow the console role.
+    if role != eConsole {
+        return S_OK;
+    }
+    let device = if flow == eCapture {
+        default_input_device()
+    } else {
+        default_output_device()
+    };
+    // A poisoned lock means the callback panicked before; don't call it again.
+    let Ok(mut handler) = client.handler.lock() else {
+        return S_OK;
+    };
+    match &mut *handler {
+        Handler::Default {
+            flow: watched,
+            callback,
+        } => {
+            if flow == *watched {
+                callback(DefaultDeviceEvent::DefaultChanged { device });
+            }
+        }
+        Handler::Devices { callback, .. } => {
+            let Ok(mut callback) = callback.lock() else {
+                return S_OK;
+            };
+            if flow == eCapture {
+                callback(DeviceEvent::DefaultInputChanged { device });
+            } else {
+                callback(DeviceEvent::DefaultOutputChanged { device });
+            }
+        }
+        Handler::Configs { .. } => {}
+    }
+    S_OK
+}
+
+// Any property may affect the configs, such as the mix format and the format of the device
+// itself, so the key is not checked.
+unsafe extern "system" fn on_property_value_changed(
+    this: *mut IMMNotificationClient,
+    device_id: LPCWSTR,
+    _key: PROPERTYKEY,
+) -> HRESULT {
+    let client = &*(this as *const NotificationClient);
+    if let Some(id) = device_id_from_wide(device_id) {
+        configs_changed(client, id);
+    }
+    S_OK
+}
+
+// Reports changes to the volume of one device to the callback of `watch_devices`. Laid out as a
+// COM object, like `NotificationClient`.
+#[repr(C)]
+struct VolumeClient {
+    vtbl: *const IAudioEndpointVolumeCallbackVtbl,
+    refs: AtomicU32,
+    device: Device,
+    // The bits of the volume last reported, as notifications are also sent for muting.
+    volume: AtomicU32,
+    callback: Arc<Mutex<Box<DevicesCallback>>>,
+}
+
+static VOLUME_VTBL: IAudioEndpointVolumeCallbackVtbl = IAudioEndpointVolumeCallbackVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: volume_query_interface,
+        AddRef: volume_add_ref,
+        Release: volume_release,
+    },
+    OnNotify: on_volume_notify,
+};
+
+// A `VolumeClient` registered with the endpoint volume of its device, unregistered on drop.
+struct VolumeNotifications {
+    endpoint_volume: EndpointVolume,
+    // Holds one reference to the client, released on drop.
+    client: *mut VolumeClient,
+}
+
+impl VolumeNotifications {
+    // Returns `None` if the device has no volume to watch.
+    fn register(device: &Device, callback: &Arc<Mutex<Box<DevicesCallback>>>) -> Option<Self> {
+        let endpoint_volume = device.endpoint_volume().ok()?;
+        let volume = DeviceTrait::volume(device)?;
+        let client = Box::into_raw(Box::new(VolumeClient {
+            vtbl: &VOLUME_VTBL,
+            refs: AtomicU32::new(1),
+            device: device.clone(),
+            volume: AtomicU32::new(volume.to_bits()),
+            callback: callback.clone(),
+        }));
+        let hresult = unsafe {
+            (*endpoint_volume.0)
+                .RegisterControlChangeNotify(client as *mut IAudioEndpointVolumeCallback)
+        };
+        if check_result(hresult).is_err() {
+            // The client was not registered, so ours is its only reference.
+            unsafe { volume_release(client as *mut IUnknown) };
+            return None;
+        }
+        Some(VolumeNotifications {
+            endpoint_volume,
+            client,
+        })
+    }
+}
+
+impl Drop for VolumeNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.endpoint_volume.0)
+                .UnregisterControlChangeNotify(self.client as *mut IAudioEndpointVolumeCallback);
+            volume_release(self.client as *mut IUnknown);
+        }
+    }
+}
+
+unsafe extern "system" fn volume_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    if object.is_null() {
+        return E_POINTER;
+    }
+    if IsEqualIID(&*riid, &IUnknown::uuidof())
+        || IsEqualIID(&*riid, &IAudioEndpointVolumeCallback::uuidof())
+    {
+        volume_add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn volume_add_ref(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const VolumeClient);
+    client.refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn volume_release(this: *mut IUnknown) -> ULONG {
+    let client = &*(this as *const VolumeClient);
+    let refs = client.refs.fetch_sub(1, Ordering::Release) - 1;
+    if refs == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this as *mut VolumeClient));
+    }
+    refs
+}
+
+// Called by WASAPI on one of its own threads whenever the volume or the muting of the device
+// changes.
+unsafe extern "system" fn on_volume_notify(
+    this: *mut IAudioEndpointVolumeCallback,
+    notify: PAUDIO_VOLUME_NOTIFICATION_DATA,
+) -> HRESULT {
+    if notify.is_null() {
+        return E_POINTER;
+    }
+    let client = &*(this as *const VolumeClient);
+    let volume = (*notify).fMasterVolume;
+    if client.volume.swap(volume.to_bits(), Ordering::Relaxed) == volume.to_bits() {
+        return S_OK;
+    }
+    // A poisoned lock means the callback panicked before; don't call it again.
+    if let Ok(mut callback) = client.callback.lock() {
+        callback(DeviceEvent::VolumeChanged {
+            device: client.device.clone(),
+            volume,
+        });
+    }
+    S_OK
+}
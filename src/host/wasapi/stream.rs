@@ -1,27 +1,35 @@
 use super::check_result;
 use super::winapi::shared::basetsd::{UINT32, UINT64};
-use super::winapi::shared::minwindef::{BYTE, FALSE, WORD};
+use super::winapi::shared::minwindef::{BYTE, DWORD, FALSE, WORD};
+use super::winapi::shared::winerror;
 use super::winapi::um::audioclient::{self, AUDCLNT_E_DEVICE_INVALIDATED, AUDCLNT_S_BUFFER_EMPTY};
 use super::winapi::um::handleapi;
 use super::winapi::um::synchapi;
 use super::winapi::um::winbase;
 use super::winapi::um::winnt;
+use crate::host::audio_thread::{AudioThread, DROP_TIMEOUT};
+use crate::host::keep_alive::{OutputMode, PauseState};
+use crate::samples_formats::fill_silence;
 use crate::traits::StreamTrait;
 use crate::{
-    BackendSpecificError, Data, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
-    PlayStreamError, SampleFormat, StreamError,
+    BackendSpecificError, ChannelCount, ChannelMix, Data, DeviceTimestamp, FrameCount,
+    InputCallbackInfo, KeepAlive, KeepAliveError, OutputCallbackInfo, PauseStreamError,
+    PlayStreamError, SampleFormat, SampleRate, StreamError,
 };
 use std::mem;
 use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread::{self, JoinHandle};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 pub struct Stream {
     /// The high-priority audio processing thread calling callbacks.
-    /// Option used for moving out in destructor.
     ///
     /// TODO: Actually set the thread priority.
-    thread: Option<JoinHandle<()>>,
+    thread: AudioThread,
 
     // Commands processed by the `run()` method that is currently running.
     // `pending_scheduled_event` must be signalled whenever a command is added here, so that it
@@ -31,6 +39,17 @@ pub struct Stream {
     // This event is signalled after a new entry is added to `commands`, so that the `run()`
     // method can be notified.
     pending_scheduled_event: winnt::HANDLE,
+
+    // The configuration and sample format with which the stream was created.
+    config: crate::StreamConfig,
+    sample_format: SampleFormat,
+    bit_depth: u8,
+    // The frames of the current or last call to the data callback of an output stream, see
+    // `StreamTrait::next_block_frames`. `None` for input streams.
+    next_block_frames: Option<Arc<AtomicU32>>,
+
+    // Pause handling for output streams, which support `KeepAlive`. `None` for input streams.
+    output_pause: Option<Arc<PauseState>>,
 }
 
 struct RunContext {
@@ -78,6 +97,53 @@ pub struct StreamInner {
     pub config: crate::StreamConfig,
     // The sample format with which the stream was created.
     pub sample_format: SampleFormat,
+    // The bits of each sample that reach the device, see `StreamTrait::effective_bit_depth`.
+    pub bit_depth: u8,
+    // The number of frames written by the current or last call to the data callback, starting at
+    // the size of the buffer, which is empty before the first.
+    pub next_block_frames: Arc<AtomicU32>,
+    // For loopback streams, the client keeping the output device rendering.
+    pub silence_client: Option<SilenceClient>,
+    // True if the stream thread joins MMCSS, for exclusive streams.
+    pub realtime: bool,
+}
+
+// A client rendering silence to the output device of a loopback stream, played along with it.
+//
+// The audio engine only produces loopback packets while a stream renders to the device, so
+// without it a loopback stream would not capture anything while nothing else is playing.
+pub struct SilenceClient {
+    pub audio_client: *mut audioclient::IAudioClient,
+    pub render_client: *mut audioclient::IAudioRenderClient,
+    pub max_frames_in_buffer: UINT32,
+}
+
+impl SilenceClient {
+    // Queues silence in the free part of the buffer.
+    unsafe fn fill(&self) -> Result<(), StreamError> {
+        let mut padding = 0u32;
+        stream_error_from_hresult((*self.audio_client).GetCurrentPadding(&mut padding))?;
+        let frames = self.max_frames_in_buffer - padding;
+        if frames == 0 {
+            return Ok(());
+        }
+        let mut buffer: *mut BYTE = ptr::null_mut();
+        stream_error_from_hresult((*self.render_client).GetBuffer(frames, &mut buffer))?;
+        stream_error_from_hresult(
+            (*self.render_client).ReleaseBuffer(frames, audioclient::AUDCLNT_BUFFERFLAGS_SILENT),
+        )
+    }
+}
+
+impl Drop for SilenceClient {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.render_client.is_null() {
+                (*self.render_client).Release();
+            }
+            (*self.audio_client).Release();
+        }
+    }
 }
 
 impl Stream {
@@ -93,6 +159,9 @@ impl Stream {
         let pending_scheduled_event =
             unsafe { synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null()) };
         let (tx, rx) = channel();
+        let config = stream_inner.config.clone();
+        let sample_format = stream_inner.sample_format;
+        let bit_depth = stream_inner.bit_depth;
 
         let run_context = RunContext {
             handles: vec![pending_scheduled_event, stream_inner.event],
@@ -100,15 +169,21 @@ impl Stream {
             commands: rx,
         };
 
-        let thread = thread::Builder::new()
-            .name("cpal_wasapi_in".to_owned())
-            .spawn(move || run_input(run_context, &mut data_callback, &mut error_callback))
-            .unwrap();
+        let thread = AudioThread::spawn(
+            thread::Builder::new().name("cpal_wasapi_in".to_owned()),
+            move || run_input(run_context, &mut data_callback, &mut error_callback),
+        )
+        .unwrap();
 
         Stream {
-            thread: Some(thread),
+            thread,
             commands: tx,
             pending_scheduled_event,
+            config,
+            sample_format,
+            bit_depth,
+            next_block_frames: None,
+            output_pause: None,
         }
     }
 
@@ -124,6 +199,16 @@ impl Stream {
         let pending_scheduled_event =
             unsafe { synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null()) };
         let (tx, rx) = channel();
+        let config = stream_inner.config.clone();
+        let sample_format = stream_inner.sample_format;
+        let bit_depth = stream_inner.bit_depth;
+        let next_block_frames = stream_inner.next_block_frames.clone();
+        // An exclusive stream wakes up once per buffer.
+        let realtime_period = stream_inner.realtime.then(|| {
+            Duration::from_secs_f64(
+                stream_inner.max_frames_in_buffer as f64 / config.sample_rate.0 as f64,
+            )
+        });
 
         let run_context = RunContext {
             handles: vec![pending_scheduled_event, stream_inner.event],
@@ -131,15 +216,36 @@ impl Stream {
             commands: rx,
         };
 
-        let thread = thread::Builder::new()
-            .name("cpal_wasapi_out".to_owned())
-            .spawn(move || run_output(run_context, &mut data_callback, &mut error_callback))
-            .unwrap();
+        // The audio client is only started by the first `play`.
+        let output_pause = Arc::new(PauseState::new(false));
+        let pause_state = output_pause.clone();
+        let thread = AudioThread::spawn(
+            thread::Builder::new().name("cpal_wasapi_out".to_owned()),
+            move || {
+                if let Some(period) = realtime_period {
+                    if let Err(err) = crate::rt::set_thread_realtime(period, period / 2, period) {
+                        debug!(%err, "failed to raise the priority of the audio thread");
+                    }
+                }
+                run_output(
+                    run_context,
+                    &pause_state,
+                    &mut data_callback,
+                    &mut error_callback,
+                )
+            },
+        )
+        .unwrap();
 
         Stream {
-            thread: Some(thread),
+            thread,
             commands: tx,
             pending_scheduled_event,
+            config,
+            sample_format,
+            bit_depth,
+            next_block_frames: Some(next_block_frames),
+            output_pause: Some(output_pause),
         }
     }
 
@@ -154,25 +260,83 @@ impl Stream {
     }
 }
 
+impl Stream {
+    fn stop(&mut self, timeout: Duration) -> Result<(), StreamError> {
+        if self.pending_scheduled_event.is_null() {
+            return Ok(());
+        }
+        self.push_command(Command::Terminate);
+        let result = self.thread.join_timeout(timeout);
+        // A thread that did not finish in time may still be waiting on the event, so it is
+        // leaked rather than closed.
+        if !matches!(result, Err(StreamError::CloseTimedOut)) {
+            unsafe {
+                handleapi::CloseHandle(self.pending_scheduled_event);
+            }
+        }
+        self.pending_scheduled_event = ptr::null_mut();
+        result
+    }
+}
+
 impl Drop for Stream {
     #[inline]
     fn drop(&mut self) {
-        self.push_command(Command::Terminate);
-        self.thread.take().unwrap().join().unwrap();
-        unsafe {
-            handleapi::CloseHandle(self.pending_scheduled_event);
-        }
+        let _span = debug_span!("drop_stream");
+        // `drop` has no way of reporting a timeout; callers who care use `close`.
+        let _ = self.stop(DROP_TIMEOUT);
     }
 }
 
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
-        self.push_command(Command::PlayStream);
-        Ok(())
+        let start = || {
+            self.push_command(Command::PlayStream);
+            Ok(())
+        };
+        match self.output_pause {
+            Some(ref state) => state.play(start),
+            None => start(),
+        }
     }
     fn pause(&self) -> Result<(), PauseStreamError> {
-        self.push_command(Command::PauseStream);
-        Ok(())
+        let stop = || {
+            self.push_command(Command::PauseStream);
+            Ok(())
+        };
+        match self.output_pause {
+            Some(ref state) => state.pause(stop),
+            None => stop(),
+        }
+    }
+    fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate
+    }
+    fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+    fn effective_bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+    fn next_block_frames(&self) -> Option<FrameCount> {
+        let frames = self.next_block_frames.as_ref()?;
+        Some(frames.load(Ordering::Relaxed))
+    }
+    fn set_keep_alive(&self, keep_alive: KeepAlive) -> Result<(), KeepAliveError> {
+        match (&self.output_pause, keep_alive) {
+            (None, KeepAlive::Off) => Ok(()),
+            (None, _) => Err(KeepAliveError::NotSupported),
+            (Some(state), _) => state.set_keep_alive(keep_alive, || {
+                self.push_command(Command::PauseStream);
+                Ok(())
+            }),
+        }
+    }
+    fn close(mut self, timeout: Duration) -> Result<(), StreamError> {
+        self.stop(timeout)
     }
 }
 
@@ -206,6 +370,12 @@ fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
         match command {
             Command::PlayStream => {
                 if !run_context.stream.playing {
+                    if let Some(ref silence_client) = run_context.stream.silence_client {
+                        unsafe {
+                            silence_client.fill()?;
+                            stream_error_from_hresult((*silence_client.audio_client).Start())?;
+                        }
+                    }
                     let hresult = unsafe { (*run_context.stream.audio_client).Start() };
 
                     if let Err(err) = stream_error_from_hresult(hresult) {
@@ -220,6 +390,10 @@ fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
                     if let Err(err) = stream_error_from_hresult(hresult) {
                         return Err(err);
                     }
+                    if let Some(ref silence_client) = run_context.stream.silence_client {
+                        let hresult = unsafe { (*silence_client.audio_client).Stop() };
+                        stream_error_from_hresult(hresult)?;
+                    }
                     run_context.stream.playing = false;
                 }
             }
@@ -231,34 +405,54 @@ fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
 
     Ok(true)
 }
-// Wait for any of the given handles to be signalled.
+// Wait for any of the given handles to be signalled, for at most `timeout` milliseconds.
 //
-// Returns the index of the `handle` that was signalled, or an `Err` if
-// `WaitForMultipleObjectsEx` fails.
+// Returns the index of the `handle` that was signalled, `None` if the timeout elapsed, or an
+// `Err` if `WaitForMultipleObjectsEx` fails.
 //
 // This is called when the `run` thread is ready to wait for the next event. The
 // next event might be some command submitted by the user (the first handle) or
 // might indicate that one of the streams is ready to deliver or receive audio.
-fn wait_for_handle_signal(handles: &[winnt::HANDLE]) -> Result<usize, BackendSpecificError> {
+fn wait_for_handle_signal(
+    handles: &[winnt::HANDLE],
+    timeout: DWORD,
+) -> Result<Option<usize>, BackendSpecificError> {
     debug_assert!(handles.len() <= winnt::MAXIMUM_WAIT_OBJECTS as usize);
     let result = unsafe {
         synchapi::WaitForMultipleObjectsEx(
             handles.len() as u32,
             handles.as_ptr(),
-            FALSE,             // Don't wait for all, just wait for the first
-            winbase::INFINITE, // TODO: allow setting a timeout
-            FALSE,             // irrelevant parameter here
+            FALSE, // Don't wait for all, just wait for the first
+            timeout,
+            FALSE, // irrelevant parameter here
         )
     };
+    if result == winerror::WAIT_TIMEOUT {
+        return Ok(None);
+    }
     if result == winbase::WAIT_FAILED {
         let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
         let description = format!("`WaitForMultipleObjectsEx failed: {}", err);
-        let err = BackendSpecificError { description };
+        let err = BackendSpecificError {
+            description,
+            code: Some(err as i32),
+        };
         return Err(err);
     }
     // Notifying the corresponding task handler.
     let handle_idx = (result - winbase::WAIT_OBJECT_0) as usize;
-    Ok(handle_idx)
+    Ok(Some(handle_idx))
+}
+
+// How long to wait for the event of `stream`. Loopback streams are also polled every half buffer,
+// as WASAPI does not signal their event on every version of Windows.
+fn wait_timeout(stream: &StreamInner) -> DWORD {
+    if stream.silence_client.is_some() {
+        let period = frames_to_duration(stream.max_frames_in_buffer / 2, stream.config.sample_rate);
+        (period.as_millis() as DWORD).max(1)
+    } else {
+        winbase::INFINITE
+    }
 }
 
 // Get the number of available frames that are available for writing/reading.
@@ -274,11 +468,15 @@ fn get_available_frames(stream: &StreamInner) -> Result<u32, StreamError> {
 // Convert the given `HRESULT` into a `StreamError` if it does indicate an error.
 fn stream_error_from_hresult(hresult: winnt::HRESULT) -> Result<(), StreamError> {
     if hresult == AUDCLNT_E_DEVICE_INVALIDATED {
+        warn!("device was invalidated");
         return Err(StreamError::DeviceNotAvailable);
     }
     if let Err(err) = check_result(hresult) {
         let description = format!("{}", err);
-        let err = BackendSpecificError { description };
+        let err = BackendSpecificError {
+            description,
+            code: err.raw_os_error(),
+        };
         return Err(err.into());
     }
     Ok(())
@@ -313,6 +511,7 @@ fn run_input(
 
 fn run_output(
     mut run_ctxt: RunContext,
+    pause_state: &PauseState,
     data_callback: &mut dyn FnMut(&mut Data, &OutputCallbackInfo),
     error_callback: &mut dyn FnMut(StreamError),
 ) {
@@ -329,6 +528,7 @@ fn run_output(
         match process_output(
             &mut run_ctxt.stream,
             render_client,
+            pause_state,
             data_callback,
             error_callback,
         ) {
@@ -358,7 +558,8 @@ fn process_commands_and_await_signal(
     };
 
     // Wait for any of the handles to be signalled.
-    let handle_idx = match wait_for_handle_signal(&run_context.handles) {
+    let timeout = wait_timeout(&run_context.stream);
+    let handle_idx = match wait_for_handle_signal(&run_context.handles, timeout) {
         Ok(idx) => idx,
         Err(err) => {
             error_callback(err.into());
@@ -367,8 +568,9 @@ fn process_commands_and_await_signal(
     };
 
     // If `handle_idx` is 0, then it's `pending_scheduled_event` that was signalled in
-    // order for us to pick up the pending commands. Otherwise, a stream needs data.
-    if handle_idx == 0 {
+    // order for us to pick up the pending commands. Otherwise, a stream needs data, or a loopback
+    // stream is polled.
+    if handle_idx == Some(0) {
         return Some(ControlFlow::Continue);
     }
 
@@ -384,6 +586,13 @@ fn process_input(
 ) -> ControlFlow {
     let mut frames_available = 0;
     unsafe {
+        if let Some(ref silence_client) = stream.silence_client {
+            if let Err(err) = silence_client.fill() {
+                error_callback(err);
+                return ControlFlow::Break;
+            }
+        }
+
         // Get the available data in the shared buffer.
         let mut buffer: *mut BYTE = ptr::null_mut();
         let mut flags = mem::MaybeUninit::uninit();
@@ -396,12 +605,13 @@ fn process_input(
             if frames_available == 0 {
                 return ControlFlow::Continue;
             }
+            let mut device_position: UINT64 = 0;
             let mut qpc_position: UINT64 = 0;
             let hresult = (*capture_client).GetBuffer(
                 &mut buffer,
                 &mut frames_available,
                 flags.as_mut_ptr(),
-                ptr::null_mut(),
+                &mut device_position,
                 &mut qpc_position,
             );
 
@@ -428,7 +638,15 @@ fn process_input(
                     return ControlFlow::Break;
                 }
             };
-            let info = InputCallbackInfo { timestamp };
+            let device_timestamp = Some(DeviceTimestamp {
+                samples: device_position,
+                sample_rate: stream.config.sample_rate,
+            });
+            let info = InputCallbackInfo {
+                timestamp,
+                device_timestamp,
+                channel_mix: ChannelMix::None,
+            };
             data_callback(&data, &info);
 
             // Release the buffer.
@@ -445,6 +663,7 @@ fn process_input(
 fn process_output(
     stream: &StreamInner,
     render_client: *mut audioclient::IAudioRenderClient,
+    pause_state: &PauseState,
     data_callback: &mut dyn FnMut(&mut Data, &OutputCallbackInfo),
     error_callback: &mut dyn FnMut(StreamError),
 ) -> ControlFlow {
@@ -469,22 +688,52 @@ fn process_output(
 
         debug_assert!(!buffer.is_null());
 
-        let data = buffer as *mut ();
-        let len = frames_available as usize * stream.bytes_per_frame as usize
-            / stream.sample_format.sample_size();
-        let mut data = Data::from_parts(data, len, stream.sample_format);
-        let sample_rate = stream.config.sample_rate;
-        let timestamp = match output_timestamp(stream, frames_available, sample_rate) {
-            Ok(ts) => ts,
-            Err(err) => {
-                error_callback(err);
-                return ControlFlow::Break;
+        let len_bytes = frames_available as usize * stream.bytes_per_frame as usize;
+        let flags = match pause_state.output_mode() {
+            OutputMode::Callback => {
+                stream
+                    .next_block_frames
+                    .store(frames_available, Ordering::Relaxed);
+                let data = buffer as *mut ();
+                let len = len_bytes / stream.sample_format.sample_size();
+                let mut data = Data::from_parts(data, len, stream.sample_format);
+                let sample_rate = stream.config.sample_rate;
+                let timestamp = match output_timestamp(stream, frames_available, sample_rate) {
+                    Ok(ts) => ts,
+                    Err(err) => {
+                        error_callback(err);
+                        return ControlFlow::Break;
+                    }
+                };
+                // The first frame of the buffer plays once the frames already queued have.
+                let padding = stream.max_frames_in_buffer - frames_available;
+                let device_timestamp = match device_position(stream) {
+                    Ok(position) => position.map(|position| DeviceTimestamp {
+                        samples: position + padding as u64,
+                        sample_rate,
+                    }),
+                    Err(err) => {
+                        error_callback(err);
+                        return ControlFlow::Break;
+                    }
+                };
+                let info = OutputCallbackInfo {
+                    timestamp,
+                    device_timestamp,
+                };
+                data_callback(&mut data, &info);
+                0
+            }
+            OutputMode::Silence => {
+                let bytes = slice::from_raw_parts_mut(buffer, len_bytes);
+                fill_silence(bytes, stream.sample_format);
+                0
             }
+            // Let WASAPI render silence without touching the buffer.
+            OutputMode::Idle => audioclient::AUDCLNT_BUFFERFLAGS_SILENT,
         };
-        let info = OutputCallbackInfo { timestamp };
-        data_callback(&mut data, &info);
 
-        let hresult = (*render_client).ReleaseBuffer(frames_available as u32, 0);
+        let hresult = (*render_client).ReleaseBuffer(frames_available as u32, flags);
         if let Err(err) = stream_error_from_hresult(hresult) {
             error_callback(err);
             return ControlFlow::Break;
@@ -517,6 +766,26 @@ fn stream_instant(stream: &StreamInner) -> Result<crate::StreamInstant, StreamEr
     Ok(instant)
 }
 
+/// Use the stream's `IAudioClock` to produce the position of the device, in frames.
+///
+/// Returns `None` if the driver reports a clock frequency of zero.
+fn device_position(stream: &StreamInner) -> Result<Option<u64>, StreamError> {
+    let mut frequency: UINT64 = 0;
+    let mut position: UINT64 = 0;
+    unsafe {
+        stream_error_from_hresult((*stream.audio_clock).GetFrequency(&mut frequency))?;
+        stream_error_from_hresult(
+            (*stream.audio_clock).GetPosition(&mut position, ptr::null_mut()),
+        )?;
+    }
+    if frequency == 0 {
+        return Ok(None);
+    }
+    // The position is in units of `frequency` per second, which need not be frames.
+    let frames = position as u128 * stream.config.sample_rate.0 as u128 / frequency as u128;
+    Ok(Some(frames as u64))
+}
+
 /// Produce the input stream timestamp.
 ///
 /// `buffer_qpc_position` is the `qpc_position` returned via the `GetBuffer` call on the capture
@@ -0,0 +1,177 @@
+//! Detecting a data callback that has stopped returning, see `StreamOptions::callback_watchdog`.
+//!
+//! The data callback is wrapped so that it publishes a heartbeat, a counter whose lowest bit is set
+//! while the callback runs, with one relaxed store on entry and one on return. A monitor thread
+//! samples the heartbeat once per timeout, and reports `StreamError::CallbackStalled` when it finds
+//! the same call still running twice in a row. A stream that is paused or not yet playing has no
+//! call in progress and is never reported.
+//!
+//! The monitor only holds weak references to the wrapped callbacks, and exits once the stream has
+//! dropped them.
+
+use crate::{Data, InputCallbackInfo, OutputCallbackInfo, StreamError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread;
+use std::time::Duration;
+
+// Stores the heartbeat for the end of a call when dropped, so that a call that unwinds still
+// counts as having returned.
+struct Beat<'a> {
+    heartbeat: &'a AtomicUsize,
+    value: usize,
+}
+
+impl Drop for Beat<'_> {
+    fn drop(&mut self) {
+        self.heartbeat.store(self.value, Ordering::Relaxed);
+    }
+}
+
+// Marks the start of a call, returning a guard that marks its end.
+fn beat<'a>(heartbeat: &'a AtomicUsize, beats: &mut usize) -> Beat<'a> {
+    heartbeat.store(*beats | 1, Ordering::Relaxed);
+    *beats = beats.wrapping_add(2);
+    Beat {
+        heartbeat,
+        value: *beats,
+    }
+}
+
+/// The heartbeat of a watched stream, published by its data callback.
+pub(crate) struct Heartbeat(Arc<AtomicUsize>);
+
+/// Spawns the monitor thread of a stream, returning the heartbeat to pass to `watch_input` or
+/// `watch_output` along with the wrapped error callback.
+pub(crate) fn spawn<E>(
+    timeout: Duration,
+    error_callback: E,
+) -> std::io::Result<(Heartbeat, impl FnMut(StreamError) + Send + 'static)>
+where
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let heartbeat = Arc::new(AtomicUsize::new(0));
+    let error_callback = Arc::new(Mutex::new(error_callback));
+    let weak_heartbeat = Arc::downgrade(&heartbeat);
+    let weak_error_callback = Arc::downgrade(&error_callback);
+    let builder = thread::Builder::new().name("cpal_watchdog".to_string());
+    builder.spawn(move || monitor(timeout, weak_heartbeat, weak_error_callback))?;
+    let error_callback = move |err| {
+        let mut error_callback = error_callback
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        (*error_callback)(err)
+    };
+    Ok((Heartbeat(heartbeat), error_callback))
+}
+
+fn monitor<E>(timeout: Duration, heartbeat: Weak<AtomicUsize>, error_callback: Weak<Mutex<E>>)
+where
+    E: FnMut(StreamError),
+{
+    let mut last = 0;
+    let mut reported = false;
+    loop {
+        thread::sleep(timeout);
+        let current = match heartbeat.upgrade() {
+            Some(heartbeat) => heartbeat.load(Ordering::Relaxed),
+            None => return,
+        };
+        if current & 1 == 1 && current == last {
+            if !reported {
+                reported = true;
+                warn!(?timeout, "data callback stalled");
+                let error_callback = match error_callback.upgrade() {
+                    Some(error_callback) => error_callback,
+                    None => return,
+                };
+                let mut error_callback = error_callback
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                (*error_callback)(StreamError::CallbackStalled { timeout });
+            }
+        } else {
+            reported = false;
+        }
+        last = current;
+    }
+}
+
+/// Wraps the data callback of an input stream to publish `heartbeat`.
+pub(crate) fn watch_input<D>(
+    heartbeat: Heartbeat,
+    mut data_callback: D,
+) -> impl FnMut(&Data, &InputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+{
+    let mut beats = 0;
+    move |data: &Data, info: &InputCallbackInfo| {
+        let _beat = beat(&heartbeat.0, &mut beats);
+        data_callback(data, info);
+    }
+}
+
+/// Wraps the data callback of an output stream to publish `heartbeat`.
+pub(crate) fn watch_output<D>(
+    heartbeat: Heartbeat,
+    mut data_callback: D,
+) -> impl FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+{
+    let mut beats = 0;
+    move |data: &mut Data, info: &OutputCallbackInfo| {
+        let _beat = beat(&heartbeat.0, &mut beats);
+        data_callback(data, info);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{spawn, watch_output};
+    use crate::mock::output_info;
+    use crate::{Data, OutputCallbackInfo, SampleFormat, StreamError};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn call(data_callback: &mut impl FnMut(&mut Data, &OutputCallbackInfo)) {
+        let mut buffer = [0.0f32; 4];
+        let mut data =
+            unsafe { Data::from_parts(buffer.as_mut_ptr() as *mut (), 4, SampleFormat::F32) };
+        data_callback(&mut data, &output_info());
+    }
+
+    #[test]
+    fn test_stalled_callback_is_reported_once() {
+        let timeout = Duration::from_millis(20);
+        let (err_tx, err_rx) = mpsc::channel();
+        let (heartbeat, _error_callback) =
+            spawn(timeout, move |err| err_tx.send(err).unwrap()).unwrap();
+        let mut data_callback =
+            watch_output(heartbeat, move |_: &mut Data, _: &OutputCallbackInfo| {
+                thread::sleep(timeout * 10)
+            });
+        call(&mut data_callback);
+        let errors: Vec<_> = err_rx.try_iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            StreamError::CallbackStalled { timeout: t } if t == timeout
+        ));
+    }
+
+    #[test]
+    fn test_idle_stream_is_not_reported() {
+        let timeout = Duration::from_millis(10);
+        let (err_tx, err_rx) = mpsc::channel();
+        let (heartbeat, _error_callback) =
+            spawn(timeout, move |err| err_tx.send(err).unwrap()).unwrap();
+        let mut data_callback = watch_output(heartbeat, |_: &mut Data, _: &OutputCallbackInfo| {});
+        call(&mut data_callback);
+        // No call in progress, as when the stream is paused.
+        thread::sleep(timeout * 5);
+        assert_eq!(err_rx.try_iter().count(), 0);
+    }
+}
@@ -6,12 +6,14 @@ use self::js_sys::eval;
 use self::wasm_bindgen::prelude::*;
 use self::wasm_bindgen::JsCast;
 use self::web_sys::{AudioContext, AudioContextOptions};
+use crate::host::callback_guard;
 use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crate::{
-    BackendSpecificError, BufferSize, BuildStreamError, Data, DefaultStreamConfigError,
-    DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo, PauseStreamError,
-    PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError, SupportedBufferSize,
-    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+    BackendSpecificError, BufferSize, BuildStreamError, ChannelCount, Data,
+    DefaultStreamConfigError, DeviceNameError, DevicesError, InputCallbackInfo, OutputCallbackInfo,
+    PauseStreamError, PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError,
+    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
 };
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex, RwLock};
@@ -187,12 +189,14 @@ impl DeviceTrait for Device {
         config: &StreamConfig,
         sample_format: SampleFormat,
         data_callback: D,
-        _error_callback: E,
+        error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
     where
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
+        let (data_callback, _error_callback) =
+            callback_guard::guard_output(data_callback, error_callback);
         if !valid_config(config, sample_format) {
             return Err(BuildStreamError::StreamConfigNotSupported);
         }
@@ -221,7 +225,10 @@ impl DeviceTrait for Device {
             AudioContext::new_with_context_options(&stream_opts).map_err(
                 |err| -> BuildStreamError {
                     let description = format!("{:?}", err);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
                     err.into()
                 },
             )?,
@@ -254,7 +261,10 @@ impl DeviceTrait for Device {
                 )
                 .map_err(|err| -> BuildStreamError {
                     let description = format!("{:?}", err);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
                     err.into()
                 })?;
 
@@ -292,7 +302,10 @@ impl DeviceTrait for Device {
                         let callback = crate::StreamInstant::from_secs_f64(now);
                         let playback = crate::StreamInstant::from_secs_f64(time_at_start_of_buffer);
                         let timestamp = crate::OutputStreamTimestamp { callback, playback };
-                        let info = OutputCallbackInfo { timestamp };
+                        let info = OutputCallbackInfo {
+                            timestamp,
+                            device_timestamp: None,
+                        };
                         (data_callback.deref_mut())(&mut data, &info);
                     }
 
@@ -388,7 +401,10 @@ impl StreamTrait for Stream {
             }
             Err(err) => {
                 let description = format!("{:?}", err);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: None,
+                };
                 Err(err.into())
             }
         }
@@ -399,16 +415,37 @@ impl StreamTrait for Stream {
             Ok(_) => Ok(()),
             Err(err) => {
                 let description = format!("{:?}", err);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError {
+                    description,
+                    code: None,
+                };
                 Err(err.into())
             }
         }
     }
+
+    fn sample_rate(&self) -> SampleRate {
+        SampleRate(self.ctx.sample_rate() as u32)
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        SUPPORTED_SAMPLE_FORMAT
+    }
 }
 
 impl Drop for Stream {
     fn drop(&mut self) {
         let _ = self.ctx.close();
+        // JavaScript runs the closures on this thread, so none of them can be in flight here.
+        // Each closure holds a handle to itself in order to reschedule itself, so it has to be
+        // released explicitly for the data callback and everything it captured to be dropped.
+        for on_ended_closure in self.on_ended_closures.iter() {
+            on_ended_closure.write().unwrap().take();
+        }
     }
 }
 
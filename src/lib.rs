@@ -60,7 +60,7 @@
 //! use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 //! # let host = cpal::default_host();
 //! # let device = host.default_output_device().unwrap();
-//! # let config = device.default_output_config().unwrap().into();
+//! # let config = device.default_output_config().unwrap().config();
 //! let stream = device.build_output_stream(
 //!     &config,
 //!     move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
@@ -88,18 +88,22 @@
 //! In this example, we simply fill the given output buffer with silence.
 //!
 //! ```no_run
-//! use cpal::{Data, Sample, SampleFormat};
+//! use cpal::{Data, Sample, SampleFormat, I24, I24Packed};
 //! use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 //! # let host = cpal::default_host();
 //! # let device = host.default_output_device().unwrap();
 //! # let supported_config = device.default_output_config().unwrap();
 //! let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
 //! let sample_format = supported_config.sample_format();
-//! let config = supported_config.into();
+//! let config = supported_config.config();
 //! let stream = match sample_format {
 //!     SampleFormat::F32 => device.build_output_stream(&config, write_silence::<f32>, err_fn),
 //!     SampleFormat::I16 => device.build_output_stream(&config, write_silence::<i16>, err_fn),
 //!     SampleFormat::U16 => device.build_output_stream(&config, write_silence::<u16>, err_fn),
+//!     SampleFormat::I24 => device.build_output_stream(&config, write_silence::<I24>, err_fn),
+//!     SampleFormat::I24Packed => {
+//!         device.build_output_stream(&config, write_silence::<I24Packed>, err_fn)
+//!     }
 //! }.unwrap();
 //!
 //! fn write_silence<T: Sample>(data: &mut [T], _: &cpal::OutputCallbackInfo) {
@@ -118,7 +122,7 @@
 //! # let device = host.default_output_device().unwrap();
 //! # let supported_config = device.default_output_config().unwrap();
 //! # let sample_format = supported_config.sample_format();
-//! # let config = supported_config.into();
+//! # let config = supported_config.config();
 //! # let data_fn = move |_data: &mut cpal::Data, _: &cpal::OutputCallbackInfo| {};
 //! # let err_fn = move |_err| {};
 //! # let stream = device.build_output_stream_raw(&config, sample_format, data_fn, err_fn).unwrap();
@@ -134,7 +138,7 @@
 //! # let device = host.default_output_device().unwrap();
 //! # let supported_config = device.default_output_config().unwrap();
 //! # let sample_format = supported_config.sample_format();
-//! # let config = supported_config.into();
+//! # let config = supported_config.config();
 //! # let data_fn = move |_data: &mut cpal::Data, _: &cpal::OutputCallbackInfo| {};
 //! # let err_fn = move |_err| {};
 //! # let stream = device.build_output_stream_raw(&config, sample_format, data_fn, err_fn).unwrap();
@@ -152,19 +156,42 @@ extern crate lazy_static;
 extern crate stdweb;
 extern crate thiserror;
 
+pub use duplex::{
+    DuplexCallbackInfo, DuplexStream, DuplexStreamConfig, DuplexStreamTimestamp,
+    DUPLEX_RING_DURATION,
+};
 pub use error::*;
+pub use fanout::{FanoutOverflow, InputFanout, InputReceiver, FANOUT_DURATION};
 pub use platform::{
     available_hosts, default_host, host_from_id, Device, Devices, Host, HostId, Stream,
     SupportedInputConfigs, SupportedOutputConfigs, ALL_HOSTS,
 };
-pub use samples_formats::{Sample, SampleFormat};
+pub use samples_formats::{I24Packed, Sample, SampleFormat, I24};
 use std::convert::TryInto;
 use std::ops::{Div, Mul};
 use std::time::Duration;
 
+#[macro_use]
+mod trace;
+
+pub mod analysis;
+#[cfg(feature = "async")]
+pub mod async_stream;
+pub mod batch;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod compat;
+mod duplex;
 mod error;
+mod fanout;
 mod host;
+#[cfg(test)]
+mod mock;
+pub mod offline;
 pub mod platform;
+pub mod reconfigurable;
+mod ring;
+pub mod rt;
 mod samples_formats;
 pub mod traits;
 
@@ -181,6 +208,29 @@ pub type ChannelCount = u16;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SampleRate(pub u32);
 
+impl SampleRate {
+    /// The number of frames per millisecond, which need not be whole, e.g. 44.1 at 44.1 kHz.
+    pub fn samples_per_ms(&self) -> f64 {
+        self.0 as f64 / 1_000.0
+    }
+
+    /// [`samples_per_ms`](Self::samples_per_ms), rounded to the nearest whole frame.
+    pub fn samples_per_ms_rounded(&self) -> u32 {
+        (self.0 + 500) / 1_000
+    }
+
+    /// The number of frames in `duration`, which need not be whole.
+    pub fn frames_in(&self, duration: Duration) -> f64 {
+        duration.as_secs_f64() * self.0 as f64
+    }
+
+    /// [`frames_in`](Self::frames_in), rounded to the nearest whole frame.
+    pub fn frames_in_rounded(&self, duration: Duration) -> u64 {
+        let nanos = duration.as_nanos() * self.0 as u128;
+        ((nanos + 500_000_000) / 1_000_000_000) as u64
+    }
+}
+
 impl<T> Mul<T> for SampleRate
 where
     u32: Mul<T, Output = u32>,
@@ -217,6 +267,15 @@ pub enum BufferSize {
     Fixed(FrameCount),
 }
 
+/// The smallest buffer size, in frames, tried by `DeviceTrait::build_output_stream_low_latency`.
+///
+/// Smaller buffers are accepted by some hosts but rarely play without underruns.
+pub const LOW_LATENCY_MIN_FRAMES: FrameCount = 64;
+
+/// The shortest buffer requested by converting a `SupportedStreamConfig` into a `StreamConfig`
+/// with `TryFrom`, unless the device only supports shorter ones.
+pub const MIN_BUFFER_DURATION: Duration = Duration::from_millis(10);
+
 /// The set of parameters used to describe how to open a stream.
 ///
 /// The sample format is omitted in favour of using a sample type.
@@ -227,6 +286,460 @@ pub struct StreamConfig {
     pub buffer_size: BufferSize,
 }
 
+impl StreamConfig {
+    /// The number of frames the data callback is expected to receive per call.
+    ///
+    /// This is the requested buffer size, and `None` for `BufferSize::Default`. Hosts may pass
+    /// fewer frames to some calls, or settle on another size, so it should only be used to size
+    /// buffers ahead of time, not to index into the data passed.
+    pub fn frames_per_callback_estimate(&self) -> Option<FrameCount> {
+        match self.buffer_size {
+            BufferSize::Fixed(frames) => Some(frames),
+            BufferSize::Default => None,
+        }
+    }
+}
+
+/// Optional behaviour of a stream, see `DeviceTrait::build_input_stream_with_options`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StreamOptions {
+    /// Reports `StreamError::CallbackStalled` when a call to the data callback has not returned
+    /// after this long.
+    ///
+    /// A monitor thread checks on the callback once per timeout, so a stall is reported between
+    /// one and two timeouts after the call began. The callback itself only gains two relaxed
+    /// atomic stores per call. The error callback is called from the monitor thread, and may run
+    /// concurrently with the stalled data callback. The stream keeps running; pause or drop it
+    /// from the thread that owns it if the stall can't be recovered from.
+    pub callback_watchdog: Option<Duration>,
+    /// Defers opening the device until the stream is first played.
+    ///
+    /// Building the stream then only checks the config against those supported by the device, and
+    /// errors opening the device are returned by `StreamTrait::play` instead. Pausing the stream
+    /// closes the device again, so that other applications may use it, and the data callback is
+    /// not called while paused. Only `KeepAlive::Off` is supported.
+    ///
+    /// | Host | Deferral |
+    /// | ---- | -------- |
+    /// | ALSA | Honoured |
+    ///
+    /// All other hosts open the device when the stream is built, as if this was `false`.
+    pub defer_open: bool,
+    /// The channels an input stream presents to its data callback. Ignored by output streams.
+    pub channel_mode: ChannelMode,
+    /// Moves a `BufferSize::Fixed` outside the range of the device's default config to the
+    /// nearest end of it, instead of failing with `BuildStreamError::BufferSizeTooSmall` or
+    /// `BufferSizeTooLarge`.
+    ///
+    /// The stream then runs with a buffer size other than the one requested, which
+    /// `SupportedBufferSize::clamp` computes ahead of time.
+    pub clamp_buffer_size: bool,
+    /// Ramps the gain of an output stream up from zero over this long when it starts playing, and
+    /// down to zero before `StreamTrait::close` stops it. Ignored by input streams.
+    ///
+    /// The ramp is applied to the samples rendered by the data callback, and starts again each
+    /// time the stream is played after a `pause`. `close` waits for the ramp to end and for the
+    /// device to play the frames already written, within its timeout. Dropping the stream stops
+    /// it without fading out.
+    ///
+    /// | Host | Fade |
+    /// | ---- | ---- |
+    /// | ALSA | Honoured |
+    ///
+    /// All other hosts only fade the stream in when it first starts playing. It neither fades in
+    /// again after a `pause` nor fades out before `close`.
+    pub fade: Option<Duration>,
+}
+
+/// The channels of an input stream, set via `StreamOptions::channel_mode`.
+///
+/// Some backends sum a stereo source to mono, or present a mono microphone as two identical
+/// channels, e.g. a Bluetooth headset using the Hands-Free profile. Forcing a mode lets the stream
+/// report such mixing through `InputCallbackInfo::channel_mix`, and has CPAL mix the device's
+/// channels itself when the device does not support the requested count.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ChannelMode {
+    /// The stream has the channels of its config, exactly as the backend delivers them, and
+    /// reports `ChannelMix::None`. This is the default.
+    #[default]
+    AsReported,
+    /// The stream has one channel, which must be the channel count of its config. If the device
+    /// only supports two channels, CPAL averages them.
+    ForceMono,
+    /// The stream has two channels, which must be the channel count of its config. If the device
+    /// only supports one channel, CPAL duplicates it.
+    ForceStereo,
+}
+
+/// How the channels passed to an input stream's data callback were mixed from those captured by
+/// the device, see `InputCallbackInfo::channel_mix`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ChannelMix {
+    /// No mixing is known of. Streams built with `ChannelMode::AsReported` always report this.
+    #[default]
+    None,
+    /// The device has more physical channels than the stream, which the backend sums or drops.
+    BackendDownmix,
+    /// The device has fewer physical channels than the stream, which the backend duplicates.
+    BackendUpmix,
+    /// CPAL averages the two channels opened on the device into one, for
+    /// `ChannelMode::ForceMono`.
+    Downmix,
+    /// CPAL duplicates the one channel opened on the device into two, for
+    /// `ChannelMode::ForceStereo`.
+    Upmix,
+}
+
+/// How long a stream expects to hold audio focus, used within a `FocusRequest`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FocusGain {
+    /// Focus is held for an unknown duration, e.g. for music playback.
+    Permanent,
+    /// Focus is held for a short time, e.g. for a navigation prompt or notification.
+    Transient,
+    /// Focus is held for a short time during which no other audio should play, e.g. for voice
+    /// recording.
+    TransientExclusive,
+}
+
+/// A request for audio focus, made via `StreamTrait::request_focus`.
+///
+/// Audio focus allows a stream to ask the system to pause or duck (lower the volume of) audio
+/// played by other applications.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FocusRequest {
+    pub gain: FocusGain,
+    /// Whether other applications may keep playing at a reduced volume rather than pausing.
+    ///
+    /// Only supported with `FocusGain::Transient`; requesting other gains with it returns
+    /// `RequestFocusError::NotSupported`.
+    pub duck_others: bool,
+}
+
+/// What an output stream does with the device while paused, set via
+/// `StreamTrait::set_keep_alive`.
+///
+/// Some USB and Bluetooth devices power down after a few seconds of silence, then click or drop
+/// the first few hundred milliseconds of audio when playback resumes. Keeping the device active
+/// avoids this, at the cost of the power drawn by the device and the audio thread, which keep
+/// running for as long as the stream is paused.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum KeepAlive {
+    /// Pausing stops the backend stream, letting the device idle. This is the default.
+    #[default]
+    Off,
+    /// Pausing keeps the backend stream running, with CPAL feeding it silence instead of calling
+    /// the data callback.
+    Silence,
+    /// Pausing keeps the device active using a host facility, without CPAL producing samples.
+    Hardware,
+}
+
+/// Whether a stream captures audio from, or plays audio to, a device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StreamDirection {
+    Input,
+    Output,
+}
+
+/// The Bluetooth profile a device is connected with, see `DeviceTrait::bluetooth_profile`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BtProfile {
+    /// A2DP, for high-quality playback. A2DP has no input.
+    A2dp,
+    /// The Hands-Free or Headset profile, which carries voice in both directions at a sample rate
+    /// of 8 or 16 kHz. Systems switch a headset to it when its microphone is used, and playback
+    /// sounds noticeably worse than over A2DP.
+    HandsFree,
+    /// The device is connected over Bluetooth, but the host doesn't reveal the profile.
+    Unknown,
+}
+
+/// Identifies a device of a host, see `DeviceTrait::id` and `HostTrait::device_names`.
+///
+/// Unlike a device's name, which is meant for display, the ID is the key to look the device up by
+/// with `HostTrait::device_by_id`. Where the host provides a persistent endpoint identifier, the ID
+/// is unique among its devices and survives restarts, so that it can be saved in settings; other
+/// hosts derive it from the device's name.
+///
+/// To save it, write its string form with `Display` and parse it back with `FromStr`, or enable
+/// the `serde` feature, which serializes it as that string.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DeviceId(String);
+
+impl DeviceId {
+    /// Creates an ID from its string form, as returned by `as_str`.
+    pub fn new(id: impl Into<String>) -> Self {
+        DeviceId(id.into())
+    }
+
+    /// The string form of the ID, in a format specific to the host.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for DeviceId {
+    type Err = std::convert::Infallible;
+
+    /// Parses the string form of an ID, as written by `Display`. Any string is a valid ID, but
+    /// only those of existing devices are found by `HostTrait::device_by_id`.
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        Ok(DeviceId::new(id))
+    }
+}
+
+/// Serialized as its string form, see `DeviceId::as_str`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for DeviceId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DeviceId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(DeviceId)
+    }
+}
+
+/// Information about a host and the build of CPAL, for bug reports. See `Host::diagnostics`.
+///
+/// The `Display` implementation formats it as a report of one item per line:
+///
+/// ```no_run
+/// let host = cpal::default_host();
+/// println!("{}", host.diagnostics());
+/// ```
+#[derive(Clone, Debug)]
+pub struct HostDiagnostics {
+    /// The version of CPAL.
+    pub cpal_version: &'static str,
+    pub host: HostId,
+    /// The version of the library or system the host is backed by, see
+    /// `HostTrait::backend_version`.
+    pub backend_version: Option<String>,
+    /// The hosts CPAL was compiled with, see `ALL_HOSTS`.
+    pub compiled_hosts: Vec<HostId>,
+    /// Whether `rt::set_thread_realtime` succeeds on this system.
+    pub realtime_threads: bool,
+    /// Features of the system the host may use, see `HostTrait::capabilities`.
+    pub capabilities: Vec<(&'static str, bool)>,
+}
+
+impl std::fmt::Display for HostDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let yes_no = |available| if available { "yes" } else { "no" };
+        writeln!(
+            f,
+            "cpal {} ({} {})",
+            self.cpal_version,
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        )?;
+        write!(f, "host: {}", self.host.name())?;
+        if let Some(version) = &self.backend_version {
+            write!(f, ", {}", version)?;
+        }
+        let compiled_hosts: Vec<_> = self.compiled_hosts.iter().map(HostId::name).collect();
+        writeln!(f)?;
+        writeln!(f, "compiled hosts: {}", compiled_hosts.join(", "))?;
+        write!(f, "realtime threads: {}", yes_no(self.realtime_threads))?;
+        for &(capability, available) in &self.capabilities {
+            write!(f, "\n{}: {}", capability, yes_no(available))?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a device, for bug reports. See `DeviceTrait::diagnostics`.
+///
+/// The `Display` implementation formats it as a report of one item per line, leaving out what
+/// isn't known.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeviceDiagnostics {
+    pub name: Option<String>,
+    pub id: Option<DeviceId>,
+    /// The name of the driver the device belongs to.
+    pub driver: Option<String>,
+    /// The version of the driver, in a format specific to the host.
+    pub driver_version: Option<String>,
+}
+
+impl DeviceDiagnostics {
+    // The diagnostics every host can provide, to be completed by the host.
+    pub(crate) fn new<D: traits::DeviceTrait + ?Sized>(device: &D) -> Self {
+        DeviceDiagnostics {
+            name: device.name().ok(),
+            id: device.id().ok(),
+            driver: None,
+            driver_version: None,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let unknown = "unknown".to_string();
+        write!(f, "device: {}", self.name.as_ref().unwrap_or(&unknown))?;
+        if let Some(id) = &self.id {
+            write!(f, "\nid: {}", id)?;
+        }
+        if let Some(driver) = &self.driver {
+            write!(f, "\ndriver: {}", driver)?;
+        }
+        if let Some(version) = &self.driver_version {
+            write!(f, "\ndriver version: {}", version)?;
+        }
+        Ok(())
+    }
+}
+
+/// The purpose a default device is requested for, see `HostTrait::default_output_device_for_role`.
+///
+/// Some systems keep a separate default device per role, so that, for example, calls go to a
+/// headset while music keeps playing through the speakers.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DeviceRole {
+    /// Games, system sounds and voice commands. This is the default device returned by
+    /// `HostTrait::default_input_device` and `HostTrait::default_output_device`.
+    Console,
+    /// Music, movies and other media playback or recording.
+    Multimedia,
+    /// Voice communication, such as VoIP calls and chat.
+    Communications,
+}
+
+/// A change reported to the callback of `HostTrait::watch_default`.
+#[derive(Clone, Debug)]
+pub enum DefaultDeviceEvent<D> {
+    /// The system's default device changed to `device`, or `None` if there is no default device
+    /// anymore.
+    DefaultChanged { device: Option<D> },
+}
+
+impl<D> DefaultDeviceEvent<D> {
+    // Converts the device of the event, e.g. from a host's device to a `platform::Device`.
+    pub(crate) fn map<E>(self, f: impl FnOnce(D) -> E) -> DefaultDeviceEvent<E> {
+        match self {
+            DefaultDeviceEvent::DefaultChanged { device } => DefaultDeviceEvent::DefaultChanged {
+                device: device.map(f),
+            },
+        }
+    }
+}
+
+/// Watches the system's default device, see `HostTrait::watch_default`.
+///
+/// Dropping the watcher stops watching.
+pub struct DefaultWatcher {
+    // The host's registration for notifications, which unregisters when dropped.
+    _registration: Box<dyn Send>,
+}
+
+impl DefaultWatcher {
+    // Unused on platforms where no host can watch the default device.
+    #[allow(dead_code)]
+    pub(crate) fn new(registration: impl Send + 'static) -> Self {
+        DefaultWatcher {
+            _registration: Box::new(registration),
+        }
+    }
+}
+
+impl std::fmt::Debug for DefaultWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DefaultWatcher").finish_non_exhaustive()
+    }
+}
+
+/// A change reported to the callback of `HostTrait::watch_devices`.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent<D> {
+    /// `device` became available, e.g. because it was plugged in.
+    Added { device: D },
+    /// The device with ID `id` is not available anymore, e.g. because it was unplugged. Streams
+    /// on it report `StreamError::DeviceNotAvailable`.
+    Removed { id: DeviceId },
+    /// The system's default input device changed to `device`, or `None` if there is no default
+    /// input device anymore.
+    DefaultInputChanged { device: Option<D> },
+    /// The system's default output device changed to `device`, or `None` if there is no default
+    /// output device anymore.
+    DefaultOutputChanged { device: Option<D> },
+    /// The volume of `device` changed to `volume`, as returned by `DeviceTrait::volume`.
+    VolumeChanged { device: D, volume: f32 },
+}
+
+impl<D> DeviceEvent<D> {
+    // Converts the device of the event, e.g. from a host's device to a `platform::Device`.
+    pub(crate) fn map<E>(self, f: impl FnOnce(D) -> E) -> DeviceEvent<E> {
+        match self {
+            DeviceEvent::Added { device } => DeviceEvent::Added { device: f(device) },
+            DeviceEvent::Removed { id } => DeviceEvent::Removed { id },
+            DeviceEvent::DefaultInputChanged { device } => DeviceEvent::DefaultInputChanged {
+                device: device.map(f),
+            },
+            DeviceEvent::DefaultOutputChanged { device } => DeviceEvent::DefaultOutputChanged {
+                device: device.map(f),
+            },
+            DeviceEvent::VolumeChanged { device, volume } => DeviceEvent::VolumeChanged {
+                device: f(device),
+                volume,
+            },
+        }
+    }
+}
+
+/// Watches the devices of a host, see `HostTrait::watch_devices`.
+///
+/// Dropping the watcher stops watching.
+pub struct DeviceWatcher {
+    // The host's registration for notifications, which unregisters when dropped.
+    _registration: Box<dyn Send>,
+}
+
+impl DeviceWatcher {
+    // Unused on platforms where no host can watch its devices.
+    #[allow(dead_code)]
+    pub(crate) fn new(registration: impl Send + 'static) -> Self {
+        DeviceWatcher {
+            _registration: Box::new(registration),
+        }
+    }
+}
+
+impl std::fmt::Debug for DeviceWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DeviceWatcher").finish_non_exhaustive()
+    }
+}
+
+/// Whether the application may capture audio input, see `HostTrait::input_permission_status`.
+///
+/// Where input is gated by a permission, building an input stream without it usually succeeds, but
+/// the stream captures nothing but silence.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PermissionStatus {
+    /// The user has granted access to audio input.
+    Granted,
+    /// The user has denied access to audio input, or it is restricted by policy.
+    Denied,
+    /// The user has not been asked yet. They are prompted by
+    /// `HostTrait::request_input_permission` or, on most systems, when an input stream is first
+    /// built.
+    Undetermined,
+    /// Audio input is not gated by a permission on this host, or the host cannot tell.
+    NotApplicable,
+}
+
 /// Describes the minimum and maximum supported buffer size for the device
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum SupportedBufferSize {
@@ -239,6 +752,42 @@ pub enum SupportedBufferSize {
     Unknown,
 }
 
+impl SupportedBufferSize {
+    /// `buffer_size` moved into the range, if it is a fixed size outside of it.
+    pub fn clamp(&self, buffer_size: &BufferSize) -> BufferSize {
+        match (self, buffer_size) {
+            (SupportedBufferSize::Range { min, max }, BufferSize::Fixed(frames)) => {
+                BufferSize::Fixed((*frames).clamp(*min, (*max).max(*min)))
+            }
+            _ => buffer_size.clone(),
+        }
+    }
+
+    // Fails with `BufferSizeTooSmall` or `BufferSizeTooLarge` if `buffer_size` is a fixed size
+    // outside the range.
+    pub(crate) fn check(&self, buffer_size: &BufferSize) -> Result<(), BuildStreamError> {
+        match (self, buffer_size) {
+            (SupportedBufferSize::Range { min, .. }, &BufferSize::Fixed(requested))
+                if requested < *min =>
+            {
+                Err(BuildStreamError::BufferSizeTooSmall {
+                    requested,
+                    min: *min,
+                })
+            }
+            (SupportedBufferSize::Range { max, .. }, &BufferSize::Fixed(requested))
+                if requested > *max =>
+            {
+                Err(BuildStreamError::BufferSizeTooLarge {
+                    requested,
+                    max: *max,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Describes a range of supported stream configurations, retrieved via the
 /// `Device::supported_input/output_configs` method.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -321,16 +870,44 @@ pub struct OutputStreamTimestamp {
     pub playback: StreamInstant,
 }
 
+/// A position on the device's own sample clock, as reported by the backend.
+///
+/// Unlike a `StreamInstant`, which is converted from the device clock to a host clock, this is
+/// the raw count of frames, and so stays sample-accurate when synchronising with other equipment
+/// driven by the same clock. The origin of the count is backend specific.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DeviceTimestamp {
+    /// The number of frames the clock has counted since its origin.
+    pub samples: u64,
+    /// The rate at which the clock counts frames.
+    pub sample_rate: SampleRate,
+}
+
 /// Information relevant to a single call to the user's input stream data callback.
 #[derive(Debug, Clone, PartialEq)]
 pub struct InputCallbackInfo {
     timestamp: InputStreamTimestamp,
+    device_timestamp: Option<DeviceTimestamp>,
+    channel_mix: ChannelMix,
 }
 
 /// Information relevant to a single call to the user's output stream data callback.
 #[derive(Debug, Clone, PartialEq)]
 pub struct OutputCallbackInfo {
     timestamp: OutputStreamTimestamp,
+    device_timestamp: Option<DeviceTimestamp>,
+}
+
+// The callbacks of streams that are stored before the stream is built, e.g. by
+// `ReconfigurableStream` or `HostTrait::build_streams`.
+pub(crate) type InputCallback = dyn FnMut(&Data, &InputCallbackInfo) + Send;
+pub(crate) type OutputCallback = dyn FnMut(&mut Data, &OutputCallbackInfo) + Send;
+pub(crate) type ErrorCallback = dyn FnMut(StreamError) + Send;
+
+// The data callback of a stream in either direction.
+pub(crate) enum DataCallback {
+    Input(Box<InputCallback>),
+    Output(Box<OutputCallback>),
 }
 
 impl SupportedStreamConfig {
@@ -350,11 +927,40 @@ impl SupportedStreamConfig {
         self.sample_format
     }
 
+    /// Narrow the supported buffer size down to exactly `frames`.
+    ///
+    /// A `StreamConfig` produced from the result via `config` or `try_into` will request
+    /// `BufferSize::Fixed(frames)`.
+    ///
+    /// **panic!**s if the given `frames` is outside the supported buffer size range.
+    pub fn with_buffer_size(self, frames: FrameCount) -> SupportedStreamConfig {
+        if let SupportedBufferSize::Range { min, max } = self.buffer_size {
+            assert!(min <= frames && frames <= max);
+        }
+        SupportedStreamConfig {
+            buffer_size: SupportedBufferSize::Range {
+                min: frames,
+                max: frames,
+            },
+            ..self
+        }
+    }
+
+    /// Produce the `StreamConfig` described by this supported config.
+    ///
+    /// If the supported buffer size range holds a single value (e.g. the host only allows one
+    /// size or it was chosen via `with_buffer_size`), that value is requested as
+    /// `BufferSize::Fixed`. Otherwise `BufferSize::Default` is used; convert with `try_into` to
+    /// request a size from the range instead.
     pub fn config(&self) -> StreamConfig {
+        let buffer_size = match self.buffer_size {
+            SupportedBufferSize::Range { min, max } if min == max => BufferSize::Fixed(min),
+            _ => BufferSize::Default,
+        };
         StreamConfig {
             channels: self.channels,
             sample_rate: self.sample_rate,
-            buffer_size: BufferSize::Default,
+            buffer_size,
         }
     }
 }
@@ -362,16 +968,22 @@ impl SupportedStreamConfig {
 impl StreamInstant {
     /// The amount of time elapsed from another instant to this one.
     ///
-    /// Returns `None` if `earlier` is later than self.
+    /// Returns `None` if `earlier` is later than self. The same as `checked_duration_since`.
     pub fn duration_since(&self, earlier: &Self) -> Option<Duration> {
+        self.checked_duration_since(earlier)
+    }
+
+    /// The amount of time elapsed from another instant to this one, or `None` if `earlier` is
+    /// later than self, e.g. when instants arrive out of order.
+    ///
+    /// The elapsed time is computed exactly, in nanoseconds, however long the stream has run.
+    pub fn checked_duration_since(&self, earlier: &Self) -> Option<Duration> {
         if self < earlier {
-            None
-        } else {
-            (self.as_nanos() - earlier.as_nanos())
-                .try_into()
-                .ok()
-                .map(Duration::from_nanos)
+            return None;
         }
+        let nanos = self.as_nanos() - earlier.as_nanos();
+        let secs = (nanos / 1_000_000_000).try_into().ok()?;
+        Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
     }
 
     /// Returns the instant in time after the given duration has passed.
@@ -399,23 +1011,19 @@ impl StreamInstant {
         (self.secs as i128 * 1_000_000_000) + self.nanos as i128
     }
 
+    // The seconds are rounded down so that the nanoseconds stay in `0..1_000_000_000` for
+    // instants before the origin as well.
     #[allow(dead_code)]
     fn from_nanos(nanos: i64) -> Self {
-        let secs = nanos / 1_000_000_000;
-        let subsec_nanos = nanos - secs * 1_000_000_000;
-        Self::new(secs as i64, subsec_nanos as u32)
+        let secs = nanos.div_euclid(1_000_000_000);
+        let subsec_nanos = nanos.rem_euclid(1_000_000_000);
+        Self::new(secs, subsec_nanos as u32)
     }
 
-    #[allow(dead_code)]
     fn from_nanos_i128(nanos: i128) -> Option<Self> {
-        let secs = nanos / 1_000_000_000;
-        if secs > i64::MAX as i128 || secs < i64::MIN as i128 {
-            None
-        } else {
-            let subsec_nanos = nanos - secs * 1_000_000_000;
-            debug_assert!(subsec_nanos < u32::MAX as i128);
-            Some(Self::new(secs as i64, subsec_nanos as u32))
-        }
+        let secs = nanos.div_euclid(1_000_000_000).try_into().ok()?;
+        let subsec_nanos = nanos.rem_euclid(1_000_000_000);
+        Some(Self::new(secs, subsec_nanos as u32))
     }
 
     #[allow(dead_code)]
@@ -430,11 +1038,35 @@ impl StreamInstant {
     }
 }
 
+impl DeviceTimestamp {
+    /// The time the clock has counted since its origin, at its nominal rate.
+    pub fn duration(&self) -> Duration {
+        let secs = self.samples / self.sample_rate.0 as u64;
+        let frames = self.samples % self.sample_rate.0 as u64;
+        let nanos = frames * 1_000_000_000 / self.sample_rate.0 as u64;
+        Duration::new(secs, nanos as u32)
+    }
+}
+
 impl InputCallbackInfo {
     /// The timestamp associated with the call to an input stream's data callback.
     pub fn timestamp(&self) -> InputStreamTimestamp {
         self.timestamp
     }
+
+    /// The position on the device clock of the first frame of the buffer, which corresponds to
+    /// `InputStreamTimestamp::capture`.
+    ///
+    /// See `OutputCallbackInfo::timestamp_device` for the clock each host uses.
+    pub fn timestamp_device(&self) -> Option<DeviceTimestamp> {
+        self.device_timestamp
+    }
+
+    /// How the channels of the buffer were mixed from those captured by the device, as requested
+    /// via `StreamOptions::channel_mode`.
+    pub fn channel_mix(&self) -> ChannelMix {
+        self.channel_mix
+    }
 }
 
 impl OutputCallbackInfo {
@@ -442,6 +1074,27 @@ impl OutputCallbackInfo {
     pub fn timestamp(&self) -> OutputStreamTimestamp {
         self.timestamp
     }
+
+    /// The position on the device clock of the first frame of the buffer, which corresponds to
+    /// `OutputStreamTimestamp::playback`.
+    ///
+    /// The clock used by each host, and whether it keeps counting through an xrun (an overrun or
+    /// underrun), is as follows. Positions of a clock that pauses drift from the host clock after
+    /// each xrun.
+    ///
+    /// | Host | Clock | Counts through xruns |
+    /// | ---- | ----- | -------------------- |
+    /// | alsa | frames transferred by the stream | no |
+    /// | coreaudio | `AudioTimeStamp::mSampleTime` | yes |
+    /// | wasapi | input: `IAudioCaptureClient::GetBuffer` position, output: `IAudioClock` | yes |
+    /// | asio | `ASIOTimeInfo::samplePosition` | yes |
+    /// | jack | `jack_last_frame_time` | yes |
+    /// | `offline` | frames rendered or captured | - |
+    ///
+    /// Returns `None` on the other hosts.
+    pub fn timestamp_device(&self) -> Option<DeviceTimestamp> {
+        self.device_timestamp
+    }
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -724,9 +1377,260 @@ fn test_cmp_default_heuristics() {
     assert_eq!(formats[4].channels(), 2);
 }
 
-impl From<SupportedStreamConfig> for StreamConfig {
-    fn from(conf: SupportedStreamConfig) -> Self {
-        conf.config()
+/// The configs a device supports in one direction, normalized for presentation, see
+/// `DeviceTrait::supported_configs`.
+///
+/// Ranges are sorted by channel count, sample format, buffer size and sample rate. Ranges that
+/// only differ in sample rate are merged where their rates overlap or adjoin, and empty ranges are
+/// dropped.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SupportedConfigSet {
+    ranges: Vec<SupportedStreamConfigRange>,
+}
+
+impl SupportedConfigSet {
+    /// Normalizes the given ranges, as yielded by `DeviceTrait::supported_output_configs`.
+    pub fn new<I>(ranges: I) -> Self
+    where
+        I: IntoIterator<Item = SupportedStreamConfigRange>,
+    {
+        fn sort_key(range: &SupportedStreamConfigRange) -> impl Ord {
+            let buffer_size = match range.buffer_size {
+                SupportedBufferSize::Unknown => None,
+                SupportedBufferSize::Range { min, max } => Some((min, max)),
+            };
+            (
+                range.channels,
+                format_order(range.sample_format),
+                buffer_size,
+                range.min_sample_rate,
+                range.max_sample_rate,
+            )
+        }
+
+        let mut sorted: Vec<_> = ranges
+            .into_iter()
+            .filter(|range| range.min_sample_rate <= range.max_sample_rate)
+            .collect();
+        sorted.sort_by_key(sort_key);
+        let mut ranges: Vec<SupportedStreamConfigRange> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            if let Some(last) = ranges.last_mut() {
+                let mergeable = last.channels == range.channels
+                    && last.sample_format == range.sample_format
+                    && last.buffer_size == range.buffer_size
+                    && range.min_sample_rate.0 <= last.max_sample_rate.0.saturating_add(1);
+                if mergeable {
+                    last.max_sample_rate = last.max_sample_rate.max(range.max_sample_rate);
+                    continue;
+                }
+            }
+            ranges.push(range);
+        }
+        SupportedConfigSet { ranges }
+    }
+
+    /// The normalized ranges.
+    pub fn ranges(&self) -> &[SupportedStreamConfigRange] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The supported channel counts, in ascending order.
+    pub fn channel_counts(&self) -> Vec<ChannelCount> {
+        let mut channel_counts: Vec<_> = self.ranges.iter().map(|range| range.channels).collect();
+        channel_counts.dedup();
+        channel_counts
+    }
+
+    /// The supported sample formats, in the order `I16`, `U16`, `I24`, `I24Packed`, `F32`.
+    pub fn formats(&self) -> Vec<SampleFormat> {
+        let mut formats: Vec<_> = self
+            .ranges
+            .iter()
+            .map(|range| range.sample_format)
+            .collect();
+        formats.sort_by_key(|&format| format_order(format));
+        formats.dedup();
+        formats
+    }
+
+    /// The supported sample rates, in ascending order.
+    ///
+    /// Ranges are represented by their bounds and the commonly used rates within them, so that a
+    /// device supporting any rate from 8 to 192 kHz doesn't list every one of them.
+    pub fn sample_rates(&self) -> Vec<SampleRate> {
+        let mut sample_rates = Vec::new();
+        for range in &self.ranges {
+            let (min, max) = (range.min_sample_rate, range.max_sample_rate);
+            sample_rates.push(min);
+            sample_rates.extend(
+                COMMON_SAMPLE_RATES
+                    .iter()
+                    .filter(|&&rate| min < rate && rate < max),
+            );
+            sample_rates.push(max);
+        }
+        sample_rates.sort();
+        sample_rates.dedup();
+        sample_rates
+    }
+
+    /// The smallest and largest buffer size over all ranges, or `SupportedBufferSize::Unknown` if
+    /// no range knows its buffer sizes.
+    pub fn buffer_size_range(&self) -> SupportedBufferSize {
+        self.ranges
+            .iter()
+            .fold(SupportedBufferSize::Unknown, |acc, range| {
+                match (acc, &range.buffer_size) {
+                    (acc, SupportedBufferSize::Unknown) => acc,
+                    (SupportedBufferSize::Unknown, buffer_size) => buffer_size.clone(),
+                    (
+                        SupportedBufferSize::Range { min, max },
+                        &SupportedBufferSize::Range {
+                            min: range_min,
+                            max: range_max,
+                        },
+                    ) => SupportedBufferSize::Range {
+                        min: min.min(range_min),
+                        max: max.max(range_max),
+                    },
+                }
+            })
+    }
+
+    /// Whether a range supports `config` with `sample_format`. A fixed buffer size must lie
+    /// within the range's buffer sizes, unless those are unknown.
+    pub fn contains(&self, config: &StreamConfig, sample_format: SampleFormat) -> bool {
+        self.ranges.iter().any(|range| {
+            let buffer_size_supported = match (&config.buffer_size, &range.buffer_size) {
+                (BufferSize::Fixed(frames), SupportedBufferSize::Range { min, max }) => {
+                    min <= frames && frames <= max
+                }
+                _ => true,
+            };
+            range.channels == config.channels
+                && range.sample_format == sample_format
+                && range.min_sample_rate <= config.sample_rate
+                && config.sample_rate <= range.max_sample_rate
+                && buffer_size_supported
+        })
+    }
+}
+
+impl FromIterator<SupportedStreamConfigRange> for SupportedConfigSet {
+    fn from_iter<I: IntoIterator<Item = SupportedStreamConfigRange>>(iter: I) -> Self {
+        SupportedConfigSet::new(iter)
+    }
+}
+
+// The order of sample formats within a `SupportedConfigSet`.
+fn format_order(format: SampleFormat) -> u8 {
+    match format {
+        SampleFormat::I16 => 0,
+        SampleFormat::U16 => 1,
+        SampleFormat::I24 => 2,
+        SampleFormat::I24Packed => 3,
+        SampleFormat::F32 => 4,
+    }
+}
+
+#[test]
+fn test_supported_config_set_normalizes_ranges() {
+    let range = |channels, min, max, sample_format, buffer_size| SupportedStreamConfigRange {
+        channels,
+        min_sample_rate: SampleRate(min),
+        max_sample_rate: SampleRate(max),
+        buffer_size,
+        sample_format,
+    };
+    let unknown = SupportedBufferSize::Unknown;
+    let small = SupportedBufferSize::Range { min: 64, max: 512 };
+    let large = SupportedBufferSize::Range {
+        min: 256,
+        max: 4096,
+    };
+    let set = SupportedConfigSet::new(vec![
+        range(2, 44_100, 48_000, SampleFormat::F32, small.clone()),
+        range(1, 8_000, 8_000, SampleFormat::I16, unknown.clone()),
+        // A duplicate, an overlap and an adjoining range, which merge with the first.
+        range(2, 44_100, 48_000, SampleFormat::F32, small.clone()),
+        range(2, 32_000, 44_100, SampleFormat::F32, small.clone()),
+        range(2, 48_001, 96_000, SampleFormat::F32, small.clone()),
+        // Other buffer sizes and formats stay apart.
+        range(2, 44_100, 48_000, SampleFormat::F32, large.clone()),
+        range(2, 44_100, 48_000, SampleFormat::I16, small.clone()),
+        // Separated by a gap.
+        range(1, 16_000, 16_000, SampleFormat::I16, unknown.clone()),
+        // Empty.
+        range(6, 48_000, 44_100, SampleFormat::F32, small.clone()),
+    ]);
+    assert_eq!(
+        set.ranges(),
+        &[
+            range(1, 8_000, 8_000, SampleFormat::I16, unknown.clone()),
+            range(1, 16_000, 16_000, SampleFormat::I16, unknown),
+            range(2, 44_100, 48_000, SampleFormat::I16, small.clone()),
+            range(2, 32_000, 96_000, SampleFormat::F32, small),
+            range(2, 44_100, 48_000, SampleFormat::F32, large),
+        ][..]
+    );
+    assert_eq!(set.channel_counts(), [1, 2]);
+    assert_eq!(set.formats(), [SampleFormat::I16, SampleFormat::F32]);
+    assert_eq!(
+        set.sample_rates(),
+        [8_000, 16_000, 32_000, 44_100, 48_000, 64_000, 88_200, 96_000]
+            .iter()
+            .map(|&rate| SampleRate(rate))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        set.buffer_size_range(),
+        SupportedBufferSize::Range { min: 64, max: 4096 }
+    );
+
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(88_200),
+        buffer_size: BufferSize::Fixed(128),
+    };
+    assert!(set.contains(&config, SampleFormat::F32));
+    assert!(!set.contains(&config, SampleFormat::I16));
+    let config = StreamConfig {
+        buffer_size: BufferSize::Fixed(8192),
+        ..config
+    };
+    assert!(!set.contains(&config, SampleFormat::F32));
+}
+
+#[test]
+fn test_supported_config_set_empty() {
+    let set: SupportedConfigSet = std::iter::empty().collect();
+    assert!(set.is_empty());
+    assert!(set.sample_rates().is_empty());
+    assert_eq!(set.buffer_size_range(), SupportedBufferSize::Unknown);
+}
+
+/// Requests a fixed buffer size from the supported range: the size closest to
+/// `MIN_BUFFER_DURATION` at the config's sample rate. Fails if the host doesn't report the range.
+/// Use `SupportedStreamConfig::config` to leave the buffer size to the host instead.
+impl TryFrom<SupportedStreamConfig> for StreamConfig {
+    type Error = BufferSizeUnknown;
+
+    fn try_from(conf: SupportedStreamConfig) -> Result<Self, Self::Error> {
+        let (min, max) = match conf.buffer_size {
+            SupportedBufferSize::Range { min, max } => (min, max),
+            SupportedBufferSize::Unknown => return Err(BufferSizeUnknown),
+        };
+        let floor = conf.sample_rate.frames_in_rounded(MIN_BUFFER_DURATION);
+        let frames = floor.clamp(u64::from(min), u64::from(max)) as FrameCount;
+        Ok(StreamConfig {
+            buffer_size: BufferSize::Fixed(frames),
+            ..conf.config()
+        })
     }
 }
 
@@ -734,8 +1638,7 @@ impl From<SupportedStreamConfig> for StreamConfig {
 // of commonly used rates. This is always the case for wasapi and is sometimes the case for alsa.
 //
 // If a rate you desire is missing from this list, feel free to add it!
-#[cfg(target_os = "windows")]
-const COMMON_SAMPLE_RATES: &'static [SampleRate] = &[
+const COMMON_SAMPLE_RATES: &[SampleRate] = &[
     SampleRate(5512),
     SampleRate(8000),
     SampleRate(11025),
@@ -751,6 +1654,51 @@ const COMMON_SAMPLE_RATES: &'static [SampleRate] = &[
     SampleRate(192000),
 ];
 
+#[test]
+fn test_supported_stream_config_buffer_size() {
+    let supported = SupportedStreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(48000),
+        buffer_size: SupportedBufferSize::Range { min: 64, max: 4096 },
+        sample_format: SampleFormat::F32,
+    };
+    assert_eq!(supported.config().buffer_size, BufferSize::Default);
+
+    // 10 ms at 48 kHz.
+    let converted = StreamConfig::try_from(supported.clone()).unwrap();
+    assert_eq!(converted.buffer_size, BufferSize::Fixed(480));
+
+    let fixed: StreamConfig = supported.clone().with_buffer_size(256).try_into().unwrap();
+    assert_eq!(fixed.buffer_size, BufferSize::Fixed(256));
+    assert_eq!(
+        supported.clone().with_buffer_size(256).config().buffer_size,
+        BufferSize::Fixed(256)
+    );
+
+    let unknown = SupportedStreamConfig {
+        buffer_size: SupportedBufferSize::Unknown,
+        ..supported
+    };
+    assert_eq!(unknown.config().buffer_size, BufferSize::Default);
+    assert!(StreamConfig::try_from(unknown.clone()).is_err());
+    assert_eq!(
+        unknown.with_buffer_size(512).config().buffer_size,
+        BufferSize::Fixed(512)
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_supported_stream_config_buffer_size_out_of_range() {
+    let supported = SupportedStreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(48000),
+        buffer_size: SupportedBufferSize::Range { min: 64, max: 4096 },
+        sample_format: SampleFormat::F32,
+    };
+    supported.with_buffer_size(32);
+}
+
 #[test]
 fn test_stream_instant() {
     let a = StreamInstant::new(2, 0);
@@ -784,3 +1732,164 @@ fn test_stream_instant() {
     );
     assert_eq!(max.add(Duration::from_secs(1)), None);
 }
+
+#[test]
+fn test_stream_instant_sub_second_before_origin() {
+    let origin = StreamInstant::new(0, 0);
+    let before = origin.sub(Duration::from_millis(1)).unwrap();
+    assert_eq!(before, StreamInstant::new(-1, 999_000_000));
+    assert!(before < origin);
+    assert_eq!(
+        origin.checked_duration_since(&before),
+        Some(Duration::from_millis(1))
+    );
+    assert_eq!(before.checked_duration_since(&origin), None);
+    assert_eq!(
+        StreamInstant::from_nanos(-1),
+        StreamInstant::new(-1, 999_999_999)
+    );
+}
+
+#[test]
+fn test_stream_instant_long_running() {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    let start = StreamInstant::new(1_234, 567);
+    let later = start.add(DAY * 3_650).unwrap();
+    assert_eq!(later.checked_duration_since(&start), Some(DAY * 3_650));
+    // Nanosecond steps still register after years.
+    let next = later.add(Duration::from_nanos(1)).unwrap();
+    assert_eq!(
+        next.checked_duration_since(&later),
+        Some(Duration::from_nanos(1))
+    );
+    assert_eq!(start.checked_duration_since(&later), None);
+}
+
+#[test]
+fn test_diagnostics_report() {
+    let host = default_host();
+    let report = host.diagnostics().to_string();
+    assert!(report.starts_with(&format!("cpal {}", env!("CARGO_PKG_VERSION"))));
+    assert!(report.contains(&format!("host: {}", host.id().name())));
+    assert!(report.contains("realtime threads: "));
+
+    let device = DeviceDiagnostics {
+        name: Some("Speakers".to_string()),
+        driver: Some("snd_hda_intel".to_string()),
+        ..DeviceDiagnostics::default()
+    };
+    assert_eq!(
+        device.to_string(),
+        "device: Speakers\ndriver: snd_hda_intel"
+    );
+}
+
+#[test]
+fn test_raw_os_error() {
+    let err = BackendSpecificError {
+        description: "device or resource busy".to_string(),
+        code: Some(16),
+    };
+    assert_eq!(BuildStreamError::from(err.clone()).raw_os_error(), Some(16));
+    assert_eq!(DevicesError::from(err).raw_os_error(), Some(16));
+    assert_eq!(BuildStreamError::DeviceNotAvailable.raw_os_error(), None);
+}
+
+#[test]
+fn test_default_device_event_map() {
+    let DefaultDeviceEvent::DefaultChanged { device } =
+        DefaultDeviceEvent::DefaultChanged { device: Some(2) }.map(|id| id * 10);
+    assert_eq!(device, Some(20));
+    let DefaultDeviceEvent::DefaultChanged { device } =
+        DefaultDeviceEvent::<u32>::DefaultChanged { device: None }.map(|id| id * 10);
+    assert_eq!(device, None);
+}
+
+#[test]
+fn test_device_id_round_trips_through_its_string_form() {
+    let id = DeviceId::new("hw:CARD=PCH,DEV=0");
+    assert_eq!(id.to_string().parse::<DeviceId>(), Ok(id));
+}
+
+#[test]
+fn test_device_event_map() {
+    let DeviceEvent::Added { device } = DeviceEvent::Added { device: 2 }.map(|id| id * 10) else {
+        panic!("the event changed kind");
+    };
+    assert_eq!(device, 20);
+    let DeviceEvent::Removed { id } = DeviceEvent::<u32>::Removed {
+        id: DeviceId::new("usb"),
+    }
+    .map(|id| id * 10) else {
+        panic!("the event changed kind");
+    };
+    assert_eq!(id, DeviceId::new("usb"));
+    let DeviceEvent::VolumeChanged { device, volume } = DeviceEvent::VolumeChanged {
+        device: 3,
+        volume: 0.5,
+    }
+    .map(|id| id * 10) else {
+        panic!("the event changed kind");
+    };
+    assert_eq!((device, volume), (30, 0.5));
+}
+
+#[test]
+fn test_sample_rate_cadence() {
+    let rate = SampleRate(44_100);
+    assert_eq!(rate.samples_per_ms(), 44.1);
+    assert_eq!(rate.samples_per_ms_rounded(), 44);
+    assert_eq!(SampleRate(22_050).samples_per_ms_rounded(), 22);
+    assert_eq!(SampleRate(11_025).samples_per_ms_rounded(), 11);
+    assert_eq!(SampleRate(48_000).samples_per_ms_rounded(), 48);
+    assert_eq!(rate.frames_in(Duration::from_millis(10)), 441.0);
+    assert_eq!(rate.frames_in_rounded(Duration::from_micros(100)), 4);
+    assert_eq!(
+        rate.frames_in_rounded(Duration::from_secs(3_600)),
+        158_760_000
+    );
+}
+
+#[test]
+fn test_frames_per_callback_estimate() {
+    let mut config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(48_000),
+        buffer_size: BufferSize::Default,
+    };
+    assert_eq!(config.frames_per_callback_estimate(), None);
+    config.buffer_size = BufferSize::Fixed(256);
+    assert_eq!(config.frames_per_callback_estimate(), Some(256));
+}
+
+#[test]
+fn test_supported_buffer_size_bounds() {
+    let range = SupportedBufferSize::Range { min: 256, max: 512 };
+    assert!(range.check(&BufferSize::Fixed(256)).is_ok());
+    assert!(range.check(&BufferSize::Default).is_ok());
+    assert!(matches!(
+        range.check(&BufferSize::Fixed(128)),
+        Err(BuildStreamError::BufferSizeTooSmall {
+            requested: 128,
+            min: 256
+        })
+    ));
+    assert!(matches!(
+        range.check(&BufferSize::Fixed(1024)),
+        Err(BuildStreamError::BufferSizeTooLarge {
+            requested: 1024,
+            max: 512
+        })
+    ));
+    assert!(SupportedBufferSize::Unknown
+        .check(&BufferSize::Fixed(1))
+        .is_ok());
+
+    assert_eq!(range.clamp(&BufferSize::Fixed(128)), BufferSize::Fixed(256));
+    assert_eq!(
+        range.clamp(&BufferSize::Fixed(1024)),
+        BufferSize::Fixed(512)
+    );
+    assert_eq!(range.clamp(&BufferSize::Fixed(300)), BufferSize::Fixed(300));
+    assert_eq!(range.clamp(&BufferSize::Default), BufferSize::Default);
+}
@@ -0,0 +1,274 @@
+//! A host, device and stream without any backend, for testing the stream machinery shared by all
+//! hosts.
+
+use crate::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crate::{
+    BufferSize, BuildStreamError, ChannelCount, ChannelMix, Data, DataCallback,
+    DefaultStreamConfigError, DeviceNameError, DevicesError, ErrorCallback, FrameCount,
+    InputCallbackInfo, InputStreamTimestamp, OutputCallbackInfo, OutputStreamTimestamp,
+    PauseStreamError, PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError,
+    StreamInstant, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
+};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// A host without devices, for calling the provided methods of `HostTrait` on `MockDevice`s.
+pub(crate) struct MockHost;
+
+// A device that supports streams of up to two channels in either direction. Its default output
+// config is stereo `F32` at 48kHz; it has no default input config.
+#[derive(Default)]
+pub(crate) struct MockDevice {
+    // Makes `play` report `StreamError::DeviceNotAvailable` instead of calling the data callback.
+    pub(crate) fails: bool,
+    // Makes building a stream fail while another stream of the device is alive.
+    pub(crate) exclusive: bool,
+    // The number of times `play` calls the data callback of output streams.
+    pub(crate) calls_on_play: usize,
+    // The buffer sizes of the default output config, `SupportedBufferSize::Unknown` if `None`.
+    pub(crate) buffer_size: Option<SupportedBufferSize>,
+    // Makes building a stream with a fixed buffer size of fewer frames fail.
+    pub(crate) min_frames: FrameCount,
+    // The number of streams built.
+    pub(crate) builds: Cell<usize>,
+    // The number of streams that are alive.
+    pub(crate) alive: Arc<AtomicUsize>,
+    // The samples rendered by the last call to the data callback of an output stream.
+    pub(crate) rendered: Arc<Mutex<Vec<f32>>>,
+}
+
+pub(crate) struct MockStream {
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    data_callback: Mutex<DataCallback>,
+    error_callback: Mutex<Box<ErrorCallback>>,
+    pub(crate) playing: Cell<bool>,
+    fails: bool,
+    calls_on_play: usize,
+    alive: Arc<AtomicUsize>,
+    rendered: Arc<Mutex<Vec<f32>>>,
+}
+
+impl MockDevice {
+    fn build(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: DataCallback,
+        error_callback: Box<ErrorCallback>,
+    ) -> Result<MockStream, BuildStreamError> {
+        if config.channels > 2 {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        if let BufferSize::Fixed(frames) = config.buffer_size {
+            if frames < self.min_frames {
+                return Err(BuildStreamError::StreamConfigNotSupported);
+            }
+        }
+        if self.exclusive && self.alive.load(Ordering::SeqCst) > 0 {
+            return Err(BuildStreamError::DeviceNotAvailable);
+        }
+        self.builds.set(self.builds.get() + 1);
+        self.alive.fetch_add(1, Ordering::SeqCst);
+        Ok(MockStream {
+            config: config.clone(),
+            sample_format,
+            data_callback: Mutex::new(data_callback),
+            error_callback: Mutex::new(error_callback),
+            playing: Cell::new(false),
+            fails: self.fails,
+            calls_on_play: self.calls_on_play,
+            alive: self.alive.clone(),
+            rendered: self.rendered.clone(),
+        })
+    }
+}
+
+impl MockStream {
+    // Calls the data callback of an output stream with two frames of `F32` samples, initialised
+    // to NaN, and returns the samples it rendered.
+    pub(crate) fn render(&self) -> Vec<f32> {
+        let mut samples = vec![f32::NAN; 2 * self.config.channels as usize];
+        let mut data = unsafe {
+            Data::from_parts(
+                samples.as_mut_ptr() as *mut (),
+                samples.len(),
+                SampleFormat::F32,
+            )
+        };
+        match *self.data_callback.lock().unwrap() {
+            DataCallback::Output(ref mut data_callback) => data_callback(&mut data, &output_info()),
+            DataCallback::Input(_) => panic!("rendering an input stream"),
+        }
+        self.rendered.lock().unwrap().clone_from(&samples);
+        samples
+    }
+}
+
+// The info passed to the data callback of an output stream, with both timestamps at `instant`.
+pub(crate) fn output_info_at(instant: StreamInstant) -> OutputCallbackInfo {
+    OutputCallbackInfo {
+        timestamp: OutputStreamTimestamp {
+            callback: instant,
+            playback: instant,
+        },
+        device_timestamp: None,
+    }
+}
+
+// The info passed to the data callback of an output stream, with zero timestamps.
+pub(crate) fn output_info() -> OutputCallbackInfo {
+    output_info_at(StreamInstant::new(0, 0))
+}
+
+// The info passed to the data callback of an input stream, with zero timestamps.
+pub(crate) fn input_info() -> InputCallbackInfo {
+    let instant = StreamInstant::new(0, 0);
+    InputCallbackInfo {
+        timestamp: InputStreamTimestamp {
+            callback: instant,
+            capture: instant,
+        },
+        device_timestamp: None,
+        channel_mix: ChannelMix::None,
+    }
+}
+
+impl HostTrait for MockHost {
+    type Devices = std::vec::IntoIter<MockDevice>;
+    type Device = MockDevice;
+
+    fn is_available() -> bool {
+        true
+    }
+
+    fn devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(vec![].into_iter())
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        None
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        None
+    }
+}
+
+impl DeviceTrait for MockDevice {
+    type SupportedInputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    type Stream = MockStream;
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok("mock".to_string())
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Self::SupportedInputConfigs, SupportedStreamConfigsError> {
+        Ok(vec![].into_iter())
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(vec![].into_iter())
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Err(DefaultStreamConfigError::StreamTypeNotSupported)
+    }
+
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(SupportedStreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            buffer_size: self
+                .buffer_size
+                .clone()
+                .unwrap_or(SupportedBufferSize::Unknown),
+            sample_format: SampleFormat::F32,
+        })
+    }
+
+    fn build_input_stream_raw<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        self.build(
+            config,
+            sample_format,
+            DataCallback::Input(Box::new(data_callback)),
+            Box::new(error_callback),
+        )
+    }
+
+    fn build_output_stream_raw<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        self.build(
+            config,
+            sample_format,
+            DataCallback::Output(Box::new(data_callback)),
+            Box::new(error_callback),
+        )
+    }
+}
+
+impl StreamTrait for MockStream {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        if self.fails {
+            (self.error_callback.lock().unwrap())(StreamError::DeviceNotAvailable);
+            return Ok(());
+        }
+        self.playing.set(true);
+        let output = matches!(*self.data_callback.lock().unwrap(), DataCallback::Output(_));
+        if output {
+            for _ in 0..self.calls_on_play {
+                self.render();
+            }
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        self.playing.set(false);
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.config.channels
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.alive.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,297 @@
+//! Running stream callbacks without a device, for tests and offline rendering.
+//!
+//! The functions in this module call a data callback exactly as a stream would, in blocks of
+//! `config.buffer_size` frames (or `DEFAULT_BLOCK_FRAMES` with `BufferSize::Default`), but as fast
+//! as possible and on the calling thread. Timestamps follow a simulated clock that starts at zero
+//! and advances by exactly one block per call, with one block of latency between the callback and
+//! the device. The results are therefore fully deterministic.
+//!
+//! ```
+//! use cpal::{BufferSize, SampleRate, StreamConfig};
+//!
+//! let config = StreamConfig {
+//!     channels: 2,
+//!     sample_rate: SampleRate(48_000),
+//!     buffer_size: BufferSize::Fixed(128),
+//! };
+//! let output = cpal::offline::render(&config, 1_000, |data, _info| {
+//!     for sample in data.iter_mut() {
+//!         *sample = 0.5;
+//!     }
+//! });
+//! assert_eq!(output.len(), 2_000);
+//! ```
+
+use crate::{
+    BufferSize, ChannelMix, DeviceTimestamp, InputCallbackInfo, InputStreamTimestamp,
+    OutputCallbackInfo, OutputStreamTimestamp, SampleRate, StreamConfig, StreamInstant,
+};
+
+/// The number of frames per callback when the config uses `BufferSize::Default`.
+pub const DEFAULT_BLOCK_FRAMES: u32 = 512;
+
+/// Calls `callback` until it has produced `frames` frames of interleaved output, and returns them.
+///
+/// Each buffer passed to `callback` is filled with silence beforehand. The last buffer is shorter
+/// than the others if `frames` is not a multiple of the block size.
+///
+/// # Panics
+///
+/// Panics if the config has no channels, a sample rate of zero or a fixed buffer size of zero.
+pub fn render<F>(config: &StreamConfig, frames: usize, mut callback: F) -> Vec<f32>
+where
+    F: FnMut(&mut [f32], &OutputCallbackInfo),
+{
+    let channels = config.channels as usize;
+    let block_frames = block_frames(config);
+    let mut output = vec![0.0; frames * channels];
+    for (index, block) in output.chunks_mut(block_frames * channels).enumerate() {
+        let position = index * block_frames;
+        let timestamp = OutputStreamTimestamp {
+            callback: instant(position, config.sample_rate),
+            playback: instant(position + block_frames, config.sample_rate),
+        };
+        let device_timestamp = Some(DeviceTimestamp {
+            samples: (position + block_frames) as u64,
+            sample_rate: config.sample_rate,
+        });
+        callback(
+            block,
+            &OutputCallbackInfo {
+                timestamp,
+                device_timestamp,
+            },
+        );
+    }
+    output
+}
+
+/// Feeds `input`, interleaved samples as if captured from a device, to `callback`.
+///
+/// The last buffer is shorter than the others if the number of frames in `input` is not a multiple
+/// of the block size.
+///
+/// # Panics
+///
+/// Panics if the length of `input` is not a multiple of the number of channels, or under the same
+/// conditions as [`render`].
+pub fn capture<F>(config: &StreamConfig, input: &[f32], mut callback: F)
+where
+    F: FnMut(&[f32], &InputCallbackInfo),
+{
+    let channels = config.channels as usize;
+    let block_frames = block_frames(config);
+    assert_eq!(
+        input.len() % channels,
+        0,
+        "the input must consist of whole frames of {} channels",
+        channels
+    );
+    for (index, block) in input.chunks(block_frames * channels).enumerate() {
+        let position = index * block_frames;
+        let timestamp = InputStreamTimestamp {
+            callback: instant(position + block_frames, config.sample_rate),
+            capture: instant(position, config.sample_rate),
+        };
+        let device_timestamp = Some(DeviceTimestamp {
+            samples: position as u64,
+            sample_rate: config.sample_rate,
+        });
+        callback(
+            block,
+            &InputCallbackInfo {
+                timestamp,
+                device_timestamp,
+                channel_mix: ChannelMix::None,
+            },
+        );
+    }
+}
+
+fn block_frames(config: &StreamConfig) -> usize {
+    assert!(
+        config.channels > 0,
+        "the config must have at least one channel"
+    );
+    assert!(config.sample_rate.0 > 0, "the sample rate must not be zero");
+    match config.buffer_size {
+        BufferSize::Fixed(0) => panic!("the buffer size must not be zero"),
+        BufferSize::Fixed(frames) => frames as usize,
+        BufferSize::Default => DEFAULT_BLOCK_FRAMES as usize,
+    }
+}
+
+// The instant of the frame at `position` on the simulated clock.
+fn instant(position: usize, sample_rate: SampleRate) -> StreamInstant {
+    let nanos = position as i128 * 1_000_000_000 / sample_rate.0 as i128;
+    StreamInstant::from_nanos_i128(nanos).expect("simulated stream time out of range")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{capture, render, DEFAULT_BLOCK_FRAMES};
+    use crate::{BufferSize, SampleRate, StreamConfig, StreamInstant};
+    use std::time::Duration;
+
+    fn config(buffer_size: BufferSize) -> StreamConfig {
+        StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(1_000),
+            buffer_size,
+        }
+    }
+
+    #[test]
+    fn test_render_blocks_and_timestamps() {
+        let mut calls = Vec::new();
+        let output = render(&config(BufferSize::Fixed(4)), 10, |data, info| {
+            let frames = data.len() / 2;
+            for (i, sample) in data.iter_mut().enumerate() {
+                *sample = (calls.len() * 10 + i) as f32;
+            }
+            calls.push((frames, info.timestamp()));
+        });
+        assert_eq!(output.len(), 20);
+        assert_eq!(output[8], 10.0);
+        let frames: Vec<_> = calls.iter().map(|&(frames, _)| frames).collect();
+        assert_eq!(frames, [4, 4, 2]);
+        let first = calls[0].1;
+        let third = calls[2].1;
+        assert_eq!(
+            third.callback.duration_since(&first.callback),
+            Some(Duration::from_millis(8))
+        );
+        assert_eq!(
+            first.playback.duration_since(&first.callback),
+            Some(Duration::from_millis(4))
+        );
+    }
+
+    #[test]
+    fn test_render_default_block_size() {
+        let mut calls = 0;
+        let output = render(
+            &config(BufferSize::Default),
+            DEFAULT_BLOCK_FRAMES as usize * 3,
+            |data, _| {
+                assert!(data.iter().all(|&s| s == 0.0));
+                calls += 1;
+            },
+        );
+        assert_eq!(calls, 3);
+        assert!(output.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_capture_feeds_whole_input() {
+        let input: Vec<f32> = (0..14).map(|s| s as f32).collect();
+        let mut captured = Vec::new();
+        let mut last_capture = None;
+        capture(&config(BufferSize::Fixed(3)), &input, |data, info| {
+            let timestamp = info.timestamp();
+            assert!(timestamp.capture < timestamp.callback);
+            if let Some(previous) = last_capture {
+                assert_eq!(
+                    timestamp.capture.duration_since(&previous),
+                    Some(Duration::from_millis(3))
+                );
+            }
+            last_capture = Some(timestamp.capture);
+            captured.extend_from_slice(data);
+        });
+        assert_eq!(captured, input);
+    }
+
+    #[test]
+    fn test_device_timestamps_match_instants() {
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(44_100),
+            buffer_size: BufferSize::Fixed(441),
+        };
+        let origin = StreamInstant::new(0, 0);
+        render(&config, 44_100 * 3, |_, info| {
+            let device = info.timestamp_device().unwrap();
+            assert_eq!(device.sample_rate, config.sample_rate);
+            assert_eq!(
+                origin.add(device.duration()),
+                Some(info.timestamp().playback)
+            );
+        });
+        let input = vec![0.0; 44_100 * 3];
+        capture(&config, &input, |_, info| {
+            let device = info.timestamp_device().unwrap();
+            assert_eq!(
+                origin.add(device.duration()),
+                Some(info.timestamp().capture)
+            );
+        });
+    }
+
+    #[test]
+    fn test_high_channel_counts() {
+        for &channels in &[32, 64] {
+            let config = StreamConfig {
+                channels,
+                sample_rate: SampleRate(48_000),
+                buffer_size: BufferSize::Fixed(5),
+            };
+            let channels = channels as usize;
+            let mut frame = 0;
+            let mut frames = Vec::new();
+            let output = render(&config, 12, |data, _| {
+                assert_eq!(data.len() % channels, 0);
+                for samples in data.chunks_mut(channels) {
+                    for (channel, sample) in samples.iter_mut().enumerate() {
+                        *sample = (frame * channels + channel) as f32;
+                    }
+                    frame += 1;
+                }
+                frames.push(data.len() / channels);
+            });
+            assert_eq!(frames, [5, 5, 2]);
+            assert_eq!(output.len(), 12 * channels);
+            assert!(output.iter().enumerate().all(|(i, &s)| s == i as f32));
+
+            let mut frames = Vec::new();
+            capture(&config, &output, |data, _| {
+                assert_eq!(data.len() % channels, 0);
+                frames.push(data.len() / channels);
+            });
+            assert_eq!(frames, [5, 5, 2]);
+        }
+    }
+
+    #[test]
+    fn test_timestamps_over_days() {
+        // One frame per second keeps a multi-day run cheap to simulate.
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(1),
+            buffer_size: BufferSize::Fixed(3_600),
+        };
+        let day = Duration::from_secs(24 * 60 * 60);
+        let origin = StreamInstant::new(0, 0);
+        let mut last = None;
+        render(&config, 3 * 24 * 3_600, |_, info| {
+            let timestamp = info.timestamp();
+            if let Some(previous) = last {
+                assert_eq!(
+                    timestamp.callback.checked_duration_since(&previous),
+                    Some(Duration::from_secs(3_600))
+                );
+            }
+            last = Some(timestamp.callback);
+        });
+        assert_eq!(
+            last.unwrap().checked_duration_since(&origin),
+            Some(day * 3 - Duration::from_secs(3_600))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_capture_rejects_partial_frames() {
+        capture(&config(BufferSize::Default), &[0.0; 3], |_, _| {});
+    }
+}
@@ -191,6 +191,22 @@ macro_rules! impl_platform_host {
                 }
             }
 
+            /// Information about this host and the build of CPAL, for bug reports.
+            ///
+            /// This probes whether realtime threads are permitted by promoting a short-lived
+            /// thread, so it shouldn't be called from a data callback.
+            pub fn diagnostics(&self) -> crate::HostDiagnostics {
+                use crate::traits::HostTrait;
+                crate::HostDiagnostics {
+                    cpal_version: env!("CARGO_PKG_VERSION"),
+                    host: self.id(),
+                    backend_version: self.backend_version(),
+                    compiled_hosts: ALL_HOSTS.to_vec(),
+                    realtime_threads: crate::rt::is_thread_realtime_available(),
+                    capabilities: self.capabilities(),
+                }
+            }
+
             /// Returns a reference to the underlying platform specific implementation of this
             /// `Host`.
             pub fn as_inner(&self) -> &HostInner {
@@ -310,6 +326,24 @@ macro_rules! impl_platform_host {
                 }
             }
 
+            fn id(&self) -> Result<crate::DeviceId, crate::DeviceNameError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.id(),
+                    )*
+                }
+            }
+
+            fn diagnostics(&self) -> crate::DeviceDiagnostics {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.diagnostics(),
+                    )*
+                }
+            }
+
             fn supported_input_configs(&self) -> Result<Self::SupportedInputConfigs, crate::SupportedStreamConfigsError> {
                 match self.0 {
                     $(
@@ -336,6 +370,36 @@ macro_rules! impl_platform_host {
                 }
             }
 
+            fn supported_input_configs_incremental<F>(
+                &self,
+                callback: F,
+            ) -> Result<(), crate::SupportedStreamConfigsError>
+            where
+                F: FnMut(crate::SupportedStreamConfigRange) -> std::ops::ControlFlow<()> + Send,
+            {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.supported_input_configs_incremental(callback),
+                    )*
+                }
+            }
+
+            fn supported_output_configs_incremental<F>(
+                &self,
+                callback: F,
+            ) -> Result<(), crate::SupportedStreamConfigsError>
+            where
+                F: FnMut(crate::SupportedStreamConfigRange) -> std::ops::ControlFlow<()> + Send,
+            {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.supported_output_configs_incremental(callback),
+                    )*
+                }
+            }
+
             fn default_input_config(&self) -> Result<crate::SupportedStreamConfig, crate::DefaultStreamConfigError> {
                 match self.0 {
                     $(
@@ -354,6 +418,96 @@ macro_rules! impl_platform_host {
                 }
             }
 
+            fn physical_channels(
+                &self,
+                direction: crate::StreamDirection,
+            ) -> Option<crate::ChannelCount> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.physical_channels(direction),
+                    )*
+                }
+            }
+
+            fn native_sample_format(
+                &self,
+                direction: crate::StreamDirection,
+            ) -> Option<crate::SampleFormat> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.native_sample_format(direction),
+                    )*
+                }
+            }
+
+            fn bluetooth_profile(&self) -> Option<crate::BtProfile> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.bluetooth_profile(),
+                    )*
+                }
+            }
+
+            fn is_virtual(&self) -> bool {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.is_virtual(),
+                    )*
+                }
+            }
+
+            fn is_loopback(&self) -> bool {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.is_loopback(),
+                    )*
+                }
+            }
+
+            fn volume(&self) -> Option<f32> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.volume(),
+                    )*
+                }
+            }
+
+            fn input_sensitivity(&self) -> Option<f32> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.input_sensitivity(),
+                    )*
+                }
+            }
+
+            fn set_volume(&self, volume: f32) -> Result<(), crate::DeviceVolumeError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.set_volume(volume),
+                    )*
+                }
+            }
+
+            fn set_sample_rate(
+                &self,
+                rate: crate::SampleRate,
+            ) -> Result<crate::SampleRate, crate::DeviceSampleRateError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d.set_sample_rate(rate),
+                    )*
+                }
+            }
+
             fn build_input_stream_raw<D, E>(
                 &self,
                 config: &crate::StreamConfig,
@@ -365,7 +519,8 @@ macro_rules! impl_platform_host {
                 D: FnMut(&crate::Data, &crate::InputCallbackInfo) + Send + 'static,
                 E: FnMut(crate::StreamError) + Send + 'static,
             {
-                match self.0 {
+                let _span = debug_span!("build_input_stream", ?config, ?sample_format);
+                let result = match self.0 {
                     $(
                         $(#[cfg($feat)])?
                         DeviceInner::$HostVariant(ref d) => d
@@ -378,7 +533,9 @@ macro_rules! impl_platform_host {
                             .map(StreamInner::$HostVariant)
                             .map(Stream::from),
                     )*
-                }
+                };
+                crate::trace::stream_built(&result);
+                result
             }
 
             fn build_output_stream_raw<D, E>(
@@ -392,7 +549,8 @@ macro_rules! impl_platform_host {
                 D: FnMut(&mut crate::Data, &crate::OutputCallbackInfo) + Send + 'static,
                 E: FnMut(crate::StreamError) + Send + 'static,
             {
-                match self.0 {
+                let _span = debug_span!("build_output_stream", ?config, ?sample_format);
+                let result = match self.0 {
                     $(
                         $(#[cfg($feat)])?
                         DeviceInner::$HostVariant(ref d) => d
@@ -405,7 +563,99 @@ macro_rules! impl_platform_host {
                             .map(StreamInner::$HostVariant)
                             .map(Stream::from),
                     )*
-                }
+                };
+                crate::trace::stream_built(&result);
+                result
+            }
+
+            fn build_input_stream_raw_with_options<D, E>(
+                &self,
+                config: &crate::StreamConfig,
+                sample_format: crate::SampleFormat,
+                data_callback: D,
+                error_callback: E,
+                options: &crate::StreamOptions,
+            ) -> Result<Self::Stream, crate::BuildStreamError>
+            where
+                D: FnMut(&crate::Data, &crate::InputCallbackInfo) + Send + 'static,
+                E: FnMut(crate::StreamError) + Send + 'static,
+            {
+                let _span = debug_span!("build_input_stream", ?config, ?sample_format, ?options);
+                let result = match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d
+                            .build_input_stream_raw_with_options(
+                                config,
+                                sample_format,
+                                data_callback,
+                                error_callback,
+                                options,
+                            )
+                            .map(StreamInner::$HostVariant)
+                            .map(Stream::from),
+                    )*
+                };
+                crate::trace::stream_built(&result);
+                result
+            }
+
+            fn build_output_stream_raw_with_options<D, E>(
+                &self,
+                config: &crate::StreamConfig,
+                sample_format: crate::SampleFormat,
+                data_callback: D,
+                error_callback: E,
+                options: &crate::StreamOptions,
+            ) -> Result<Self::Stream, crate::BuildStreamError>
+            where
+                D: FnMut(&mut crate::Data, &crate::OutputCallbackInfo) + Send + 'static,
+                E: FnMut(crate::StreamError) + Send + 'static,
+            {
+                let _span = debug_span!("build_output_stream", ?config, ?sample_format, ?options);
+                let result = match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d
+                            .build_output_stream_raw_with_options(
+                                config,
+                                sample_format,
+                                data_callback,
+                                error_callback,
+                                options,
+                            )
+                            .map(StreamInner::$HostVariant)
+                            .map(Stream::from),
+                    )*
+                };
+                crate::trace::stream_built(&result);
+                result
+            }
+
+            fn build_output_stream_low_latency<D, E>(
+                &self,
+                data_callback: D,
+                error_callback: E,
+            ) -> Result<(Self::Stream, crate::StreamConfig), crate::BuildStreamError>
+            where
+                D: FnMut(&mut crate::Data, &crate::OutputCallbackInfo) + Send + 'static,
+                E: FnMut(crate::StreamError) + Send + 'static,
+            {
+                let _span = debug_span!("build_output_stream_low_latency");
+                let mut config = None;
+                let result = match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        DeviceInner::$HostVariant(ref d) => d
+                            .build_output_stream_low_latency(data_callback, error_callback)
+                            .map(|(stream, stream_config)| {
+                                config = Some(stream_config);
+                                Stream::from(StreamInner::$HostVariant(stream))
+                            }),
+                    )*
+                };
+                crate::trace::stream_built(&result);
+                result.map(|stream| (stream, config.expect("the stream was built with a config")))
             }
         }
 
@@ -422,16 +672,67 @@ macro_rules! impl_platform_host {
             }
 
             fn devices(&self) -> Result<Self::Devices, crate::DevicesError> {
+                let _span = debug_span!("devices", host = self.id().name());
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            h.devices()
+                                .map_err(|err| {
+                                    warn!(%err, "failed to enumerate devices");
+                                    err
+                                })
+                                .map(DevicesInner::$HostVariant)
+                                .map(Devices::from)
+                        }
+                    )*
+                }
+            }
+
+            fn device_names(
+                &self,
+            ) -> Result<impl Iterator<Item = (crate::DeviceId, String)>, crate::DevicesError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            let names: Box<dyn Iterator<Item = (crate::DeviceId, String)>> =
+                                Box::new(h.device_names()?);
+                            Ok(names)
+                        }
+                    )*
+                }
+            }
+
+            fn device_by_id(&self, id: &crate::DeviceId) -> Option<Self::Device> {
                 match self.0 {
                     $(
                         $(#[cfg($feat)])?
                         HostInner::$HostVariant(ref h) => {
-                            h.devices().map(DevicesInner::$HostVariant).map(Devices::from)
+                            h.device_by_id(id).map(DeviceInner::$HostVariant).map(Device::from)
                         }
                     )*
                 }
             }
 
+            fn backend_version(&self) -> Option<String> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => h.backend_version(),
+                    )*
+                }
+            }
+
+            fn capabilities(&self) -> Vec<(&'static str, bool)> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => h.capabilities(),
+                    )*
+                }
+            }
+
             fn default_input_device(&self) -> Option<Self::Device> {
                 match self.0 {
                     $(
@@ -453,30 +754,223 @@ macro_rules! impl_platform_host {
                     )*
                 }
             }
+
+            fn default_input_device_for_role(
+                &self,
+                role: crate::DeviceRole,
+            ) -> Option<Self::Device> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            h.default_input_device_for_role(role)
+                                .map(DeviceInner::$HostVariant)
+                                .map(Device::from)
+                        }
+                    )*
+                }
+            }
+
+            fn default_output_device_for_role(
+                &self,
+                role: crate::DeviceRole,
+            ) -> Option<Self::Device> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            h.default_output_device_for_role(role)
+                                .map(DeviceInner::$HostVariant)
+                                .map(Device::from)
+                        }
+                    )*
+                }
+            }
+
+            fn watch_default<F>(
+                &self,
+                direction: crate::StreamDirection,
+                mut callback: F,
+            ) -> Result<crate::DefaultWatcher, crate::WatchDefaultError>
+            where
+                F: FnMut(crate::DefaultDeviceEvent<Self::Device>) + Send + 'static,
+            {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            h.watch_default(direction, move |event| {
+                                callback(event.map(|device| {
+                                    Device::from(DeviceInner::$HostVariant(device))
+                                }))
+                            })
+                        }
+                    )*
+                }
+            }
+
+            fn watch_devices<F>(
+                &self,
+                mut callback: F,
+            ) -> Result<crate::DeviceWatcher, crate::WatchDevicesError>
+            where
+                F: FnMut(crate::DeviceEvent<Self::Device>) + Send + 'static,
+            {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => {
+                            h.watch_devices(move |event| {
+                                callback(event.map(|device| {
+                                    Device::from(DeviceInner::$HostVariant(device))
+                                }))
+                            })
+                        }
+                    )*
+                }
+            }
+
+            fn input_permission_status(&self) -> crate::PermissionStatus {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => h.input_permission_status(),
+                    )*
+                }
+            }
+
+            fn request_input_permission<F>(&self, callback: F)
+            where
+                F: FnOnce(crate::PermissionStatus) + Send + 'static,
+            {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        HostInner::$HostVariant(ref h) => h.request_input_permission(callback),
+                    )*
+                }
+            }
         }
 
         impl crate::traits::StreamTrait for Stream {
             fn play(&self) -> Result<(), crate::PlayStreamError> {
+                let _span = debug_span!("play");
                 match self.0 {
                     $(
                         $(#[cfg($feat)])?
                         StreamInner::$HostVariant(ref s) => {
-                            s.play()
+                            s.play().map_err(|err| {
+                                warn!(%err, "failed to play stream");
+                                err
+                            })
                         }
                     )*
                 }
             }
 
             fn pause(&self) -> Result<(), crate::PauseStreamError> {
+                let _span = debug_span!("pause");
                 match self.0 {
                     $(
                         $(#[cfg($feat)])?
                         StreamInner::$HostVariant(ref s) => {
-                            s.pause()
+                            s.pause().map_err(|err| {
+                                warn!(%err, "failed to pause stream");
+                                err
+                            })
                         }
                     )*
                 }
             }
+
+            fn sample_rate(&self) -> crate::SampleRate {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => {
+                            s.sample_rate()
+                        }
+                    )*
+                }
+            }
+
+            fn channels(&self) -> crate::ChannelCount {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => {
+                            s.channels()
+                        }
+                    )*
+                }
+            }
+
+            fn sample_format(&self) -> crate::SampleFormat {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => {
+                            s.sample_format()
+                        }
+                    )*
+                }
+            }
+
+            fn effective_bit_depth(&self) -> u8 {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => s.effective_bit_depth(),
+                    )*
+                }
+            }
+
+            fn next_block_frames(&self) -> Option<crate::FrameCount> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => s.next_block_frames(),
+                    )*
+                }
+            }
+
+            fn request_focus(
+                &self,
+                request: crate::FocusRequest,
+            ) -> Result<(), crate::RequestFocusError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => {
+                            s.request_focus(request)
+                        }
+                    )*
+                }
+            }
+
+            fn set_keep_alive(
+                &self,
+                keep_alive: crate::KeepAlive,
+            ) -> Result<(), crate::KeepAliveError> {
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(ref s) => {
+                            s.set_keep_alive(keep_alive)
+                        }
+                    )*
+                }
+            }
+
+            fn close(self, timeout: std::time::Duration) -> Result<(), crate::StreamError> {
+                let _span = debug_span!("close_stream", ?timeout);
+                match self.0 {
+                    $(
+                        $(#[cfg($feat)])?
+                        StreamInner::$HostVariant(s) => s.close(timeout),
+                    )*
+                }
+            }
         }
 
         impl From<DeviceInner> for Device {
@@ -547,11 +1041,16 @@ macro_rules! impl_platform_host {
 
         /// Given a unique host identifier, initialise and produce the host if it is available.
         pub fn host_from_id(id: HostId) -> Result<Host, crate::HostUnavailable> {
+            let _span = debug_span!("host_from_id", host = id.name());
             match id {
                 $(
                     $(#[cfg($feat)])?
                     HostId::$HostVariant => {
                         crate::host::$host_mod::Host::new()
+                            .map_err(|err| {
+                                warn!("host unavailable");
+                                err
+                            })
                             .map(HostInner::$HostVariant)
                             .map(Host::from)
                     }
@@ -565,8 +1064,9 @@ macro_rules! impl_platform_host {
 #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
 mod platform_impl {
     pub use crate::host::alsa::{
-        Device as AlsaDevice, Devices as AlsaDevices, Host as AlsaHost, Stream as AlsaStream,
-        SupportedInputConfigs as AlsaSupportedInputConfigs,
+        AlsaHostExt, CardInfo as AlsaCardInfo, Device as AlsaDevice, Devices as AlsaDevices,
+        Host as AlsaHost, PcmDirection as AlsaPcmDirection, PcmInfo as AlsaPcmInfo,
+        Stream as AlsaStream, SupportedInputConfigs as AlsaSupportedInputConfigs,
         SupportedOutputConfigs as AlsaSupportedOutputConfigs,
     };
     #[cfg(feature = "jack")]
@@ -580,6 +1080,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         AlsaHost::new()
             .expect("the default host should always be available")
             .into()
@@ -588,6 +1089,8 @@ mod platform_impl {
 
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 mod platform_impl {
+    #[cfg(target_os = "macos")]
+    pub use crate::host::coreaudio::DeviceExt as CoreAudioDeviceExt;
     pub use crate::host::coreaudio::{
         Device as CoreAudioDevice, Devices as CoreAudioDevices, Host as CoreAudioHost,
         Stream as CoreAudioStream, SupportedInputConfigs as CoreAudioSupportedInputConfigs,
@@ -598,6 +1101,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         CoreAudioHost::new()
             .expect("the default host should always be available")
             .into()
@@ -616,6 +1120,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         EmscriptenHost::new()
             .expect("the default host should always be available")
             .into()
@@ -634,6 +1139,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         WebAudioHost::new()
             .expect("the default host should always be available")
             .into()
@@ -644,20 +1150,22 @@ mod platform_impl {
 mod platform_impl {
     #[cfg(feature = "asio")]
     pub use crate::host::asio::{
-        Device as AsioDevice, Devices as AsioDevices, Host as AsioHost, Stream as AsioStream,
-        SupportedInputConfigs as AsioSupportedInputConfigs,
+        Device as AsioDevice, DeviceExt as AsioDeviceExt, Devices as AsioDevices, Host as AsioHost,
+        Stream as AsioStream, SupportedInputConfigs as AsioSupportedInputConfigs,
         SupportedOutputConfigs as AsioSupportedOutputConfigs,
     };
     pub use crate::host::wasapi::{
-        Device as WasapiDevice, Devices as WasapiDevices, Host as WasapiHost,
-        Stream as WasapiStream, SupportedInputConfigs as WasapiSupportedInputConfigs,
-        SupportedOutputConfigs as WasapiSupportedOutputConfigs,
+        Device as WasapiDevice, DeviceExt as WasapiDeviceExt, Devices as WasapiDevices,
+        Host as WasapiHost, SrcQuality as WasapiSrcQuality, Stream as WasapiStream,
+        SupportedInputConfigs as WasapiSupportedInputConfigs,
+        SupportedOutputConfigs as WasapiSupportedOutputConfigs, WasapiConfig,
     };
 
     impl_platform_host!(#[cfg(feature = "asio")] Asio asio "ASIO", Wasapi wasapi "WASAPI");
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         WasapiHost::new()
             .expect("the default host should always be available")
             .into()
@@ -676,6 +1184,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         OboeHost::new()
             .expect("the default host should always be available")
             .into()
@@ -704,6 +1213,7 @@ mod platform_impl {
 
     /// The default host for the current compilation target platform.
     pub fn default_host() -> Host {
+        let _span = debug_span!("default_host");
         NullHost::new()
             .expect("the default host should always be available")
             .into()
@@ -0,0 +1,440 @@
+//! Streams whose config can be changed while keeping their callbacks.
+//!
+//! Applications that follow the format of their content, such as media players, change the sample
+//! rate or buffer size of their stream often. A `ReconfigurableStream` keeps the device and the
+//! user's callbacks, so that only the stream is renegotiated.
+
+use crate::traits::{DeviceTrait, StreamTrait};
+use crate::{
+    BackendSpecificError, BuildStreamError, ChannelCount, Data, DataCallback, ErrorCallback,
+    FrameCount, InputCallbackInfo, KeepAlive, KeepAliveError, OutputCallbackInfo, PauseStreamError,
+    PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError, StreamOptions,
+};
+use std::cell::Cell;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// A stream that can be rebuilt with another config, keeping the device, the callbacks, the
+/// options and whether it is playing.
+///
+/// No host renegotiates the format of a running stream in place yet: `reconfigure` stops the
+/// stream and builds a new one on the same device. The callbacks are the same objects throughout,
+/// so that their state carries over, and are never called by two streams at once. Keep-alive is
+/// applied to each new stream; audio focus is not, and must be requested again.
+///
+/// Each stream owns the callbacks and hands them back when dropped, so that the audio thread never
+/// waits for them. A stream whose audio thread doesn't stop within the time `drop` waits for it
+/// keeps them until its callback returns, and `reconfigure` fails meanwhile.
+///
+/// ```no_run
+/// use cpal::reconfigurable::ReconfigurableStream;
+/// use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+/// use cpal::{SampleRate, StreamOptions};
+///
+/// let device = cpal::default_host().default_output_device().unwrap();
+/// let supported_config = device.default_output_config().unwrap();
+/// let sample_format = supported_config.sample_format();
+/// let mut config = supported_config.config();
+/// let mut stream = ReconfigurableStream::new_output(
+///     device,
+///     &config,
+///     sample_format,
+///     |data: &mut cpal::Data, _: &cpal::OutputCallbackInfo| {
+///         // Fill `data` according to `data.sample_format()`.
+///     },
+///     |err| eprintln!("an error occurred on the output stream: {}", err),
+///     &StreamOptions::default(),
+/// )
+/// .unwrap();
+/// stream.play().unwrap();
+///
+/// // The next track is at 44.1 kHz.
+/// config.sample_rate = SampleRate(44_100);
+/// stream.reconfigure(&config, sample_format).unwrap();
+/// ```
+pub struct ReconfigurableStream<D: DeviceTrait> {
+    device: D,
+    // The user's callbacks while no stream has them. The stream they are lent to sends them back
+    // through the channels when it drops them.
+    data_callback: Option<DataCallback>,
+    error_callback: Option<Box<ErrorCallback>>,
+    data_callback_returned: (Sender<DataCallback>, Receiver<DataCallback>),
+    error_callback_returned: (Sender<Box<ErrorCallback>>, Receiver<Box<ErrorCallback>>),
+    options: StreamOptions,
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    playing: Cell<bool>,
+    keep_alive: Cell<KeepAlive>,
+    // `None` only if rebuilding the stream with the previous config failed as well.
+    stream: Option<D::Stream>,
+}
+
+impl<D: DeviceTrait> ReconfigurableStream<D> {
+    /// Builds an input stream on `device`, as `DeviceTrait::build_input_stream_raw_with_options`.
+    pub fn new_input<F, E>(
+        device: D,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: F,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self, BuildStreamError>
+    where
+        F: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let data_callback = DataCallback::Input(Box::new(data_callback));
+        Self::new(
+            device,
+            config,
+            sample_format,
+            data_callback,
+            error_callback,
+            options,
+        )
+    }
+
+    /// Builds an output stream on `device`, as
+    /// `DeviceTrait::build_output_stream_raw_with_options`.
+    pub fn new_output<F, E>(
+        device: D,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: F,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self, BuildStreamError>
+    where
+        F: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let data_callback = DataCallback::Output(Box::new(data_callback));
+        Self::new(
+            device,
+            config,
+            sample_format,
+            data_callback,
+            error_callback,
+            options,
+        )
+    }
+
+    fn new<E>(
+        device: D,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: DataCallback,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self, BuildStreamError>
+    where
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let mut stream = ReconfigurableStream {
+            device,
+            data_callback: Some(data_callback),
+            error_callback: Some(Box::new(error_callback)),
+            data_callback_returned: mpsc::channel(),
+            error_callback_returned: mpsc::channel(),
+            options: options.clone(),
+            config: config.clone(),
+            sample_format,
+            playing: Cell::new(false),
+            keep_alive: Cell::new(KeepAlive::Off),
+            stream: None,
+        };
+        stream.stream = Some(stream.build(config, sample_format)?);
+        Ok(stream)
+    }
+
+    /// Replaces the stream with one built with `config` and `sample_format`, playing it if the
+    /// stream was playing.
+    ///
+    /// The old stream is dropped first, so that hosts with exclusive access to the device can open
+    /// it again. If the new stream can't be built, the stream is rebuilt with the previous config
+    /// and the error is returned.
+    pub fn reconfigure(
+        &mut self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+    ) -> Result<(), BuildStreamError> {
+        self.stream = None;
+        match self.build(config, sample_format) {
+            Ok(stream) => {
+                self.config = config.clone();
+                self.sample_format = sample_format;
+                self.resume(stream)
+            }
+            Err(err) => {
+                let config = self.config.clone();
+                if let Ok(stream) = self.build(&config, self.sample_format) {
+                    // The error that matters is the one of the requested config.
+                    let _ = self.resume(stream);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The device the stream is built on.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// The config the stream was last built with.
+    pub fn config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    /// The current stream, or `None` if it could not be rebuilt after a failed `reconfigure`.
+    pub fn stream(&self) -> Option<&D::Stream> {
+        self.stream.as_ref()
+    }
+
+    // Builds a stream with the callbacks, which a failed build sends back right away.
+    fn build(
+        &mut self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+    ) -> Result<D::Stream, BuildStreamError> {
+        if let Ok(data_callback) = self.data_callback_returned.1.try_recv() {
+            self.data_callback = Some(data_callback);
+        }
+        if let Ok(error_callback) = self.error_callback_returned.1.try_recv() {
+            self.error_callback = Some(error_callback);
+        }
+        let (data_callback, error_callback) =
+            match (self.data_callback.take(), self.error_callback.take()) {
+                (Some(data_callback), Some(error_callback)) => (data_callback, error_callback),
+                (data_callback, error_callback) => {
+                    self.data_callback = data_callback;
+                    self.error_callback = error_callback;
+                    let description = "the callbacks are still held by the previous stream, \
+                                       whose audio thread did not stop in time"
+                        .to_string();
+                    let err = BackendSpecificError {
+                        description,
+                        code: None,
+                    };
+                    return Err(err.into());
+                }
+            };
+        let mut error_callback = Lent::new(error_callback, self.error_callback_returned.0.clone());
+        let error_callback = move |err| (error_callback.get())(err);
+        // The direction never changes, so each stream only calls the variant it was built for.
+        let input = matches!(data_callback, DataCallback::Input(_));
+        let mut data_callback = Lent::new(data_callback, self.data_callback_returned.0.clone());
+        if input {
+            self.device.build_input_stream_raw_with_options(
+                config,
+                sample_format,
+                move |data, info| {
+                    if let DataCallback::Input(ref mut data_callback) = *data_callback.get() {
+                        data_callback(data, info)
+                    }
+                },
+                error_callback,
+                &self.options,
+            )
+        } else {
+            self.device.build_output_stream_raw_with_options(
+                config,
+                sample_format,
+                move |data, info| {
+                    if let DataCallback::Output(ref mut data_callback) = *data_callback.get() {
+                        data_callback(data, info)
+                    }
+                },
+                error_callback,
+                &self.options,
+            )
+        }
+    }
+
+    // Brings a newly built stream to the state of the one it replaces.
+    fn resume(&mut self, stream: D::Stream) -> Result<(), BuildStreamError> {
+        let stream = self.stream.insert(stream);
+        if self.keep_alive.get() != KeepAlive::Off {
+            // Not every config supports every mode; the stream then pauses as usual.
+            let _ = stream.set_keep_alive(self.keep_alive.get());
+        }
+        if self.playing.get() {
+            stream.play().map_err(|err| match err {
+                PlayStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+                PlayStreamError::BackendSpecific { err } => err.into(),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: DeviceTrait> StreamTrait for ReconfigurableStream<D> {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        let stream = self
+            .stream
+            .as_ref()
+            .ok_or(PlayStreamError::DeviceNotAvailable)?;
+        stream.play()?;
+        self.playing.set(true);
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        let stream = self
+            .stream
+            .as_ref()
+            .ok_or(PauseStreamError::DeviceNotAvailable)?;
+        stream.pause()?;
+        self.playing.set(false);
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.stream
+            .as_ref()
+            .map_or(self.config.sample_rate, StreamTrait::sample_rate)
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.stream
+            .as_ref()
+            .map_or(self.config.channels, StreamTrait::channels)
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.stream
+            .as_ref()
+            .map_or(self.sample_format, StreamTrait::sample_format)
+    }
+
+    fn effective_bit_depth(&self) -> u8 {
+        match &self.stream {
+            Some(stream) => stream.effective_bit_depth(),
+            None => self.sample_format.bits(),
+        }
+    }
+
+    fn next_block_frames(&self) -> Option<FrameCount> {
+        self.stream.as_ref()?.next_block_frames()
+    }
+
+    fn set_keep_alive(&self, keep_alive: KeepAlive) -> Result<(), KeepAliveError> {
+        if let Some(stream) = &self.stream {
+            stream.set_keep_alive(keep_alive)?;
+        }
+        self.keep_alive.set(keep_alive);
+        Ok(())
+    }
+
+    fn close(self, timeout: Duration) -> Result<(), StreamError> {
+        match self.stream {
+            Some(stream) => stream.close(timeout),
+            None => Ok(()),
+        }
+    }
+}
+
+// A callback lent to one stream, sent back through `returned` when the stream drops it. A
+// callback that panicked is sent back as well, so that a rebuilt stream calls it again.
+struct Lent<T> {
+    // Only `None` once dropped.
+    callback: Option<T>,
+    returned: Sender<T>,
+}
+
+impl<T> Lent<T> {
+    fn new(callback: T, returned: Sender<T>) -> Self {
+        Lent {
+            callback: Some(callback),
+            returned,
+        }
+    }
+
+    fn get(&mut self) -> &mut T {
+        self.callback
+            .as_mut()
+            .expect("the callback is lent until dropped")
+    }
+}
+
+impl<T> Drop for Lent<T> {
+    fn drop(&mut self) {
+        if let Some(callback) = self.callback.take() {
+            // The stream may outlive the `ReconfigurableStream` it was built by.
+            let _ = self.returned.send(callback);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReconfigurableStream;
+    use crate::mock::MockDevice;
+    use crate::traits::StreamTrait;
+    use crate::{
+        BufferSize, BuildStreamError, ChannelCount, Data, OutputCallbackInfo, SampleFormat,
+        SampleRate, StreamConfig, StreamOptions,
+    };
+
+    fn config(channels: ChannelCount, sample_rate: u32) -> StreamConfig {
+        StreamConfig {
+            channels,
+            sample_rate: SampleRate(sample_rate),
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    fn counting_stream() -> ReconfigurableStream<MockDevice> {
+        let mut calls = 0.0;
+        // Exclusive, so that building a stream fails unless the previous one was dropped first.
+        let device = MockDevice {
+            exclusive: true,
+            ..MockDevice::default()
+        };
+        ReconfigurableStream::new_output(
+            device,
+            &config(2, 48_000),
+            SampleFormat::F32,
+            move |data: &mut Data, _: &OutputCallbackInfo| {
+                calls += 1.0;
+                data.as_slice_mut::<f32>().unwrap()[0] = calls;
+            },
+            |_| (),
+            &StreamOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reconfigure_keeps_callback_and_state() {
+        let mut stream = counting_stream();
+        stream.play().unwrap();
+        assert_eq!(stream.stream().unwrap().render()[0], 1.0);
+
+        stream
+            .reconfigure(&config(1, 44_100), SampleFormat::F32)
+            .unwrap();
+        assert_eq!(stream.device().builds.get(), 2);
+        assert_eq!(stream.sample_rate(), SampleRate(44_100));
+        assert_eq!(stream.channels(), 1);
+        assert!(stream.stream().unwrap().playing.get());
+        // The callback continues counting rather than starting over.
+        assert_eq!(stream.stream().unwrap().render()[0], 2.0);
+    }
+
+    #[test]
+    fn test_failed_reconfigure_restores_previous_config() {
+        let mut stream = counting_stream();
+        assert_eq!(stream.stream().unwrap().render()[0], 1.0);
+
+        let result = stream.reconfigure(&config(8, 44_100), SampleFormat::F32);
+        assert!(matches!(
+            result,
+            Err(BuildStreamError::StreamConfigNotSupported)
+        ));
+        assert_eq!(stream.config(), &config(2, 48_000));
+        assert_eq!(stream.sample_rate(), SampleRate(48_000));
+        assert!(!stream.stream().unwrap().playing.get());
+        assert_eq!(stream.stream().unwrap().render()[0], 2.0);
+    }
+}
@@ -0,0 +1,170 @@
+//! A lock-free ring of samples between the data callback of a stream and another thread.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
+
+// A lock-free ring of samples, written by one thread and read by another.
+//
+// The samples are stored as the bits of `f32`s in atomics, so that neither side needs `unsafe`.
+// `written` and `read` count the samples written and read so far, wrapping around.
+//
+// The writer may also discard the oldest samples, see `push_overwrite`, so reading is validated
+// like a seqlock: the reader only takes the samples it copied if `read` did not move meanwhile.
+pub(crate) struct Ring {
+    samples: Box<[AtomicU32]>,
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Ring {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Ring {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    // The number of samples that can be read.
+    pub(crate) fn len(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    // The number of samples that can be written.
+    pub(crate) fn free(&self) -> usize {
+        self.samples.len() - self.len()
+    }
+
+    // Writes as many of `samples` as fit, returning how many. Only called by the writing thread.
+    pub(crate) fn push(&self, samples: &[f32]) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        let len = samples.len().min(self.free());
+        for (i, sample) in samples[..len].iter().enumerate() {
+            let index = written.wrapping_add(i) % self.samples.len();
+            self.samples[index].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written
+            .store(written.wrapping_add(len), Ordering::Release);
+        len
+    }
+
+    // Writes all of `samples`, discarding the oldest samples of the ring in steps of `frame_len`
+    // to make room, and returns the number of samples discarded. If `samples` holds more than the
+    // ring, its first samples are discarded as well.
+    //
+    // Only called by the writing thread. The ring only holds whole frames as long as every write
+    // and read is of whole frames.
+    pub(crate) fn push_overwrite(&self, samples: &[f32], frame_len: usize) -> usize {
+        let capacity = self.samples.len();
+        let skipped = if samples.len() > capacity {
+            (samples.len() - capacity).div_ceil(frame_len) * frame_len
+        } else {
+            0
+        };
+        let samples = &samples[skipped.min(samples.len())..];
+        let mut discarded = skipped;
+        loop {
+            let read = self.read.load(Ordering::Acquire);
+            let written = self.written.load(Ordering::Relaxed);
+            let len = written.wrapping_sub(read);
+            let free = capacity - len;
+            if free >= samples.len() {
+                break;
+            }
+            let discard = ((samples.len() - free).div_ceil(frame_len) * frame_len).min(len);
+            let moved = self.read.compare_exchange(
+                read,
+                read.wrapping_add(discard),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if moved.is_ok() {
+                discarded += discard;
+                break;
+            }
+            // The reader took samples meanwhile, which may have made enough room.
+        }
+        // Readers that copied the discarded samples see `read` moved before the samples change.
+        fence(Ordering::Release);
+        self.push(samples);
+        discarded
+    }
+
+    // Reads as many samples as are available into `samples`, returning how many. Only called by
+    // the reading thread.
+    pub(crate) fn pop(&self, samples: &mut [f32]) -> usize {
+        loop {
+            let read = self.read.load(Ordering::Acquire);
+            let written = self.written.load(Ordering::Acquire);
+            let len = samples.len().min(written.wrapping_sub(read));
+            for (i, sample) in samples[..len].iter_mut().enumerate() {
+                let index = read.wrapping_add(i) % self.samples.len();
+                *sample = f32::from_bits(self.samples[index].load(Ordering::Relaxed));
+            }
+            fence(Ordering::Acquire);
+            // Fails if the writer discarded samples meanwhile, which it may have overwritten.
+            let taken = self.read.compare_exchange(
+                read,
+                read.wrapping_add(len),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+            if taken.is_ok() {
+                return len;
+            }
+        }
+    }
+
+    // Discards up to `len` of the oldest samples, returning how many. Only called by the reading
+    // thread.
+    pub(crate) fn skip(&self, len: usize) -> usize {
+        loop {
+            let read = self.read.load(Ordering::Acquire);
+            let written = self.written.load(Ordering::Acquire);
+            let len = len.min(written.wrapping_sub(read));
+            let skipped = self.read.compare_exchange(
+                read,
+                read.wrapping_add(len),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+            if skipped.is_ok() {
+                return len;
+            }
+        }
+    }
+
+    // Discards the samples in the ring. Only called while neither side uses the ring.
+    pub(crate) fn clear(&self) {
+        let written = self.written.load(Ordering::Acquire);
+        self.read.store(written, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ring;
+
+    #[test]
+    fn test_ring_wraps_around() {
+        let ring = Ring::new(4);
+        let mut samples = [0.0; 4];
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(ring.pop(&mut samples[..2]), 2);
+        assert_eq!(ring.push(&[4.0, 5.0, 6.0, 7.0]), 3);
+        assert_eq!(ring.pop(&mut samples), 4);
+        assert_eq!(samples, [3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn test_push_overwrite_discards_oldest_frames() {
+        let ring = Ring::new(6);
+        assert_eq!(ring.push_overwrite(&[1.0, 1.0, 2.0, 2.0], 2), 0);
+        assert_eq!(ring.push_overwrite(&[3.0, 3.0, 4.0, 4.0], 2), 2);
+        assert_eq!(ring.push_overwrite(&[5.0; 8], 2), 8);
+        let mut samples = [0.0; 6];
+        assert_eq!(ring.pop(&mut samples), 6);
+        assert_eq!(samples, [5.0; 6]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Realtime scheduling for threads that do audio work alongside CPAL's own.
+//!
+//! Audio hosts run the data callback on a thread with realtime priority. Threads that prepare
+//! audio for the callback, e.g. DSP workers filling a ring buffer, must keep up with it and need
+//! the same treatment, or they are preempted by ordinary work and the callback runs dry.
+
+use crate::ThreadRealtimeError;
+use std::time::Duration;
+
+/// Configures the calling thread for realtime scheduling, on a best-effort basis.
+///
+/// The thread is expected to wake up every `period`, needs `computation` of CPU time to do its
+/// work and must be done within `constraint` of waking up, so that
+/// `computation <= constraint <= period`. For a thread feeding a stream, `period` is typically the
+/// duration of one buffer.
+///
+/// | Platform | Scheduling |
+/// | -------- | ---------- |
+/// | macOS, iOS | `thread_policy_set` with `THREAD_TIME_CONSTRAINT_POLICY`, using the given durations |
+/// | Linux, DragonFly BSD, FreeBSD | `SCHED_FIFO` at priority 10, the default of JACK clients |
+/// | Windows | MMCSS, joining the "Pro Audio" task |
+///
+/// Only macOS and iOS make use of the durations; the other platforms merely check them. The
+/// scheduling lasts until the thread exits.
+///
+/// Returns `ThreadRealtimeError::PermissionDenied` if the system does not allow the thread to be
+/// promoted, e.g. on Linux without `CAP_SYS_NICE` or an `rtprio` limit, and
+/// `ThreadRealtimeError::NotSupported` on all other platforms.
+pub fn set_thread_realtime(
+    period: Duration,
+    computation: Duration,
+    constraint: Duration,
+) -> Result<(), ThreadRealtimeError> {
+    if computation.is_zero() || computation > constraint || constraint > period {
+        return Err(ThreadRealtimeError::InvalidConstraints);
+    }
+    imp::set_thread_realtime(period, computation, constraint)
+}
+
+// Whether `set_thread_realtime` succeeds, tried on a thread of its own so that the caller keeps
+// its scheduling.
+pub(crate) fn is_thread_realtime_available() -> bool {
+    std::thread::spawn(|| {
+        let period = Duration::from_millis(10);
+        set_thread_realtime(period, period / 5, period / 2).is_ok()
+    })
+    .join()
+    .unwrap_or(false)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod imp {
+    extern crate mach;
+
+    use self::mach::kern_return::{KERN_NO_ACCESS, KERN_PROTECTION_FAILURE, KERN_SUCCESS};
+    use self::mach::mach_init::mach_thread_self;
+    use self::mach::mach_port::mach_port_deallocate;
+    use self::mach::mach_time::{mach_timebase_info, mach_timebase_info_data_t};
+    use self::mach::thread_policy::{
+        thread_policy_set, thread_policy_t, thread_time_constraint_policy_data_t,
+        THREAD_TIME_CONSTRAINT_POLICY, THREAD_TIME_CONSTRAINT_POLICY_COUNT,
+    };
+    use self::mach::traps::mach_task_self;
+    use crate::{BackendSpecificError, ThreadRealtimeError};
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    pub(super) fn set_thread_realtime(
+        period: Duration,
+        computation: Duration,
+        constraint: Duration,
+    ) -> Result<(), ThreadRealtimeError> {
+        let mut timebase = mach_timebase_info_data_t::default();
+        if unsafe { mach_timebase_info(&mut timebase) } != KERN_SUCCESS {
+            let description = "failed to get the mach timebase".to_string();
+            return Err(BackendSpecificError {
+                description,
+                code: None,
+            }
+            .into());
+        }
+        // The policy is expressed in mach absolute time units.
+        let to_abs = |duration: Duration| -> Result<u32, ThreadRealtimeError> {
+            let abs = duration.as_nanos() * timebase.denom as u128 / timebase.numer as u128;
+            abs.try_into()
+                .map_err(|_| ThreadRealtimeError::InvalidConstraints)
+        };
+        let mut policy = thread_time_constraint_policy_data_t {
+            period: to_abs(period)?,
+            computation: to_abs(computation)?,
+            constraint: to_abs(constraint)?,
+            preemptible: 1,
+        };
+        let result = unsafe {
+            let thread = mach_thread_self();
+            let result = thread_policy_set(
+                thread,
+                THREAD_TIME_CONSTRAINT_POLICY,
+                &mut policy as *mut _ as thread_policy_t,
+                THREAD_TIME_CONSTRAINT_POLICY_COUNT,
+            );
+            mach_port_deallocate(mach_task_self(), thread);
+            result
+        };
+        match result {
+            KERN_SUCCESS => Ok(()),
+            KERN_NO_ACCESS | KERN_PROTECTION_FAILURE => Err(ThreadRealtimeError::PermissionDenied),
+            err => {
+                let description = format!("thread_policy_set failed: {}", err);
+                Err(BackendSpecificError {
+                    description,
+                    code: Some(err),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
+mod imp {
+    extern crate libc;
+
+    use crate::{BackendSpecificError, ThreadRealtimeError};
+    use std::io;
+    use std::mem;
+    use std::time::Duration;
+
+    // JACK's default for client threads, which leaves room above for the server.
+    const PRIORITY: libc::c_int = 10;
+
+    pub(super) fn set_thread_realtime(
+        _period: Duration,
+        _computation: Duration,
+        _constraint: Duration,
+    ) -> Result<(), ThreadRealtimeError> {
+        unsafe {
+            let min = libc::sched_get_priority_min(libc::SCHED_FIFO);
+            let max = libc::sched_get_priority_max(libc::SCHED_FIFO);
+            let mut param: libc::sched_param = mem::zeroed();
+            param.sched_priority = PRIORITY.max(min).min(max);
+            match libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) {
+                0 => Ok(()),
+                libc::EPERM => Err(ThreadRealtimeError::PermissionDenied),
+                err => {
+                    let description = format!(
+                        "pthread_setschedparam failed: {}",
+                        io::Error::from_raw_os_error(err)
+                    );
+                    Err(BackendSpecificError {
+                        description,
+                        code: Some(err),
+                    }
+                    .into())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+    extern crate winapi;
+
+    use self::winapi::um::avrt::AvSetMmThreadCharacteristicsW;
+    use crate::{BackendSpecificError, ThreadRealtimeError};
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::time::Duration;
+
+    pub(super) fn set_thread_realtime(
+        _period: Duration,
+        _computation: Duration,
+        _constraint: Duration,
+    ) -> Result<(), ThreadRealtimeError> {
+        let task_name: Vec<u16> = OsStr::new("Pro Audio")
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let mut task_index = 0;
+        // The returned handle is only needed to revert the characteristics, which are kept until
+        // the thread exits.
+        let handle = unsafe { AvSetMmThreadCharacteristicsW(task_name.as_ptr(), &mut task_index) };
+        if !handle.is_null() {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            return Err(ThreadRealtimeError::PermissionDenied);
+        }
+        let description = format!("AvSetMmThreadCharacteristicsW failed: {}", err);
+        Err(BackendSpecificError {
+            description,
+            code: err.raw_os_error(),
+        }
+        .into())
+    }
+}
+
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "windows"
+)))]
+mod imp {
+    use crate::ThreadRealtimeError;
+    use std::time::Duration;
+
+    pub(super) fn set_thread_realtime(
+        _period: Duration,
+        _computation: Duration,
+        _constraint: Duration,
+    ) -> Result<(), ThreadRealtimeError> {
+        Err(ThreadRealtimeError::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::set_thread_realtime;
+    use crate::ThreadRealtimeError;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_invalid_constraints() {
+        let ms = Duration::from_millis;
+        for &(period, computation, constraint) in &[
+            (ms(10), ms(0), ms(5)),
+            (ms(10), ms(6), ms(5)),
+            (ms(10), ms(2), ms(11)),
+        ] {
+            assert!(matches!(
+                set_thread_realtime(period, computation, constraint),
+                Err(ThreadRealtimeError::InvalidConstraints)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_promotes_or_reports_denial() {
+        // On a thread of its own, so that the test harness threads are left alone.
+        let result = thread::spawn(|| {
+            set_thread_realtime(
+                Duration::from_millis(10),
+                Duration::from_millis(2),
+                Duration::from_millis(5),
+            )
+        })
+        .join()
+        .unwrap();
+        assert!(matches!(
+            result,
+            Ok(()) | Err(ThreadRealtimeError::PermissionDenied)
+        ));
+    }
+}
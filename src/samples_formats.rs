@@ -7,6 +7,12 @@ pub enum SampleFormat {
     I16,
     /// The value 0 corresponds to 32768.
     U16,
+    /// A signed 24-bit integer in the lower three bytes of a 32-bit container, see `I24`. The
+    /// value 0 corresponds to 0.
+    I24,
+    /// A signed 24-bit integer packed into three bytes, see `I24Packed`. The value 0 corresponds
+    /// to 0.
+    I24Packed,
     /// The boundaries are (-1.0, 1.0).
     F32,
 }
@@ -18,9 +24,20 @@ impl SampleFormat {
         match *self {
             SampleFormat::I16 => mem::size_of::<i16>(),
             SampleFormat::U16 => mem::size_of::<u16>(),
+            SampleFormat::I24 => mem::size_of::<I24>(),
+            SampleFormat::I24Packed => mem::size_of::<I24Packed>(),
             SampleFormat::F32 => mem::size_of::<f32>(),
         }
     }
+
+    // The number of bits of a sample of this format that carry its value, which is fewer than the
+    // bits of its container for `I24`.
+    pub(crate) fn bits(&self) -> u8 {
+        match *self {
+            SampleFormat::I24 | SampleFormat::I24Packed => 24,
+            format => (format.sample_size() * 8) as u8,
+        }
+    }
 }
 
 /// Trait for containers that contain PCM data.
@@ -34,8 +51,14 @@ pub unsafe trait Sample: Copy + Clone {
     fn to_i16(&self) -> i16;
     /// Converts this sample into a standard u16 sample.
     fn to_u16(&self) -> u16;
+    /// Converts this sample into a 24-bit sample.
+    ///
+    /// Defaults to converting through `to_f32`, as there is no 24-bit equivalent of every type.
+    fn to_i24(&self) -> I24 {
+        self.to_f32().to_i24()
+    }
 
-    /// Converts any sample type to this one by calling `to_i16`, `to_u16` or `to_f32`.
+    /// Converts any sample type to this one by calling `to_i16`, `to_u16`, `to_i24` or `to_f32`.
     fn from<S>(s: &S) -> Self
     where
         S: Sample;
@@ -59,6 +82,11 @@ unsafe impl Sample for u16 {
         *self
     }
 
+    #[inline]
+    fn to_i24(&self) -> I24 {
+        self.to_i16().to_i24()
+    }
+
     #[inline]
     fn from<S>(sample: &S) -> Self
     where
@@ -90,6 +118,11 @@ unsafe impl Sample for i16 {
         self.wrapping_add(i16::MIN) as u16
     }
 
+    #[inline]
+    fn to_i24(&self) -> I24 {
+        I24(i32::from(*self) << 8)
+    }
+
     #[inline]
     fn from<S>(sample: &S) -> Self
     where
@@ -122,6 +155,16 @@ unsafe impl Sample for f32 {
             .round() as u16
     }
 
+    #[inline]
+    fn to_i24(&self) -> I24 {
+        let sample = self.clamp(-1.0, 1.0);
+        if sample >= 0.0 {
+            I24((sample * I24::MAX.0 as f32).round() as i32)
+        } else {
+            I24((-sample * I24::MIN.0 as f32).round() as i32)
+        }
+    }
+
     #[inline]
     fn from<S>(sample: &S) -> Self
     where
@@ -131,9 +174,193 @@ unsafe impl Sample for f32 {
     }
 }
 
+/// A signed 24-bit sample, as used by `SampleFormat::I24`: the lower three bytes of an `i32`,
+/// sign-extended into its upper byte.
+///
+/// This is the layout of ALSA's `S24_LE` and ASIO's `Int32LSB24`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct I24(i32);
+
+impl I24 {
+    /// The lowest value of a 24-bit sample, -2^23.
+    pub const MIN: I24 = I24(-(1 << 23));
+    /// The highest value of a 24-bit sample, 2^23 - 1.
+    pub const MAX: I24 = I24((1 << 23) - 1);
+
+    /// The sample with the given value, or `None` if it is outside of `MIN..=MAX`.
+    #[inline]
+    pub fn new(value: i32) -> Option<Self> {
+        (I24::MIN.0..=I24::MAX.0)
+            .contains(&value)
+            .then_some(I24(value))
+    }
+
+    /// The sample with the given value, clamped to `MIN..=MAX`.
+    #[inline]
+    pub fn new_clamped(value: i32) -> Self {
+        I24(value.clamp(I24::MIN.0, I24::MAX.0))
+    }
+
+    /// The value of the sample.
+    #[inline]
+    pub fn get(self) -> i32 {
+        self.0
+    }
+}
+
+impl From<I24> for i32 {
+    #[inline]
+    fn from(sample: I24) -> i32 {
+        sample.0
+    }
+}
+
+unsafe impl Sample for I24 {
+    const FORMAT: SampleFormat = SampleFormat::I24;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        if self.0 < 0 {
+            self.0 as f32 / -(I24::MIN.0 as f32)
+        } else {
+            self.0 as f32 / I24::MAX.0 as f32
+        }
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        (self.0 >> 8) as i16
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.to_i16().to_u16()
+    }
+
+    #[inline]
+    fn to_i24(&self) -> I24 {
+        *self
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+    where
+        S: Sample,
+    {
+        sample.to_i24()
+    }
+}
+
+/// A signed 24-bit sample packed into three bytes in native byte order, as used by
+/// `SampleFormat::I24Packed`.
+///
+/// This is the layout of ALSA's `S24_3LE`, ASIO's `Int24LSB` and 24-bit WAV files.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[repr(transparent)]
+pub struct I24Packed([u8; 3]);
+
+impl I24Packed {
+    /// Packs `sample` into three bytes.
+    #[inline]
+    pub fn new(sample: I24) -> Self {
+        if cfg!(target_endian = "little") {
+            let [low, middle, high, _] = sample.0.to_le_bytes();
+            I24Packed([low, middle, high])
+        } else {
+            let [_, high, middle, low] = sample.0.to_be_bytes();
+            I24Packed([high, middle, low])
+        }
+    }
+
+    /// The unpacked sample.
+    #[inline]
+    pub fn get(self) -> I24 {
+        // Shifting the value down from the upper three bytes sign-extends it.
+        let [a, b, c] = self.0;
+        if cfg!(target_endian = "little") {
+            I24(i32::from_le_bytes([0, a, b, c]) >> 8)
+        } else {
+            I24(i32::from_be_bytes([a, b, c, 0]) >> 8)
+        }
+    }
+}
+
+impl From<I24> for I24Packed {
+    #[inline]
+    fn from(sample: I24) -> Self {
+        I24Packed::new(sample)
+    }
+}
+
+impl From<I24Packed> for I24 {
+    #[inline]
+    fn from(sample: I24Packed) -> Self {
+        sample.get()
+    }
+}
+
+unsafe impl Sample for I24Packed {
+    const FORMAT: SampleFormat = SampleFormat::I24Packed;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        self.get().to_f32()
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        self.get().to_i16()
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.get().to_u16()
+    }
+
+    #[inline]
+    fn to_i24(&self) -> I24 {
+        self.get()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+    where
+        S: Sample,
+    {
+        I24Packed::new(sample.to_i24())
+    }
+}
+
+/// Fills `buffer`, holding samples of the given format in native byte order, with silence.
+pub(crate) fn fill_silence(buffer: &mut [u8], sample_format: SampleFormat) {
+    match sample_format {
+        SampleFormat::I16 | SampleFormat::I24 | SampleFormat::I24Packed | SampleFormat::F32 => {
+            buffer.fill(0)
+        }
+        SampleFormat::U16 => {
+            let equilibrium = (i16::MAX as u16 + 1).to_ne_bytes();
+            for sample in buffer.chunks_exact_mut(equilibrium.len()) {
+                sample.copy_from_slice(&equilibrium);
+            }
+        }
+    }
+}
+
+/// Sign-extends the `SampleFormat::I24` samples of `buffer`, in native byte order, from their lower
+/// three bytes, for hosts that leave the upper byte of captured samples zeroed.
+// Unused on platforms where no host captures 24-bit samples in a 32-bit container.
+#[allow(dead_code)]
+pub(crate) fn sign_extend_i24(buffer: &mut [u8]) {
+    for sample in buffer.chunks_exact_mut(4) {
+        let value = i32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]);
+        sample.copy_from_slice(&((value << 8) >> 8).to_ne_bytes());
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Sample;
+    use super::{fill_silence, sign_extend_i24, I24Packed, Sample, SampleFormat, I24};
 
     #[test]
     fn i16_to_i16() {
@@ -203,4 +430,72 @@ mod test {
         assert_eq!((-0.7f32).to_f32(), -0.7);
         assert_eq!(1.0f32.to_f32(), 1.0);
     }
+
+    #[test]
+    fn i24_conversions() {
+        assert_eq!(I24::MAX.to_i16(), i16::MAX);
+        assert_eq!(I24::MIN.to_i16(), i16::MIN);
+        assert_eq!((-16384i16).to_i24(), I24::new(-16384 << 8).unwrap());
+        assert_eq!(I24::MAX.to_f32(), 1.0);
+        assert_eq!(I24::MIN.to_f32(), -1.0);
+        assert_eq!(I24::new(0).unwrap().to_u16(), 32768);
+        assert_eq!(I24::new(1 << 23), None);
+        assert_eq!(I24::new_clamped(i32::MIN), I24::MIN);
+        assert_eq!(i32::from(I24::new_clamped(i32::MAX)), (1 << 23) - 1);
+    }
+
+    #[test]
+    fn f32_to_i24() {
+        assert_eq!(0.0f32.to_i24(), I24::new(0).unwrap());
+        assert_eq!(1.0f32.to_i24(), I24::MAX);
+        assert_eq!((-1.0f32).to_i24(), I24::MIN);
+        // Out of range samples are clamped rather than wrapped.
+        assert_eq!(1.5f32.to_i24(), I24::MAX);
+        assert_eq!((-1.5f32).to_i24(), I24::MIN);
+        for i in -1000..=1000 {
+            let sample = i as f32 / 1000.0;
+            let round_trip = sample.to_i24().to_f32();
+            assert!((round_trip - sample).abs() <= 1.0 / I24::MAX.get() as f32);
+        }
+    }
+
+    #[test]
+    fn i24_packed_round_trip() {
+        for value in [0, 1, -1, 0x12_3456, -0x12_3456, (1 << 23) - 1, -(1 << 23)] {
+            let sample = I24::new(value).unwrap();
+            let packed = I24Packed::new(sample);
+            assert_eq!(packed.get(), sample);
+            assert_eq!(packed.to_f32(), sample.to_f32());
+        }
+        assert_eq!(
+            std::mem::size_of::<I24Packed>(),
+            SampleFormat::I24Packed.sample_size()
+        );
+        assert_eq!(SampleFormat::I24.sample_size(), 4);
+    }
+
+    #[test]
+    fn sign_extend_i24_negative_samples() {
+        let mut buffer = [0x00ff_fffei32, 0x0000_0002, -3]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect::<Vec<u8>>();
+        sign_extend_i24(&mut buffer);
+        let values: Vec<i32> = buffer
+            .chunks_exact(4)
+            .map(|sample| i32::from_ne_bytes(sample.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [-2, 2, -3]);
+    }
+
+    #[test]
+    fn test_fill_silence() {
+        let mut buffer = [0xffu8; 8];
+        fill_silence(&mut buffer, SampleFormat::U16);
+        let equilibrium = 0x8000u16.to_ne_bytes();
+        assert_eq!(buffer[..2], equilibrium);
+        assert_eq!(buffer[6..], equilibrium);
+        fill_silence(&mut buffer, SampleFormat::F32);
+        assert_eq!(buffer, [0; 8]);
+    }
 }
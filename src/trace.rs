@@ -0,0 +1,79 @@
+//! Optional instrumentation via the [`tracing`](https://docs.rs/tracing) crate.
+//!
+//! Enabling the `tracing` feature makes CPAL emit spans around host creation, device
+//! enumeration, stream creation and stream control, along with events for error paths (including
+//! raw OS error codes), xruns, device notifications and format fallbacks. Without the feature the
+//! macros below expand to nothing and no `tracing` code is compiled in.
+//!
+//! Nothing is ever logged from within a stream's data callback, or per buffer on the audio thread:
+//! a subscriber may allocate, lock or perform I/O, none of which is acceptable in a realtime
+//! context. Events on the audio thread are limited to paths where the stream is already failing or
+//! recovering, such as an xrun or a backend error that is about to be passed to the user's error
+//! callback.
+
+#[cfg(feature = "tracing")]
+macro_rules! debug_span {
+    ($($arg:tt)*) => {
+        ::tracing::debug_span!($($arg)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug_span {
+    ($($arg:tt)*) => {
+        $crate::trace::DisabledSpan
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        ::tracing::warn!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::tracing::debug!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+/// Stand-in for an entered span when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct DisabledSpan;
+
+/// Reports the outcome of building a stream, including the configuration that was negotiated.
+#[cfg(feature = "tracing")]
+pub(crate) fn stream_built<S>(result: &Result<S, crate::BuildStreamError>)
+where
+    S: crate::traits::StreamTrait,
+{
+    match result {
+        Ok(stream) => tracing::debug!(
+            sample_rate = stream.sample_rate().0,
+            channels = stream.channels(),
+            sample_format = ?stream.sample_format(),
+            "stream built",
+        ),
+        Err(err) => tracing::warn!(%err, "failed to build stream"),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub(crate) fn stream_built<S>(_result: &Result<S, crate::BuildStreamError>) {}
@@ -1,11 +1,25 @@
 //! The suite of traits allowing CPAL to abstract over hosts, devices, event loops and stream IDs.
 
+use crate::batch::{self, BuildStreamsResult, OnFailure, StreamSpec};
+use crate::duplex::{self, DuplexCallbackInfo, DuplexStream, DuplexStreamConfig};
+use crate::host::{channel_mode, fade, watchdog};
+use crate::samples_formats::fill_silence;
 use crate::{
-    BuildStreamError, Data, DefaultStreamConfigError, DeviceNameError, DevicesError,
-    InputCallbackInfo, InputDevices, OutputCallbackInfo, OutputDevices, PauseStreamError,
-    PlayStreamError, Sample, SampleFormat, StreamConfig, StreamError, SupportedStreamConfig,
-    SupportedStreamConfigRange, SupportedStreamConfigsError,
+    BackendSpecificError, BtProfile, BufferSize, BuildStreamError, ChannelCount, Data,
+    DefaultDeviceEvent, DefaultStreamConfigError, DefaultWatcher, DeviceDiagnostics, DeviceEvent,
+    DeviceId, DeviceNameError, DeviceRole, DeviceSampleRateError, DeviceVolumeError, DeviceWatcher,
+    DevicesError, FocusRequest, FrameCount, InputCallbackInfo, InputDevices, KeepAlive,
+    KeepAliveError, OutputCallback, OutputCallbackInfo, OutputDevices, PauseStreamError,
+    PermissionStatus, PlayStreamError, RequestFocusError, Sample, SampleFormat, SampleRate,
+    SmokeTestError, StreamConfig, StreamDirection, StreamError, StreamOptions, SupportedBufferSize,
+    SupportedConfigSet, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError, WatchDefaultError, WatchDevicesError, LOW_LATENCY_MIN_FRAMES,
 };
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// A **Host** provides access to the available audio devices on the system.
 ///
@@ -40,6 +54,95 @@ pub trait HostTrait {
     /// Can be empty if the system does not support audio in general.
     fn devices(&self) -> Result<Self::Devices, DevicesError>;
 
+    /// An iterator yielding the ID and name of all devices currently available to the host,
+    /// without opening or probing them.
+    ///
+    /// This is faster than calling `name()` on each of `devices()` where opening a device is
+    /// expensive, such as to populate a device menu. Pass the ID of the selected device to
+    /// `device_by_id` to get the `Device`. The listing may include devices that turn out to be
+    /// unusable once opened.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | PCM names from `snd_device_name_hint`, without opening each PCM as `devices()` does |
+    ///
+    /// All other hosts list `devices()`, skipping those whose ID or name cannot be retrieved.
+    fn device_names(&self) -> Result<impl Iterator<Item = (DeviceId, String)>, DevicesError> {
+        Ok(self
+            .devices()?
+            .filter_map(|device| Some((device.id().ok()?, device.name().ok()?))))
+    }
+
+    /// An iterator yielding the devices for which `DeviceTrait::is_virtual` is true, such as
+    /// virtual cables to record the audio played by other applications from.
+    fn virtual_devices(&self) -> Result<impl Iterator<Item = Self::Device>, DevicesError> {
+        Ok(self.devices()?.filter(|device| device.is_virtual()))
+    }
+
+    /// An iterator yielding the output devices for which `DeviceTrait::is_loopback` is true, whose
+    /// input streams record what they play.
+    fn loopback_devices(&self) -> Result<impl Iterator<Item = Self::Device>, DevicesError> {
+        Ok(self.devices()?.filter(|device| device.is_loopback()))
+    }
+
+    /// Builds a stream for each of `specs`, returning them in the same order.
+    ///
+    /// If any stream fails to build, the others are still attempted so that every failure is
+    /// reported, and the error lists the index of each failed spec with the reason. The streams
+    /// that were built are then dropped, releasing their devices, or returned within the error,
+    /// as chosen by `on_failure`.
+    fn build_streams<'a>(
+        &self,
+        specs: impl IntoIterator<Item = StreamSpec<'a, Self::Device>>,
+        on_failure: OnFailure,
+    ) -> BuildStreamsResult<<Self::Device as DeviceTrait>::Stream>
+    where
+        Self::Device: 'a,
+    {
+        batch::build_streams(specs, on_failure)
+    }
+
+    /// The device with the given ID, as returned by `DeviceTrait::id` or `device_names`.
+    ///
+    /// Returns `None` if no such device is available.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | The PCM with that name, which is not opened until it is used |
+    /// | WASAPI | `IMMDeviceEnumerator::GetDevice` |
+    ///
+    /// All other hosts search `devices()`.
+    fn device_by_id(&self, id: &DeviceId) -> Option<Self::Device> {
+        self.devices()
+            .ok()?
+            .find(|device| device.id().ok().as_ref() == Some(id))
+    }
+
+    /// The version of the library or system the host is backed by, as reported by
+    /// `Host::diagnostics`.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | `snd_asoundlib_version`, the version of alsa-lib |
+    /// | WASAPI | The version and build of Windows, from `RtlGetVersion` |
+    ///
+    /// All other hosts return `None`.
+    fn backend_version(&self) -> Option<String> {
+        None
+    }
+
+    /// Features of the system that matter to the host, each with whether it is available, as
+    /// reported by `Host::diagnostics`.
+    ///
+    /// | Host | Capabilities |
+    /// | ---- | ------------ |
+    /// | WASAPI | `IAudioClient3`, available from Windows 10 |
+    ///
+    /// All other hosts return none.
+    fn capabilities(&self) -> Vec<(&'static str, bool)> {
+        Vec::new()
+    }
+
     /// The default input audio device on the system.
     ///
     /// Returns `None` if no input device is available.
@@ -50,10 +153,89 @@ pub trait HostTrait {
     /// Returns `None` if no output device is available.
     fn default_output_device(&self) -> Option<Self::Device>;
 
+    /// The default input audio device on the system for the given role.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | `IMMDeviceEnumerator::GetDefaultAudioEndpoint` with `eConsole`, `eMultimedia` or `eCommunications` |
+    ///
+    /// All other hosts have a single default device and return `default_input_device()` for
+    /// every role.
+    fn default_input_device_for_role(&self, role: DeviceRole) -> Option<Self::Device> {
+        let _ = role;
+        self.default_input_device()
+    }
+
+    /// The default output audio device on the system for the given role.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | `IMMDeviceEnumerator::GetDefaultAudioEndpoint` with `eConsole`, `eMultimedia` or `eCommunications` |
+    ///
+    /// All other hosts have a single default device and return `default_output_device()` for
+    /// every role.
+    fn default_output_device_for_role(&self, role: DeviceRole) -> Option<Self::Device> {
+        let _ = role;
+        self.default_output_device()
+    }
+
+    /// Calls `callback` whenever the system's default device in the given direction changes,
+    /// until the returned watcher is dropped.
+    ///
+    /// This is cheaper than watching the list of devices, and is all that is needed to follow the
+    /// default device, e.g. to move playback to headphones when they are plugged in: build a new
+    /// stream on the device passed to `callback`. The callback is called on a thread of the host,
+    /// and should return quickly.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | `IMMNotificationClient::OnDefaultDeviceChanged` for the `eConsole` role |
+    /// | CoreAudio (macOS) | Listeners on `kAudioHardwarePropertyDefaultInputDevice` or `kAudioHardwarePropertyDefaultOutputDevice` |
+    ///
+    /// All other hosts return `WatchDefaultError::NotSupported`.
+    fn watch_default<F>(
+        &self,
+        direction: StreamDirection,
+        callback: F,
+    ) -> Result<DefaultWatcher, WatchDefaultError>
+    where
+        F: FnMut(DefaultDeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let _ = (direction, callback);
+        Err(WatchDefaultError::NotSupported)
+    }
+
+    /// Calls `callback` whenever a device is added to or removed from the host, the system's
+    /// default input or output device changes, or the volume of a device changes, until the
+    /// returned watcher is dropped.
+    ///
+    /// The watcher may be created before or after building streams, and dropping it unregisters
+    /// from the host. The callback is called on a thread of the host, never from a data callback,
+    /// and should return quickly. To follow the default device only, `watch_default` is cheaper.
+    ///
+    /// Devices are reported as they are listed by `devices`, and removed devices by their
+    /// `DeviceId`, as the device itself can no longer be opened.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | `IMMNotificationClient`: `OnDeviceAdded`, `OnDeviceRemoved`, `OnDeviceStateChanged` and `OnDefaultDeviceChanged` for the `eConsole` role. `IAudioEndpointVolumeCallback` of each device for its volume |
+    /// | CoreAudio (macOS) | Listeners on `kAudioHardwarePropertyDevices`, `kAudioHardwarePropertyDefaultInputDevice` and `kAudioHardwarePropertyDefaultOutputDevice`, and on `kAudioDevicePropertyVolumeScalar` of each device |
+    ///
+    /// All other hosts return `WatchDevicesError::NotSupported`.
+    fn watch_devices<F>(&self, callback: F) -> Result<DeviceWatcher, WatchDevicesError>
+    where
+        F: FnMut(DeviceEvent<Self::Device>) + Send + 'static,
+    {
+        let _ = callback;
+        Err(WatchDevicesError::NotSupported)
+    }
+
     /// An iterator yielding all `Device`s currently available to the system that support one or more
     /// input stream formats.
     ///
-    /// Can be empty if the system does not support audio input.
+    /// This includes output devices that support input streams in loopback mode, which
+    /// `loopback_devices` lists on their own. Can be empty if the system does not support audio
+    /// input.
     fn input_devices(&self) -> Result<InputDevices<Self::Devices>, DevicesError> {
         fn supports_input<D: DeviceTrait>(device: &D) -> bool {
             device
@@ -77,12 +259,49 @@ pub trait HostTrait {
         }
         Ok(self.devices()?.filter(supports_output::<Self::Device>))
     }
+
+    /// Whether the application may capture audio input.
+    ///
+    /// Systems that gate microphone access behind a permission typically deliver silence to input
+    /// streams when it is denied, rather than failing. Check this to tell the user how to grant
+    /// access instead.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | CoreAudio (macOS, iOS) | `AVCaptureDevice` authorization status for audio |
+    ///
+    /// All other hosts return `PermissionStatus::NotApplicable`. This includes oboe, which does
+    /// not query the Android `RECORD_AUDIO` permission.
+    fn input_permission_status(&self) -> PermissionStatus {
+        PermissionStatus::NotApplicable
+    }
+
+    /// Asks the user for permission to capture audio input if they have not been asked yet, then
+    /// calls `callback` with the resulting status.
+    ///
+    /// The callback may be called on another thread, after the user has answered a system
+    /// prompt. If the host cannot prompt the user, or the status is already determined, it is
+    /// called immediately with `input_permission_status()`.
+    fn request_input_permission<F>(&self, callback: F)
+    where
+        F: FnOnce(PermissionStatus) + Send + 'static,
+    {
+        callback(self.input_permission_status())
+    }
 }
 
 /// A device that is capable of audio input and/or output.
 ///
 /// Please note that `Device`s may become invalid if they get disconnected. Therefore, all the
 /// methods that involve a device return a `Result` allowing the user to handle this case.
+///
+/// # Panics in callbacks
+///
+/// A panic in a stream's data callback never unwinds into the host. It is passed to the error
+/// callback once as `StreamError::CallbackPanicked`, and the data callback is not called again:
+/// an output stream plays silence until it is paused or dropped, and an input stream discards its
+/// data. A panic in the error callback is caught too, and later errors are then ignored. On
+/// targets that abort on panic, such as `wasm32-unknown-unknown`, none of this applies.
 pub trait DeviceTrait {
     /// The iterator type yielding supported input stream formats.
     type SupportedInputConfigs: Iterator<Item = SupportedStreamConfigRange>;
@@ -94,8 +313,39 @@ pub trait DeviceTrait {
     /// The human-readable name of the device.
     fn name(&self) -> Result<String, DeviceNameError>;
 
+    /// The ID of the device, unique among the devices of its host. See `DeviceId`.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | The PCM name, e.g. `hw:CARD=PCH,DEV=0`, which names the card by its ID rather than its index |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyDeviceUID` |
+    /// | WASAPI | `IMMDevice::GetId` |
+    ///
+    /// All other hosts return the name of the device, so that devices sharing a name share an ID
+    /// there.
+    fn id(&self) -> Result<DeviceId, DeviceNameError> {
+        self.name().map(DeviceId::new)
+    }
+
+    /// Information about the device, for bug reports.
+    ///
+    /// | Host | Driver |
+    /// | ---- | ------ |
+    /// | ALSA | The kernel driver of the card the device is on, from `snd_ctl_card_info_get_driver` |
+    /// | ASIO | The name of the driver, and the version it reports to `ASIOInit` |
+    ///
+    /// All other hosts only report the name and ID of the device.
+    fn diagnostics(&self) -> DeviceDiagnostics {
+        DeviceDiagnostics::new(self)
+    }
+
     /// An iterator yielding formats that are supported by the backend.
     ///
+    /// Each yielded range describes a valid combination of channel count, sample rate and sample
+    /// format. On hosts where the channel count depends on the sample rate (e.g. ALSA), this
+    /// requires probing the device once per supported rate, so the call may be slow for devices
+    /// with many rates and channels.
+    ///
     /// Can return an error if the device is no longer valid (e.g. it has been disconnected).
     fn supported_input_configs(
         &self,
@@ -103,17 +353,253 @@ pub trait DeviceTrait {
 
     /// An iterator yielding output stream formats that are supported by the device.
     ///
+    /// See [`supported_input_configs`](DeviceTrait::supported_input_configs) for what each range
+    /// describes and when the call may be slow.
+    ///
     /// Can return an error if the device is no longer valid (e.g. it has been disconnected).
     fn supported_output_configs(
         &self,
     ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError>;
 
+    /// The configs supported in the given direction, sorted and merged, see
+    /// `SupportedConfigSet`.
+    ///
+    /// The set is collected once from `supported_input_configs` or `supported_output_configs`.
+    fn supported_configs(
+        &self,
+        direction: StreamDirection,
+    ) -> Result<SupportedConfigSet, SupportedStreamConfigsError> {
+        Ok(match direction {
+            StreamDirection::Input => self.supported_input_configs()?.collect(),
+            StreamDirection::Output => self.supported_output_configs()?.collect(),
+        })
+    }
+
+    /// Calls `callback` with each input stream format supported by the device, as soon as it has
+    /// been found.
+    ///
+    /// This yields the same ranges as [`supported_input_configs`], but hosts that probe the
+    /// device one format at a time (ASIO and WASAPI) report each range as soon as it has been
+    /// probed, so that a UI can be populated progressively. Probing stops early when `callback`
+    /// returns `ControlFlow::Break`. Other hosts enumerate all formats first.
+    ///
+    /// Once all the ranges of a device have been probed, ASIO and WASAPI cache them by the ID of
+    /// the device, and later calls, including the ones of [`supported_input_configs`], pass the
+    /// cached ranges without probing. The cache is cleared on the changes `watch_devices` is
+    /// notified about:
+    ///
+    /// | Host | Cleared when |
+    /// | ---- | ------------ |
+    /// | ASIO | A driver requests to be reset, as it does when its settings change |
+    /// | WASAPI | The state or a property of the device changes, such as its mix format |
+    ///
+    /// `callback` is called on the calling thread while the device is being probed, and must not
+    /// query the same device.
+    ///
+    /// [`supported_input_configs`]: DeviceTrait::supported_input_configs
+    fn supported_input_configs_incremental<F>(
+        &self,
+        mut callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        for config in self.supported_input_configs()? {
+            if let ControlFlow::Break(()) = callback(config) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` with each output stream format supported by the device, as soon as it has
+    /// been found.
+    ///
+    /// See [`supported_input_configs_incremental`](DeviceTrait::supported_input_configs_incremental).
+    fn supported_output_configs_incremental<F>(
+        &self,
+        mut callback: F,
+    ) -> Result<(), SupportedStreamConfigsError>
+    where
+        F: FnMut(SupportedStreamConfigRange) -> ControlFlow<()> + Send,
+    {
+        for config in self.supported_output_configs()? {
+            if let ControlFlow::Break(()) = callback(config) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// The default input stream format for the device.
     fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError>;
 
     /// The default output stream format for the device.
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError>;
 
+    /// The number of physical channels of the device in the given direction.
+    ///
+    /// Drivers may expose more channels than the hardware has, e.g. virtual loopback channels or
+    /// channels up- or downmixed by a plugin. Compare this with the channel counts of
+    /// `supported_input_configs` or `supported_output_configs` to tell them apart. Returns `None`
+    /// if the host cannot tell.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | Maximum channel count of the hardware PCM the device is backed by |
+    /// | CoreAudio (macOS) | `kAudioStreamPropertyPhysicalFormat` of the device's streams |
+    ///
+    /// All other hosts return `None`.
+    fn physical_channels(&self, direction: StreamDirection) -> Option<ChannelCount> {
+        let _ = direction;
+        None
+    }
+
+    /// The sample format the device runs at in the given direction, so that streams of any other
+    /// format are converted on their way to or from the device.
+    ///
+    /// Use this together with `supported_input_configs` or `supported_output_configs` to mark the
+    /// formats that imply conversion, or to prefer the native one. Returns `None` if the host
+    /// cannot tell, or the native format has no `SampleFormat` equivalent, e.g. 64-bit floats.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | The widest of `I24`, `I24Packed`, `F32`, `I16` and `U16` accepted by the hardware PCM the device is backed by |
+    /// | WASAPI | `IAudioClient::GetMixFormat`, the format the shared-mode audio engine mixes in |
+    /// | CoreAudio (macOS) | `kAudioStreamPropertyPhysicalFormat` of the device's first stream |
+    /// | ASIO | The sample type of the driver's channels |
+    /// | JACK | Always `F32` |
+    ///
+    /// All other hosts return `None`.
+    fn native_sample_format(&self, direction: StreamDirection) -> Option<SampleFormat> {
+        let _ = direction;
+        None
+    }
+
+    /// The Bluetooth profile the device is connected with, or `None` if it isn't a Bluetooth
+    /// device.
+    ///
+    /// Use this to warn users when a headset has switched to the narrowband Hands-Free profile,
+    /// typically because its microphone is in use. The profile is read when this is called. On
+    /// CoreAudio, where a device switches profile in place, streams also report each switch to
+    /// their error callback as `StreamError::BluetoothProfileChanged`; other hosts expose each
+    /// profile as a device of its own.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | The `PROFILE` argument of BlueALSA devices, e.g. `bluealsa:PROFILE=sco` |
+    /// | WASAPI | The Bluetooth enumerator of the endpoint's adapter, or else the endpoints sharing its `PKEY_Device_ContainerId` |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyTransportType`, with a nominal sample rate of 16 kHz or less taken to mean Hands-Free |
+    /// | oboe | `AudioDeviceInfo::device_type` |
+    ///
+    /// All other hosts return `None`.
+    fn bluetooth_profile(&self) -> Option<BtProfile> {
+        None
+    }
+
+    /// Whether the device is implemented in software rather than backed by hardware, such as a
+    /// virtual cable routing audio between applications.
+    ///
+    /// Recording the input side of such a device captures what other applications play to its
+    /// output side. These devices are opened like any other.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | ALSA | Devices of the `snd-aloop` loopback card |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyTransportType`, e.g. for BlackHole or Loopback |
+    ///
+    /// All other hosts return `false`.
+    fn is_virtual(&self) -> bool {
+        false
+    }
+
+    /// Whether this is an output device whose input streams capture what it plays, also known as
+    /// loopback recording.
+    ///
+    /// Such devices report their output format in `supported_input_configs` and
+    /// `default_input_config`, and are listed by `HostTrait::loopback_devices` as well as
+    /// `HostTrait::input_devices`.
+    ///
+    /// | Host | Loopback devices |
+    /// | ---- | ---------------- |
+    /// | WASAPI | Every output device, captured with `AUDCLNT_STREAMFLAGS_LOOPBACK` in its mix format |
+    ///
+    /// All other hosts return `false`, and building an input stream on an output-only device
+    /// fails. Record from a virtual device instead, see `is_virtual`.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
+    /// The volume of the device, in the range `0.0..=1.0`.
+    ///
+    /// This is the volume of the endpoint as shown by the system mixer, which applies to every
+    /// stream on the device. Returns `None` if the host cannot read it, the device has no volume
+    /// control or the device is no longer available.
+    ///
+    /// | Host | Control |
+    /// | ---- | ------- |
+    /// | WASAPI | `IAudioEndpointVolume` master volume |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyVolumeScalar` of the main element, or the average over the channels |
+    ///
+    /// All other hosts return `None`.
+    fn volume(&self) -> Option<f32> {
+        None
+    }
+
+    /// The input gain of the device, in decibels.
+    ///
+    /// This is the level of the device's input volume control, which the driver may apply in
+    /// hardware or in software. Measurement applications capturing integer samples through
+    /// `build_input_stream_raw` can combine it with the full-scale input level of the interface at
+    /// 0 dB gain, as given by its datasheet, to convert sample values to physical units: a
+    /// full-scale sample corresponds to that level lowered by this gain. Drivers don't report the
+    /// full-scale level itself.
+    ///
+    /// Returns `None` if the host cannot read the gain, the device has no input volume control or
+    /// the device is no longer available.
+    ///
+    /// | Host | Property |
+    /// | ---- | -------- |
+    /// | WASAPI | `IAudioEndpointVolume::GetMasterVolumeLevel` of capture endpoints |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyVolumeDecibels` of the main element of the input scope |
+    ///
+    /// All other hosts return `None`.
+    fn input_sensitivity(&self) -> Option<f32> {
+        None
+    }
+
+    /// Sets the volume of the device, see [`volume`](DeviceTrait::volume).
+    ///
+    /// `volume` is clamped to `0.0..=1.0`. Hosts that don't support reading the volume return
+    /// `DeviceVolumeError::NotSupported`.
+    fn set_volume(&self, volume: f32) -> Result<(), DeviceVolumeError> {
+        let _ = volume;
+        Err(DeviceVolumeError::NotSupported)
+    }
+
+    /// Changes the nominal sample rate of the device, returning the rate the device runs at
+    /// afterwards.
+    ///
+    /// The sample rate belongs to the device rather than to a stream: changing it affects every
+    /// stream on the device, including those of other applications, and lasts after the device
+    /// is dropped. Streams that are running may have their rate changed underneath them, so this
+    /// is best called before building any. Opening a device at its nominal rate avoids resampling
+    /// by the host.
+    ///
+    /// The returned rate may differ from `rate` if the device settles on another rate. Devices
+    /// whose clock is driven by another device, or that another process has exclusive access to,
+    /// fail with `DeviceSampleRateError::SampleRateLocked`.
+    ///
+    /// | Host | Property |
+    /// | ---- | -------- |
+    /// | CoreAudio (macOS) | `kAudioDevicePropertyNominalSampleRate` |
+    ///
+    /// All other hosts return `DeviceSampleRateError::NotSupported`.
+    fn set_sample_rate(&self, rate: SampleRate) -> Result<SampleRate, DeviceSampleRateError> {
+        let _ = rate;
+        Err(DeviceSampleRateError::NotSupported)
+    }
+
     /// Create an input stream.
     fn build_input_stream<T, D, E>(
         &self,
@@ -166,6 +652,134 @@ pub trait DeviceTrait {
         )
     }
 
+    /// Create an input stream with the given options.
+    fn build_input_stream_with_options<T, D, E>(
+        &self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        T: Sample,
+        D: FnMut(&[T], &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        self.build_input_stream_raw_with_options(
+            config,
+            T::FORMAT,
+            move |data, info| {
+                data_callback(
+                    data.as_slice()
+                        .expect("host supplied incorrect sample type"),
+                    info,
+                )
+            },
+            error_callback,
+            options,
+        )
+    }
+
+    /// Create an output stream with the given options.
+    fn build_output_stream_with_options<T, D, E>(
+        &self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        T: Sample,
+        D: FnMut(&mut [T], &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        self.build_output_stream_raw_with_options(
+            config,
+            T::FORMAT,
+            move |data, info| {
+                data_callback(
+                    data.as_slice_mut()
+                        .expect("host supplied incorrect sample type"),
+                    info,
+                )
+            },
+            error_callback,
+            options,
+        )
+    }
+
+    /// Create a dynamically typed input stream with the given options.
+    fn build_input_stream_raw_with_options<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&Data, &InputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let config = check_buffer_size(self, StreamDirection::Input, config, options)?;
+        let (config, data_callback) = channel_mode::apply(
+            self,
+            &config,
+            sample_format,
+            options.channel_mode,
+            data_callback,
+        )?;
+        match options.callback_watchdog {
+            None => {
+                self.build_input_stream_raw(&config, sample_format, data_callback, error_callback)
+            }
+            Some(timeout) => {
+                let (heartbeat, error_callback) =
+                    watchdog::spawn(timeout, error_callback).map_err(watchdog_spawn_error)?;
+                let data_callback = watchdog::watch_input(heartbeat, data_callback);
+                self.build_input_stream_raw(&config, sample_format, data_callback, error_callback)
+            }
+        }
+    }
+
+    /// Create a dynamically typed output stream with the given options.
+    fn build_output_stream_raw_with_options<D, E>(
+        &self,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+        data_callback: D,
+        error_callback: E,
+        options: &StreamOptions,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let config = check_buffer_size(self, StreamDirection::Output, config, options)?;
+        // The stream can't be reached from here to fade out or in again, so the ramp only fades
+        // it in once.
+        let data_callback = match options.fade {
+            None => Box::new(data_callback) as Box<OutputCallback>,
+            Some(duration) => {
+                let channels = config.channels as usize;
+                let (_, data_callback) =
+                    fade::apply(duration, channels, config.sample_rate, data_callback);
+                Box::new(data_callback)
+            }
+        };
+        match options.callback_watchdog {
+            None => {
+                self.build_output_stream_raw(&config, sample_format, data_callback, error_callback)
+            }
+            Some(timeout) => {
+                let (heartbeat, error_callback) =
+                    watchdog::spawn(timeout, error_callback).map_err(watchdog_spawn_error)?;
+                let data_callback = watchdog::watch_output(heartbeat, data_callback);
+                self.build_output_stream_raw(&config, sample_format, data_callback, error_callback)
+            }
+        }
+    }
+
     /// Create a dynamically typed input stream.
     fn build_input_stream_raw<D, E>(
         &self,
@@ -189,9 +803,303 @@ pub trait DeviceTrait {
     where
         D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static;
+
+    /// Create an output stream with the lowest latency the device reliably opens with, returning
+    /// the stream along with the config it runs at.
+    ///
+    /// The stream uses the device's default output config, which is its native format on most
+    /// hosts, so the data callback receives samples in whatever format the device prefers; check
+    /// `Data::sample_format` or `StreamTrait::sample_format`. The buffer size starts at the
+    /// smallest size the device supports, but no less than `LOW_LATENCY_MIN_FRAMES`:
+    ///
+    /// - If building the stream fails, the buffer size is doubled and the stream built again,
+    ///   until the largest supported buffer size has been tried.
+    /// - If all fixed sizes fail, or the device doesn't report a buffer size range, the stream is
+    ///   built with `BufferSize::Default`.
+    ///
+    /// Only failures to build the stream trigger a fallback. A buffer size that builds but later
+    /// underruns on a loaded system is kept. Each buffer size is tried by building a stream with
+    /// silent callbacks first, so the device is opened twice for the buffer size that succeeds.
+    ///
+    /// Some hosts do more than that:
+    ///
+    /// | Host | Exclusive mode | Callbacks | Thread priority |
+    /// | ---- | -------------- | --------- | --------------- |
+    /// | WASAPI | Tried first, in the default format, then in 24 and 16 bit, with the device's minimum period. Falls back to shared mode with `BufferSize::Default`. | Event-driven, as all WASAPI streams | Raised with MMCSS ("Pro Audio") for exclusive streams |
+    /// | CoreAudio | Not used, the device runs at the smallest buffer size any process asks for, so hog mode wouldn't lower the latency | Driven by the device's IO cycle, as all CoreAudio streams | Real-time, as all CoreAudio IO threads |
+    ///
+    /// All other hosts only try the buffer sizes and leave the priority of the audio thread to
+    /// the host.
+    fn build_output_stream_low_latency<D, E>(
+        &self,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<(Self::Stream, StreamConfig), BuildStreamError>
+    where
+        D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        try_low_latency_buffer_sizes(self, data_callback, error_callback)
+    }
+
+    /// Create a duplex stream capturing from this device and playing on `output_device`, with one
+    /// data callback receiving both the input and the output buffer.
+    ///
+    /// Both buffers hold the same number of frames, `config.input_channels` and
+    /// `config.output_channels` samples each, and `DuplexCallbackInfo::timestamp` gives the
+    /// capture instant of the input alongside the playback instant of the output. Pass the same
+    /// device twice to capture and play on one device.
+    ///
+    /// Returns `BuildStreamError::StreamConfigNotSupported` before building either stream unless
+    /// this device supports `config.input_config()` with `T` and `output_device` supports
+    /// `config.output_config()` with `U`, so that both run at the same sample rate and buffer
+    /// size.
+    ///
+    /// On every host, the duplex stream is a fallback made of an input and an output stream
+    /// bridged by a ring, and the data callback is called by the output stream's. The two streams
+    /// start and stop one after the other rather than atomically; see `DuplexStream` for how the
+    /// input is aligned with the output.
+    fn build_duplex_stream<T, U, D, E>(
+        &self,
+        output_device: &Self,
+        config: &DuplexStreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<DuplexStream<Self::Stream>, BuildStreamError>
+    where
+        T: Sample + Send + 'static,
+        U: Sample,
+        D: FnMut(&[T], &mut [U], &DuplexCallbackInfo) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        duplex::build(self, output_device, config, data_callback, error_callback)
+    }
+
+    /// Briefly run a stream in `direction` at the default config to check that the device
+    /// actually streams, returning the config the stream ran at.
+    ///
+    /// Enumerating configs doesn't catch every broken device: some enumerate fine but fail to
+    /// open or start, or start but never call back. This builds a stream at the default config,
+    /// plays it until its data callback has been called `SMOKE_TEST_CALLBACKS` times and drops
+    /// it. Output streams play silence.
+    ///
+    /// Returns the first failure along the way, or `SmokeTestError::NoCallbacks` if the callbacks
+    /// don't arrive within `SMOKE_TEST_TIMEOUT`. Blocks until then, which usually takes a few
+    /// buffer periods.
+    fn smoke_test(&self, direction: StreamDirection) -> Result<StreamConfig, SmokeTestError> {
+        let supported_config = match direction {
+            StreamDirection::Input => self.default_input_config()?,
+            StreamDirection::Output => self.default_output_config()?,
+        };
+        let sample_format = supported_config.sample_format();
+        let config = supported_config.config();
+
+        let callbacks = Arc::new(AtomicUsize::new(0));
+        let stream_error = Arc::new(Mutex::new(None));
+        let error_callback = {
+            let stream_error = stream_error.clone();
+            move |err| {
+                stream_error.lock().unwrap().get_or_insert(err);
+            }
+        };
+        let stream = {
+            let callbacks = callbacks.clone();
+            match direction {
+                StreamDirection::Input => self.build_input_stream_raw(
+                    &config,
+                    sample_format,
+                    move |_: &Data, _: &InputCallbackInfo| {
+                        callbacks.fetch_add(1, Ordering::Relaxed);
+                    },
+                    error_callback,
+                )?,
+                StreamDirection::Output => self.build_output_stream_raw(
+                    &config,
+                    sample_format,
+                    move |data: &mut Data, _: &OutputCallbackInfo| {
+                        let sample_format = data.sample_format();
+                        fill_silence(data.bytes_mut(), sample_format);
+                        callbacks.fetch_add(1, Ordering::Relaxed);
+                    },
+                    error_callback,
+                )?,
+            }
+        };
+        stream.play()?;
+
+        let deadline = Instant::now() + SMOKE_TEST_TIMEOUT;
+        loop {
+            if let Some(err) = stream_error.lock().unwrap().take() {
+                return Err(err.into());
+            }
+            let called = callbacks.load(Ordering::Relaxed);
+            if called >= SMOKE_TEST_CALLBACKS {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(SmokeTestError::NoCallbacks {
+                    callbacks: called,
+                    timeout: SMOKE_TEST_TIMEOUT,
+                });
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(stream);
+        Ok(config)
+    }
 }
 
+fn default_config_build_error(err: DefaultStreamConfigError) -> BuildStreamError {
+    match err {
+        DefaultStreamConfigError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        DefaultStreamConfigError::StreamTypeNotSupported => {
+            BuildStreamError::StreamConfigNotSupported
+        }
+        DefaultStreamConfigError::BackendSpecific { err } => {
+            BuildStreamError::BackendSpecific { err }
+        }
+    }
+}
+
+// Checks a fixed buffer size in `config` against the range of the device's default config in
+// `direction`, so that hosts which don't check it themselves fail in the same way. Returns
+// `config` with its buffer size clamped to the range instead if `options.clamp_buffer_size` is
+// set.
+pub(crate) fn check_buffer_size<T>(
+    device: &T,
+    direction: StreamDirection,
+    config: &StreamConfig,
+    options: &StreamOptions,
+) -> Result<StreamConfig, BuildStreamError>
+where
+    T: DeviceTrait + ?Sized,
+{
+    if config.buffer_size == BufferSize::Default {
+        return Ok(config.clone());
+    }
+    let supported_config = match direction {
+        StreamDirection::Input => device.default_input_config(),
+        StreamDirection::Output => device.default_output_config(),
+    };
+    let supported_config = match supported_config {
+        Ok(supported_config) => supported_config,
+        Err(err) if options.clamp_buffer_size => return Err(default_config_build_error(err)),
+        // Building the stream reports the problem with the device, if there is one.
+        Err(_) => return Ok(config.clone()),
+    };
+    if !options.clamp_buffer_size {
+        supported_config.buffer_size().check(&config.buffer_size)?;
+        return Ok(config.clone());
+    }
+    let buffer_size = supported_config.buffer_size().clamp(&config.buffer_size);
+    if buffer_size != config.buffer_size {
+        debug!(requested = ?config.buffer_size, clamped = ?buffer_size, "clamping the buffer size");
+    }
+    Ok(StreamConfig {
+        buffer_size,
+        ..config.clone()
+    })
+}
+
+// The buffer size search of `DeviceTrait::build_output_stream_low_latency`, also used by hosts
+// that override it as their fallback.
+pub(crate) fn try_low_latency_buffer_sizes<T, D, E>(
+    device: &T,
+    data_callback: D,
+    error_callback: E,
+) -> Result<(T::Stream, StreamConfig), BuildStreamError>
+where
+    T: DeviceTrait + ?Sized,
+    D: FnMut(&mut Data, &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let supported_config = device
+        .default_output_config()
+        .map_err(default_config_build_error)?;
+    let sample_format = supported_config.sample_format();
+    let mut buffer_sizes = vec![];
+    if let SupportedBufferSize::Range { min, max } = *supported_config.buffer_size() {
+        let mut frames = min.max(LOW_LATENCY_MIN_FRAMES);
+        while frames <= max {
+            buffer_sizes.push(BufferSize::Fixed(frames));
+            frames = match frames.checked_mul(2) {
+                Some(frames) => frames,
+                None => break,
+            };
+        }
+    }
+    buffer_sizes.push(BufferSize::Default);
+
+    // Each buffer size is probed with silent callbacks, so that the user's callbacks are moved
+    // into the one stream that is built with them and the audio thread takes no lock to reach
+    // them.
+    let mut callbacks = Some((data_callback, error_callback));
+    let mut config = supported_config.config();
+    let mut last_err = None;
+    for buffer_size in buffer_sizes {
+        config.buffer_size = buffer_size;
+        let probe = device.build_output_stream_raw(
+            &config,
+            sample_format,
+            |data: &mut Data, _: &OutputCallbackInfo| {
+                let sample_format = data.sample_format();
+                fill_silence(data.bytes_mut(), sample_format);
+            },
+            |_| (),
+        );
+        match probe {
+            Ok(probe) => {
+                drop(probe);
+                let (data_callback, error_callback) =
+                    callbacks.take().expect("the callbacks are only taken once");
+                let stream = device.build_output_stream_raw(
+                    &config,
+                    sample_format,
+                    data_callback,
+                    error_callback,
+                )?;
+                return Ok((stream, config));
+            }
+            Err(err) => {
+                debug!(%err, buffer_size = ?config.buffer_size, "falling back to a larger buffer");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("`BufferSize::Default` is always tried"))
+}
+
+pub(crate) fn watchdog_spawn_error(err: std::io::Error) -> BuildStreamError {
+    let description = format!("failed to spawn the callback watchdog thread: {}", err);
+    BackendSpecificError {
+        description,
+        code: None,
+    }
+    .into()
+}
+
+/// The number of data callbacks `DeviceTrait::smoke_test` waits for.
+pub const SMOKE_TEST_CALLBACKS: usize = 3;
+
+/// How long `DeviceTrait::smoke_test` waits for `SMOKE_TEST_CALLBACKS` data callbacks.
+///
+/// Long enough for the largest buffers hosts use by default and for devices that take a while to
+/// start, such as Bluetooth headsets.
+pub const SMOKE_TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// A stream created from `Device`, with methods to control playback.
+///
+/// # Dropping a stream
+///
+/// Dropping a stream stops the backend stream, waits for a data callback that is currently
+/// running to return and then releases the stream's resources, including the callbacks and
+/// everything they captured. Once `drop` returns the data callback is never called again.
+///
+/// The wait is bounded: on hosts where CPAL runs its own audio thread (ALSA and WASAPI), `drop`
+/// gives up after two seconds and detaches the thread, which then releases the callbacks
+/// whenever the callback returns. The same applies on macOS to a stream whose device has been
+/// removed, since stopping CoreAudio's audio unit may then block indefinitely. Use
+/// [`close`](StreamTrait::close) to choose the timeout and to find out whether it elapsed.
 pub trait StreamTrait {
     /// Run the stream.
     ///
@@ -205,4 +1113,272 @@ pub trait StreamTrait {
     /// Note: Not all devices support suspending the stream at the hardware level. This method may
     /// fail in these cases.
     fn pause(&self) -> Result<(), PauseStreamError>;
+
+    /// The sample rate at which the stream is running.
+    ///
+    /// This is the rate negotiated with the device, which may differ from the requested rate on
+    /// hosts that pick the nearest rate supported by the hardware.
+    fn sample_rate(&self) -> SampleRate;
+
+    /// The number of channels of the stream.
+    fn channels(&self) -> ChannelCount;
+
+    /// The format of the samples delivered to or expected from the stream's data callback.
+    fn sample_format(&self) -> SampleFormat;
+
+    /// The number of bits of each sample that reach the device or come from it.
+    ///
+    /// This may be fewer than the container of `sample_format`, e.g. 24 for a device with 24-bit
+    /// converters, or 16 when the system converts the samples of an `F32` stream for a 16-bit
+    /// device.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | The valid bits per sample of `PKEY_AudioEngine_DeviceFormat` |
+    /// | CoreAudio (macOS) | `mBitsPerChannel` of the physical format of the device's streams |
+    ///
+    /// All other hosts return the bits of `sample_format`, which is 24 for `I24` and `I24Packed`
+    /// and the size of the sample otherwise.
+    fn effective_bit_depth(&self) -> u8 {
+        self.sample_format().bits()
+    }
+
+    /// The number of frames the next call to the data callback of this output stream is expected
+    /// to fill, for applications that prepare a fixed amount of work per block.
+    ///
+    /// This is a hint, not a guarantee. Within the data callback it is the number of frames of the
+    /// current call. Between calls it is the number of frames of the last call, which the next one
+    /// usually matches. Returns `None` when the host cannot tell, in which case the largest block
+    /// the stream may request, such as `StreamConfig::frames_per_callback_estimate`, is the bound
+    /// to prepare for.
+    ///
+    /// | Host | Source |
+    /// | ---- | ------ |
+    /// | WASAPI | The free space of the endpoint buffer, i.e. its size minus `GetCurrentPadding` |
+    /// | CoreAudio (macOS) | The frames of the render call, or `kAudioDevicePropertyBufferFrameSize` before the first |
+    ///
+    /// Input streams, and all other hosts, return `None`.
+    fn next_block_frames(&self) -> Option<FrameCount> {
+        None
+    }
+
+    /// Request audio focus for the lifetime of the stream, asking the system to pause or duck
+    /// audio played by other applications.
+    ///
+    /// Focus is released automatically when the stream is dropped. If another application takes
+    /// the focus, the error callback is called with `StreamError::FocusLost`.
+    ///
+    /// | Host | Mechanism |
+    /// | ---- | --------- |
+    /// | oboe | `AudioManager.requestAudioFocus`, with focus loss reported from Android 8.0 (API level 26) |
+    ///
+    /// All other hosts return `RequestFocusError::NotSupported`. This includes WASAPI, where
+    /// Windows ducks other applications on its own while a communications stream plays, and
+    /// CoreAudio on iOS, where ducking is a category option of the application's shared
+    /// `AVAudioSession` rather than of a stream.
+    fn request_focus(&self, request: FocusRequest) -> Result<(), RequestFocusError> {
+        let _ = request;
+        Err(RequestFocusError::NotSupported)
+    }
+
+    /// Choose what pausing this output stream does with the device, see `KeepAlive`.
+    ///
+    /// Keep-alive only affects `pause`. Enabling it while the stream is paused takes effect from
+    /// the next `pause`; disabling it while paused stops the backend stream immediately.
+    ///
+    /// | Host | `Silence` | `Hardware` |
+    /// | ---- | --------- | ---------- |
+    /// | ALSA | yes | no |
+    /// | WASAPI | yes | keeps the client started, releasing buffers flagged as silent |
+    /// | CoreAudio (macOS) | yes | no |
+    ///
+    /// Input streams, and all other hosts, only accept `KeepAlive::Off` and return
+    /// `KeepAliveError::NotSupported` otherwise.
+    fn set_keep_alive(&self, keep_alive: KeepAlive) -> Result<(), KeepAliveError> {
+        match keep_alive {
+            KeepAlive::Off => Ok(()),
+            _ => Err(KeepAliveError::NotSupported),
+        }
+    }
+
+    /// Stop and drop the stream, waiting at most `timeout` for a data callback that is currently
+    /// running to return.
+    ///
+    /// Returns `StreamError::CloseTimedOut` if the callback was still running when `timeout`
+    /// elapsed. The stream is stopped either way, and the callbacks are released as soon as the
+    /// running callback returns.
+    ///
+    /// Only ALSA and WASAPI honour `timeout`. All other hosts stop their stream synchronously,
+    /// waiting for the running callback without a bound, exactly as when the stream is dropped.
+    fn close(self, timeout: Duration) -> Result<(), StreamError>
+    where
+        Self: Sized,
+    {
+        let _ = timeout;
+        drop(self);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeviceTrait, StreamTrait, SMOKE_TEST_CALLBACKS};
+    use crate::mock::{MockDevice, MockStream};
+    use crate::{
+        BufferSize, BuildStreamError, Data, DefaultStreamConfigError, FrameCount,
+        OutputCallbackInfo, SampleFormat, SampleRate, SmokeTestError, StreamConfig,
+        StreamDirection, StreamError, StreamOptions, SupportedBufferSize, LOW_LATENCY_MIN_FRAMES,
+    };
+
+    #[test]
+    fn test_smoke_test_plays_silence() {
+        let device = MockDevice {
+            calls_on_play: SMOKE_TEST_CALLBACKS,
+            ..MockDevice::default()
+        };
+        let config = device.smoke_test(StreamDirection::Output).unwrap();
+        assert_eq!(config.channels, 2);
+        assert_eq!(config.sample_rate, SampleRate(48_000));
+        assert_eq!(*device.rendered.lock().unwrap(), [0.0; 4]);
+    }
+
+    #[test]
+    fn test_smoke_test_reports_failing_step() {
+        let device = MockDevice {
+            fails: true,
+            ..MockDevice::default()
+        };
+        assert!(matches!(
+            device.smoke_test(StreamDirection::Input),
+            Err(SmokeTestError::DefaultStreamConfig {
+                err: DefaultStreamConfigError::StreamTypeNotSupported
+            })
+        ));
+        assert!(matches!(
+            device.smoke_test(StreamDirection::Output),
+            Err(SmokeTestError::Stream {
+                err: StreamError::DeviceNotAvailable
+            })
+        ));
+    }
+
+    #[test]
+    fn test_effective_bit_depth_of_sample_format() {
+        let device = MockDevice::default();
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            buffer_size: BufferSize::Default,
+        };
+        let bit_depth = |sample_format| {
+            device
+                .build_output_stream_raw(
+                    &config,
+                    sample_format,
+                    |_: &mut Data, _: &OutputCallbackInfo| (),
+                    |_| (),
+                )
+                .unwrap()
+                .effective_bit_depth()
+        };
+        assert_eq!(bit_depth(SampleFormat::I16), 16);
+        assert_eq!(bit_depth(SampleFormat::I24), 24);
+        assert_eq!(bit_depth(SampleFormat::I24Packed), 24);
+        assert_eq!(bit_depth(SampleFormat::F32), 32);
+    }
+
+    // A device whose default output config supports 16 to 1024 frames, but which fails to build
+    // streams of fewer than `min_frames`.
+    fn buffer_range_device(min_frames: FrameCount) -> MockDevice {
+        MockDevice {
+            buffer_size: Some(SupportedBufferSize::Range { min: 16, max: 1024 }),
+            min_frames,
+            ..MockDevice::default()
+        }
+    }
+
+    #[test]
+    fn test_fixed_buffer_size_is_checked_with_options() {
+        let device = buffer_range_device(0);
+        let build = |frames, options: &StreamOptions| {
+            let config = StreamConfig {
+                channels: 2,
+                sample_rate: SampleRate(48_000),
+                buffer_size: BufferSize::Fixed(frames),
+            };
+            let data_callback = |_: &mut Data, _: &OutputCallbackInfo| ();
+            device.build_output_stream_raw_with_options(
+                &config,
+                SampleFormat::F32,
+                data_callback,
+                |_| (),
+                options,
+            )
+        };
+        let options = StreamOptions::default();
+        assert!(matches!(
+            build(8, &options),
+            Err(BuildStreamError::BufferSizeTooSmall {
+                requested: 8,
+                min: 16
+            })
+        ));
+        assert!(matches!(
+            build(2048, &options),
+            Err(BuildStreamError::BufferSizeTooLarge {
+                requested: 2048,
+                max: 1024
+            })
+        ));
+        assert!(build(256, &options).is_ok());
+        let options = StreamOptions {
+            clamp_buffer_size: true,
+            ..StreamOptions::default()
+        };
+        assert!(build(8, &options).is_ok());
+    }
+
+    fn build_low_latency(device: &MockDevice) -> (MockStream, StreamConfig) {
+        let mut calls = 0.0;
+        device
+            .build_output_stream_low_latency(
+                move |data: &mut Data, _: &OutputCallbackInfo| {
+                    calls += 1.0;
+                    data.as_slice_mut::<f32>().unwrap()[0] = calls;
+                },
+                |_| (),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_low_latency_starts_at_minimum() {
+        let (stream, config) = build_low_latency(&buffer_range_device(0));
+        assert_eq!(
+            config.buffer_size,
+            BufferSize::Fixed(LOW_LATENCY_MIN_FRAMES)
+        );
+        assert_eq!(stream.render()[0], 1.0);
+        assert_eq!(stream.render()[0], 2.0);
+    }
+
+    #[test]
+    fn test_low_latency_falls_back_to_larger_buffers() {
+        let device = buffer_range_device(200);
+        let (stream, config) = build_low_latency(&device);
+        assert_eq!(config.buffer_size, BufferSize::Fixed(256));
+        // The probe with silent callbacks and the stream itself.
+        assert_eq!(device.builds.get(), 2);
+        assert_eq!(stream.render()[0], 1.0);
+    }
+
+    #[test]
+    fn test_low_latency_falls_back_to_default_buffer() {
+        let (stream, config) = build_low_latency(&buffer_range_device(2048));
+        assert_eq!(config.buffer_size, BufferSize::Default);
+        assert_eq!(stream.render()[0], 1.0);
+
+        let (_, config) = build_low_latency(&MockDevice::default());
+        assert_eq!(config.buffer_size, BufferSize::Default);
+    }
 }
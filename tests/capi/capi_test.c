@@ -0,0 +1,150 @@
+// Exercises the C API from C. Build CPAL as a static library with the `capi` feature and link
+// against it, e.g. on Linux:
+//
+//     cargo rustc --lib --features capi --crate-type staticlib
+//     cc -std=c99 -Wall -Wextra -Werror -Iinclude -o target/capi_test tests/capi/capi_test.c
+//         target/debug/libcpal.a -lasound -lpthread -ldl -lm
+//     ./target/capi_test
+//
+// The test doesn't require an audio device: device enumeration is exercised on whatever the
+// default host reports, and the stream callback trampoline through `cpal_offline_render`.
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "cpal.h"
+
+#define CHECK(cond)                                                                    \
+    do {                                                                               \
+        if (!(cond)) {                                                                 \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond);   \
+            exit(1);                                                                   \
+        }                                                                              \
+    } while (0)
+
+#define CHECK_OK(expr)                                                                 \
+    do {                                                                               \
+        CpalError err_ = (expr);                                                       \
+        if (err_ != CPAL_ERROR_OK) {                                                   \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__, #expr,       \
+                    cpal_error_message(err_));                                         \
+            exit(1);                                                                   \
+        }                                                                              \
+    } while (0)
+
+static void test_error_messages(void) {
+    CHECK(strcmp(cpal_error_message(CPAL_ERROR_OK), "no error") == 0);
+    CHECK(strlen(cpal_error_message(CPAL_ERROR_BACKEND_SPECIFIC)) > 0);
+}
+
+static void test_null_arguments(void) {
+    CpalStreamConfig config = {2, 48000, 0};
+    CpalSampleFormat format;
+    float out[4];
+
+    CHECK(cpal_default_host(NULL) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_host_devices(NULL, NULL) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_device_name(NULL, NULL, 0, NULL) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_device_default_output_config(NULL, &config, &format) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_offline_render(&config, 2, NULL, NULL, out, 4) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_stream_play(NULL) == CPAL_ERROR_NULL_ARGUMENT);
+    CHECK(cpal_device_list_len(NULL) == 0);
+    CHECK(cpal_device_list_get(NULL, 0) == NULL);
+
+    // Freeing null is a no-op.
+    cpal_host_free(NULL);
+    cpal_device_free(NULL);
+    cpal_device_list_free(NULL);
+    cpal_stream_free(NULL);
+}
+
+// Returns the device's name, which the caller must free.
+static char *device_name(const CpalDevice *device) {
+    size_t needed = 0;
+    CHECK(cpal_device_name(device, NULL, 0, &needed) == CPAL_ERROR_BUFFER_TOO_SMALL);
+    CHECK(needed > 0);
+    char *name = malloc(needed);
+    CHECK(name != NULL);
+    CHECK_OK(cpal_device_name(device, name, needed, NULL));
+    CHECK(strlen(name) + 1 == needed);
+    return name;
+}
+
+static void test_host_and_devices(void) {
+    CpalHost *host = NULL;
+    CHECK_OK(cpal_default_host(&host));
+    CHECK(host != NULL);
+
+    char host_name[64];
+    CHECK_OK(cpal_host_name(host, host_name, sizeof(host_name), NULL));
+    printf("host: %s\n", host_name);
+
+    CpalDeviceList *devices = NULL;
+    CHECK_OK(cpal_host_devices(host, &devices));
+    size_t len = cpal_device_list_len(devices);
+    for (size_t i = 0; i < len; i++) {
+        const CpalDevice *device = cpal_device_list_get(devices, i);
+        CHECK(device != NULL);
+        char *name = device_name(device);
+        printf("device %zu: %s\n", i, name);
+        free(name);
+    }
+    CHECK(cpal_device_list_get(devices, len) == NULL);
+    cpal_device_list_free(devices);
+
+    CpalDevice *device = NULL;
+    CpalError err = cpal_host_default_output_device(host, &device);
+    CHECK(err == CPAL_ERROR_OK || err == CPAL_ERROR_DEVICE_NOT_AVAILABLE);
+    if (err == CPAL_ERROR_OK) {
+        char *name = device_name(device);
+        printf("default output device: %s\n", name);
+        free(name);
+        cpal_device_free(device);
+    }
+
+    cpal_host_free(host);
+}
+
+struct ramp {
+    float next;
+    size_t calls;
+};
+
+static void ramp_callback(void *user_data, void *data, size_t samples, CpalSampleFormat format) {
+    struct ramp *ramp = user_data;
+    float *out = data;
+    CHECK(format == CPAL_SAMPLE_FORMAT_F32);
+    for (size_t i = 0; i < samples; i++) {
+        out[i] = ramp->next;
+        ramp->next += 1.0f;
+    }
+    ramp->calls++;
+}
+
+static void test_offline_render(void) {
+    CpalStreamConfig config = {2, 48000, 3};
+    struct ramp ramp = {0.0f, 0};
+    float out[10];
+
+    CHECK_OK(cpal_offline_render(&config, 5, ramp_callback, &ramp, out, 10));
+    CHECK(ramp.calls == 2);
+    for (size_t i = 0; i < 10; i++) {
+        CHECK(out[i] == (float)i);
+    }
+
+    CHECK(cpal_offline_render(&config, 6, ramp_callback, &ramp, out, 10) ==
+          CPAL_ERROR_BUFFER_TOO_SMALL);
+    config.channels = 0;
+    CHECK(cpal_offline_render(&config, 5, ramp_callback, &ramp, out, 10) ==
+          CPAL_ERROR_INVALID_ARGUMENT);
+}
+
+int main(void) {
+    test_error_messages();
+    test_null_arguments();
+    test_host_and_devices();
+    test_offline_render();
+    printf("ok\n");
+    return 0;
+}